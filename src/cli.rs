@@ -1,8 +1,22 @@
 extern crate clap;
 
-use clap::Clap;
+use clap::{Clap, IntoApp};
+use clap_generate::generate;
+use clap_generate::generators::{Bash, Elvish, Fish, PowerShell, Zsh};
+use std::io::Write;
 use std::path::PathBuf;
 
+use crate::compare::{Assertion, Compare, ExitCodes, LineOrder, Transform};
+use crate::driver::{ImportFormat, MAX_JOBS};
+use crate::report::ReportFormat;
+use crate::term::ColorChoice;
+
+const BIN_NAME: &str = "parrot";
+
+/// Size of the output of a command over which it is killed, unless
+/// `--max-output` is passed.
+pub const DEFAULT_MAX_OUTPUT: usize = 10 << 20;
+
 #[derive(Clap)]
 #[clap(version = "0.0.3")]
 #[clap(verbatim_doc_comment)]
@@ -16,42 +30,836 @@ pub struct Config {
     #[clap(subcommand)]
     pub cmd: Option<Command>,
 
-    /// Base path
-    #[clap(short, long, default_value = ".", parse(from_os_str))]
-    pub path: PathBuf,
+    /// Base path, defaults to the closest directory with a parrot folder among
+    /// the current one and its parents
+    #[clap(short, long, parse(from_os_str))]
+    pub path: Option<PathBuf>,
+
+    /// Verbose mode, `run` then lists the snapshots as they run, and with
+    /// `-vv` shows the outputs of the ones that pass
+    #[clap(short, long, parse(from_occurrences), global = true)]
+    pub verbose: u8,
+
+    /// Only print the verdict of `run`
+    #[clap(short, long, conflicts_with = "verbose", global = true)]
+    pub quiet: bool,
+
+    /// Machine-readable JSON output (run, list, status and show)
+    #[clap(long, global = true)]
+    pub json: bool,
+
+    /// When to color the output, `auto` colors it when stdout is a terminal
+    /// and NO_COLOR is not set
+    #[clap(long, value_name = "WHEN", possible_values = &["auto", "always", "never"], global = true)]
+    pub color: Option<ColorChoice>,
 
-    /// Verbode mode
-    #[clap(short, long)]
-    pub verbose: bool,
+    /// Kill commands running for more than this number of seconds, unless
+    /// their snapshot has its own timeout, `add` sets it as the timeout of the
+    /// new snapshot
+    #[clap(long, value_name = "SECONDS", global = true)]
+    pub timeout: Option<u64>,
+
+    /// Kill commands writing more than this size on stdout or stderr, in bytes
+    /// or with a K, M or G suffix, 10M by default
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size), global = true)]
+    pub max_output: Option<usize>,
+
+    /// Parrot folder to use instead of the `.parrot` folder of the project
+    #[clap(long, value_name = "DIR", env = "PARROT_DIR", parse(from_os_str), global = true)]
+    pub store: Option<PathBuf>,
+
+    /// Suite of snapshots to use, each suite is stored apart from the others
+    /// and from the snapshots outside of any suite
+    #[clap(long, value_name = "NAME", global = true)]
+    pub suite: Option<String>,
+
+    /// Wait for other parrot processes using the parrot folder to finish,
+    /// instead of failing
+    #[clap(long, global = true)]
+    pub wait: bool,
 }
 
 #[derive(Clap)]
+#[allow(clippy::large_enum_variant)] // Parsed once, boxing the add options is not worth it
 pub enum Command {
+    /// Accept the pending outputs of failing snapshots as their expected ones
+    Accept {
+        /// Name of the snapshot, all pending snapshots are accepted otherwise
+        name: Option<String>,
+    },
+
     /// Add a new snapshot for the given command
     Add {
         /// The command to execute
-        cmd: String,
+        #[clap(required_unless = "from-file")]
+        cmd: Option<String>,
 
         /// Optional name for the snapshot
         #[clap(short, long)]
         name: Option<String>,
 
+        /// Input fed to the command's stdin
+        #[clap(long, value_name = "INPUT", conflicts_with_all = &["stdin-file", "edit-stdin"])]
+        stdin: Option<String>,
+
+        /// A file whose content is fed to the command's stdin
+        #[clap(long, parse(from_os_str))]
+        stdin_file: Option<PathBuf>,
+
+        /// Write the input fed to the command's stdin in the editor
+        #[clap(long, conflicts_with = "stdin-file")]
+        edit_stdin: bool,
+
+        /// An environment variable to set when running the command
+        #[clap(short, long, value_name = "VAR=VALUE", number_of_values = 1, parse(try_from_str = parse_env_var))]
+        env: Vec<(String, String)>,
+
+        /// Record the current value of an environment variable and set it
+        /// when running the command
+        #[clap(long, value_name = "VAR", number_of_values = 1)]
+        capture_env: Vec<String>,
+
+        /// Fail the snapshot if the command runs for longer than this
+        /// duration, such as `500ms`, `2s` or `1m` (seconds by default)
+        #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+        max_duration: Option<u64>,
+
+        /// Run the command in a pseudo-terminal, as if it was run by hand
+        #[clap(long)]
+        pty: bool,
+
+        /// Record stdout and stderr together, in the order they are written,
+        /// as stdout
+        #[clap(long, conflicts_with = "pty")]
+        combined: bool,
+
+        /// Run the command in a container of the given image, with `docker`
+        /// or `podman`, such as `docker:alpine:3.19`
+        #[clap(long, value_name = "RUNTIME:IMAGE", parse(try_from_str = parse_runner))]
+        runner: Option<String>,
+
+        /// Run the command in a scrubbed environment, with only the variables
+        /// set with `--env` and a default `PATH`
+        #[clap(long)]
+        hermetic: bool,
+
+        /// An environment variable passed through the scrubbed environment
+        #[clap(long, value_name = "VAR", number_of_values = 1, requires = "hermetic")]
+        pass_env: Vec<String>,
+
+        /// A command run before the snapshot's command, to set up fixtures
+        #[clap(long, value_name = "CMD")]
+        before: Option<String>,
+
+        /// A command run after the snapshot's command, to clean up fixtures
+        #[clap(long, value_name = "CMD")]
+        after: Option<String>,
+
+        /// Execute the command again, up to N times, if the snapshot fails
+        #[clap(short, long, value_name = "N", default_value = "0")]
+        retries: u32,
+
+        /// Add a snapshot, named `<name>@<value>`, for each value of an
+        /// environment variable set when running the command, several
+        /// variables add a snapshot for each combination of their values
+        #[clap(short, long, value_name = "VAR=V1,V2...", number_of_values = 1, parse(try_from_str = parse_matrix))]
+        matrix: Vec<(String, Vec<String>)>,
+
+        /// A snapshot that must pass before this one is run, this one is
+        /// skipped otherwise
+        #[clap(long, value_name = "NAME", number_of_values = 1)]
+        needs: Vec<String>,
+
+        /// A group of snapshots that are never run in parallel with each
+        /// other, because they use the same database for instance
+        #[clap(long, value_name = "GROUP")]
+        serial: Option<String>,
+
+        /// A file the outputs of the command depend on, `update` does not
+        /// execute the command again as long as its inputs don't change
+        #[clap(long = "input", value_name = "FILE", number_of_values = 1)]
+        inputs: Vec<String>,
+
+        /// Remove ANSI escape sequences, such as colors, from the outputs
+        /// before comparing them
+        #[clap(long)]
+        strip_ansi: bool,
+
+        /// Ignore spaces and tabs at the end of lines when comparing outputs
+        #[clap(long)]
+        trim_trailing_whitespace: bool,
+
+        /// Consider consecutive blank lines as a single one when comparing
+        /// outputs
+        #[clap(long)]
+        collapse_blank_lines: bool,
+
+        /// Ignore all whitespace, line breaks included, when comparing outputs
+        #[clap(long)]
+        ignore_all_whitespace: bool,
+
+        /// Ignore the order of the lines when comparing outputs, for commands
+        /// whose output order is nondeterministic
+        #[clap(long)]
+        sort_lines: bool,
+
+        /// Consider CRLF line endings the same as LF ones when comparing
+        /// outputs, for snapshots recorded on another platform
+        #[clap(long)]
+        normalize_newlines: bool,
+
+        /// Only compare the exit code of the command, its outputs are neither
+        /// recorded nor compared
+        #[clap(long)]
+        exit_code_only: bool,
+
+        /// Neither record nor compare stdout
+        #[clap(long)]
+        ignore_stdout: bool,
+
+        /// Neither record nor compare stderr, for commands logging their
+        /// progress on it for instance
+        #[clap(long)]
+        ignore_stderr: bool,
+
+        /// Let the numbers of outputs differ by up to this value, for commands
+        /// printing measurements for instance, the text around them must match
+        #[clap(long, value_name = "EPSILON")]
+        abs_tolerance: Option<f64>,
+
+        /// Let the numbers of outputs differ by up to this ratio of the largest
+        /// one, 0.05 for 5%
+        #[clap(long, value_name = "RATIO")]
+        rel_tolerance: Option<f64>,
+
+        /// Accept these exit codes instead of the recorded one: `any`,
+        /// `nonzero`, a range such as `0..=1` or a list such as `1,2`
+        #[clap(long, value_name = "CODES")]
+        exit_codes: Option<ExitCodes>,
+
+        /// Assert a property of an output instead of comparing its content:
+        /// `stdout bytes 100..=2000`, `stdout lines 10..` or `stderr empty`
+        /// for instance
+        #[clap(long = "assert", value_name = "ASSERTION", number_of_values = 1)]
+        assertions: Vec<Assertion>,
+
+        /// Judge whether the outputs match with a command, run with the paths
+        /// of the expected and actual outputs as arguments, succeeding if they
+        /// match and explaining why they differ on its stdout otherwise
+        #[clap(long, value_name = "CMD")]
+        comparator: Option<String>,
+
+        /// Expect the snapshot to fail, to track known-broken behavior: it
+        /// passes if its outputs don't match or its command exits with a
+        /// non-zero code, and fails once it works
+        #[clap(long)]
+        xfail: bool,
+
+        /// A file written by the command, relative to the directory it runs
+        /// in, whose content is recorded and compared along with the outputs
+        #[clap(long = "artifact", value_name = "FILE", number_of_values = 1)]
+        artifacts: Vec<String>,
+
+        /// Keep the expected stdout in a file of the project, such as an
+        /// existing golden file, which is then the expected stdout, instead of
+        /// the snapshots folder
+        #[clap(long, value_name = "FILE")]
+        expected_stdout: Option<String>,
+
+        /// Only check that the outputs contain the sections, separated by blank
+        /// lines, of the stored outputs, which can be edited down to the lines
+        /// that matter
+        #[clap(long, value_name = "ORDER", possible_values = &["in-order", "any-order"])]
+        contains: Option<LineOrder>,
+
+        /// Show the outputs as hexdumps, even if they look like text, outputs
+        /// that are not valid UTF-8 always are
+        #[clap(long)]
+        binary: bool,
+
+        /// Compare stdout as JSON, YAML or TOML, regardless of key order and
+        /// formatting, failures are shown as the JSON pointers of the values
+        /// that differ
+        #[clap(long, value_name = "MODE", possible_values = &["text", "json", "yaml", "toml"])]
+        compare: Option<Compare>,
+
+        /// Redact a common source of nondeterminism from the outputs, with a
+        /// built-in filter
+        #[clap(
+            long = "filter",
+            value_name = "FILTER",
+            number_of_values = 1,
+            possible_values = &["timestamps", "uuids", "hex-addresses", "durations", "tmp-paths", "home-dir"]
+        )]
+        filters: Vec<String>,
+
+        /// Replace the matches of a regex in the outputs before comparing
+        /// them, to hide timestamps or temporary paths for instance, `$1` in
+        /// the replacement refers to the first capture group
+        #[clap(long, value_name = "REGEX=REPLACEMENT", number_of_values = 1, parse(try_from_str = parse_redaction))]
+        redact: Vec<(String, String)>,
+
+        /// Add a step to the pipeline transforming the outputs, in order,
+        /// before they are stored and compared: `strip-ansi`, `filter NAME`,
+        /// `redact REGEX=REPLACEMENT`, `sort-lines`, `trim-trailing-whitespace`,
+        /// `collapse-blank-lines` or `normalize-newlines`
+        #[clap(long = "pipe", value_name = "STEP", number_of_values = 1)]
+        pipeline: Vec<Transform>,
+
+        /// Add a snapshot for each command of a file, either one command per
+        /// line or a JSON list of objects with `cmd`, `name`, `description`,
+        /// `tags`, `env`, `timeout`, `max_duration_ms`, `pty`, `combined`,
+        /// `runner`, `hermetic` (the variables passed through), `before`,
+        /// `after`, `retries`, `needs`, `serial`, `inputs`, `strip_ansi`,
+        /// `filters`, `redact` (a list of objects with `pattern` and
+        /// `replacement` fields), `pipeline` (a list of steps, such as
+        /// `"sort-lines"`), `normalize` (an object with
+        /// `trim_trailing_whitespace`, `collapse_blank_lines`,
+        /// `ignore_all_whitespace`, `sort_lines` and `normalize_newlines`
+        /// fields), `exit_code_only`,
+        /// `ignore_stdout`, `ignore_stderr`, `binary`, `compare` (`text`,
+        /// `json`, `yaml` or `toml`), `tolerance` (an object with `absolute`
+        /// and `relative` fields), `contains` (`in-order` or `any-order`),
+        /// `exit_codes`, `assertions` (a list, such as `["stderr empty"]`),
+        /// `comparator`, `xfail`, `artifacts` and `expected_stdout` fields
+        #[clap(
+            long,
+            parse(from_os_str),
+            conflicts_with_all = &[
+                "cmd", "name", "stdin", "stdin-file", "edit-stdin", "env", "capture-env",
+                "max-duration", "pty", "combined", "runner", "hermetic", "pass-env", "before", "after",
+                "retries", "matrix", "needs", "serial", "inputs", "strip-ansi", "filters", "redact", "pipeline",
+                "trim-trailing-whitespace", "collapse-blank-lines", "ignore-all-whitespace", "sort-lines",
+                "normalize-newlines", "exit-code-only", "ignore-stdout", "ignore-stderr", "binary", "compare",
+                "abs-tolerance", "rel-tolerance", "contains", "exit-codes", "assertions", "comparator", "xfail",
+                "artifacts", "expected-stdout"
+            ]
+        )]
+        from_file: Option<PathBuf>,
+
         /// Accept the snapshot
         #[clap(short, long)]
         yes: bool,
     },
 
+    /// Move a snapshot out of the active set, without deleting it
+    Archive {
+        /// Name of the snapshot to archive
+        name: String,
+    },
+
+    /// Delete snapshot files without metadata and snapshots without files
+    Clean {
+        /// Only report what would be deleted
+        #[clap(short, long)]
+        dry_run: bool,
+    },
+
+    /// Print a completion script for the given shell
+    Completions {
+        #[clap(possible_values = &["bash", "elvish", "fish", "powershell", "zsh"])]
+        shell: String,
+    },
+
+    /// Copy a snapshot, along with its expected outputs
+    Copy {
+        /// Name of the snapshot to copy
+        name: String,
+
+        /// Name of the copy
+        #[clap(short, long)]
+        new_name: String,
+
+        /// Command of the copy, defaults to the command of the original
+        #[clap(short, long)]
+        cmd: Option<String>,
+    },
+
+    /// Show the diff between a snapshot and the current output of its command
+    Diff {
+        /// Name of the snapshot
+        name: String,
+
+        /// Print a plain diff, without colors, for use with other tools, the
+        /// same as `--color never`
+        #[clap(long)]
+        no_color: bool,
+    },
+
+    /// Check the integrity of the parrot folder
+    Doctor {},
+
+    /// Edit the name and description of a snapshot in your editor
+    Edit {
+        /// Name of the snapshot to edit
+        name: String,
+    },
+
     /// Execute a script
     Exec { cmd: String },
 
+    /// Delete the stored data no snapshot refers to anymore
+    Gc {
+        /// Only report what would be deleted
+        #[clap(short, long)]
+        dry_run: bool,
+
+        /// Also delete the snapshot files no snapshot refers to and the
+        /// divergent outputs of repeated runs
+        #[clap(long)]
+        aggressive: bool,
+
+        /// Number of previous versions kept in the history of each snapshot,
+        /// defaults to the configured one
+        #[clap(long, value_name = "N")]
+        keep_history: Option<usize>,
+    },
+
+    /// List the previous versions of the expected outputs of a snapshot
+    History {
+        /// Name of the snapshot
+        name: String,
+    },
+
+    /// Import the snapshots of insta (recorded with insta-cmd), cram or turnt,
+    /// with the outputs they expect
+    Import {
+        /// Format of the snapshots
+        #[clap(long, possible_values = &["insta", "cram", "turnt"])]
+        format: ImportFormat,
+
+        /// A snapshot file, or a folder searched for snapshot files
+        #[clap(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Import the snapshots without asking
+        #[clap(short, long)]
+        yes: bool,
+    },
+
     /// Initialize Parrot
     Init {},
 
+    /// List snapshots
+    List {
+        /// Only list snapshots with the given tag, can be repeated
+        #[clap(short, long)]
+        tags: Vec<String>,
+
+        /// Only list snapshots whose name contains the given string, or
+        /// matches it if it contains wildcards (`*` or `?`)
+        #[clap(short, long)]
+        name: Option<String>,
+
+        /// Only print the name of the snapshots, one per line
+        #[clap(long)]
+        names_only: bool,
+
+        /// List archived snapshots instead of active ones
+        #[clap(long)]
+        archived: bool,
+
+        /// Only list snapshots last changed more than the given age ago, such
+        /// as 2w, 3d, 12h or 30m
+        #[clap(long, value_name = "AGE", parse(try_from_str = parse_age))]
+        older_than: Option<u64>,
+
+        /// Only list snapshots last changed less than the given age ago
+        #[clap(long, value_name = "AGE", parse(try_from_str = parse_age))]
+        newer_than: Option<u64>,
+    },
+
+    /// Upgrade the parrot folder to the format of this version of parrot
+    Migrate {},
+
+    /// Discard the pending outputs of failing snapshots
+    Reject {
+        /// Name of the snapshot, all pending snapshots are rejected otherwise
+        name: Option<String>,
+    },
+
+    /// Delete a snapshot, it is kept in the trash for a while
+    #[clap(name = "rm", alias = "remove")]
+    Remove {
+        /// Name of the snapshot to delete
+        name: String,
+
+        /// Do not ask for confirmation
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Rename a snapshot
+    Rename {
+        /// Current name of the snapshot
+        name: String,
+
+        /// New name of the snapshot
+        new_name: String,
+    },
+
+    /// Resolve the snapshots git left conflicted after a merge, picking either
+    /// version or re-recording them
+    Resolve {},
+
+    /// Bring an archived snapshot back to the active set
+    Restore {
+        /// Name of the snapshot to restore
+        name: String,
+    },
+
+    /// Run snapshots, then review failing ones one by one
+    Review {},
+
+    /// Restore a previous version of the expected outputs of a snapshot
+    Rollback {
+        /// Name of the snapshot
+        name: String,
+
+        /// Version to restore, defaults to the most recent one
+        #[clap(long, value_name = "VERSION")]
+        to: Option<u32>,
+    },
+
     /// Run snapshot tests
-    Run {},
+    Run {
+        /// Only run snapshots whose name contains the pattern, or matches it if
+        /// it contains wildcards (`*` or `?`)
+        pattern: Option<String>,
+
+        /// Stop after N failures
+        #[clap(long, value_name = "N")]
+        max_failures: Option<usize>,
+
+        /// Exit successfully if at most N snapshots fail
+        #[clap(long, value_name = "N", default_value = "0")]
+        allow_failures: usize,
+
+        /// Only run snapshots that failed during the last run
+        #[clap(short, long)]
+        last_failed: bool,
+
+        /// Only run snapshots with the given tag, can be repeated
+        #[clap(long = "tag", value_name = "TAG", number_of_values = 1)]
+        tags: Vec<String>,
+
+        /// Skip snapshots with the given tag, can be repeated
+        #[clap(long = "exclude-tag", value_name = "TAG", number_of_values = 1)]
+        exclude_tags: Vec<String>,
+
+        /// Only run snapshots whose name matches the regular expression
+        #[clap(long, value_name = "REGEX")]
+        name_regex: Option<String>,
+
+        /// List the snapshots that would be run, without running them
+        #[clap(short, long)]
+        dry_run: bool,
+
+        /// Number of snapshots executed in parallel, at most 64
+        #[clap(short, long, value_name = "N", default_value = "1", parse(try_from_str = parse_jobs))]
+        jobs: usize,
+
+        /// Run the snapshots N times to flush out nondeterminism, stops at the
+        /// first failure
+        #[clap(long, value_name = "N")]
+        repeat: Option<usize>,
+
+        /// Run the snapshots again and again until one fails
+        #[clap(long)]
+        until_failure: bool,
+
+        /// Keep the outputs of the failing snapshots as pending, to accept or
+        /// reject them afterwards
+        #[clap(long)]
+        pending: bool,
+
+        /// Write a report of the run to a file, such as `junit=report.xml`,
+        /// `json=report.json`, `html=report.html` or `markdown=summary.md`,
+        /// can be repeated
+        #[clap(long, value_name = "FORMAT=PATH", number_of_values = 1, parse(try_from_str = parse_report))]
+        report: Vec<(ReportFormat, PathBuf)>,
+
+        /// Print a GitHub Actions annotation for each failing snapshot, the
+        /// default when GITHUB_ACTIONS is set
+        #[clap(long)]
+        gha: bool,
+    },
+
+    /// Show a snapshot
+    Show {
+        /// Name of the snapshot to show
+        name: String,
+
+        /// Only print the stored stdout, as is
+        #[clap(short, long)]
+        raw: bool,
+    },
+
+    /// Print statistics on the snapshots and their run history
+    Stats {
+        /// Number of snapshots listed as slowest and largest
+        #[clap(long, value_name = "N", default_value = "5")]
+        top: usize,
+    },
+
+    /// Print a summary of the snapshots and their last status
+    Status {},
+
+    /// Manage snapshot tags
+    Tag {
+        #[clap(subcommand)]
+        cmd: TagCommand,
+    },
+
+    /// Manage the removed snapshots kept in the trash: list them, restore the
+    /// most recently removed snapshot of a name or delete them all
+    Trash {
+        // Not subcommands, which the zsh completions only support when they
+        // share their name with a command
+        #[clap(possible_values = &["list", "restore", "empty"], default_value = "list")]
+        action: String,
+
+        /// Name of the snapshot to restore
+        #[clap(required_if("action", "restore"))]
+        name: Option<String>,
+    },
+
+    /// Update snapshots with the current output of their commands
+    Update {
+        /// Only update snapshots whose name contains the pattern, or matches it
+        /// if it contains wildcards (`*` or `?`)
+        pattern: Option<String>,
+
+        /// Update all snapshots
+        #[clap(short, long, conflicts_with = "pattern")]
+        all: bool,
+
+        /// Only update failing snapshots, their diff is displayed
+        #[clap(short, long)]
+        failed_only: bool,
+
+        /// List the snapshots that would be updated, without running them
+        #[clap(short, long)]
+        dry_run: bool,
+
+        /// Execute snapshots even if their inputs did not change since they
+        /// last passed
+        #[clap(long)]
+        no_cache: bool,
+    },
+}
+
+#[derive(Clap)]
+pub enum TagCommand {
+    /// Add a tag to snapshots
+    Add {
+        /// The tag to add
+        tag: String,
+
+        /// Only tag snapshots whose name contains the given string, or matches
+        /// it if it contains wildcards (`*` or `?`)
+        #[clap(short, long)]
+        name: Option<String>,
+
+        /// Tag all snapshots
+        #[clap(short, long, conflicts_with = "name")]
+        all: bool,
+    },
+
+    /// List tags along with the number of snapshots using them
+    List {},
+
+    /// Remove a tag from snapshots
+    #[clap(name = "rm", alias = "remove")]
+    Remove {
+        /// The tag to remove
+        tag: String,
+
+        /// Only untag snapshots whose name contains the given string, or
+        /// matches it if it contains wildcards (`*` or `?`)
+        #[clap(short, long)]
+        name: Option<String>,
+
+        /// Remove the tag from all snapshots
+        #[clap(short, long, conflicts_with = "name")]
+        all: bool,
+    },
 }
 
 /// Parse CLI args, may terminate the program
 pub fn parse() -> Config {
     Config::parse()
 }
+
+/// Parses a `VAR=VALUE` environment variable.
+fn parse_env_var(arg: &str) -> Result<(String, String), String> {
+    match arg.find('=') {
+        Some(idx) if idx > 0 => Ok((arg[..idx].to_owned(), arg[idx + 1..].to_owned())),
+        _ => Err(format!("expected VAR=VALUE, got '{}'", arg)),
+    }
+}
+
+fn parse_matrix(arg: &str) -> Result<(String, Vec<String>), String> {
+    let (var, values) = parse_env_var(arg).map_err(|_| format!("expected VAR=V1,V2..., got '{}'", arg))?;
+    if values.split(',').any(str::is_empty) {
+        return Err(format!("expected non-empty values, got '{}'", arg));
+    }
+    Ok((var, values.split(',').map(str::to_owned).collect()))
+}
+
+/// Parses a redaction, the replacement follows the last '='.
+fn parse_redaction(arg: &str) -> Result<(String, String), String> {
+    match arg.rsplit_once('=') {
+        Some((pattern, replacement)) if !pattern.is_empty() => match regex::bytes::Regex::new(pattern) {
+            Ok(_) => Ok((pattern.to_owned(), replacement.to_owned())),
+            Err(_) => Err(format!("invalid regex '{}'", pattern)),
+        },
+        _ => Err(format!("expected REGEX=REPLACEMENT, got '{}'", arg)),
+    }
+}
+
+/// Parses a number of parallel jobs, the interrupt handler keeps track of the
+/// processes of `MAX_JOBS` of them.
+fn parse_jobs(arg: &str) -> Result<usize, String> {
+    match arg.parse::<usize>() {
+        Ok(jobs) if (1..=MAX_JOBS).contains(&jobs) => Ok(jobs),
+        _ => Err(format!("expected a number of jobs between 1 and {}, got '{}'", MAX_JOBS, arg)),
+    }
+}
+
+fn parse_runner(arg: &str) -> Result<String, String> {
+    match arg.split_once(':') {
+        Some(("docker", image)) | Some(("podman", image)) if !image.is_empty() => Ok(arg.to_owned()),
+        _ => Err(format!("expected docker:IMAGE or podman:IMAGE, got '{}'", arg)),
+    }
+}
+
+/// Parses a duration in milliseconds, in seconds unless followed by a `ms`,
+/// `s` or `m` unit.
+fn parse_duration(arg: &str) -> Result<u64, String> {
+    let (number, unit) = if let Some(number) = arg.strip_suffix("ms") {
+        (number, 1.0)
+    } else if let Some(number) = arg.strip_suffix('s') {
+        (number, 1000.0)
+    } else if let Some(number) = arg.strip_suffix('m') {
+        (number, 60_000.0)
+    } else {
+        (arg, 1000.0)
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok((number * unit).round() as u64),
+        _ => Err(format!("expected a duration such as 500ms or 2s, got '{}'", arg)),
+    }
+}
+
+/// Parses a `FORMAT=PATH` report file.
+fn parse_report(arg: &str) -> Result<(ReportFormat, PathBuf), String> {
+    let (format, path) = match arg.split_once('=') {
+        Some((format, path)) if !path.is_empty() => (format, path),
+        _ => return Err(format!("expected FORMAT=PATH, got '{}'", arg)),
+    };
+    match format {
+        "junit" => Ok((ReportFormat::JUnit, PathBuf::from(path))),
+        "json" => Ok((ReportFormat::Json, PathBuf::from(path))),
+        "html" => Ok((ReportFormat::Html, PathBuf::from(path))),
+        "markdown" => Ok((ReportFormat::Markdown, PathBuf::from(path))),
+        _ => Err(format!("unknown report format '{}', expected junit, json, html or markdown", format)),
+    }
+}
+
+/// Parses an age in seconds, followed by a `m`, `h`, `d` or `w` unit.
+fn parse_age(arg: &str) -> Result<u64, String> {
+    let (number, unit) = match arg.char_indices().last() {
+        Some((idx, 'm')) => (&arg[..idx], 60),
+        Some((idx, 'h')) => (&arg[..idx], 3600),
+        Some((idx, 'd')) => (&arg[..idx], 24 * 3600),
+        Some((idx, 'w')) => (&arg[..idx], 7 * 24 * 3600),
+        _ => return Err(format!("expected an age such as 3d or 12h, got '{}'", arg)),
+    };
+    match number.parse::<u64>() {
+        Ok(number) => Ok(number * unit),
+        Err(_) => Err(format!("expected an age such as 3d or 12h, got '{}'", arg)),
+    }
+}
+
+/// Parses a size in bytes, optionally followed by a K, M or G (binary) unit.
+fn parse_size(arg: &str) -> Result<usize, String> {
+    let (number, unit) = match arg.char_indices().last() {
+        Some((idx, 'K')) => (&arg[..idx], 1 << 10),
+        Some((idx, 'M')) => (&arg[..idx], 1 << 20),
+        Some((idx, 'G')) => (&arg[..idx], 1 << 30),
+        _ => (arg, 1),
+    };
+    match number.parse::<usize>() {
+        Ok(number) => Ok(number * unit),
+        Err(_) => Err(format!("expected a size such as 512K or 10M, got '{}'", arg)),
+    }
+}
+
+/// Writes the completion script for the given shell to buffer.
+/// Bash, fish and zsh scripts also complete snapshot names, by calling
+/// `parrot list --names-only`.
+pub fn completions<B: Write>(shell: &str, buffer: &mut B) {
+    let mut app = Config::into_app();
+    let mut script = Vec::new();
+    match shell {
+        "bash" => generate::<Bash, _>(&mut app, BIN_NAME, &mut script),
+        "elvish" => generate::<Elvish, _>(&mut app, BIN_NAME, &mut script),
+        "fish" => generate::<Fish, _>(&mut app, BIN_NAME, &mut script),
+        "powershell" => generate::<PowerShell, _>(&mut app, BIN_NAME, &mut script),
+        _ => generate::<Zsh, _>(&mut app, BIN_NAME, &mut script),
+    }
+    let mut script = String::from_utf8_lossy(&script).into_owned();
+    match shell {
+        "bash" => script.push_str(BASH_SNAPSHOTS),
+        "fish" => script.push_str(FISH_SNAPSHOTS),
+        "zsh" => {
+            // Complete positional snapshot names and patterns instead of files
+            script = script
+                .lines()
+                .map(|line| {
+                    if line.starts_with("':name -- ") || line.starts_with("'::pattern -- ") {
+                        line.replace(":_files'", ":_parrot_snapshots'")
+                    } else {
+                        line.to_owned()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            let call = "\n_parrot \"$@\"";
+            script = script.replacen(call, &format!("{}{}", ZSH_SNAPSHOTS, call), 1);
+            script.push('\n');
+        }
+        _ => (),
+    }
+    buffer.write_all(script.as_bytes()).unwrap();
+}
+
+// Complete snapshot names as the first argument of the subcommands taking one.
+const BASH_SNAPSHOTS: &str = r#"
+_parrot_snapshots() {
+    _parrot "$@"
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [[ ${cur} == -* || ${COMP_CWORD} -lt 2 || ${COMP_WORDS[COMP_CWORD-2]} == tag ]] ; then
+        return 0
+    fi
+    case "${COMP_WORDS[COMP_CWORD-1]}" in
+        copy|diff|rename|rm|run|show|update)
+            COMPREPLY=( $(compgen -W "$(parrot list --names-only 2>/dev/null)" -- "${cur}") )
+            ;;
+    esac
+}
+
+complete -F _parrot_snapshots -o bashdefault -o default parrot
+"#;
+
+const FISH_SNAPSHOTS: &str = r#"complete -c parrot -n "__fish_seen_subcommand_from copy diff rename rm run show update; and not __fish_seen_subcommand_from tag" -f -a "(parrot list --names-only 2>/dev/null)"
+"#;
+
+const ZSH_SNAPSHOTS: &str = r#"
+(( $+functions[_parrot_snapshots] )) ||
+_parrot_snapshots() {
+    local snapshots; snapshots=(${(f)"$(parrot list --names-only 2>/dev/null)"})
+    _describe -t snapshots 'snapshots' snapshots
+}
+"#;
@@ -0,0 +1,739 @@
+//! How outputs are compared with the stored ones, once normalized, with
+//! placeholders or numbers within tolerance, and the checks that replace or
+//! complement the comparison.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use crate::data::Redaction;
+
+/// How outputs are normalized when they are compared, the stored outputs are
+/// left untouched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Normalize {
+    /// Ignore spaces and tabs at the end of lines.
+    pub trim_trailing_whitespace: bool,
+    /// Consider consecutive blank lines as a single one.
+    pub collapse_blank_lines: bool,
+    /// Ignore all whitespace, line breaks included.
+    pub ignore_all_whitespace: bool,
+    /// Ignore the order of the lines, for commands printing from parallel
+    /// workers or iterating over hash maps for instance.
+    pub sort_lines: bool,
+    /// Consider CRLF line endings the same as LF ones.
+    pub normalize_newlines: bool,
+}
+
+impl Normalize {
+    /// Returns true if outputs are compared as is.
+    pub fn is_none(&self) -> bool {
+        *self == Normalize::default()
+    }
+
+    /// Returns the options set in either `self` or `other`.
+    pub fn or(&self, other: &Normalize) -> Normalize {
+        Normalize {
+            trim_trailing_whitespace: self.trim_trailing_whitespace || other.trim_trailing_whitespace,
+            collapse_blank_lines: self.collapse_blank_lines || other.collapse_blank_lines,
+            ignore_all_whitespace: self.ignore_all_whitespace || other.ignore_all_whitespace,
+            sort_lines: self.sort_lines || other.sort_lines,
+            normalize_newlines: self.normalize_newlines || other.normalize_newlines,
+        }
+    }
+
+    /// Normalizes an output.
+    pub fn apply<'a>(&self, body: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.is_none() {
+            return Cow::Borrowed(body);
+        }
+        let mut lines: Vec<&[u8]> = Vec::new();
+        // The final line break is kept last when lines are sorted.
+        let (body, end) = match body.strip_suffix(b"\n") {
+            Some(body) if self.sort_lines => (body, &b"\n"[..]),
+            _ => (body, &b""[..]),
+        };
+        for mut line in body.split(|byte| *byte == b'\n') {
+            if self.normalize_newlines {
+                line = line.strip_suffix(b"\r").unwrap_or(line);
+            }
+            if self.trim_trailing_whitespace {
+                while let Some((b' ', rest)) | Some((b'\t', rest)) | Some((b'\r', rest)) = line.split_last() {
+                    line = rest;
+                }
+            }
+            let is_blank = |line: &[u8]| line.iter().all(u8::is_ascii_whitespace);
+            if self.collapse_blank_lines && is_blank(line) && lines.last().is_some_and(|last| is_blank(last)) {
+                continue;
+            }
+            lines.push(line);
+        }
+        if self.sort_lines {
+            lines.sort_unstable();
+        }
+        let mut body = lines.join(&b'\n');
+        body.extend_from_slice(end);
+        if self.ignore_all_whitespace {
+            body.retain(|byte| !byte.is_ascii_whitespace());
+        }
+        Cow::Owned(body)
+    }
+
+    /// Returns true if two outputs are the same once normalized.
+    pub fn same(&self, a: &[u8], b: &[u8]) -> bool {
+        a == b || self.apply(a) == self.apply(b)
+    }
+}
+
+/// How close the numbers of outputs must be to match, outputs containing
+/// measurements for instance, the text around the numbers must be the same.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tolerance {
+    /// The maximum difference between two numbers.
+    pub absolute: f64,
+    /// The maximum difference between two numbers, relative to the largest.
+    pub relative: f64,
+}
+
+impl Tolerance {
+    /// Returns true if numbers must be equal.
+    pub fn is_none(&self) -> bool {
+        self.absolute == 0.0 && self.relative == 0.0
+    }
+
+    fn close(&self, a: f64, b: f64) -> bool {
+        let difference = (a - b).abs();
+        difference <= self.absolute || difference <= self.relative * a.abs().max(b.abs())
+    }
+
+    /// Returns true if two outputs are the same, apart from their numbers
+    /// which only need to be within tolerance.
+    pub fn same(&self, a: &[u8], b: &[u8]) -> bool {
+        if a == b {
+            return true;
+        }
+        let number = regex::bytes::Regex::new(r"[-+]?(\d+\.?\d*|\.\d+)([eE][-+]?\d+)?").unwrap();
+        let parse = |number: &[u8]| std::str::from_utf8(number).ok().and_then(|number| number.parse::<f64>().ok());
+        let (mut a_numbers, mut b_numbers) = (number.find_iter(a), number.find_iter(b));
+        let (mut a_end, mut b_end) = (0, 0);
+        loop {
+            match (a_numbers.next(), b_numbers.next()) {
+                (None, None) => return a[a_end..] == b[b_end..],
+                (Some(a_number), Some(b_number)) => {
+                    if a[a_end..a_number.start()] != b[b_end..b_number.start()] {
+                        return false;
+                    }
+                    match (parse(a_number.as_bytes()), parse(b_number.as_bytes())) {
+                        (Some(x), Some(y)) if self.close(x, y) => (),
+                        _ => return false,
+                    }
+                    a_end = a_number.end();
+                    b_end = b_number.end();
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// Returns a regex matching the outputs a stored output stands for, if it
+/// contains placeholders: `[..]` for any text within a line, `[DIGITS]` for
+/// a number and `[EXE]` for the extension of executables.
+fn placeholder_pattern(expected: &[u8]) -> Option<regex::bytes::Regex> {
+    let expected = std::str::from_utf8(expected).ok()?;
+    let placeholder = regex::Regex::new(r"\[(\.\.|DIGITS|EXE)\]").unwrap();
+    if !placeholder.is_match(expected) {
+        return None;
+    }
+    let mut pattern = String::from(r"\A");
+    let mut end = 0;
+    for found in placeholder.find_iter(expected) {
+        pattern.push_str(&regex::escape(&expected[end..found.start()]));
+        match found.as_str() {
+            "[..]" => pattern.push_str(r"[^\n]*?"),
+            "[DIGITS]" => pattern.push_str("[0-9]+"),
+            _ => pattern.push_str(&regex::escape(std::env::consts::EXE_SUFFIX)),
+        }
+        end = found.end();
+    }
+    pattern.push_str(&regex::escape(&expected[end..]));
+    pattern.push_str(r"\z");
+    regex::bytes::Regex::new(&pattern).ok()
+}
+
+/// 64 bits FNV-1a, a simple hash that is stable across platforms and
+/// versions.
+pub struct Fnv(pub u64);
+
+impl Fnv {
+    pub fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Hashes a length-prefixed chunk of bytes, so that consecutive chunks
+    /// can't be confused.
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Returns true if a stored output contains placeholders matching an actual
+/// output.
+pub fn matches_placeholders(expected: &[u8], actual: &[u8]) -> bool {
+    placeholder_pattern(expected).is_some_and(|pattern| pattern.is_match(actual))
+}
+
+/// Replaces the lines of an actual output matching the lines with
+/// placeholders of a stored output by these lines, so that they don't show
+/// up in diffs.
+pub fn mask_placeholders<'a>(expected: &'a [u8], actual: &'a [u8]) -> Cow<'a, [u8]> {
+    if placeholder_pattern(expected).is_none() {
+        return Cow::Borrowed(actual);
+    }
+    let expected_lines: Vec<(&[u8], _)> =
+        expected.split(|byte| *byte == b'\n').map(|line| (line, placeholder_pattern(line))).collect();
+    let mut next = 0;
+    let mut lines: Vec<&[u8]> = Vec::new();
+    for line in actual.split(|byte| *byte == b'\n') {
+        let found = expected_lines[next..].iter().position(|(expected, pattern)| {
+            *expected == line || pattern.as_ref().is_some_and(|pattern| pattern.is_match(line))
+        });
+        match found {
+            Some(offset) => {
+                lines.push(expected_lines[next + offset].0);
+                next += offset + 1;
+            }
+            None => lines.push(line),
+        }
+    }
+    Cow::Owned(lines.join(&b'\n'))
+}
+
+/// How the stdout of a snapshot is compared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compare {
+    /// Byte for byte, once normalized.
+    #[default]
+    Text,
+    /// As JSON, YAML or TOML values, regardless of key order and formatting,
+    /// outputs that can't be parsed are compared as text.
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Compare {
+    pub fn is_text(&self) -> bool {
+        *self == Compare::Text
+    }
+
+    /// The name of the format outputs are compared as.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compare::Text => "text",
+            Compare::Json => "JSON",
+            Compare::Yaml => "YAML",
+            Compare::Toml => "TOML",
+        }
+    }
+}
+
+impl std::str::FromStr for Compare {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Compare, String> {
+        match mode {
+            "text" => Ok(Compare::Text),
+            "json" => Ok(Compare::Json),
+            "yaml" => Ok(Compare::Yaml),
+            "toml" => Ok(Compare::Toml),
+            _ => Err(format!("unknown comparison mode '{}'", mode)),
+        }
+    }
+}
+
+/// Whether the sections of a stored output must appear in the same order in
+/// the actual output, when the snapshot only asserts that they are contained.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineOrder {
+    InOrder,
+    AnyOrder,
+}
+
+impl std::str::FromStr for LineOrder {
+    type Err = String;
+
+    fn from_str(order: &str) -> Result<LineOrder, String> {
+        match order {
+            "in-order" => Ok(LineOrder::InOrder),
+            "any-order" => Ok(LineOrder::AnyOrder),
+            _ => Err(format!("unknown line order '{}'", order)),
+        }
+    }
+}
+
+/// Returns the sections of a stored output, separated by blank lines, that
+/// don't appear in an actual output, the lines of a section must appear
+/// consecutively and may contain placeholders.
+pub fn missing_sections<'a>(expected: &'a [u8], actual: &[u8], order: LineOrder) -> Vec<Vec<&'a [u8]>> {
+    let mut sections: Vec<Vec<&[u8]>> = vec![Vec::new()];
+    for line in expected.split(|byte| *byte == b'\n') {
+        match sections.last_mut() {
+            Some(section) if !line.iter().all(u8::is_ascii_whitespace) => section.push(line),
+            Some(section) if section.is_empty() => (),
+            _ => sections.push(Vec::new()),
+        }
+    }
+    let actual_lines: Vec<&[u8]> = actual.split(|byte| *byte == b'\n').collect();
+    let mut start = 0;
+    let mut missing = Vec::new();
+    for section in sections.into_iter().filter(|section| !section.is_empty()) {
+        let patterns: Vec<_> = section.iter().map(|line| placeholder_pattern(line)).collect();
+        let matches_at = |index: usize| {
+            section.iter().zip(&patterns).enumerate().all(|(offset, (line, pattern))| {
+                actual_lines.get(index + offset).is_some_and(|actual| {
+                    actual == line || pattern.as_ref().is_some_and(|pattern| pattern.is_match(actual))
+                })
+            })
+        };
+        let from = if order == LineOrder::InOrder { start } else { 0 };
+        match (from..actual_lines.len()).find(|index| matches_at(*index)) {
+            Some(index) if order == LineOrder::InOrder => start = index + section.len(),
+            Some(_) => (),
+            None => missing.push(section),
+        }
+    }
+    missing
+}
+
+/// The exit codes a snapshot accepts instead of the recorded one, for
+/// commands whose failure code varies across platforms for instance.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum ExitCodes {
+    /// Any exit code, as long as the command was not killed by a signal.
+    Any,
+    Nonzero,
+    /// An inclusive range, such as `0..=1`.
+    Range(i32, i32),
+    /// A list of codes, such as `1,2`.
+    List(Vec<i32>),
+}
+
+impl ExitCodes {
+    /// Returns true if an actual exit code is accepted.
+    pub fn matches(&self, code: Option<i32>) -> bool {
+        let code = match code {
+            Some(code) => code,
+            None => return false,
+        };
+        match self {
+            ExitCodes::Any => true,
+            ExitCodes::Nonzero => code != 0,
+            ExitCodes::Range(start, end) => (*start..=*end).contains(&code),
+            ExitCodes::List(codes) => codes.contains(&code),
+        }
+    }
+}
+
+impl std::str::FromStr for ExitCodes {
+    type Err = String;
+
+    fn from_str(codes: &str) -> Result<ExitCodes, String> {
+        let code = |code: &str| code.trim().parse::<i32>().map_err(|_| format!("invalid exit code '{}'", code));
+        match codes.trim() {
+            "any" => Ok(ExitCodes::Any),
+            "nonzero" => Ok(ExitCodes::Nonzero),
+            codes => {
+                if let Some((start, end)) = codes.split_once("..=") {
+                    Ok(ExitCodes::Range(code(start)?, code(end)?))
+                } else if let Some((start, end)) = codes.split_once("..") {
+                    Ok(ExitCodes::Range(code(start)?, code(end)? - 1))
+                } else {
+                    Ok(ExitCodes::List(codes.split(',').map(code).collect::<Result<_, _>>()?))
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ExitCodes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExitCodes::Any => write!(f, "any"),
+            ExitCodes::Nonzero => write!(f, "nonzero"),
+            ExitCodes::Range(start, end) => write!(f, "{}..={}", start, end),
+            ExitCodes::List(codes) => {
+                let codes: Vec<String> = codes.iter().map(i32::to_string).collect();
+                write!(f, "{}", codes.join(","))
+            }
+        }
+    }
+}
+
+impl From<ExitCodes> for String {
+    fn from(codes: ExitCodes) -> String {
+        codes.to_string()
+    }
+}
+
+impl TryFrom<String> for ExitCodes {
+    type Error = String;
+
+    fn try_from(codes: String) -> Result<ExitCodes, String> {
+        codes.parse()
+    }
+}
+
+/// Inclusive bounds of a size, written as `3`, `1..=5`, `1..6` or `10..`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Bounds {
+    pub fn contains(&self, size: usize) -> bool {
+        size >= self.min && self.max.is_none_or(|max| size <= max)
+    }
+}
+
+impl std::str::FromStr for Bounds {
+    type Err = String;
+
+    fn from_str(bounds: &str) -> Result<Bounds, String> {
+        let size = |size: &str| size.trim().parse::<usize>().map_err(|_| format!("invalid size '{}'", size));
+        let (min, max) = if let Some((min, max)) = bounds.split_once("..=") {
+            (size(min)?, Some(size(max)?))
+        } else if let Some((min, max)) = bounds.split_once("..") {
+            match max.trim() {
+                "" => (size(min)?, None),
+                max => (size(min)?, Some(size(max)?.checked_sub(1).ok_or("empty range")?)),
+            }
+        } else {
+            (size(bounds)?, Some(size(bounds)?))
+        };
+        match max {
+            Some(max) if max < min => Err(format!("empty range '{}'", bounds)),
+            _ => Ok(Bounds { min, max }),
+        }
+    }
+}
+
+impl std::fmt::Display for Bounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{}..={}", self.min, max),
+            None => write!(f, "{}..", self.min),
+        }
+    }
+}
+
+/// A property of an output checked instead of its content, for generators
+/// whose exact output changes but whose shape must stay stable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Empty,
+    /// The size of the output, in bytes.
+    Bytes(Bounds),
+    Lines(Bounds),
+}
+
+/// An assertion on a property of stdout or stderr, written as
+/// `stdout bytes 100..=2000`, `stdout lines 10..` or `stderr empty`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Assertion {
+    pub stderr: bool,
+    pub property: Property,
+}
+
+impl Assertion {
+    fn output(&self) -> &'static str {
+        if self.stderr {
+            "stderr"
+        } else {
+            "stdout"
+        }
+    }
+
+    /// Checks an actual output, returns why it fails otherwise.
+    pub fn check(&self, body: &[u8]) -> Result<(), String> {
+        let lines = body.split(|byte| *byte == b'\n').count() - body.ends_with(b"\n") as usize;
+        let lines = if body.is_empty() { 0 } else { lines };
+        match self.property {
+            Property::Empty if body.is_empty() => Ok(()),
+            Property::Empty => Err(format!("{} is not empty, {} bytes", self.output(), body.len())),
+            Property::Bytes(bounds) if bounds.contains(body.len()) => Ok(()),
+            Property::Bytes(bounds) => Err(format!("{} has {} bytes, expected {}", self.output(), body.len(), bounds)),
+            Property::Lines(bounds) if bounds.contains(lines) => Ok(()),
+            Property::Lines(bounds) => Err(format!("{} has {} lines, expected {}", self.output(), lines, bounds)),
+        }
+    }
+}
+
+impl std::str::FromStr for Assertion {
+    type Err = String;
+
+    fn from_str(assertion: &str) -> Result<Assertion, String> {
+        let words: Vec<&str> = assertion.split_whitespace().collect();
+        let stderr = match words.first() {
+            Some(&"stdout") => false,
+            Some(&"stderr") => true,
+            _ => return Err(format!("expected an assertion on stdout or stderr, got '{}'", assertion)),
+        };
+        let property = match words[1..] {
+            ["empty"] => Property::Empty,
+            ["bytes", bounds] => Property::Bytes(bounds.parse()?),
+            ["lines", bounds] => Property::Lines(bounds.parse()?),
+            _ => return Err(format!("expected 'empty', 'bytes BOUNDS' or 'lines BOUNDS', got '{}'", assertion)),
+        };
+        Ok(Assertion { stderr, property })
+    }
+}
+
+impl std::fmt::Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.property {
+            Property::Empty => write!(f, "{} empty", self.output()),
+            Property::Bytes(bounds) => write!(f, "{} bytes {}", self.output(), bounds),
+            Property::Lines(bounds) => write!(f, "{} lines {}", self.output(), bounds),
+        }
+    }
+}
+
+impl From<Assertion> for String {
+    fn from(assertion: Assertion) -> String {
+        assertion.to_string()
+    }
+}
+
+impl TryFrom<String> for Assertion {
+    type Error = String;
+
+    fn try_from(assertion: String) -> Result<Assertion, String> {
+        assertion.parse()
+    }
+}
+
+/// A step of the pipeline transforming the outputs of a snapshot before they
+/// are stored or compared, written as `strip-ansi`, `filter NAME`,
+/// `redact REGEX=REPLACEMENT`, `sort-lines`, `trim-trailing-whitespace`,
+/// `collapse-blank-lines` or `normalize-newlines`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum Transform {
+    StripAnsi,
+    /// A built-in filter.
+    Filter(String),
+    Redact(Redaction),
+    /// One of the normalization options.
+    Normalize(Normalize),
+}
+
+impl std::str::FromStr for Transform {
+    type Err = String;
+
+    fn from_str(step: &str) -> Result<Transform, String> {
+        let (name, arg) = step.trim().split_once(' ').unwrap_or((step.trim(), ""));
+        let normalize = |normalize: Normalize| Ok(Transform::Normalize(normalize));
+        match (name, arg.trim()) {
+            ("strip-ansi", "") => Ok(Transform::StripAnsi),
+            ("filter", filter) if !filter.is_empty() => Ok(Transform::Filter(filter.to_owned())),
+            ("redact", redaction) => match redaction.rsplit_once('=') {
+                Some((pattern, replacement)) if !pattern.is_empty() => match regex::bytes::Regex::new(pattern) {
+                    Ok(_) => Ok(Transform::Redact(Redaction {
+                        pattern: pattern.to_owned(),
+                        replacement: replacement.to_owned(),
+                    })),
+                    Err(err) => Err(format!("invalid regex '{}': {}", pattern, err)),
+                },
+                _ => Err(format!("expected 'redact REGEX=REPLACEMENT', got '{}'", step)),
+            },
+            ("sort-lines", "") => normalize(Normalize {
+                sort_lines: true,
+                ..Normalize::default()
+            }),
+            ("trim-trailing-whitespace", "") => normalize(Normalize {
+                trim_trailing_whitespace: true,
+                ..Normalize::default()
+            }),
+            ("collapse-blank-lines", "") => normalize(Normalize {
+                collapse_blank_lines: true,
+                ..Normalize::default()
+            }),
+            ("normalize-newlines", "") => normalize(Normalize {
+                normalize_newlines: true,
+                ..Normalize::default()
+            }),
+            _ => Err(format!("unknown pipeline step '{}'", step)),
+        }
+    }
+}
+
+impl std::fmt::Display for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Transform::StripAnsi => write!(f, "strip-ansi"),
+            Transform::Filter(filter) => write!(f, "filter {}", filter),
+            Transform::Redact(redaction) => write!(f, "redact {}={}", redaction.pattern, redaction.replacement),
+            Transform::Normalize(normalize) if normalize.sort_lines => write!(f, "sort-lines"),
+            Transform::Normalize(normalize) if normalize.trim_trailing_whitespace => {
+                write!(f, "trim-trailing-whitespace")
+            }
+            Transform::Normalize(normalize) if normalize.collapse_blank_lines => write!(f, "collapse-blank-lines"),
+            Transform::Normalize(_) => write!(f, "normalize-newlines"),
+        }
+    }
+}
+
+impl From<Transform> for String {
+    fn from(step: Transform) -> String {
+        step.to_string()
+    }
+}
+
+impl TryFrom<String> for Transform {
+    type Error = String;
+
+    fn try_from(step: String) -> Result<Transform, String> {
+        step.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let trim = Normalize {
+            trim_trailing_whitespace: true,
+            ..Normalize::default()
+        };
+        assert!(trim.same(b"a  \nb\t\r\n", b"a\nb\n"));
+        assert!(!trim.same(b"a b", b"ab"));
+        let collapse = Normalize {
+            collapse_blank_lines: true,
+            ..Normalize::default()
+        };
+        assert!(collapse.same(b"a\n\n\n\nb\n", b"a\n\nb\n"));
+        assert!(!collapse.same(b"a\n\nb", b"a\nb"));
+        let ignore = Normalize {
+            ignore_all_whitespace: true,
+            ..Normalize::default()
+        };
+        assert!(ignore.same(b"a b\n\n c", b"abc"));
+        assert!(!Normalize::default().same(b"a ", b"a"));
+        assert!(trim.or(&collapse).same(b"a \n\n \nb", b"a\n\nb"));
+        let sort = Normalize {
+            sort_lines: true,
+            ..Normalize::default()
+        };
+        assert_eq!(sort.apply(b"b\nc\na\n"), &b"a\nb\nc\n"[..]);
+        assert!(sort.same(b"worker 2\nworker 1", b"worker 1\nworker 2"));
+        assert!(!sort.same(b"a\nb\n", b"a\nb\nb\n"));
+        assert!(sort.or(&trim).same(b"b \na", b"a\nb"));
+        let newlines = Normalize {
+            normalize_newlines: true,
+            ..Normalize::default()
+        };
+        assert!(newlines.same(b"a\r\nb\r\n", b"a\nb\n"));
+        assert!(!newlines.same(b"a\rb\n", b"ab\n"));
+        assert!(!newlines.same(b"a \r\n", b"a\n"));
+    }
+
+    #[test]
+    fn test_transform() {
+        for step in &["strip-ansi", "filter uuids", "redact [0-9]+=N", "sort-lines", "trim-trailing-whitespace"] {
+            assert_eq!(step.parse::<Transform>().unwrap().to_string(), *step);
+        }
+        assert_eq!("redact a=b=c".parse::<Transform>().unwrap().to_string(), "redact a=b=c");
+        assert!("redact (=x".parse::<Transform>().is_err());
+        assert!("redact".parse::<Transform>().is_err());
+        assert!("filter".parse::<Transform>().is_err());
+        assert!("sort-lines now".parse::<Transform>().is_err());
+        assert!("upcase".parse::<Transform>().is_err());
+    }
+
+    #[test]
+    fn test_assertions() {
+        let assertion = |assertion: &str| assertion.parse::<Assertion>().unwrap();
+        assert!(assertion("stderr empty").check(b"").is_ok());
+        assert_eq!(assertion("stderr empty").check(b"oops\n").unwrap_err(), "stderr is not empty, 5 bytes");
+        assert!(assertion("stdout lines 2").check(b"a\nb\n").is_ok());
+        assert!(assertion("stdout lines 2").check(b"a\nb").is_ok());
+        assert!(assertion("stdout lines 1..").check(b"").is_err());
+        let failure = assertion("stdout lines 1..3").check(b"a\nb\nc\n").unwrap_err();
+        assert_eq!(failure, "stdout has 3 lines, expected 1..=2");
+        assert!(assertion("stdout bytes 2..=4").check(b"abcd").is_ok());
+        assert_eq!(assertion("stdout bytes 10..").to_string(), "stdout bytes 10..");
+        assert!("stdout lines 3..1".parse::<Assertion>().is_err());
+        assert!("stdout words 3".parse::<Assertion>().is_err());
+        assert!("exit lines 3".parse::<Assertion>().is_err());
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let absolute = Tolerance {
+            absolute: 0.5,
+            relative: 0.0,
+        };
+        assert!(absolute.same(b"took 10.2 ms, 3 runs", b"took 10.6 ms, 3 runs"));
+        assert!(!absolute.same(b"took 10.2 ms", b"took 11 ms"));
+        assert!(!absolute.same(b"took 10.2 ms", b"took 10.2 s"));
+        assert!(!absolute.same(b"1 2", b"1"));
+        let relative = Tolerance {
+            absolute: 0.0,
+            relative: 0.1,
+        };
+        assert!(relative.same(b"1e3 ops/s", b"1050 ops/s"));
+        assert!(!relative.same(b"0.001", b"0.002"));
+        assert!(!relative.same(b"run 1: ok", b"run 1: failed"));
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert!(matches_placeholders(b"built in [..]s\n", b"built in 1.52s\n"));
+        assert!(matches_placeholders(b"pid [DIGITS] exited", b"pid 4242 exited"));
+        assert!(!matches_placeholders(b"pid [DIGITS] exited", b"pid none exited"));
+        assert!(!matches_placeholders(b"a [..] c", b"a b\nc"));
+        let exe = format!("run target/parrot{}", std::env::consts::EXE_SUFFIX);
+        assert!(matches_placeholders(b"run target/parrot[EXE]", exe.as_bytes()));
+        assert!(!matches_placeholders(b"a (b)", b"a (c)"));
+        let masked = mask_placeholders(b"start\ntook [..]\nend\n", b"start\ntook 2s\nbad\n");
+        assert_eq!(masked, &b"start\ntook [..]\nbad\n"[..]);
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let parse = |codes: &str| codes.parse::<ExitCodes>().unwrap();
+        assert!(parse("any").matches(Some(3)) && !parse("any").matches(None));
+        assert!(parse("nonzero").matches(Some(2)) && !parse("nonzero").matches(Some(0)));
+        assert_eq!(parse("0..=1"), ExitCodes::Range(0, 1));
+        assert_eq!(parse("0..2"), ExitCodes::Range(0, 1));
+        assert!(parse("1, 2").matches(Some(2)) && !parse("1,2").matches(Some(3)));
+        assert_eq!(parse("1, 2").to_string(), "1,2");
+        assert!("one".parse::<ExitCodes>().is_err());
+        assert!("1..x".parse::<ExitCodes>().is_err());
+    }
+
+    #[test]
+    fn test_missing_sections() {
+        let actual = b"compiling a\ncompiling b\nwarning: unused\n  --> a.rs\nfinished in 2s\n";
+        let expected = b"compiling b\n\nwarning: unused\n  --> a.rs\n\nfinished in [..]\n";
+        assert!(missing_sections(expected, actual, LineOrder::InOrder).is_empty());
+        let reordered = b"finished in [..]\n\ncompiling a\n";
+        assert_eq!(missing_sections(reordered, actual, LineOrder::InOrder), vec![vec![&b"compiling a"[..]]]);
+        assert!(missing_sections(reordered, actual, LineOrder::AnyOrder).is_empty());
+        let split = b"compiling a\nwarning: unused\n";
+        assert_eq!(missing_sections(split, actual, LineOrder::AnyOrder).len(), 1);
+    }
+}
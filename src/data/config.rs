@@ -0,0 +1,124 @@
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{Layout, Redaction};
+use crate::compare::Normalize;
+use crate::error::{wrap, Error};
+
+/// Settings of the whole test suite, written by hand in `config.json`.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// A command run once before the first snapshot of `parrot run`.
+    pub before_all: Option<String>,
+    /// A command run once after the last snapshot of `parrot run`.
+    pub after_all: Option<String>,
+    /// Built-in filters redacting the outputs of all snapshots.
+    #[serde(default)]
+    pub filters: Vec<String>,
+    /// Patterns replaced in the outputs of all snapshots, after the filters.
+    #[serde(default)]
+    pub redact: Vec<Redaction>,
+    /// How the outputs of all snapshots are normalized when they are compared.
+    #[serde(default)]
+    pub normalize: Normalize,
+    /// Keep the home directory, the repository root and the temporary
+    /// directory in the outputs, instead of replacing them with placeholders.
+    #[serde(default)]
+    pub keep_paths: bool,
+    /// Keep the outputs of the failing snapshots as pending ones, as with
+    /// `parrot run --pending`.
+    #[serde(default)]
+    pub pending: bool,
+    /// Store the metadata of each snapshot in a file of its own, in the
+    /// `metadata` folder, rather than all of them in `metadata.json`.
+    #[serde(default)]
+    pub per_snapshot_metadata: bool,
+    /// Store each snapshot in a directory of its own in the `snaps` folder,
+    /// holding its metadata and its outputs. Takes precedence over
+    /// `per_snapshot_metadata`.
+    #[serde(default)]
+    pub snapshot_directories: bool,
+    /// Store each snapshot in a plain-text file of the `files` folder, its
+    /// metadata as front matter followed by its stdout. Takes precedence over
+    /// `per_snapshot_metadata`.
+    #[serde(default)]
+    pub snapshot_files: bool,
+    /// Store the outputs by the hash of their content in the `blobs` folder,
+    /// so that identical outputs are stored once.
+    #[serde(default)]
+    pub deduplicate: bool,
+    /// Number of previous expectations kept for each snapshot when it is
+    /// updated, 5 if not set.
+    pub keep_versions: Option<usize>,
+    /// Number of days removed snapshots are kept in the trash, 30 if not set.
+    /// They are deleted right away if it is 0.
+    pub trash_days: Option<u64>,
+    /// Where the snapshots are stored instead of `.parrot`, relative to the
+    /// project. Only this configuration file is left in `.parrot`.
+    pub store: Option<String>,
+    /// Where the outputs larger than `remote_threshold` are stored, out of
+    /// the repository: an HTTP URL or a directory relative to the project.
+    pub remote: Option<String>,
+    /// Size in bytes above which outputs are stored in the remote, 1 MiB if
+    /// not set.
+    pub remote_threshold: Option<usize>,
+    /// Size in bytes above which the outputs of a snapshot are not stored,
+    /// adding or updating it fails instead. Outputs sent to the remote are
+    /// not counted. Unlimited if not set.
+    pub max_snapshot_size: Option<usize>,
+    /// Size in bytes of the outputs kept in memory while browsing the
+    /// snapshots in the REPL, 256 MiB if not set. The least recently used
+    /// ones are read again when needed.
+    pub memory_cap: Option<u64>,
+    /// The age public keys the outputs are encrypted for, they are stored in
+    /// clear if there is none.
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// A file holding the age key decrypting the outputs, relative to the
+    /// project, used when neither `PARROT_KEY` nor `PARROT_KEY_FILE` is set.
+    pub key_file: Option<String>,
+}
+
+pub struct ConfigManager {
+    path: PathBuf,
+}
+
+impl Config {
+    /// Returns the layout the snapshots are stored in.
+    pub fn layout(&self) -> Layout {
+        if self.snapshot_directories {
+            Layout::Directories
+        } else if self.snapshot_files {
+            Layout::Files
+        } else if self.per_snapshot_metadata {
+            Layout::PerSnapshotMetadata
+        } else {
+            Layout::Single
+        }
+    }
+}
+
+impl ConfigManager {
+    /// Initialize a new ConfigManager.
+    pub fn new(config_path: PathBuf) -> ConfigManager {
+        ConfigManager { path: config_path }
+    }
+
+    /// Returns the path of the configuration file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads and return the config from file system, the default config is
+    /// returned if there is none.
+    pub fn get_config(&self) -> Result<Config, Error> {
+        if !self.path.exists() {
+            return Ok(Config::default());
+        }
+        let file = wrap(fs::File::open(&self.path), "Could not open config.json.")?;
+        let config = wrap(serde_json::from_reader(file), "Failed to parse config.json.")?;
+        Ok(config)
+    }
+}
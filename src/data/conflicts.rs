@@ -0,0 +1,63 @@
+/// Where a line of a conflicted file is, relative to the conflict markers.
+#[derive(PartialEq)]
+enum Side {
+    Both,
+    Ours,
+    /// The common ancestor, written by git with `merge.conflictStyle = diff3`.
+    Base,
+    Theirs,
+}
+
+/// Splits a file in which git left conflict markers into the version of the
+/// current branch and the version of the merged one. Returns None if the file
+/// has no conflict.
+pub fn split(body: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+    let mut side = Side::Both;
+    let mut conflicted = false;
+    for line in body.split_inclusive(|c| *c == b'\n') {
+        let marker = |marker: &[u8]| {
+            line.starts_with(marker) && matches!(line.get(marker.len()), None | Some(b' ') | Some(b'\n') | Some(b'\r'))
+        };
+        side = match side {
+            Side::Both if marker(b"<<<<<<<") => Side::Ours,
+            Side::Ours if marker(b"|||||||") => Side::Base,
+            Side::Ours | Side::Base if marker(b"=======") => Side::Theirs,
+            Side::Theirs if marker(b">>>>>>>") => {
+                conflicted = true;
+                Side::Both
+            }
+            side => {
+                if side != Side::Theirs && side != Side::Base {
+                    ours.extend_from_slice(line);
+                }
+                if side != Side::Ours && side != Side::Base {
+                    theirs.extend_from_slice(line);
+                }
+                side
+            }
+        };
+    }
+    if conflicted && side == Side::Both {
+        Some((ours, theirs))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let body = b"a\n<<<<<<< HEAD\nb\n=======\nc\nd\n>>>>>>> feature\ne\n";
+        assert_eq!(split(body), Some((b"a\nb\ne\n".to_vec(), b"a\nc\nd\ne\n".to_vec())));
+        let diff3 = b"<<<<<<< ours\nb\n||||||| base\na\n=======\n>>>>>>> theirs\n";
+        assert_eq!(split(diff3), Some((b"b\n".to_vec(), Vec::new())));
+        assert_eq!(split(b"a\n=======\nb\n"), None);
+        assert_eq!(split(b"<<<<<<<< not a marker\n"), None);
+        // An unterminated conflict is not one git left
+        assert_eq!(split(b"<<<<<<< HEAD\nb\n=======\n"), None);
+    }
+}
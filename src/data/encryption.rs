@@ -0,0 +1,169 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use age::{Decryptor, Encryptor, Identity, IdentityFile, Recipient};
+
+use super::write_atomic;
+use crate::error::{wrap, Error};
+
+/// Appended to the files of the bodies stored encrypted.
+pub const ENCRYPTED_EXTENSION: &str = ".age";
+
+/// Environment variable holding the age key decrypting the outputs.
+const KEY_VARIABLE: &str = "PARROT_KEY";
+/// Environment variable holding the path of a file with the age key.
+const KEY_FILE_VARIABLE: &str = "PARROT_KEY_FILE";
+
+/// Encrypts the stored outputs with age for a list of recipients, and
+/// decrypts them with the key found in `PARROT_KEY`, in the file at
+/// `PARROT_KEY_FILE` or else in the key file of the configuration.
+pub struct Encryption {
+    /// The public keys the outputs are encrypted for, none if they are stored
+    /// in clear.
+    pub recipients: Vec<String>,
+    parsed: Result<Vec<age::x25519::Recipient>, Error>,
+    /// The identities of the key, none if no key is set, or why the key could
+    /// not be read. Both are only reported when an output is decrypted.
+    identities: Result<Vec<Box<dyn Identity + Send + Sync>>, Error>,
+}
+
+impl Encryption {
+    /// Initialize a new Encryption for some recipients, `key_file` is the
+    /// configured key file if any, relative to the project at `root`.
+    pub fn new(recipients: Vec<String>, key_file: Option<&str>, root: &Path) -> Encryption {
+        let parsed = recipients
+            .iter()
+            .map(|recipient| match age::x25519::Recipient::from_str(recipient) {
+                Ok(recipient) => Ok(recipient),
+                Err(err) => wrap(Err(err), &format!("Invalid age recipient: '{}'.", recipient)),
+            })
+            .collect();
+        let identities = match std::env::var(KEY_VARIABLE) {
+            Ok(key) => read_identities(&key, &format!("Invalid age key in {}.", KEY_VARIABLE)),
+            Err(_) => {
+                let file = std::env::var(KEY_FILE_VARIABLE).ok().map(|file| Path::new(&file).to_owned());
+                match file.or_else(|| key_file.map(|file| root.join(file))) {
+                    Some(file) => {
+                        let error = format!("Could not read the age key in {}.", file.display());
+                        wrap(fs::read_to_string(&file), &error).and_then(|key| read_identities(&key, &error))
+                    }
+                    None => Ok(Vec::new()),
+                }
+            }
+        };
+        Encryption {
+            recipients,
+            parsed,
+            identities,
+        }
+    }
+
+    /// Whether the outputs are encrypted when stored.
+    pub fn encrypts(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    /// Whether there is a key to decrypt the outputs with, though it may not
+    /// be one they are encrypted for.
+    pub fn has_key(&self) -> bool {
+        self.identities.as_ref().map_or(true, |identities| !identities.is_empty())
+    }
+
+    /// Returns the parsed recipients, or why one of them is invalid.
+    pub fn check(&self) -> Result<&Vec<age::x25519::Recipient>, Error> {
+        match &self.parsed {
+            Ok(recipients) => Ok(recipients),
+            Err(err) => Error::from_str(&err.message),
+        }
+    }
+
+    /// Encrypts an output for the recipients.
+    pub fn encrypt(&self, body: &[u8]) -> Result<Vec<u8>, Error> {
+        let recipients = self.check()?;
+        let error = "Failed to encrypt an output.";
+        let encryptor =
+            wrap(Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn Recipient)), error)?;
+        let mut encrypted = Vec::new();
+        let mut writer = wrap(encryptor.wrap_output(&mut encrypted), error)?;
+        wrap(writer.write_all(body), error)?;
+        wrap(writer.finish(), error)?;
+        Ok(encrypted)
+    }
+
+    /// Decrypts the output of a snapshot file with the key.
+    pub fn decrypt(&self, body: &[u8], name: &str) -> Result<Vec<u8>, Error> {
+        let identities = match &self.identities {
+            Ok(identities) if identities.is_empty() => {
+                return Error::from_str(&format!(
+                    "Snapshot {} is encrypted, set {} or {} to a key it is encrypted for to decrypt it.",
+                    name, KEY_VARIABLE, KEY_FILE_VARIABLE
+                ))
+            }
+            Ok(identities) => identities,
+            Err(err) => return Error::from_str(&err.message),
+        };
+        let error = format!("Could not decrypt snapshot {}, it is not encrypted for the given key.", name);
+        let decryptor = wrap(Decryptor::new_buffered(body), &error)?;
+        let mut reader =
+            wrap(decryptor.decrypt(identities.iter().map(|identity| identity.as_ref() as &dyn Identity)), &error)?;
+        let mut decrypted = Vec::new();
+        wrap(reader.read_to_end(&mut decrypted), &format!("Failed to decrypt snapshot {}.", name))?;
+        Ok(decrypted)
+    }
+
+    /// Writes an output kept out of the snapshot files, in the history for
+    /// instance, encrypted in its `.age` form if the outputs are, and removes
+    /// its other form.
+    pub fn write(&self, path: &Path, body: &[u8], error: &str) -> Result<(), Error> {
+        let other = if self.encrypts() {
+            wrap(write_atomic(&encrypted(path), &self.encrypt(body)?), error)?;
+            path.to_owned()
+        } else {
+            wrap(write_atomic(path, body), error)?;
+            encrypted(path)
+        };
+        if other.is_file() {
+            wrap(fs::remove_file(other), error)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an output written by `write`, in either form, `name` is the
+    /// snapshot it belongs to.
+    pub fn read(&self, path: &Path, name: &str, error: &str) -> Result<Vec<u8>, Error> {
+        if encrypted(path).is_file() {
+            self.decrypt(&wrap(fs::read(encrypted(path)), error)?, name)
+        } else {
+            wrap(fs::read(path), error)
+        }
+    }
+
+    /// Rewrites an output written by `write` for the recipients of another
+    /// encryption, if it exists in either form.
+    pub fn rewrite(&self, path: &Path, name: &str, other: &Encryption) -> Result<(), Error> {
+        if !path.is_file() && !encrypted(path).is_file() {
+            return Ok(());
+        }
+        let error = format!("Failed to rewrite an output of {} for the new recipients.", name);
+        other.write(path, &self.read(path, name, &error)?, &error)
+    }
+}
+
+/// Returns the path of the encrypted form of a file.
+pub fn encrypted(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(ENCRYPTED_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Reads the identities of an age key, the content of a key file.
+fn read_identities(key: &str, error: &str) -> Result<Vec<Box<dyn Identity + Send + Sync>>, Error> {
+    let file = wrap(IdentityFile::from_buffer(key.as_bytes()), error)?;
+    let identities = wrap(file.into_identities(), error)?;
+    if identities.is_empty() {
+        return Error::from_str(error);
+    }
+    Ok(identities)
+}
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::encryption::{encrypted, Encryption};
+use super::{dir_size, write_atomic, Expectation};
+use crate::error::{wrap, Error};
+
+const VERSIONS_PATH: &str = "versions.json";
+
+/// An expectation of a snapshot replaced by an update, its outputs are stored
+/// in files next to the list of versions.
+#[derive(Serialize, Deserialize)]
+pub struct Version {
+    /// Versions are numbered from 1, in the order they were replaced.
+    pub number: u32,
+    /// When it was replaced, in seconds since the Unix epoch.
+    pub replaced_at: u64,
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    pub stdout_len: Option<u64>,
+    pub stderr_len: Option<u64>,
+    /// The written files, along with the size of their content if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<(String, Option<u64>)>,
+}
+
+impl Version {
+    /// Returns the names of the files holding the outputs of the version, by
+    /// the outputs they hold.
+    fn files(&self) -> Vec<String> {
+        let mut files = vec![format!("{}.out", self.number), format!("{}.err", self.number)];
+        for idx in 0..self.artifacts.len() {
+            files.push(format!("{}.artifact-{}", self.number, idx));
+        }
+        files
+    }
+}
+
+/// Keeps the previous expectations of the snapshots, in a folder per
+/// snapshot. Their outputs are encrypted as the stored ones are.
+pub struct HistoryManager {
+    path: PathBuf,
+    encryption: Rc<Encryption>,
+}
+
+impl HistoryManager {
+    /// Initialize a new HistoryManager.
+    pub fn new(history_path: PathBuf, encryption: Rc<Encryption>) -> HistoryManager {
+        HistoryManager {
+            path: history_path,
+            encryption,
+        }
+    }
+
+    /// Returns the versions kept for a snapshot, oldest first.
+    pub fn versions(&self, name: &str) -> Result<Vec<Version>, Error> {
+        let path = self.path.join(name).join(VERSIONS_PATH);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = wrap(fs::File::open(path), &format!("Could not open the history of {}.", name))?;
+        wrap(serde_json::from_reader(file), &format!("Failed to parse the history of {}.", name))
+    }
+
+    /// Keeps an expectation as the most recent version of a snapshot, only the
+    /// `size` most recent versions are kept.
+    pub fn keep(&self, name: &str, expectation: &Expectation, size: usize) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        let mut versions = self.versions(name)?;
+        let path = self.path.join(name);
+        let error = format!("Failed to write the history of {}.", name);
+        wrap(fs::create_dir_all(&path), &error)?;
+        let version = Version {
+            number: versions.last().map_or(1, |version| version.number + 1),
+            replaced_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            exit_code: expectation.exit_code,
+            signal: expectation.signal,
+            stdout_len: expectation.stdout.as_ref().map(|body| body.len() as u64),
+            stderr_len: expectation.stderr.as_ref().map(|body| body.len() as u64),
+            artifacts: expectation
+                .artifacts
+                .iter()
+                .map(|(file, body)| (file.clone(), body.as_ref().map(|body| body.len() as u64)))
+                .collect(),
+        };
+        let bodies = vec![&expectation.stdout, &expectation.stderr]
+            .into_iter()
+            .chain(expectation.artifacts.iter().map(|(_, body)| body));
+        for (file, body) in version.files().iter().zip(bodies) {
+            if let Some(body) = body {
+                self.encryption.write(&path.join(file), body, &error)?;
+            }
+        }
+        versions.push(version);
+        while versions.len() > size {
+            for file in versions.remove(0).files() {
+                let _ = fs::remove_file(path.join(&file));
+                let _ = fs::remove_file(encrypted(&path.join(file)));
+            }
+        }
+        let content = wrap(serde_json::to_vec_pretty(&versions), &error)?;
+        wrap(write_atomic(&path.join(VERSIONS_PATH), &content), &error)
+    }
+
+    /// Returns a version of the expectation of a snapshot.
+    pub fn get(&self, name: &str, number: u32) -> Result<Expectation, Error> {
+        let versions = self.versions(name)?;
+        let version = match versions.iter().find(|version| version.number == number) {
+            Some(version) => version,
+            None => return Error::from_str(&format!("There is no version {} of '{}'.", number, name)),
+        };
+        let path = self.path.join(name);
+        let error = format!("Could not read version {} of {}.", number, name);
+        let files = version.files();
+        let read = |idx: usize, len: Option<u64>| match len {
+            Some(_) => self.encryption.read(&path.join(&files[idx]), name, &error).map(Some),
+            None => Ok(None),
+        };
+        let mut artifacts = Vec::with_capacity(version.artifacts.len());
+        for (idx, (file, len)) in version.artifacts.iter().enumerate() {
+            artifacts.push((file.clone(), read(idx + 2, *len)?));
+        }
+        Ok(Expectation {
+            exit_code: version.exit_code,
+            signal: version.signal,
+            stdout: read(0, version.stdout_len)?,
+            stderr: read(1, version.stderr_len)?,
+            artifacts,
+        })
+    }
+
+    /// Returns the names of the snapshots with a history.
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        if !self.path.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in wrap(fs::read_dir(&self.path), "Could not read the history folder.")? {
+            let entry = wrap(entry, "Could not read the history folder.")?;
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Deletes all but the `keep` most recent versions of a snapshot, unless
+    /// `dry_run` is set. Returns the number of versions deleted along with the
+    /// size of their outputs.
+    pub fn trim(&self, name: &str, keep: usize, dry_run: bool) -> Result<(usize, u64), Error> {
+        let mut versions = self.versions(name)?;
+        let path = self.path.join(name);
+        if keep == 0 {
+            let size = dir_size(&path)?;
+            if !dry_run {
+                self.remove(name)?;
+            }
+            return Ok((versions.len(), size));
+        }
+        let count = versions.len().saturating_sub(keep);
+        let mut size = 0;
+        for version in versions.drain(..count) {
+            for file in version.files() {
+                let file = path.join(file);
+                for file in [encrypted(&file), file] {
+                    size += fs::metadata(&file).map_or(0, |metadata| metadata.len());
+                    if !dry_run {
+                        let _ = fs::remove_file(file);
+                    }
+                }
+            }
+        }
+        if count > 0 && !dry_run {
+            let error = format!("Failed to write the history of {}.", name);
+            let content = wrap(serde_json::to_vec_pretty(&versions), &error)?;
+            wrap(write_atomic(&path.join(VERSIONS_PATH), &content), &error)?;
+        }
+        Ok((count, size))
+    }
+
+    /// Rewrites the outputs of all the versions for the recipients of another
+    /// encryption.
+    pub fn rewrite(&self, encryption: &Encryption) -> Result<(), Error> {
+        for name in self.names()? {
+            for version in self.versions(&name)? {
+                for file in version.files() {
+                    self.encryption.rewrite(&self.path.join(&name).join(file), &name, encryption)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the history of a renamed snapshot.
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        let path = self.path.join(name);
+        if !path.exists() {
+            return Ok(());
+        }
+        wrap(fs::rename(path, self.path.join(new_name)), &format!("Failed to move the history of {}.", name))
+    }
+
+    /// Deletes the history of a removed snapshot.
+    pub fn remove(&self, name: &str) -> Result<(), Error> {
+        let path = self.path.join(name);
+        if !path.exists() {
+            return Ok(());
+        }
+        wrap(fs::remove_dir_all(path), &format!("Failed to delete the history of {}.", name))
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use crate::error::{wrap, Error};
+
+/// An advisory lock on the parrot folder, held until it is dropped so that
+/// two parrot processes never touch the snapshots at the same time.
+pub struct Lock {
+    /// The lock is released when the file is closed.
+    _file: fs::File,
+}
+
+impl Lock {
+    /// Locks the parrot folder through the lock file at `path`, which records
+    /// the PID of the process holding it. Fails if another process holds the
+    /// lock, unless `wait` is set in which case it waits for it to be
+    /// released.
+    pub fn acquire(path: PathBuf, wait: bool) -> Result<Lock, Error> {
+        let mut file = wrap(
+            fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path),
+            "Failed to open the lock of the parrot folder.",
+        )?;
+        if let Err(err) = flock(&file, libc::LOCK_EX | libc::LOCK_NB) {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return wrap(Err(err), "Failed to lock the parrot folder.");
+            }
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            let holder = match pid.trim() {
+                "" => "another parrot process".to_owned(),
+                pid => format!("PID {}", pid),
+            };
+            if !wait {
+                return Error::from_str(&format!(
+                    "The parrot folder is locked by {}, pass --wait to wait for it.",
+                    holder
+                ));
+            }
+            eprintln!("Waiting for {} to release the parrot folder...", holder);
+            wrap(flock(&file, libc::LOCK_EX), "Failed to lock the parrot folder.")?;
+        }
+        let error = "Failed to write the lock of the parrot folder.";
+        wrap(file.set_len(0), error)?;
+        wrap(file.seek(SeekFrom::Start(0)), error)?;
+        wrap(writeln!(file, "{}", std::process::id()), error)?;
+        Ok(Lock { _file: file })
+    }
+}
+
+fn flock(file: &fs::File, operation: libc::c_int) -> io::Result<()> {
+    // Safe: the file descriptor is valid as long as the file is borrowed
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -1,11 +1,23 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use super::Snapshot;
+use super::{
+    write_atomic, Layout, Redaction, Snapshot, FILES_PATH, META_FILE, METADATA_FOLDER_PATH, METADATA_PATH, SNAPS_PATH,
+    SNAP_FILE_EXTENSION,
+};
+use crate::compare::{Assertion, Compare, ExitCodes, LineOrder, Normalize, Tolerance, Transform};
 use crate::error::{wrap, Error};
+use crate::formats::{toml, yaml};
+
+const FRONT_MATTER_DELIMITER: &str = "---\n";
+/// Keys written first in the front matter of a snapshot file, the ones a
+/// reviewer looks for.
+const FRONT_MATTER_HEAD: [&str; 5] = ["name", "cmd", "description", "tags", "exit_code"];
 
 #[derive(Serialize, Deserialize)]
 pub struct Metadatas {
@@ -19,18 +31,269 @@ pub struct Metadata {
     pub description: Option<String>,
     pub tags: Vec<String>,
     pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    /// Sizes of the payloads in bytes, used to detect corrupted files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_len: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_len: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin_len: Option<u64>,
+    /// Checksums of the outputs, used to detect corrupted content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdout_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<ArtifactMetadata>,
+    /// Path of the golden file holding stdout, relative to the project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_stdout: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Timeout in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pty: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub combined: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runner: Option<String>,
+    /// Variables passed through the scrubbed environment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hermetic: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retries: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub needs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub strip_ansi: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact: Vec<Redaction>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipeline: Vec<Transform>,
+    #[serde(default, skip_serializing_if = "Normalize::is_none")]
+    pub normalize: Normalize,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub exit_code_only: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ignore_stdout: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ignore_stderr: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub binary: bool,
+    #[serde(default, skip_serializing_if = "Compare::is_text")]
+    pub compare: Compare,
+    #[serde(default, skip_serializing_if = "Tolerance::is_none")]
+    pub tolerance: Tolerance,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<LineOrder>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_codes: Option<ExitCodes>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assertions: Vec<Assertion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comparator: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub xfail: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub archived: bool,
+    /// When the snapshot was created, in seconds since the Unix epoch, and by
+    /// whom.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    /// When its expectation was last updated, and by whom.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_by: Option<String>,
+}
+
+/// A file written by the command, `path` is not set if it was not written.
+#[derive(Serialize, Deserialize)]
+pub struct ArtifactMetadata {
+    pub file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub len: Option<u64>,
+}
+
+impl Metadata {
+    /// Returns the paths of the snapshot's data along with their recorded size.
+    pub fn data_paths(&self) -> Vec<(&String, Option<u64>)> {
+        let mut paths = Vec::new();
+        if let Some(path) = &self.stdout {
+            paths.push((path, self.stdout_len));
+        }
+        if let Some(path) = &self.stderr {
+            paths.push((path, self.stderr_len));
+        }
+        if let Some(path) = &self.stdin {
+            paths.push((path, self.stdin_len));
+        }
+        for artifact in &self.artifacts {
+            if let Some(path) = &artifact.path {
+                paths.push((path, artifact.len));
+            }
+        }
+        paths
+    }
+}
+
+impl From<&Snapshot> for Metadata {
+    fn from(snap: &Snapshot) -> Metadata {
+        // A golden stdout is not part of the snapshots folder
+        let stdout = snap.stdout.as_ref().filter(|_| snap.expected_stdout.is_none());
+        let stdout_len = stdout.map(|data| data.body.size());
+        let stdout_hash = stdout.map(|data| data.body.checksum());
+        let stdout = stdout.map(|data| data.path.clone());
+        let stderr = snap.stderr.as_ref().map(|data| data.path.clone());
+        let stdin = snap.stdin.as_ref().map(|data| data.path.clone());
+        Metadata {
+            cmd: snap.cmd.clone(),
+            name: snap.name.clone(),
+            description: snap.description.clone(),
+            tags: snap.tags.clone(),
+            exit_code: snap.exit_code,
+            signal: snap.signal,
+            stdout,
+            stderr,
+            stdin,
+            stdout_len,
+            stderr_len: snap.stderr.as_ref().map(|data| data.body.size()),
+            stdin_len: snap.stdin.as_ref().map(|data| data.body.size()),
+            stdout_hash,
+            stderr_hash: snap.stderr.as_ref().map(|data| data.body.checksum()),
+            artifacts: snap
+                .artifacts
+                .iter()
+                .map(|artifact| ArtifactMetadata {
+                    file: artifact.file.clone(),
+                    path: artifact.data.as_ref().map(|data| data.path.clone()),
+                    len: artifact.data.as_ref().map(|data| data.body.size()),
+                })
+                .collect(),
+            expected_stdout: snap.expected_stdout.clone(),
+            env: snap.env.clone(),
+            timeout: snap.timeout,
+            max_duration_ms: snap.max_duration_ms,
+            pty: snap.pty,
+            combined: snap.combined,
+            runner: snap.runner.clone(),
+            hermetic: snap.hermetic.clone(),
+            before: snap.before.clone(),
+            after: snap.after.clone(),
+            retries: snap.retries,
+            needs: snap.needs.clone(),
+            serial: snap.serial.clone(),
+            inputs: snap.inputs.clone(),
+            strip_ansi: snap.strip_ansi,
+            filters: snap.filters.clone(),
+            redact: snap.redact.clone(),
+            pipeline: snap.pipeline.clone(),
+            normalize: snap.normalize,
+            exit_code_only: snap.exit_code_only,
+            ignore_stdout: snap.ignore_stdout,
+            ignore_stderr: snap.ignore_stderr,
+            binary: snap.binary,
+            compare: snap.compare,
+            tolerance: snap.tolerance,
+            contains: snap.contains,
+            exit_codes: snap.exit_codes.clone(),
+            assertions: snap.assertions.clone(),
+            comparator: snap.comparator.clone(),
+            xfail: snap.xfail,
+            archived: snap.archived,
+            created_at: snap.created.as_ref().map(|stamp| stamp.at),
+            created_by: snap.created.as_ref().and_then(|stamp| stamp.by.clone()),
+            updated_at: snap.updated.as_ref().map(|stamp| stamp.at),
+            updated_by: snap.updated.as_ref().and_then(|stamp| stamp.by.clone()),
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+/// Splits the content of a snapshot file into its front matter and its body,
+/// returns `None` if it does not start with a front matter.
+pub fn split_front_matter(content: &[u8]) -> Option<(&str, &[u8])> {
+    let delimiter = FRONT_MATTER_DELIMITER.as_bytes();
+    let rest = content.strip_prefix(delimiter)?;
+    let end = if rest.starts_with(delimiter) {
+        0
+    } else {
+        rest.windows(delimiter.len() + 1).position(|window| window[0] == b'\n' && &window[1..] == delimiter)? + 1
+    };
+    Some((std::str::from_utf8(&rest[..end]).ok()?, &rest[end + delimiter.len()..]))
+}
+
+/// Joins a front matter and a body into the content of a snapshot file.
+pub fn join_front_matter(front_matter: &str, body: &[u8]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(front_matter.len() + body.len() + 2 * FRONT_MATTER_DELIMITER.len());
+    content.extend_from_slice(FRONT_MATTER_DELIMITER.as_bytes());
+    content.extend_from_slice(front_matter.as_bytes());
+    content.extend_from_slice(FRONT_MATTER_DELIMITER.as_bytes());
+    content.extend_from_slice(body);
+    content
+}
+
+/// Writes the metadata of a snapshot as YAML front matter, the keys a reviewer
+/// looks for first.
+fn write_front_matter(mut table: serde_json::Map<String, Value>) -> Result<String, serde_yaml::Error> {
+    let mut head = serde_json::Map::new();
+    let mut front_matter = String::new();
+    for key in FRONT_MATTER_HEAD.iter() {
+        if let Some(value) = table.remove(*key) {
+            head.insert(key.to_string(), value);
+            front_matter.push_str(&yaml::write(&head)?);
+            head.clear();
+        }
+    }
+    front_matter.push_str(&yaml::write(&table)?);
+    Ok(front_matter)
 }
 
 pub struct MetadataManager {
+    /// The parrot folder.
     path: PathBuf,
+    layout: Layout,
 }
 
 impl MetadataManager {
-    /// Initialize a new MetadataManager.
-    pub fn new(confg_path: PathBuf) -> MetadataManager {
-        MetadataManager { path: confg_path }
+    /// Initialize a new MetadataManager, reading and writing metadata in the
+    /// given layout.
+    pub fn new(parrot_path: PathBuf, layout: Layout) -> MetadataManager {
+        MetadataManager {
+            path: parrot_path,
+            layout,
+        }
     }
 
     /// Write an empty metadata file.
@@ -52,23 +315,7 @@ impl MetadataManager {
             if snap.deleted {
                 continue;
             }
-            let stdout = match &snap.stdout {
-                Some(data) => Some(data.path.clone()),
-                None => None,
-            };
-            let stderr = match &snap.stderr {
-                Some(data) => Some(data.path.clone()),
-                None => None,
-            };
-            snapshots.push(Metadata {
-                cmd: snap.cmd.clone(),
-                name: snap.name.clone(),
-                description: snap.description.clone(),
-                tags: snap.tags.clone(),
-                exit_code: snap.exit_code.clone(),
-                stdout,
-                stderr,
-            })
+            snapshots.push(Metadata::from(&*snap));
         }
         self.write(&Metadatas { snapshots })?;
         Ok(())
@@ -76,27 +323,144 @@ impl MetadataManager {
 
     /// Reads and return metadatas from file system.
     pub fn get_metadata(&self) -> Result<Metadatas, Error> {
-        let file = wrap(
-            fs::File::open(&self.path),
-            "Could not find snapshots data, try running `parrot init` first.",
-        )?;
-        let metadatas = wrap(
-            serde_json::from_reader(file),
-            "Failed to parse metadata.json.",
-        )?;
-        Ok(metadatas)
+        match self.layout {
+            Layout::Single => {
+                let file = wrap(
+                    fs::File::open(self.path.join(METADATA_PATH)),
+                    "Could not find snapshots data, try running `parrot init` first.",
+                )?;
+                let metadatas = wrap(
+                    serde_json::from_reader(file),
+                    "Failed to parse metadata.json.",
+                )?;
+                Ok(metadatas)
+            }
+            Layout::PerSnapshotMetadata | Layout::Directories | Layout::Files => {
+                let mut snapshots = Vec::new();
+                for (name, path) in self.files()? {
+                    let content = wrap(fs::read(&path), &format!("Could not open {}.", name))?;
+                    let message = format!("Failed to parse {}.", name);
+                    let text = match self.layout {
+                        Layout::Files => split_front_matter(&content).map(|(front_matter, _)| front_matter),
+                        _ => std::str::from_utf8(&content).ok(),
+                    };
+                    let value = match (self.layout, text) {
+                        (Layout::Directories, Some(text)) => toml::parse(text),
+                        (Layout::Files, Some(text)) => yaml::parse(text),
+                        (_, Some(text)) => wrap(serde_json::from_str(text), &message).map(Some)?,
+                        (_, None) => None,
+                    };
+                    let metadata = match value {
+                        Some(value) => wrap(serde_json::from_value(value), &message)?,
+                        None => return Error::from_str(&message),
+                    };
+                    snapshots.push(metadata);
+                }
+                Ok(Metadatas { snapshots })
+            }
+        }
+    }
+
+    /// Returns the metadata files of a layout with a file per snapshot, sorted
+    /// by snapshot name, along with the names they are reported by.
+    fn files(&self) -> Result<Vec<(String, PathBuf)>, Error> {
+        let (folder, error) = match self.layout {
+            Layout::Directories => (SNAPS_PATH, "Could not read the snaps folder."),
+            Layout::Files => (FILES_PATH, "Could not read the files folder."),
+            _ => (METADATA_FOLDER_PATH, "Could not read the metadata folder."),
+        };
+        let mut files = Vec::new();
+        for entry in wrap(fs::read_dir(self.path.join(folder)), error)? {
+            let path = wrap(entry, error)?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            if self.layout == Layout::Directories && path.join(META_FILE).is_file() {
+                files.push((format!("{}/{}/{}", folder, name, META_FILE), path.join(META_FILE)));
+            } else if (self.layout == Layout::PerSnapshotMetadata && name.ends_with(".json"))
+                || (self.layout == Layout::Files && name.ends_with(SNAP_FILE_EXTENSION))
+            {
+                files.push((format!("{}/{}", folder, name), path));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Returns the metadata file of a snapshot, in a layout with a file per
+    /// snapshot.
+    fn file(&self, name: &str) -> PathBuf {
+        match self.layout {
+            Layout::Directories => self.path.join(SNAPS_PATH).join(name).join(META_FILE),
+            Layout::Files => self.path.join(FILES_PATH).join(format!("{}{}", name, SNAP_FILE_EXTENSION)),
+            _ => self.path.join(METADATA_FOLDER_PATH).join(format!("{}.json", name)),
+        }
+    }
+
+    /// Writes metadatas to the file system. With a file per snapshot, the
+    /// files of the snapshots that no longer exist are removed.
+    pub fn write(&self, metadatas: &Metadatas) -> Result<(), Error> {
+        if self.layout == Layout::Single {
+            let content = wrap(serde_json::to_vec_pretty(metadatas), "Failed to write metadata.json.")?;
+            return wrap(write_atomic(&self.path.join(METADATA_PATH), &content), "Failed to write metadata.json.");
+        }
+        let mut written = HashSet::new();
+        for snap in &metadatas.snapshots {
+            let path = self.file(&snap.name);
+            let error = format!("Failed to write the metadata of '{}'.", snap.name);
+            if let Some(parent) = path.parent() {
+                wrap(fs::create_dir_all(parent), &error)?;
+            }
+            let content = match (self.layout, wrap(serde_json::to_value(snap), &error)?) {
+                (Layout::Directories, Value::Object(table)) => wrap(toml::write(&table), &error)?.into_bytes(),
+                (Layout::Files, Value::Object(table)) => {
+                    // The stdout after the front matter is kept
+                    let content = fs::read(&path).unwrap_or_default();
+                    let body = split_front_matter(&content).map_or(&[][..], |(_, body)| body);
+                    join_front_matter(&wrap(write_front_matter(table), &error)?, body)
+                }
+                _ => wrap(serde_json::to_string_pretty(snap), &error)?.into_bytes(),
+            };
+            // Unchanged files are left as they are, as syncing them is slow
+            if fs::read(&path).ok().as_ref() != Some(&content) {
+                wrap(write_atomic(&path, &content), &error)?;
+            }
+            written.insert(path);
+        }
+        for (_, path) in self.files()? {
+            if !written.contains(&path) {
+                self.remove_file(&path)?;
+            }
+        }
+        Ok(())
     }
 
-    /// Writes metadatas to the file system.
-    fn write(&self, metadatas: &Metadatas) -> Result<(), Error> {
-        let metadata_file = wrap(
-            fs::File::create(&self.path),
-            "Failed to create metadata.json.",
-        )?;
-        wrap(
-            serde_json::to_writer_pretty(metadata_file, metadatas),
-            "Failed to write metadata.json.",
-        )?;
+    /// Removes all the metadata, once moved to another layout.
+    pub fn remove_all(&self) -> Result<(), Error> {
+        if self.layout == Layout::Single {
+            return wrap(fs::remove_file(self.path.join(METADATA_PATH)), "Failed to remove metadata.json.");
+        }
+        for (_, path) in self.files()? {
+            self.remove_file(&path)?;
+        }
+        if self.layout == Layout::PerSnapshotMetadata {
+            wrap(fs::remove_dir(self.path.join(METADATA_FOLDER_PATH)), "Failed to remove the metadata folder.")?;
+        }
+        if self.layout == Layout::Files {
+            // The folder still holds the other outputs until they move
+            let _ = fs::remove_dir(self.path.join(FILES_PATH));
+        }
+        Ok(())
+    }
+
+    /// Removes a metadata file, along with the directory of its snapshot once
+    /// it is empty.
+    fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        wrap(fs::remove_file(path), "Failed to remove the metadata of a deleted snapshot.")?;
+        if self.layout == Layout::Directories {
+            if let Some(dir) = path.parent() {
+                // The directory still holds files that no snapshot refers to
+                let _ = fs::remove_dir(dir);
+            }
+        }
         Ok(())
     }
 }
@@ -1,18 +1,106 @@
-use std::cell::RefCell;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
+use std::io::{self, Write};
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::error::{wrap, Error};
+use crate::compare::{
+    matches_placeholders, missing_sections, Assertion, Compare, ExitCodes, Fnv, LineOrder, Normalize, Tolerance,
+    Transform,
+};
+use crate::error::{wrap, Error, Log};
+use crate::term::{format_size, strip_ansi};
+use store::SnapshotStore;
 
+mod config;
+mod conflicts;
+mod history;
+mod lock;
 mod metadata;
+mod pending;
+mod remote;
+mod encryption;
 mod snapshots;
+mod state;
+mod store;
+mod trash;
 
-pub const PARROT_PATH: &'static str = ".parrot";
-const SNAPSHOT_PATH: &'static str = "snapshots";
-const METADATA_PATH: &'static str = "metadata.json";
+pub use config::Config;
+pub use history::Version;
+pub use trash::Trashed;
 
-#[derive(PartialEq, Eq)]
+pub const PARROT_PATH: &str = ".parrot";
+const SNAPSHOT_PATH: &str = "snapshots";
+const METADATA_PATH: &str = "metadata.json";
+const METADATA_FOLDER_PATH: &str = "metadata";
+const SNAPS_PATH: &str = "snaps";
+/// The metadata file in the directory of a snapshot.
+const META_FILE: &str = "meta.toml";
+const FILES_PATH: &str = "files";
+const SNAP_FILE_EXTENSION: &str = ".snap";
+const BLOBS_PATH: &str = "blobs";
+const BLOBS_INDEX_PATH: &str = "blobs.json";
+const STATE_PATH: &str = "state.json";
+const CONFIG_PATH: &str = "config.json";
+const DIVERGENT_PATH: &str = "divergent";
+const VERSION_PATH: &str = "version";
+const LOCK_PATH: &str = "lock";
+const HISTORY_PATH: &str = "history";
+const PENDING_PATH: &str = "pending";
+const TRASH_PATH: &str = "trash";
+const SUITES_PATH: &str = "suites";
+const REMOTE_CACHE_PATH: &str = "remote";
+/// What the parrot folder holds out of version control.
+const UNVERSIONED_PATHS: [&str; 7] =
+    [LOCK_PATH, STATE_PATH, HISTORY_PATH, PENDING_PATH, TRASH_PATH, REMOTE_CACHE_PATH, DIVERGENT_PATH];
+/// Lists the unversioned paths, for git.
+const GITIGNORE_PATH: &str = ".gitignore";
+/// Lists the recipients the outputs are currently encrypted for.
+const RECIPIENTS_PATH: &str = "recipients";
+/// Outputs larger than this many bytes are stored in the remote, if there is
+/// one, unless configured otherwise.
+const REMOTE_THRESHOLD: usize = 1 << 20;
+/// Number of previous expectations kept for each snapshot, unless configured
+/// otherwise.
+const KEEP_VERSIONS: usize = 5;
+/// Number of days removed snapshots are kept in the trash, unless configured
+/// otherwise.
+const TRASH_DAYS: u64 = 30;
+/// Size in bytes of the stored outputs kept in memory while browsing the
+/// snapshots, unless configured otherwise.
+const MEMORY_CAP: u64 = 256 << 20;
+/// Version of the format of the parrot folder, folders without a version file
+/// are of the first one.
+/// 2: the version is recorded and the sizes of all the files are.
+pub const SCHEMA_VERSION: u32 = 2;
+/// Number of runs kept in the history of each snapshot.
+const HISTORY_SIZE: usize = 20;
+
+/// How the snapshots are stored in the parrot folder.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Layout {
+    /// All the metadata in `metadata.json`, the outputs in `snapshots`.
+    Single,
+    /// The metadata of each snapshot in `metadata/<name>.json`, the outputs in
+    /// `snapshots`.
+    PerSnapshotMetadata,
+    /// Everything about a snapshot in `snaps/<name>`, its metadata in
+    /// `meta.toml` next to its `stdout`, `stderr` and `stdin`.
+    Directories,
+    /// Each snapshot in a plain-text file, `files/<name>.snap`: its metadata
+    /// as YAML front matter followed by its stdout. Its other outputs are
+    /// stored next to it.
+    Files,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SnapshotStatus {
     Failed,
     Passed,
@@ -21,31 +109,581 @@ pub enum SnapshotStatus {
 
 pub struct Snapshot {
     pub exit_code: Option<i32>,
+    /// The signal that terminated the command, if any.
+    pub signal: Option<i32>,
     pub stderr: Option<SnapshotData>,
     pub stdout: Option<SnapshotData>,
+    pub stdin: Option<SnapshotData>,
+    /// Files written by the command, captured along with its outputs.
+    pub artifacts: Vec<Artifact>,
+    /// A file of the project holding the expected stdout, instead of the
+    /// snapshots folder.
+    pub expected_stdout: Option<String>,
     pub cmd: String,
     pub name: String,
     pub description: Option<String>,
     pub tags: Vec<String>,
     pub status: SnapshotStatus,
+    /// The number of the last run during which the snapshot was executed.
+    pub last_run: Option<u64>,
+    /// Results of the last runs, oldest first.
+    pub history: Vec<RunRecord>,
+    /// Environment variables set when running the command.
+    pub env: BTreeMap<String, String>,
+    /// Maximum execution time in seconds, overrides the default timeout.
+    pub timeout: Option<u64>,
+    /// Snapshots running for longer than this duration fail, even if their
+    /// outputs did not change.
+    pub max_duration_ms: Option<u64>,
+    /// Run the command in a pseudo-terminal.
+    pub pty: bool,
+    /// Record stdout and stderr interleaved, as stdout.
+    pub combined: bool,
+    /// Container runtime and image the command runs in, such as
+    /// `docker:alpine:3.19`.
+    pub runner: Option<String>,
+    /// Run the command in a scrubbed environment, only the listed variables
+    /// are passed through.
+    pub hermetic: Option<Vec<String>>,
+    /// Commands run before and after the snapshot's command, their outputs are
+    /// not compared.
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Number of times the command is executed again if the snapshot fails.
+    pub retries: u32,
+    /// Names of the snapshots that must pass before this one is run.
+    pub needs: Vec<String>,
+    /// Snapshots of the same serial group never run in parallel.
+    pub serial: Option<String>,
+    /// Files the outputs of the command depend on.
+    pub inputs: Vec<String>,
+    /// Remove ANSI escape sequences from the outputs before they are
+    /// compared.
+    pub strip_ansi: bool,
+    /// Built-in filters redacting the outputs, such as `uuids`.
+    pub filters: Vec<String>,
+    /// Patterns replaced in the outputs before they are compared, after the
+    /// ones of the configuration and the filters.
+    pub redact: Vec<Redaction>,
+    /// Steps transforming the outputs in order, after the redactions, before
+    /// they are stored or compared.
+    pub pipeline: Vec<Transform>,
+    /// How outputs are normalized when they are compared, on top of the
+    /// configuration.
+    pub normalize: Normalize,
+    /// Only the exit code is compared, the outputs are not recorded.
+    pub exit_code_only: bool,
+    /// The output is neither recorded nor compared.
+    pub ignore_stdout: bool,
+    pub ignore_stderr: bool,
+    /// Outputs are binary data, shown as hexdumps even if they look like
+    /// text.
+    pub binary: bool,
+    /// How stdout is compared.
+    pub compare: Compare,
+    /// How close the numbers in the outputs must be to match.
+    pub tolerance: Tolerance,
+    /// The outputs only need to contain the sections of the stored ones, in
+    /// order or not.
+    pub contains: Option<LineOrder>,
+    /// The accepted exit codes, instead of the recorded one.
+    pub exit_codes: Option<ExitCodes>,
+    /// Properties the outputs must have, the outputs they are about are not
+    /// compared.
+    pub assertions: Vec<Assertion>,
+    /// A command judging whether the outputs match, instead of comparing them.
+    pub comparator: Option<String>,
+    /// The snapshot tracks known-broken behavior: it passes when its outputs
+    /// don't match or its command fails, and fails when it works.
+    pub xfail: bool,
+    /// Hash of the command and its inputs as of the last time the snapshot
+    /// passed or was updated, if it declares inputs.
+    pub cache_key: Option<String>,
+    /// Archived snapshots are kept out of runs and of the REPL.
+    pub archived: bool,
+    /// Who created the snapshot and when.
+    pub created: Option<Stamp>,
+    /// Who last updated the expectation of the snapshot and when, if it was
+    /// since it was created.
+    pub updated: Option<Stamp>,
     pub deleted: bool,
 }
 
-#[derive(PartialEq, Eq)]
+/// A pattern replaced in the outputs of commands, so that timestamps or
+/// temporary paths for instance don't make snapshots fail.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Redaction {
+    /// A regex, matched against the raw outputs.
+    pub pattern: String,
+    /// The text replacing each match, `$1` or `${name}` refer to capture
+    /// groups.
+    pub replacement: String,
+}
+
+/// Returns the checksum of a stored output, recorded in the metadata to detect
+/// corrupted files.
+pub fn checksum(body: &[u8]) -> String {
+    let mut hash = Fnv::new();
+    hash.write(body);
+    format!("{:016x}", hash.0)
+}
+
+/// Checks that a snapshot name stays a single file name in the store: it is
+/// not empty, does not start with '.' and contains neither '/' nor NUL.
+pub fn check_name(name: &str) -> Result<(), Error> {
+    if is_valid_name(name) {
+        Ok(())
+    } else {
+        Error::from_str(&format!(
+            "Invalid snapshot name: '{}', it must not be empty, start with '.' or contain '/'.",
+            name.escape_default()
+        ))
+    }
+}
+
+/// The result of a snapshot during a run.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run: u64,
+    pub passed: bool,
+    pub duration_ms: u64,
+}
+
+/// Who made a change to a snapshot and when.
+#[derive(Clone, PartialEq, Eq, Serialize)]
+pub struct Stamp {
+    /// In seconds since the Unix epoch.
+    pub at: u64,
+    /// The git user of the project, or else the user of the system, if known.
+    pub by: Option<String>,
+}
+
+impl Stamp {
+    /// Returns the stamp recorded in the metadata as a time and an author.
+    fn from_metadata(at: Option<u64>, by: Option<String>) -> Option<Stamp> {
+        Some(Stamp { at: at?, by })
+    }
+}
+
+#[derive(PartialEq, Eq, Clone)]
 pub struct SnapshotData {
     pub path: String,
-    pub body: Vec<u8>,
+    pub body: Body,
+}
+
+/// Reads a stored output.
+type Reader = Rc<dyn Fn() -> Result<Vec<u8>, Error>>;
+
+/// Counts the uses of the outputs, to tell the least recently used ones.
+static USES: AtomicU64 = AtomicU64::new(0);
+
+/// The content of an output. The stored outputs are only read the first time
+/// they are used, so that the commands that don't run or show the snapshots
+/// only read the metadata, which records their size and checksum. They can be
+/// unloaded as long as they are unchanged, to be read again when used.
+#[derive(Clone)]
+pub struct Body {
+    content: OnceCell<Vec<u8>>,
+    read: Option<Reader>,
+    /// When the content was last used, as a count of uses of all outputs.
+    used: Cell<u64>,
+    /// The size and checksum recorded for a stored output.
+    len: Option<u64>,
+    hash: Option<String>,
+}
+
+impl Body {
+    /// Creates the body of a stored output, read when first used.
+    fn stored(read: Reader, len: Option<u64>, hash: Option<String>) -> Body {
+        Body {
+            content: OnceCell::new(),
+            read: Some(read),
+            used: Cell::new(0),
+            len,
+            hash,
+        }
+    }
+
+    /// Returns the content, read from the store if it was not read yet.
+    pub fn read(&self) -> Result<&Vec<u8>, Error> {
+        self.used.set(USES.fetch_add(1, Ordering::Relaxed) + 1);
+        if let Some(content) = self.content.get() {
+            return Ok(content);
+        }
+        let content = match &self.read {
+            Some(read) => read()?,
+            None => Vec::new(),
+        };
+        Ok(self.content.get_or_init(|| content))
+    }
+
+    /// The content of a loaded body, which can only fail to be read if it was
+    /// not loaded.
+    fn content(&self) -> &Vec<u8> {
+        self.read().unwrap_log()
+    }
+
+    /// Reads the content if it was not read yet, before its file is moved for
+    /// instance.
+    pub fn load(&self) -> Result<(), Error> {
+        self.read().map(|_| ())
+    }
+
+    /// Returns the size of the content in bytes, the recorded one if it was
+    /// not read yet.
+    pub fn size(&self) -> u64 {
+        match (self.content.get(), self.len) {
+            (None, Some(len)) => len,
+            _ => self.content().len() as u64,
+        }
+    }
+
+    /// Returns the checksum of the content, the recorded one if it was not
+    /// read yet.
+    pub fn checksum(&self) -> String {
+        match (self.content.get(), &self.hash) {
+            (None, Some(hash)) => hash.clone(),
+            _ => checksum(self.content()),
+        }
+    }
+
+    /// Whether the content does not match the checksum recorded when it was
+    /// stored, because it was edited by hand or corrupted.
+    pub fn is_corrupted(&self) -> bool {
+        self.hash.as_ref().is_some_and(|hash| checksum(self.content()) != *hash)
+    }
+
+    /// Takes the current content as the stored one, even if it does not match
+    /// the recorded checksum.
+    pub fn adopt(&mut self) {
+        self.hash = None;
+    }
+
+    /// Returns when the content was last used if it was read from the store
+    /// and is unchanged, so that it can be unloaded.
+    fn last_used(&self) -> Option<u64> {
+        match (self.content.get(), &self.read) {
+            (Some(_), Some(_)) => Some(self.used.get()),
+            _ => None,
+        }
+    }
+
+    /// Drops the content of a stored output, `read` reads it again from where
+    /// it is now stored, as it may have moved since it was read.
+    fn unload(&mut self, read: Reader) {
+        if self.read.is_some() {
+            self.len = self.content.take().map(|content| content.len() as u64).or(self.len);
+            self.read = Some(read);
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(content: Vec<u8>) -> Body {
+        Body {
+            content: OnceCell::from(content),
+            read: None,
+            used: Cell::new(0),
+            len: None,
+            hash: None,
+        }
+    }
+}
+
+impl Deref for Body {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        self.content()
+    }
+}
+
+impl DerefMut for Body {
+    /// The changed content no longer is the stored one.
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        self.content();
+        self.hash = None;
+        self.read = None;
+        self.content.get_mut().unwrap()
+    }
+}
+
+impl PartialEq for Body {
+    fn eq(&self, other: &Body) -> bool {
+        self.content() == other.content()
+    }
+}
+
+impl Eq for Body {}
+
+/// What a snapshot expects from its command, the part of it replaced when it is
+/// updated.
+pub struct Expectation {
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub stdout: Option<Vec<u8>>,
+    pub stderr: Option<Vec<u8>>,
+    /// The files the command is expected to write, along with their content
+    /// if it writes them.
+    pub artifacts: Vec<(String, Option<Vec<u8>>)>,
+}
+
+/// A file written by the command of a snapshot.
+#[derive(PartialEq, Eq, Clone)]
+pub struct Artifact {
+    /// Path of the file, relative to the directory the command runs in.
+    pub file: String,
+    /// Content of the file, `None` if the command did not write it.
+    pub data: Option<SnapshotData>,
+}
+
+impl Snapshot {
+    /// Reads all the data of the snapshot that was not read yet, so that using
+    /// it can't fail.
+    pub fn load(&self) -> Result<(), Error> {
+        self.data().iter().try_for_each(|data| data.body.load())
+    }
+
+    /// Returns all the data (stdout, stderr, stdin and artifacts) of the
+    /// snapshot stored in the snapshots folder, a golden stdout is not.
+    pub fn data(&self) -> Vec<&SnapshotData> {
+        let stdout = self.stdout.iter().filter(|_| self.expected_stdout.is_none());
+        let artifacts = self.artifacts.iter().filter_map(|artifact| artifact.data.as_ref());
+        stdout.chain(self.stderr.iter()).chain(self.stdin.iter()).chain(artifacts).collect()
+    }
+
+    /// Returns mutable references to all the data of the snapshot stored in
+    /// the snapshots folder.
+    pub fn data_mut(&mut self) -> Vec<&mut SnapshotData> {
+        let golden = self.expected_stdout.is_some();
+        let stdout = self.stdout.iter_mut().filter(|_| !golden);
+        let stderr = self.stderr.iter_mut();
+        let stdin = self.stdin.iter_mut();
+        let artifacts = self.artifacts.iter_mut().filter_map(|artifact| artifact.data.as_mut());
+        stdout.chain(stderr).chain(stdin).chain(artifacts).collect()
+    }
+
+    /// Returns a copy of what the snapshot currently expects.
+    pub fn expectation(&self) -> Expectation {
+        Expectation {
+            exit_code: self.exit_code,
+            signal: self.signal,
+            stdout: self.stdout.as_ref().map(|data| data.body.to_vec()),
+            stderr: self.stderr.as_ref().map(|data| data.body.to_vec()),
+            artifacts: self
+                .artifacts
+                .iter()
+                .map(|artifact| (artifact.file.clone(), artifact.data.as_ref().map(|data| data.body.to_vec())))
+                .collect(),
+        }
+    }
+
+    /// Returns the outputs whose stored content does not match the checksum
+    /// recorded in the metadata, because they were edited by hand or
+    /// corrupted.
+    pub fn corrupted(&self) -> Vec<&'static str> {
+        let outputs = [("stdout", &self.stdout), ("stderr", &self.stderr)];
+        outputs
+            .iter()
+            .filter(|(_, data)| data.as_ref().is_some_and(|data| data.body.is_corrupted()))
+            .map(|(output, _)| *output)
+            .collect()
+    }
+
+    /// Takes the stored outputs as they are, even if they don't match their
+    /// checksums: their checksums are recorded again.
+    pub fn adopt_outputs(&mut self) {
+        for data in self.stdout.iter_mut().chain(self.stderr.iter_mut()) {
+            data.body.adopt();
+        }
+    }
+
+    /// Adds a run to the history of the snapshot, only the most recent runs
+    /// are kept.
+    pub fn record_run(&mut self, record: RunRecord) {
+        self.history.push(record);
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+    }
+
+    /// Returns the size in bytes of the snapshot's data.
+    pub fn size(&self) -> u64 {
+        self.data().iter().map(|data| data.body.size()).sum()
+    }
+
+    /// Returns true if stdout is neither recorded nor compared.
+    pub fn ignores_stdout(&self) -> bool {
+        self.exit_code_only || self.ignore_stdout
+    }
+
+    /// Returns true if stderr is neither recorded nor compared.
+    pub fn ignores_stderr(&self) -> bool {
+        self.exit_code_only || self.ignore_stderr
+    }
+
+    /// Returns true if an actual exit code is the recorded one, or one of the
+    /// accepted ones.
+    pub fn accepts_code(&self, code: Option<i32>) -> bool {
+        match &self.exit_codes {
+            Some(codes) => codes.matches(code),
+            None => self.exit_code == code,
+        }
+    }
+
+    /// Returns the expected exit code, or codes, for display.
+    pub fn expected_code(&self) -> String {
+        match (&self.exit_codes, self.exit_code) {
+            (Some(codes), _) => codes.to_string(),
+            (None, Some(code)) => code.to_string(),
+            (None, None) => String::from("None"),
+        }
+    }
+
+    /// Returns true if a stored output and an actual one compared as text are
+    /// the same, once normalized, with their numbers within tolerance and the
+    /// placeholders of the stored output matching, or if the actual output
+    /// contains the stored one in contains mode.
+    pub fn same_output(&self, normalize: &Normalize, expected: &[u8], actual: &[u8]) -> bool {
+        if normalize.same(expected, actual) {
+            return true;
+        }
+        let (expected, actual) = (normalize.apply(expected), normalize.apply(actual));
+        if let Some(order) = self.contains {
+            return missing_sections(&expected, &actual, order).is_empty();
+        }
+        (!self.tolerance.is_none() && self.tolerance.same(&expected, &actual))
+            || matches_placeholders(&expected, &actual)
+    }
+
+    /// Returns the failures of the assertions on stdout, or stderr, or `None`
+    /// if there are none on that output and its content is compared.
+    pub fn failed_assertions(&self, stderr: bool, actual: &[u8]) -> Option<Vec<String>> {
+        let mut assertions = self.assertions.iter().filter(|assertion| assertion.stderr == stderr).peekable();
+        assertions.peek()?;
+        Some(assertions.filter_map(|assertion| assertion.check(actual).err()).collect())
+    }
+
+    /// Returns the artifacts whose actual content, given in the order they
+    /// are declared, differs from the recorded one once normalized, along with
+    /// that content.
+    pub fn changed_artifacts<'a>(
+        &'a self,
+        normalize: &Normalize,
+        actual: &'a [Option<Vec<u8>>],
+    ) -> Vec<(&'a Artifact, &'a Option<Vec<u8>>)> {
+        let changed = |(artifact, actual): &(&Artifact, &Option<Vec<u8>>)| match (&artifact.data, actual) {
+            (Some(expected), Some(actual)) => !self.same_output(normalize, &expected.body, actual),
+            (expected, actual) => expected.is_some() != actual.is_some(),
+        };
+        self.artifacts.iter().zip(actual).filter(changed).collect()
+    }
+
+    /// Removes the outputs that are not recorded.
+    pub fn drop_ignored_outputs(&mut self) {
+        if self.ignores_stdout() {
+            self.stdout = None;
+        }
+        if self.ignores_stderr() {
+            self.stderr = None;
+        }
+    }
+}
+
+/// Snapshot files without metadata and snapshots missing their files.
+/// Data deleted by `parrot gc`, or that would be.
+#[derive(Default)]
+pub struct Collected {
+    pub count: usize,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+impl Collected {
+    fn add(&mut self, size: u64) {
+        self.count += 1;
+        self.size += size;
+    }
+}
+
+/// Everything deleted by `parrot gc`, or that would be.
+#[derive(Default)]
+pub struct Garbage {
+    /// Stored outputs no snapshot refers to anymore, once deduplicated.
+    pub blobs: Collected,
+    /// Versions of the history beyond the kept ones, and the ones of deleted
+    /// snapshots.
+    pub versions: Collected,
+    /// Snapshot files no snapshot refers to, only collected when aggressive.
+    pub files: Collected,
+    /// Outputs kept by repeated runs, only collected when aggressive.
+    pub divergent: Collected,
+}
+
+pub struct Orphans {
+    pub files: Vec<String>,
+    pub snapshots: Vec<String>,
+}
+
+/// An inconsistency found in the parrot folder.
+pub enum Problem {
+    MissingFile { snapshot: String, file: String },
+    MissingGolden { snapshot: String, file: String },
+    SizeMismatch { snapshot: String, file: String, expected: u64, actual: u64 },
+    ChecksumMismatch { snapshot: String, file: String },
+    DuplicateName { name: String, count: usize },
+    MissingDependency { snapshot: String, needs: String },
+    UnreferencedFile(String),
+    InvalidState(Error),
+    OutdatedSchema(u32),
 }
 
 pub struct DataManager {
     snaps: Option<Vec<Rc<RefCell<Snapshot>>>>,
     metadata_manager: metadata::MetadataManager,
-    snap_manager: snapshots::SnapshotsManager,
+    /// Where the outputs are stored, the parrot folder in the current layout.
+    snap_manager: Rc<dyn SnapshotStore>,
+    /// The layout the snapshots are currently stored in.
+    layout: Layout,
+    /// Whether the outputs are currently stored as blobs.
+    deduplicate: bool,
+    /// Where the large outputs are stored, if configured.
+    remote: Option<Rc<remote::RemoteStore>>,
+    /// How the outputs are encrypted, for the recipients they are currently
+    /// encrypted for.
+    encryption: Rc<encryption::Encryption>,
+    state_manager: state::StateManager,
+    config_manager: config::ConfigManager,
+    history_manager: history::HistoryManager,
+    pending_manager: pending::PendingManager,
+    trash_manager: trash::TrashManager,
+    run_count: u64,
     path: PathBuf,
+    /// The project directory, golden files are relative to it.
+    root: PathBuf,
+    /// Held as long as the parrot folder is in use.
+    lock: Option<lock::Lock>,
+    /// Wait for other parrot processes to release the parrot folder rather
+    /// than failing.
+    wait: bool,
+    /// The suite of snapshots managed, in a parrot folder of its own within
+    /// the main one.
+    suite: Option<String>,
+    /// The author of the changes, looked up once.
+    author: OnceCell<Option<String>>,
 }
 
 impl DataManager {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<DataManager, Error> {
+    /// Creates a manager for the parrot folder of the project at `path`. The
+    /// folder is `.parrot` in the project unless `store` is given, or the
+    /// configuration sets another one. The snapshots of a `suite` are in a
+    /// folder of their own, sharing the configuration of the main one.
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        store: Option<&Path>,
+        suite: Option<&str>,
+        wait: bool,
+    ) -> Result<DataManager, Error> {
         let path = path.as_ref();
 
         // Ensure that the path exists.
@@ -59,34 +697,114 @@ impl DataManager {
             });
         }
 
-        let path = path.join(PARROT_PATH);
-        let metadata_path = path.join(METADATA_PATH);
-        let snapshots_path = path.join(SNAPSHOT_PATH);
+        let root = path.to_owned();
+        let path = store.map_or_else(|| root.join(PARROT_PATH), Path::to_owned);
+        let config_manager = config::ConfigManager::new(path.join(CONFIG_PATH));
+        // The configuration stays in `.parrot` when it moves the snapshots
+        // elsewhere, an invalid configuration is reported when it is read
+        let path = match config_manager.get_config().ok().and_then(|config| config.store).filter(|_| store.is_none()) {
+            Some(store) => root.join(store),
+            None => path,
+        };
+        let path = match suite {
+            Some(suite) if !is_valid_name(suite) => {
+                return Error::from_str(&format!("Invalid suite name: '{}'.", suite));
+            }
+            Some(suite) => path.join(SUITES_PATH).join(suite),
+            None => path,
+        };
+        let lock = if path.is_dir() {
+            Some(lock::Lock::acquire(path.join(LOCK_PATH), wait)?)
+        } else {
+            None
+        };
+        let state_path = path.join(STATE_PATH);
+        let (layout, deduplicate) = match stored_layout(&path) {
+            Some(layout) => (layout, path.join(BLOBS_INDEX_PATH).is_file()),
+            None => configured_layout(&config_manager),
+        };
+        let remote = configured_remote(&config_manager, &root, &path);
+        let encryption = Rc::new(encryption::Encryption::new(
+            stored_recipients(&path),
+            config_manager.get_config().ok().and_then(|config| config.key_file).as_deref(),
+            &root,
+        ));
         Ok(DataManager {
             snaps: None,
-            metadata_manager: metadata::MetadataManager::new(metadata_path),
-            snap_manager: snapshots::SnapshotsManager::new(snapshots_path),
+            metadata_manager: metadata::MetadataManager::new(path.clone(), layout),
+            snap_manager: Rc::new(snapshots::SnapshotsManager::new(
+                path.clone(),
+                layout,
+                deduplicate,
+                remote.clone(),
+                Rc::clone(&encryption),
+            )),
+            history_manager: history::HistoryManager::new(path.join(HISTORY_PATH), Rc::clone(&encryption)),
+            pending_manager: pending::PendingManager::new(path.join(PENDING_PATH), Rc::clone(&encryption)),
+            trash_manager: trash::TrashManager::new(path.join(TRASH_PATH), Rc::clone(&encryption)),
+            layout,
+            deduplicate,
+            remote,
+            encryption,
+            state_manager: state::StateManager::new(state_path),
+            config_manager,
+            run_count: 0,
             path,
+            root,
+            lock,
+            wait,
+            suite: suite.map(str::to_owned),
+            author: OnceCell::new(),
         })
     }
 
+    /// Returns the path of the parrot folder.
+    pub fn store_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the configuration of the test suite.
+    pub fn get_config(&self) -> Result<Config, Error> {
+        self.config_manager.get_config()
+    }
+
     /// Initializes the Parrot storage folder.
     pub fn initialize(&mut self) -> Result<(), Error> {
         if self.path.exists() {
             return Error::from_str("A parrot folder already exists.");
         }
         wrap(
-            fs::create_dir(&self.path),
+            fs::create_dir_all(&self.path),
             "Unable to create a parrot folder.",
         )?;
+        self.lock = Some(lock::Lock::acquire(self.path.join(LOCK_PATH), self.wait)?);
         self.metadata_manager.write_empty()?;
         self.snap_manager.create_empty()?;
+        self.write_schema_version()?;
+        self.write_gitignore()?;
+        Ok(())
+    }
+
+    /// Creates the folder of the suite if it does not exist yet, suites are
+    /// created along with their first snapshot.
+    pub fn create_suite(&mut self) -> Result<(), Error> {
+        if self.suite.is_some() && !self.path.exists() {
+            self.initialize()?;
+        }
         Ok(())
     }
 
     /// Adds a snapshot and persist all snapshots to file system.
-    pub fn add_snapshot(&mut self, snap: Snapshot) -> Result<(), Error> {
+    pub fn add_snapshot(&mut self, mut snap: Snapshot) -> Result<(), Error> {
+        check_name(&snap.name)?;
+        // Loading first moves the store to the configured layout
+        self.get_snaps()?;
+        if snap.created.is_none() {
+            snap.created = Some(self.stamp());
+        }
+        self.check_size(&snap)?;
         self.snap_manager.create(&snap)?;
+        self.write_golden(&snap)?;
         let snaps = self.get_snaps()?;
         snaps.push(Rc::new(RefCell::new(snap)));
         // Unwrap is safe because `self.get_snaps` caches snaps.
@@ -95,6 +813,272 @@ impl DataManager {
         Ok(())
     }
 
+    /// Removes a snapshot, both its data and metadata are moved to the trash.
+    pub fn remove_snapshot(&mut self, name: &str) -> Result<(), Error> {
+        let snaps = self.get_snaps()?;
+        let idx = match snaps.iter().position(|snap| snap.borrow().name == name) {
+            Some(idx) => idx,
+            None => return Err(no_such_snapshot(name)),
+        };
+        let snap = snaps.remove(idx);
+        self.trash_snapshot(&snap.borrow())?;
+        self.snap_manager.delete(&snap.borrow())?;
+        self.history_manager.remove(name)?;
+        self.pending_manager.remove(name)?;
+        self.persist_metadata()
+    }
+
+    /// Moves a removed snapshot to the trash, unless it is disabled, and
+    /// deletes the ones removed before the retention period.
+    fn trash_snapshot(&self, snap: &Snapshot) -> Result<(), Error> {
+        let days = self.get_config()?.trash_days.unwrap_or(TRASH_DAYS);
+        if days > 0 {
+            let metadata = metadata::Metadata::from(snap);
+            let paths: Vec<&String> = metadata.data_paths().into_iter().map(|(path, _)| path).collect();
+            let outputs: Vec<(String, Vec<u8>)> = snap
+                .data()
+                .into_iter()
+                .filter(|data| paths.contains(&&data.path))
+                .map(|data| (data.path.clone(), data.body.to_vec()))
+                .collect();
+            self.trash_manager.put(&metadata, &outputs)?;
+        }
+        self.trash_manager.purge(Some(days * 24 * 3600))?;
+        Ok(())
+    }
+
+    /// Returns the snapshots in the trash, the most recently removed last.
+    pub fn trash(&self) -> Result<Vec<Trashed>, Error> {
+        self.trash_manager.list()
+    }
+
+    /// Restores the most recently removed snapshot of a name from the trash,
+    /// fails if the name was taken since.
+    pub fn restore_snapshot(&mut self, name: &str) -> Result<(), Error> {
+        if self.get_snapshot(name).is_ok() {
+            return Err(already_exists(name));
+        }
+        let (trashed, metadata, outputs) = self.trash_manager.get(name)?;
+        // The metadata comes first, a snapshot file starts with it
+        let mut metadatas = self.metadata_manager.get_metadata()?;
+        metadatas.snapshots.push(metadata);
+        self.metadata_manager.write(&metadatas)?;
+        for (path, body) in outputs {
+            self.snap_manager.update_snapshot(&SnapshotData { path, body: body.into() })?;
+        }
+        self.trash_manager.remove(&trashed)?;
+        // The snapshots are loaded again along with the restored one
+        self.snaps = None;
+        Ok(())
+    }
+
+    /// Deletes all the snapshots in the trash, returns how many there were.
+    pub fn empty_trash(&self) -> Result<usize, Error> {
+        self.trash_manager.purge(None)
+    }
+
+    /// Returns the snapshots that differ between the two sides of a merge that
+    /// left conflict markers in the parrot folder. Each side is read from a
+    /// copy of the folder in which the conflicted files hold its version, so
+    /// that any layout can be resolved.
+    pub fn conflicts(&self) -> Result<Vec<Conflict>, Error> {
+        let files = conflicted_files(&self.path, Path::new(""))?;
+        if files.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ours = self.read_side(&files, "ours", |(_, ours, _)| ours)?;
+        let mut theirs = self.read_side(&files, "theirs", |(_, _, theirs)| theirs)?;
+        let mut conflicts = Vec::new();
+        for ours in ours {
+            let theirs = theirs.iter().position(|snap| snap.name == ours.name).map(|idx| theirs.remove(idx));
+            if theirs.as_ref().is_none_or(|theirs| !same_snapshot(&ours, theirs)) {
+                conflicts.push(Conflict {
+                    name: ours.name.clone(),
+                    ours: Some(ours),
+                    theirs,
+                });
+            }
+        }
+        conflicts.extend(theirs.into_iter().map(|theirs| Conflict {
+            name: theirs.name.clone(),
+            ours: None,
+            theirs: Some(theirs),
+        }));
+        Ok(conflicts)
+    }
+
+    /// Reads the snapshots of one side of a merge, from a temporary copy of the
+    /// parrot folder in which the conflicted files hold the version `pick`s.
+    fn read_side<F>(&self, files: &[ConflictedFile], side: &str, pick: F) -> Result<Vec<Snapshot>, Error>
+    where
+        F: Fn(&ConflictedFile) -> &Vec<u8>,
+    {
+        let error = "Failed to copy the parrot folder.";
+        let temp = wrap(tempfile::Builder::new().prefix(&format!("parrot-{}-", side)).tempdir(), error)?;
+        let copy = temp.path();
+        // The suites are parrot folders of their own, resolved apart
+        copy_dir(&self.path, copy, &[&UNVERSIONED_PATHS[..], &[SUITES_PATH]].concat())?;
+        // The snapshots of a suite share the configuration of the main folder
+        if !copy.join(CONFIG_PATH).exists() {
+            if let Ok(config) = fs::read(self.config_manager.path()) {
+                wrap(fs::write(copy.join(CONFIG_PATH), config), error)?;
+            }
+        }
+        for file in files {
+            wrap(fs::write(copy.join(&file.0), pick(file)), error)?;
+        }
+        let snaps = DataManager::new(&self.root, Some(copy), None, false).and_then(|mut data| {
+            data.get_snaps()?;
+            let snaps = data.snaps.take().unwrap_or_default();
+            // The outputs are read before the copy is removed
+            let snaps: Vec<Snapshot> =
+                snaps.into_iter().filter_map(|snap| Rc::try_unwrap(snap).ok()).map(RefCell::into_inner).collect();
+            for snap in &snaps {
+                snap.load()?;
+            }
+            Ok(snaps)
+        });
+        wrap(temp.close(), "Failed to remove a copy of the parrot folder.")?;
+        snaps
+    }
+
+    /// Resolves the conflicts of a merge: the conflicted files take the
+    /// version of the current branch, then each of the `resolved` snapshots
+    /// replaces the current one of its name, or removes it if it is None.
+    pub fn resolve_conflicts(&mut self, resolved: Vec<(String, Option<Snapshot>)>) -> Result<(), Error> {
+        for (file, ours, _) in conflicted_files(&self.path, Path::new(""))? {
+            wrap(write_atomic(&self.path.join(file), &ours), "Failed to resolve a conflicted file.")?;
+        }
+        self.snaps = None;
+        for (name, snap) in resolved {
+            let snaps = self.get_snaps()?;
+            if let Some(idx) = snaps.iter().position(|current| current.borrow().name == name) {
+                let current = snaps.remove(idx);
+                self.snap_manager.delete(&current.borrow())?;
+            }
+            if let Some(snap) = snap {
+                check_name(&snap.name)?;
+                self.snap_manager.create(&snap)?;
+                self.write_golden(&snap)?;
+                self.get_snaps()?.push(Rc::new(RefCell::new(snap)));
+            }
+        }
+        self.persist_metadata()
+    }
+
+    /// Renames a snapshot and moves its data files accordingly. Fails if the
+    /// new name is already taken.
+    pub fn rename_snapshot(&mut self, name: &str, new_name: &str) -> Result<(), Error> {
+        check_name(new_name)?;
+        if self.get_snapshot(new_name).is_ok() {
+            return Err(already_exists(new_name));
+        }
+        let snap = self.get_snapshot(name)?;
+        let mut snap = snap.borrow_mut();
+        let mut moved: Vec<(&str, String)> = Vec::new();
+        snap.load()?;
+        for data in snap.data() {
+            let path = renamed_path(&data.path, new_name);
+            if let Err(err) = self.snap_manager.rename(&data.path, &path) {
+                // Move files back so that the store stays consistent
+                for (old_path, path) in moved {
+                    let _ = self.snap_manager.rename(&path, old_path);
+                }
+                return Err(err);
+            }
+            moved.push((&data.path, path));
+        }
+        for data in snap.data_mut() {
+            data.path = renamed_path(&data.path, new_name);
+        }
+        snap.name = new_name.to_owned();
+        drop(snap); // Release the mutable borrow before updating dependents
+        self.history_manager.rename(name, new_name)?;
+        self.pending_manager.rename(name, new_name)?;
+        for snap in self.get_snaps()? {
+            for needs in snap.borrow_mut().needs.iter_mut().filter(|needs| *needs == name) {
+                *needs = new_name.to_owned();
+            }
+        }
+        self.persist_metadata()
+    }
+
+    /// Copies a snapshot, along with its expected outputs, under a new name.
+    /// The command of the copy is replaced by `cmd` if provided.
+    pub fn copy_snapshot(&mut self, name: &str, new_name: &str, cmd: Option<String>) -> Result<(), Error> {
+        check_name(new_name)?;
+        if self.get_snapshot(new_name).is_ok() {
+            return Err(already_exists(new_name));
+        }
+        let snap = self.get_snapshot(name)?;
+        let snap = snap.borrow();
+        let copy_data = |data: &Option<SnapshotData>| {
+            data.as_ref().map(|data| SnapshotData {
+                path: renamed_path(&data.path, new_name),
+                body: data.body.clone(),
+            })
+        };
+        let copy = Snapshot {
+            exit_code: snap.exit_code,
+            signal: snap.signal,
+            stderr: copy_data(&snap.stderr),
+            stdout: copy_data(&snap.stdout),
+            stdin: copy_data(&snap.stdin),
+            expected_stdout: snap.expected_stdout.clone(),
+            artifacts: snap
+                .artifacts
+                .iter()
+                .map(|artifact| Artifact {
+                    file: artifact.file.clone(),
+                    data: copy_data(&artifact.data),
+                })
+                .collect(),
+            cmd: cmd.unwrap_or_else(|| snap.cmd.to_owned()),
+            name: new_name.to_owned(),
+            description: snap.description.clone(),
+            tags: snap.tags.clone(),
+            status: SnapshotStatus::Waiting,
+            last_run: None,
+            history: Vec::new(),
+            env: snap.env.clone(),
+            timeout: snap.timeout,
+            max_duration_ms: snap.max_duration_ms,
+            pty: snap.pty,
+            combined: snap.combined,
+            runner: snap.runner.clone(),
+            hermetic: snap.hermetic.clone(),
+            before: snap.before.clone(),
+            after: snap.after.clone(),
+            retries: snap.retries,
+            needs: snap.needs.clone(),
+            serial: snap.serial.clone(),
+            inputs: snap.inputs.clone(),
+            strip_ansi: snap.strip_ansi,
+            filters: snap.filters.clone(),
+            redact: snap.redact.clone(),
+            pipeline: snap.pipeline.clone(),
+            normalize: snap.normalize,
+            exit_code_only: snap.exit_code_only,
+            ignore_stdout: snap.ignore_stdout,
+            ignore_stderr: snap.ignore_stderr,
+            binary: snap.binary,
+            compare: snap.compare,
+            tolerance: snap.tolerance,
+            contains: snap.contains,
+            exit_codes: snap.exit_codes.clone(),
+            assertions: snap.assertions.clone(),
+            comparator: snap.comparator.clone(),
+            xfail: snap.xfail,
+            cache_key: None,
+            archived: false,
+            created: None,
+            updated: None,
+            deleted: false,
+        };
+        drop(snap); // Release the borrow before adding the copy
+        self.add_snapshot(copy)
+    }
+
     /// Run the snapshot GC: eletes all snapshot marked as deleted, then 
     /// persist metadatas.
     ///
@@ -104,29 +1088,474 @@ impl DataManager {
             for snap in snaps {
                 let snap = snap.borrow();
                 if snap.deleted {
+                    self.trash_snapshot(&snap)?;
                     self.snap_manager.delete(&snap)?;
                 }
             }
-            self.metadata_manager.persist(&snaps)?;
+            self.metadata_manager.persist(snaps)?;
         }
         Ok(())
     }
 
+    /// Looks for snapshot files not referenced by any metadata and snapshots
+    /// whose files are missing. Orphans are deleted unless `dry_run` is set.
+    pub fn clean(&mut self, dry_run: bool) -> Result<Orphans, Error> {
+        self.schema_version()?;
+        let mut metadatas = self.metadata_manager.get_metadata()?;
+        let files: HashSet<String> = self.snap_manager.list()?.into_iter().collect();
+        let mut referenced = HashSet::new();
+        let mut orphans = Orphans {
+            files: Vec::new(),
+            snapshots: Vec::new(),
+        };
+        metadatas.snapshots.retain(|snap| {
+            let paths: Vec<&String> = snap.data_paths().into_iter().map(|(path, _)| path).collect();
+            if paths.iter().all(|path| files.contains(*path)) {
+                referenced.extend(paths.into_iter().cloned());
+                true
+            } else {
+                orphans.snapshots.push(snap.name.clone());
+                false
+            }
+        });
+        orphans.files = files.difference(&referenced).cloned().collect();
+        orphans.files.sort();
+        if !dry_run {
+            for file in &orphans.files {
+                self.snap_manager.delete_file(file)?;
+            }
+            if !orphans.snapshots.is_empty() {
+                self.metadata_manager.write(&metadatas)?;
+            }
+            // Snapshots will be reloaded from the cleaned store
+            self.snaps = None;
+        }
+        Ok(orphans)
+    }
+
+    /// Checks the integrity of the parrot folder.
+    /// Returns the names of the snapshots along with the problems found, fails if
+    /// the metadata can not be read.
+    pub fn check(&self) -> Result<(Vec<String>, Vec<Problem>), Error> {
+        let version = self.schema_version()?;
+        let metadatas = self.metadata_manager.get_metadata()?;
+        let mut problems = Vec::new();
+        if version < SCHEMA_VERSION {
+            problems.push(Problem::OutdatedSchema(version));
+        }
+        let mut names = Vec::with_capacity(metadatas.snapshots.len());
+        let mut name_count: HashMap<&String, usize> = HashMap::new();
+        let mut referenced = HashSet::new();
+        for snap in &metadatas.snapshots {
+            names.push(snap.name.clone());
+            if let Some(file) = snap.expected_stdout.as_ref().filter(|file| !self.root.join(file).is_file()) {
+                problems.push(Problem::MissingGolden {
+                    snapshot: snap.name.clone(),
+                    file: file.clone(),
+                });
+            }
+            *name_count.entry(&snap.name).or_insert(0) += 1;
+            for (path, expected) in snap.data_paths() {
+                referenced.insert(path.clone());
+                match (self.snap_manager.file_size(path), expected) {
+                    // The size of an output that cannot be decrypted is not checked
+                    (None, _) if self.snap_manager.is_locked(path) => (),
+                    (None, _) => problems.push(Problem::MissingFile {
+                        snapshot: snap.name.clone(),
+                        file: path.clone(),
+                    }),
+                    (Some(actual), Some(expected)) if actual != expected => problems.push(Problem::SizeMismatch {
+                        snapshot: snap.name.clone(),
+                        file: path.clone(),
+                        expected,
+                        actual,
+                    }),
+                    _ => (),
+                }
+            }
+            let outputs = [
+                (&snap.stdout, snap.stdout_len, &snap.stdout_hash),
+                (&snap.stderr, snap.stderr_len, &snap.stderr_hash),
+            ];
+            for (path, len, hash) in outputs {
+                if let (Some(path), Some(hash)) = (path, hash) {
+                    let body = match self.snap_manager.get(path) {
+                        Ok(body) => body,
+                        Err(_) => continue,
+                    };
+                    // A size mismatch is already reported
+                    if (len.is_none() || len == Some(body.len() as u64)) && checksum(&body) != *hash {
+                        problems.push(Problem::ChecksumMismatch {
+                            snapshot: snap.name.clone(),
+                            file: path.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        for snap in &metadatas.snapshots {
+            if let Some(count) = name_count.remove(&snap.name) {
+                if count > 1 {
+                    problems.push(Problem::DuplicateName {
+                        name: snap.name.clone(),
+                        count,
+                    });
+                }
+            }
+        }
+        for snap in &metadatas.snapshots {
+            for needs in snap.needs.iter().filter(|needs| !names.contains(needs)) {
+                problems.push(Problem::MissingDependency {
+                    snapshot: snap.name.clone(),
+                    needs: needs.clone(),
+                });
+            }
+        }
+        for file in self.snap_manager.list()? {
+            if !referenced.contains(&file) {
+                problems.push(Problem::UnreferencedFile(file));
+            }
+        }
+        if let Err(err) = self.state_manager.get_state() {
+            problems.push(Problem::InvalidState(err));
+        }
+        Ok((names, problems))
+    }
+
     /// Persists the snapshots' metadata to file system, should be used after 
     /// any snapshot metadata update update.
     pub fn persist_metadata(&self) -> Result<(), Error> {
         if let Some(snaps) = self.snaps.as_ref() {
             self.metadata_manager.persist(snaps)?;
+            self.state_manager.persist(self.run_count, snaps)?;
+        }
+        Ok(())
+    }
+
+    /// Persists the snapshots' status to the file system, should be used after
+    /// running snapshots.
+    pub fn persist_state(&self) -> Result<(), Error> {
+        if let Some(snaps) = self.snaps.as_ref() {
+            self.state_manager.persist(self.run_count, snaps)?;
         }
         Ok(())
     }
 
-    /// Persists the snapshot's stdout and stder bodies to the file system.
-    pub fn persist_snapshot_data(&self, snap: &Snapshot) -> Result<(), Error> {
+    /// Starts a new run and returns its number.
+    pub fn start_run(&mut self) -> Result<u64, Error> {
+        self.get_snaps()?;
+        self.run_count += 1;
+        Ok(self.run_count)
+    }
+
+    /// Returns the number of the current (or last) run.
+    pub fn current_run(&self) -> u64 {
+        self.run_count
+    }
+
+    /// Writes the outputs of a snapshot that diverged during a repeated run to
+    /// the divergent folder, encrypted as the stored outputs are, and returns
+    /// the path of that folder.
+    pub fn keep_divergent(&self, name: &str, output: &Output) -> Result<PathBuf, Error> {
+        let path = self.path.join(DIVERGENT_PATH);
+        let error = "Could not write the divergent outputs.";
+        wrap(fs::create_dir_all(&path), error)?;
+        self.encryption.write(&path.join(format!("{}.out", name)), &output.stdout, error)?;
+        self.encryption.write(&path.join(format!("{}.err", name)), &output.stderr, error)?;
+        Ok(path)
+    }
+
+    /// Returns the version of the format of the parrot folder, fails if it is
+    /// more recent than the ones this version of parrot can read.
+    pub fn schema_version(&self) -> Result<u32, Error> {
+        if let Some(suite) = self.suite.as_ref().filter(|_| !self.path.is_dir()) {
+            return Error::from_str(&format!(
+                "There is no suite '{}', `parrot add --suite {}` creates it along with its first snapshot.",
+                suite, suite
+            ));
+        }
+        let path = self.path.join(VERSION_PATH);
+        if !path.exists() {
+            return Ok(1);
+        }
+        let version = wrap(fs::read_to_string(path), "Could not read the version of the parrot folder.")?;
+        let version: u32 = wrap(version.trim().parse(), "Failed to parse the version of the parrot folder.")?;
+        if version > SCHEMA_VERSION {
+            return Error::from_str(&format!(
+                "The parrot folder is in format {}, this version of parrot only reads up to {}, upgrade parrot.",
+                version, SCHEMA_VERSION
+            ));
+        }
+        Ok(version)
+    }
+
+    /// Upgrades the parrot folder in place to the current format.
+    /// Returns the version of the format it was in.
+    pub fn migrate(&mut self) -> Result<u32, Error> {
+        let version = self.schema_version()?;
+        if version < SCHEMA_VERSION {
+            // Older formats are upgraded as they are loaded, persisting the
+            // snapshots writes them in the current one
+            self.get_snaps()?;
+            self.persist_metadata()?;
+            self.write_schema_version()?;
+        }
+        self.write_gitignore()?;
+        Ok(version)
+    }
+
+    /// Adds the unversioned paths missing from the `.gitignore` of the parrot
+    /// folder, the lines already there are kept.
+    fn write_gitignore(&self) -> Result<(), Error> {
+        let path = self.path.join(GITIGNORE_PATH);
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        let mut content = existing.clone();
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        for unversioned in UNVERSIONED_PATHS.iter() {
+            let line = format!("/{}", unversioned);
+            if !existing.lines().any(|existing| existing.trim() == line) {
+                content.push_str(&line);
+                content.push('\n');
+            }
+        }
+        if content == existing {
+            return Ok(());
+        }
+        wrap(write_atomic(&path, content.as_bytes()), "Failed to write the .gitignore of the parrot folder.")
+    }
+
+    fn write_schema_version(&self) -> Result<(), Error> {
+        let path = self.path.join(VERSION_PATH);
+        let content = format!("{}\n", SCHEMA_VERSION);
+        wrap(write_atomic(&path, content.as_bytes()), "Failed to write the version of the parrot folder.")
+    }
+
+    /// Deletes the data no snapshot refers to anymore, unless `dry_run` is set:
+    /// unreferenced blobs, histories of deleted snapshots and versions beyond
+    /// the `keep_history` most recent ones (the configured number if None).
+    /// Being `aggressive` also deletes the snapshot files without snapshots
+    /// and the divergent outputs.
+    pub fn gc(&mut self, dry_run: bool, aggressive: bool, keep_history: Option<usize>) -> Result<Garbage, Error> {
+        // Loading moves the outputs to the configured storage first
+        let snaps = self.get_snaps()?;
+        let names: HashSet<String> = snaps.iter().map(|snap| snap.borrow().name.clone()).collect();
+        let referenced: HashSet<String> = snaps
+            .iter()
+            .flat_map(|snap| snap.borrow().data().into_iter().map(|data| data.path.clone()).collect::<Vec<_>>())
+            .collect();
+        let mut garbage = Garbage::default();
+        if aggressive {
+            // Before the blobs, so that the ones of these files are collected
+            for file in self.snap_manager.list()?.iter().filter(|file| !referenced.contains(*file)) {
+                garbage.files.add(self.snap_manager.file_size(file).unwrap_or(0));
+                if !dry_run {
+                    self.snap_manager.delete_file(file)?;
+                }
+            }
+            let divergent = self.path.join(DIVERGENT_PATH);
+            if divergent.is_dir() {
+                for entry in wrap(fs::read_dir(&divergent), "Could not read the divergent outputs.")? {
+                    let entry = wrap(entry, "Could not read the divergent outputs.")?;
+                    garbage.divergent.add(entry.metadata().map_or(0, |metadata| metadata.len()));
+                }
+                if !dry_run {
+                    wrap(fs::remove_dir_all(divergent), "Failed to delete the divergent outputs.")?;
+                }
+            }
+        }
+        for (_, size) in self.snap_manager.gc(dry_run)? {
+            garbage.blobs.add(size);
+        }
+        let keep = match keep_history {
+            Some(keep) => keep,
+            None => self.get_config()?.keep_versions.unwrap_or(KEEP_VERSIONS),
+        };
+        for name in self.history_manager.names()? {
+            let keep = if names.contains(&name) { keep } else { 0 };
+            let (count, size) = self.history_manager.trim(&name, keep, dry_run)?;
+            garbage.versions.count += count;
+            garbage.versions.size += size;
+        }
+        Ok(garbage)
+    }
+
+    /// Returns the size in bytes of the parrot folder.
+    pub fn disk_usage(&self) -> Result<u64, Error> {
+        // The lock is not part of the snapshots
+        let lock = fs::metadata(self.path.join(LOCK_PATH)).map_or(0, |metadata| metadata.len());
+        Ok(dir_size(&self.path)? - lock)
+    }
+
+    /// Unloads the least recently used stored outputs once those in memory
+    /// exceed the configured cap, so that browsing a large suite does not hold
+    /// all of them. The snapshots in use are left as they are.
+    pub fn release_outputs(&self) -> Result<(), Error> {
+        let snaps = match self.snaps.as_ref() {
+            Some(snaps) => snaps,
+            None => return Ok(()),
+        };
+        let cap = self.get_config()?.memory_cap.unwrap_or(MEMORY_CAP);
+        let mut loaded = Vec::new();
+        for (idx, snap) in snaps.iter().enumerate() {
+            match snap.try_borrow() {
+                Ok(snap) if !snap.deleted => {
+                    for data in snap.data() {
+                        if let Some(used) = data.body.last_used() {
+                            loaded.push((used, data.body.size(), idx, data.path.clone()));
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        // The most recently used ones are kept
+        loaded.sort_by_key(|(used, ..)| std::cmp::Reverse(*used));
+        let mut total = 0;
+        for (_, size, idx, path) in loaded {
+            total += size;
+            if total <= cap {
+                continue;
+            }
+            if let Ok(mut snap) = snaps[idx].try_borrow_mut() {
+                if let Some(data) = snap.data_mut().into_iter().find(|data| data.path == path) {
+                    data.body.unload(self.reader(&path));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Persists the snapshot's stdout and stder bodies to the file system, and
+    /// stamps it as updated.
+    pub fn persist_snapshot_data(&self, snap: &mut Snapshot) -> Result<(), Error> {
+        self.check_size(snap)?;
+        snap.updated = Some(self.stamp());
         self.snap_manager.update(snap)?;
+        self.write_golden(snap)?;
         Ok(())
     }
 
+    /// Fails if the outputs of a snapshot stored in the project, those sent to
+    /// a remote aside, take more than the configured `max_snapshot_size`.
+    fn check_size(&self, snap: &Snapshot) -> Result<(), Error> {
+        let max = match self.get_config()?.max_snapshot_size {
+            Some(max) => max as u64,
+            None => return Ok(()),
+        };
+        let threshold = self.remote.as_ref().map(|remote| remote.threshold as u64);
+        let mut sizes: Vec<u64> = snap.data().iter().map(|data| data.body.size()).collect();
+        if snap.expected_stdout.is_some() {
+            sizes.extend(snap.stdout.iter().map(|data| data.body.size()));
+        }
+        let size: u64 = sizes.into_iter().filter(|size| threshold.is_none_or(|threshold| *size <= threshold)).sum();
+        if size <= max {
+            return Ok(());
+        }
+        Error::from_str(&format!(
+            "Snapshot {} is too large to be stored: its outputs take {}, more than the {} of max_snapshot_size. \
+             Redact or filter the outputs, or store the large ones out of the repository in a remote (set remote \
+             in .parrot/config.json).",
+            snap.name,
+            format_size(size),
+            format_size(max)
+        ))
+    }
+
+    /// Returns a stamp of a change made now, by the git user of the project or
+    /// else the user of the system.
+    pub fn stamp(&self) -> Stamp {
+        let by = self.author.get_or_init(|| {
+            let git = Command::new("git").args(["config", "user.name"]).current_dir(&self.root).output();
+            match git {
+                Ok(output) if output.status.success() && !output.stdout.trim_ascii().is_empty() => {
+                    Some(String::from_utf8_lossy(output.stdout.trim_ascii()).into_owned())
+                }
+                _ => std::env::var("USER").or_else(|_| std::env::var("USERNAME")).ok(),
+            }
+        });
+        Stamp {
+            at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            by: by.clone(),
+        }
+    }
+
+    /// Keeps the expectation of a snapshot replaced by an update, so that it
+    /// can be rolled back to.
+    pub fn keep_version(&self, name: &str, expectation: &Expectation) -> Result<(), Error> {
+        let size = self.get_config()?.keep_versions.unwrap_or(KEEP_VERSIONS);
+        self.history_manager.keep(name, expectation, size)
+    }
+
+    /// Returns the previous versions of the expectation of a snapshot, oldest
+    /// first.
+    pub fn versions(&mut self, name: &str) -> Result<Vec<Version>, Error> {
+        self.get_snapshot(name)?;
+        self.history_manager.versions(name)
+    }
+
+    /// Returns a previous version of the expectation of a snapshot.
+    pub fn version(&self, name: &str, number: u32) -> Result<Expectation, Error> {
+        self.history_manager.get(name, number)
+    }
+
+    /// Keeps the outputs of a failed snapshot as its pending expectation,
+    /// until it is accepted or rejected.
+    pub fn keep_pending(&self, name: &str, expectation: &Expectation) -> Result<(), Error> {
+        self.pending_manager.keep(name, expectation)
+    }
+
+    /// Returns the names of the snapshots with a pending expectation.
+    pub fn pending(&self) -> Result<Vec<String>, Error> {
+        self.pending_manager.names()
+    }
+
+    /// Returns the pending expectation of a snapshot.
+    pub fn get_pending(&self, name: &str) -> Result<Expectation, Error> {
+        self.pending_manager.get(name)
+    }
+
+    /// Discards the pending expectation of a snapshot, if any.
+    pub fn remove_pending(&self, name: &str) -> Result<(), Error> {
+        self.pending_manager.remove(name)
+    }
+
+    /// Reads the golden file holding the expected stdout of a snapshot, a
+    /// missing or empty file is no stdout.
+    pub fn read_golden(&self, name: &str, file: &str) -> Option<SnapshotData> {
+        let body = fs::read(self.root.join(file)).ok().filter(|body| !body.is_empty())?;
+        Some(SnapshotData {
+            path: format!("{}.out", name),
+            body: body.into(),
+        })
+    }
+
+    /// Writes the expected stdout of a snapshot to its golden file, if it has
+    /// one.
+    fn write_golden(&self, snap: &Snapshot) -> Result<(), Error> {
+        if let Some(file) = &snap.expected_stdout {
+            let path = self.root.join(file);
+            let error = format!("Could not write the golden file '{}'.", file);
+            if let Some(parent) = path.parent() {
+                wrap(fs::create_dir_all(parent), &error)?;
+            }
+            let body = snap.stdout.as_ref().map_or(&[][..], |data| &data.body);
+            wrap(write_atomic(&path, body), &error)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a reference to the snapshot with the given name.
+    pub fn get_snapshot(&mut self, name: &str) -> Result<Rc<RefCell<Snapshot>>, Error> {
+        for snap in self.get_snaps()? {
+            if snap.borrow().name == name {
+                return Ok(Rc::clone(snap));
+            }
+        }
+        Err(no_such_snapshot(name))
+    }
+
     /// Returns a vector of snapshot references.
     pub fn get_all_snapshots(&mut self) -> Result<Vec<Rc<RefCell<Snapshot>>>, Error> {
         let mut snaps = Vec::new();
@@ -136,6 +1565,13 @@ impl DataManager {
         Ok(snaps)
     }
 
+    /// Returns a vector of references to the snapshots that are not archived.
+    pub fn get_active_snapshots(&mut self) -> Result<Vec<Rc<RefCell<Snapshot>>>, Error> {
+        let mut snaps = self.get_all_snapshots()?;
+        snaps.retain(|snap| !snap.borrow().archived);
+        Ok(snaps)
+    }
+
     /// Lazyly loads snapshots.
     fn get_snaps(&mut self) -> Result<&mut Vec<Rc<RefCell<Snapshot>>>, Error> {
         if let Some(ref mut snaps) = self.snaps {
@@ -149,35 +1585,451 @@ impl DataManager {
     /// Loads all the snapshots from file system and cache them.
     /// `self.snaps` is Some after this function.
     fn load(&mut self) -> Result<(), Error> {
+        self.schema_version()?;
         let metadatas = self.metadata_manager.get_metadata()?;
+        let mut state = self.state_manager.get_state()?;
         let mut snaps = Vec::with_capacity(metadatas.snapshots.len());
+        let mut migrated = false;
         for snap in metadatas.snapshots {
-            let stdout = self.load_snapshot_body(snap.stdout)?;
-            let stderr = self.load_snapshot_body(snap.stderr)?;
+            let mut stdout = match &snap.expected_stdout {
+                Some(file) => self.read_golden(&snap.name, file),
+                None => self.load_snapshot_body(snap.stdout, snap.stdout_len, snap.stdout_hash),
+            };
+            let mut stderr = self.load_snapshot_body(snap.stderr, snap.stderr_len, snap.stderr_hash);
+            // Outputs recorded before the option was set may contain escape
+            // sequences, they are stripped once and for all
+            let mut stripped = false;
+            if snap.strip_ansi {
+                for data in stdout.iter_mut().chain(stderr.iter_mut()) {
+                    let body = strip_ansi(&data.body);
+                    if body != *data.body {
+                        stripped = true;
+                        data.body = body.into();
+                    }
+                }
+            }
+            let stdin = self.load_snapshot_body(snap.stdin, snap.stdin_len, None);
+            let mut artifacts = Vec::with_capacity(snap.artifacts.len());
+            for artifact in snap.artifacts {
+                artifacts.push(Artifact {
+                    file: artifact.file,
+                    data: self.load_snapshot_body(artifact.path, artifact.len, None),
+                });
+            }
+            let (status, last_run, history, cache_key) = match state.snapshots.remove(&snap.name) {
+                Some(snap_state) => (snap_state.status, snap_state.last_run, snap_state.history, snap_state.cache_key),
+                None => (SnapshotStatus::Waiting, None, Vec::new(), None),
+            };
             snaps.push(Rc::new(RefCell::new(Snapshot {
                 exit_code: snap.exit_code,
+                signal: snap.signal,
                 stderr,
                 stdout,
+                stdin,
+                artifacts,
+                expected_stdout: snap.expected_stdout,
                 cmd: snap.cmd,
                 name: snap.name,
                 description: snap.description,
                 tags: snap.tags,
-                status: SnapshotStatus::Waiting,
+                status,
+                last_run,
+                history,
+                env: snap.env,
+                timeout: snap.timeout,
+                max_duration_ms: snap.max_duration_ms,
+                pty: snap.pty,
+                combined: snap.combined,
+                runner: snap.runner,
+                hermetic: snap.hermetic,
+                before: snap.before,
+                after: snap.after,
+                retries: snap.retries,
+                needs: snap.needs,
+                serial: snap.serial,
+                inputs: snap.inputs,
+                strip_ansi: snap.strip_ansi,
+                filters: snap.filters,
+                redact: snap.redact,
+                pipeline: snap.pipeline,
+                normalize: snap.normalize,
+                exit_code_only: snap.exit_code_only,
+                ignore_stdout: snap.ignore_stdout,
+                ignore_stderr: snap.ignore_stderr,
+                binary: snap.binary,
+                compare: snap.compare,
+                tolerance: snap.tolerance,
+                contains: snap.contains,
+                exit_codes: snap.exit_codes.clone(),
+                assertions: snap.assertions,
+                comparator: snap.comparator.clone(),
+                xfail: snap.xfail,
+                cache_key,
+                archived: snap.archived,
+                created: Stamp::from_metadata(snap.created_at, snap.created_by),
+                updated: Stamp::from_metadata(snap.updated_at, snap.updated_by),
                 deleted: false,
-            })))
+            })));
+            if stripped {
+                self.snap_manager.update(&snaps[snaps.len() - 1].borrow())?;
+                migrated = true;
+            }
+        }
+        // Snapshots stored in another layout than the configured one are
+        // moved as soon as they are loaded
+        let (layout, deduplicate) = configured_layout(&self.config_manager);
+        if layout != self.layout || deduplicate != self.deduplicate {
+            self.move_to(layout, deduplicate, &snaps)?;
+        } else if migrated {
+            // Record the new sizes of the outputs
+            self.metadata_manager.persist(&snaps)?;
+        }
+        // And so are the outputs encrypted for other recipients than the
+        // configured ones
+        let recipients = self.config_manager.get_config().map(|config| config.recipients).unwrap_or_default();
+        if recipients != self.encryption.recipients {
+            self.encrypt_for(recipients, &snaps)?;
         }
         self.snaps = Some(snaps);
+        self.run_count = state.run_count;
         Ok(())
     }
 
-    /// Loads the body of a snapshot from an Option<body_path>.
-    fn load_snapshot_body(&self, path: Option<String>) -> Result<Option<SnapshotData>, Error> {
-        match path {
-            None => Ok(None),
-            Some(path) => Ok(Some(SnapshotData {
-                body: self.snap_manager.get(&path)?,
-                path: path,
-            })),
+    /// Moves the snapshots to another layout, the outputs are only rewritten
+    /// when they move to another folder.
+    fn move_to(&mut self, layout: Layout, deduplicate: bool, snaps: &Vec<Rc<RefCell<Snapshot>>>) -> Result<(), Error> {
+        let metadata_manager = metadata::MetadataManager::new(self.path.clone(), layout);
+        let remote = self.remote.clone();
+        let encryption = Rc::clone(&self.encryption);
+        let snap_manager = snapshots::SnapshotsManager::new(self.path.clone(), layout, deduplicate, remote, encryption);
+        let moves_outputs =
+            snapshots::outputs_folder(layout, deduplicate) != snapshots::outputs_folder(self.layout, self.deduplicate);
+        if moves_outputs {
+            snap_manager.create_empty()?;
+            for snap in snaps {
+                snap_manager.update(&snap.borrow())?;
+            }
+        }
+        metadata_manager.persist(snaps)?;
+        if layout != self.layout {
+            self.metadata_manager.remove_all()?;
+        }
+        if moves_outputs {
+            self.snap_manager.remove_all()?;
+        }
+        self.metadata_manager = metadata_manager;
+        self.snap_manager = Rc::new(snap_manager);
+        self.layout = layout;
+        self.deduplicate = deduplicate;
+        Ok(())
+    }
+
+    /// Rewrites the outputs encrypted for other recipients, or in clear if
+    /// there is none, along with the ones of the history, the pending
+    /// snapshots and the trash. The current recipients are recorded in the
+    /// parrot folder.
+    fn encrypt_for(&mut self, recipients: Vec<String>, snaps: &Vec<Rc<RefCell<Snapshot>>>) -> Result<(), Error> {
+        let key_file = self.get_config()?.key_file;
+        let encryption = Rc::new(encryption::Encryption::new(recipients, key_file.as_deref(), &self.root));
+        encryption.check()?;
+        let snap_manager = snapshots::SnapshotsManager::new(
+            self.path.clone(),
+            self.layout,
+            self.deduplicate,
+            self.remote.clone(),
+            Rc::clone(&encryption),
+        );
+        for snap in snaps {
+            snap.borrow().load()?;
+        }
+        // The stdouts move out of the snapshot files or back in them
+        self.snap_manager.remove_all()?;
+        snap_manager.create_empty()?;
+        for snap in snaps {
+            snap_manager.update(&snap.borrow())?;
+        }
+        self.history_manager.rewrite(&encryption)?;
+        self.pending_manager.rewrite(&encryption)?;
+        self.trash_manager.rewrite(&encryption)?;
+        // The divergent outputs are only kept for inspection
+        let divergent = self.path.join(DIVERGENT_PATH);
+        if divergent.is_dir() {
+            wrap(fs::remove_dir_all(divergent), "Failed to delete the divergent outputs.")?;
+        }
+        let path = self.path.join(RECIPIENTS_PATH);
+        if encryption.encrypts() {
+            let mut content = encryption.recipients.join("\n");
+            content.push('\n');
+            wrap(write_atomic(&path, content.as_bytes()), "Failed to write the recipients of the outputs.")?;
+        } else if path.is_file() {
+            wrap(fs::remove_file(path), "Failed to remove the recipients of the outputs.")?;
+        }
+        self.snap_manager = Rc::new(snap_manager);
+        self.history_manager = history::HistoryManager::new(self.path.join(HISTORY_PATH), Rc::clone(&encryption));
+        self.pending_manager = pending::PendingManager::new(self.path.join(PENDING_PATH), Rc::clone(&encryption));
+        self.trash_manager = trash::TrashManager::new(self.path.join(TRASH_PATH), Rc::clone(&encryption));
+        self.encryption = encryption;
+        Ok(())
+    }
+
+    /// Returns the data of a stored output from its path, its body is read
+    /// when first used. `len` and `hash` are its recorded size and checksum.
+    fn load_snapshot_body(&self, path: Option<String>, len: Option<u64>, hash: Option<String>) -> Option<SnapshotData> {
+        let path = path?;
+        Some(SnapshotData {
+            body: Body::stored(self.reader(&path), len, hash),
+            path,
+        })
+    }
+
+    /// Returns a reader of the stored output at a data path.
+    fn reader(&self, path: &str) -> Reader {
+        let snap_manager = Rc::clone(&self.snap_manager);
+        let file = path.to_owned();
+        Rc::new(move || snap_manager.get(&file))
+    }
+}
+
+/// Returns the closest directory holding a `.parrot` folder among `path` and
+/// its parents, like git finds its repository, or `path` itself if there is
+/// none.
+pub fn find_project(path: &Path) -> PathBuf {
+    if path.join(PARROT_PATH).is_dir() {
+        return path.to_owned();
+    }
+    let absolute = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    match absolute.ancestors().find(|dir| dir.join(PARROT_PATH).is_dir()) {
+        Some(dir) => dir.to_owned(),
+        None => path.to_owned(),
+    }
+}
+
+/// Returns the layout of the snapshots stored in a parrot folder, or None if
+/// there are none yet.
+fn stored_layout(path: &Path) -> Option<Layout> {
+    if path.join(METADATA_PATH).is_file() {
+        Some(Layout::Single)
+    } else if path.join(METADATA_FOLDER_PATH).is_dir() {
+        Some(Layout::PerSnapshotMetadata)
+    } else if path.join(SNAPS_PATH).is_dir() {
+        Some(Layout::Directories)
+    } else if path.join(FILES_PATH).is_dir() {
+        Some(Layout::Files)
+    } else {
+        None
+    }
+}
+
+/// Returns the layout set in the configuration, and whether the outputs are
+/// deduplicated.
+fn configured_layout(config_manager: &config::ConfigManager) -> (Layout, bool) {
+    // An invalid configuration is reported when it is read
+    config_manager
+        .get_config()
+        .map_or((Layout::Single, false), |config| (config.layout(), config.deduplicate))
+}
+
+/// Returns the recipients the outputs in the parrot folder at `path` are
+/// encrypted for, none if they are stored in clear.
+fn stored_recipients(path: &Path) -> Vec<String> {
+    fs::read_to_string(path.join(RECIPIENTS_PATH))
+        .map(|content| content.lines().map(str::to_owned).filter(|line| !line.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+/// Returns the remote set in the configuration, if any. Its downloads are
+/// cached in the parrot folder at `path`.
+fn configured_remote(
+    config_manager: &config::ConfigManager,
+    root: &Path,
+    path: &Path,
+) -> Option<Rc<remote::RemoteStore>> {
+    // An invalid configuration is reported when it is read
+    let config = config_manager.get_config().ok()?;
+    let threshold = config.remote_threshold.unwrap_or(REMOTE_THRESHOLD);
+    let store = remote::RemoteStore::new(&config.remote?, root.to_owned(), path.join(REMOTE_CACHE_PATH), threshold);
+    Some(Rc::new(store))
+}
+
+/// Returns the data path for a snapshot renamed to `name`, the extension of
+/// the current path is kept.
+fn renamed_path(path: &str, name: &str) -> String {
+    let extension = match path.rfind('.') {
+        Some(idx) => &path[idx..],
+        None => "",
+    };
+    format!("{}{}", name, extension)
+}
+
+/// Returns the total size in bytes of the files within a directory.
+fn dir_size(path: &Path) -> Result<u64, Error> {
+    let mut size = 0;
+    for entry in wrap(fs::read_dir(path), "Could not read the parrot folder.")? {
+        let entry = wrap(entry, "Could not read the parrot folder.")?;
+        let metadata = wrap(entry.metadata(), "Could not read file metadata.")?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// A conflicted file of the parrot folder, by its path within the folder, along
+/// with the version of the current branch and the version of the merged one.
+type ConflictedFile = (PathBuf, Vec<u8>, Vec<u8>);
+
+/// A snapshot that differs between the two sides of a merge, None on the side
+/// that does not have it.
+pub struct Conflict {
+    pub name: String,
+    pub ours: Option<Snapshot>,
+    pub theirs: Option<Snapshot>,
+}
+
+/// Whether two versions of a snapshot have the same metadata, which covers the
+/// checksums of their outputs.
+fn same_snapshot(a: &Snapshot, b: &Snapshot) -> bool {
+    let value = |snap| serde_json::to_value(metadata::Metadata::from(snap)).ok();
+    value(a) == value(b)
+}
+
+/// Returns the files of the parrot folder at `path` in which git left conflict
+/// markers, `relative` being the path within the folder it is at. The folders
+/// that stay out of version control, the suites and the configuration are not
+/// looked at.
+fn conflicted_files(path: &Path, relative: &Path) -> Result<Vec<ConflictedFile>, Error> {
+    let mut files = Vec::new();
+    for entry in wrap(fs::read_dir(path), "Could not read the parrot folder.")? {
+        let entry = wrap(entry, "Could not read the parrot folder.")?;
+        let name = entry.file_name();
+        let top = relative.as_os_str().is_empty();
+        if top && UNVERSIONED_PATHS.iter().chain(&[SUITES_PATH, CONFIG_PATH]).any(|skipped| name == *skipped) {
+            continue;
+        }
+        let relative = relative.join(&name);
+        if entry.path().is_dir() {
+            files.extend(conflicted_files(&entry.path(), &relative)?);
+        } else if let Some((ours, theirs)) = conflicts::split(&wrap(fs::read(entry.path()), "Could not read file.")?) {
+            files.push((relative, ours, theirs));
         }
     }
+    files.sort();
+    Ok(files)
+}
+
+/// Writes a file atomically: the content goes to a temporary file next to it,
+/// synced to disk, which then replaces it. A crash or an interruption thus
+/// leaves either the previous content or the new one, never a part of it.
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path)?;
+    // The rename is only durable once the folder holding the file is synced
+    if let Some(folder) = path.parent().and_then(|folder| fs::File::open(folder).ok()) {
+        let _ = folder.sync_all();
+    }
+    Ok(())
+}
+
+/// Copies a folder of the parrot folder recursively, but for the `skipped`
+/// entries at its top.
+fn copy_dir(from: &Path, to: &Path, skipped: &[&str]) -> Result<(), Error> {
+    let error = "Failed to copy the parrot folder.";
+    wrap(fs::create_dir_all(to), error)?;
+    for entry in wrap(fs::read_dir(from), error)? {
+        let entry = wrap(entry, error)?;
+        let name = entry.file_name();
+        if skipped.iter().any(|skipped| name == *skipped) {
+            continue;
+        }
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &to.join(&name), &[])?;
+        } else {
+            wrap(fs::copy(entry.path(), to.join(&name)), error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Builds the error returned when looking up an unknown snapshot.
+/// Returns whether a snapshot or suite name is a single file name that is not
+/// hidden.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\0'])
+}
+
+fn already_exists(name: &str) -> Error {
+    Error {
+        message: format!("A snapshot named '{}' already exists.", name),
+        cause: None,
+    }
+}
+
+fn no_such_snapshot(name: &str) -> Error {
+    Error {
+        message: format!("No snapshot named '{}'.", name),
+        cause: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        for name in &["build", "convert@json,utf8", "a.b", "_x1"] {
+            assert!(check_name(name).is_ok(), "{}", name);
+        }
+        for name in &["", ".hidden", "..", "../../escaped", "a/b", "nul\0byte"] {
+            assert!(check_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = std::env::temp_dir().join(format!("parrot-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("metadata.json");
+        write_atomic(&path, b"{}").unwrap();
+        write_atomic(&path, b"{ \"snapshots\": [] }").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{ \"snapshots\": [] }");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_body_unload() {
+        let reads = Rc::new(Cell::new(0));
+        let reader = |reads: &Rc<Cell<u32>>| -> Reader {
+            let reads = Rc::clone(reads);
+            Rc::new(move || {
+                reads.set(reads.get() + 1);
+                Ok(b"out\n".to_vec())
+            })
+        };
+        let mut body = Body::stored(reader(&reads), Some(4), None);
+        assert_eq!(body.last_used(), None);
+        assert_eq!(body.size(), 4);
+        assert_eq!(reads.get(), 0);
+        assert_eq!(*body, b"out\n");
+        let used = body.last_used().unwrap();
+        body.unload(reader(&reads));
+        assert_eq!((body.last_used(), body.size()), (None, 4));
+        assert_eq!(*body, b"out\n");
+        assert_eq!(reads.get(), 2);
+        assert!(body.last_used().unwrap() > used);
+        // A changed content is not stored anymore
+        body.push(b'!');
+        assert_eq!(body.last_used(), None);
+        body.unload(reader(&reads));
+        assert_eq!(*body, b"out\n!");
+    }
 }
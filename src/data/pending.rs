@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use super::encryption::Encryption;
+use super::{write_atomic, Expectation};
+use crate::error::{wrap, Error};
+
+const RESULT_PATH: &str = "result.json";
+const PENDING_EXTENSION: &str = ".new";
+
+/// What a failed command returned, its outputs are stored in files next to
+/// it.
+#[derive(Serialize, Deserialize)]
+struct Pending {
+    exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signal: Option<i32>,
+    stdout: bool,
+    stderr: bool,
+    /// The files the command is expected to write, along with whether it wrote
+    /// them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    artifacts: Vec<(String, bool)>,
+}
+
+/// Returns the names of the files holding the outputs of a pending snapshot,
+/// given its number of artifacts.
+fn files(artifacts: usize) -> Vec<String> {
+    let mut files = vec!["stdout".to_owned(), "stderr".to_owned()];
+    for idx in 0..artifacts {
+        files.push(format!("artifact-{}", idx));
+    }
+    files
+}
+
+/// Keeps the outputs of the failed snapshots until they are accepted or
+/// rejected, in a `<name>.new` folder per snapshot. Their outputs are
+/// encrypted as the stored ones are.
+pub struct PendingManager {
+    path: PathBuf,
+    encryption: Rc<Encryption>,
+}
+
+impl PendingManager {
+    /// Initialize a new PendingManager.
+    pub fn new(pending_path: PathBuf, encryption: Rc<Encryption>) -> PendingManager {
+        PendingManager {
+            path: pending_path,
+            encryption,
+        }
+    }
+
+    fn folder(&self, name: &str) -> PathBuf {
+        self.path.join(format!("{}{}", name, PENDING_EXTENSION))
+    }
+
+    /// Keeps an expectation as the pending one of a snapshot, replacing the
+    /// previous one if any.
+    pub fn keep(&self, name: &str, expectation: &Expectation) -> Result<(), Error> {
+        self.remove(name)?;
+        let path = self.folder(name);
+        let error = format!("Failed to write the pending outputs of {}.", name);
+        wrap(fs::create_dir_all(&path), &error)?;
+        let pending = Pending {
+            exit_code: expectation.exit_code,
+            signal: expectation.signal,
+            stdout: expectation.stdout.is_some(),
+            stderr: expectation.stderr.is_some(),
+            artifacts: expectation.artifacts.iter().map(|(file, body)| (file.clone(), body.is_some())).collect(),
+        };
+        let bodies = vec![&expectation.stdout, &expectation.stderr]
+            .into_iter()
+            .chain(expectation.artifacts.iter().map(|(_, body)| body));
+        for (file, body) in files(pending.artifacts.len()).iter().zip(bodies) {
+            if let Some(body) = body {
+                self.encryption.write(&path.join(file), body, &error)?;
+            }
+        }
+        let content = wrap(serde_json::to_vec_pretty(&pending), &error)?;
+        wrap(write_atomic(&path.join(RESULT_PATH), &content), &error)
+    }
+
+    /// Returns the pending expectation of a snapshot.
+    pub fn get(&self, name: &str) -> Result<Expectation, Error> {
+        let path = self.folder(name);
+        if !path.is_dir() {
+            return Error::from_str(&format!("No pending snapshot for '{}'.", name));
+        }
+        let error = format!("Could not read the pending outputs of {}.", name);
+        let file = wrap(fs::File::open(path.join(RESULT_PATH)), &error)?;
+        let pending: Pending = wrap(serde_json::from_reader(file), &error)?;
+        let files = files(pending.artifacts.len());
+        let read = |idx: usize, present: bool| {
+            if present {
+                self.encryption.read(&path.join(&files[idx]), name, &error).map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        let mut artifacts = Vec::with_capacity(pending.artifacts.len());
+        for (idx, (file, present)) in pending.artifacts.iter().enumerate() {
+            artifacts.push((file.clone(), read(idx + 2, *present)?));
+        }
+        Ok(Expectation {
+            exit_code: pending.exit_code,
+            signal: pending.signal,
+            stdout: read(0, pending.stdout)?,
+            stderr: read(1, pending.stderr)?,
+            artifacts,
+        })
+    }
+
+    /// Returns the names of the snapshots with pending outputs.
+    pub fn names(&self) -> Result<Vec<String>, Error> {
+        if !self.path.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut names = Vec::new();
+        for entry in wrap(fs::read_dir(&self.path), "Could not read the pending folder.")? {
+            let entry = wrap(entry, "Could not read the pending folder.")?;
+            if let Some(name) = entry.file_name().to_string_lossy().strip_suffix(PENDING_EXTENSION) {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Rewrites the pending outputs for the recipients of another encryption.
+    pub fn rewrite(&self, encryption: &Encryption) -> Result<(), Error> {
+        for name in self.names()? {
+            let path = self.folder(&name);
+            let error = format!("Could not read the pending outputs of {}.", name);
+            let file = wrap(fs::File::open(path.join(RESULT_PATH)), &error)?;
+            let pending: Pending = wrap(serde_json::from_reader(file), &error)?;
+            for file in files(pending.artifacts.len()) {
+                self.encryption.rewrite(&path.join(file), &name, encryption)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the pending outputs of a renamed snapshot.
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        let path = self.folder(name);
+        if !path.exists() {
+            return Ok(());
+        }
+        let error = format!("Failed to move the pending outputs of {}.", name);
+        wrap(fs::rename(path, self.folder(new_name)), &error)
+    }
+
+    /// Deletes the pending outputs of a snapshot.
+    pub fn remove(&self, name: &str) -> Result<(), Error> {
+        let path = self.folder(name);
+        if !path.exists() {
+            return Ok(());
+        }
+        wrap(fs::remove_dir_all(path), &format!("Failed to delete the pending outputs of {}.", name))
+    }
+}
@@ -0,0 +1,139 @@
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+use super::write_atomic;
+use crate::error::{wrap, Error};
+
+/// Environment variable holding the token sent to HTTP remotes.
+const TOKEN_VARIABLE: &str = "PARROT_REMOTE_TOKEN";
+
+/// Where large outputs are stored out of the repository, by the hash of
+/// their content.
+pub trait Remote {
+    fn put(&self, hash: &str, body: &[u8]) -> Result<(), Error>;
+    fn get(&self, hash: &str) -> Result<Vec<u8>, Error>;
+}
+
+/// A remote in a directory, a shared drive for instance.
+struct DirectoryRemote {
+    path: PathBuf,
+}
+
+impl Remote for DirectoryRemote {
+    fn put(&self, hash: &str, body: &[u8]) -> Result<(), Error> {
+        let error = format!("Failed to upload {} to the remote.", hash);
+        wrap(fs::create_dir_all(&self.path), &error)?;
+        wrap(write_atomic(&self.path.join(hash), body), &error)
+    }
+
+    fn get(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        wrap(fs::read(self.path.join(hash)), &format!("Failed to download {} from the remote.", hash))
+    }
+}
+
+/// A remote served over HTTP: outputs are uploaded with `PUT <url>/<hash>`
+/// and downloaded with `GET <url>/<hash>`, which S3 compatible stores
+/// support. The token in `PARROT_REMOTE_TOKEN`, if any, is sent as a bearer
+/// token.
+struct HttpRemote {
+    url: String,
+    token: Option<String>,
+}
+
+impl HttpRemote {
+    fn request(&self, method: &str, hash: &str) -> ureq::Request {
+        let request = ureq::request(method, &format!("{}/{}", self.url, hash));
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+}
+
+impl Remote for HttpRemote {
+    fn put(&self, hash: &str, body: &[u8]) -> Result<(), Error> {
+        let error = format!("Failed to upload {} to the remote.", hash);
+        wrap(self.request("PUT", hash).send_bytes(body), &error)?;
+        Ok(())
+    }
+
+    fn get(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        let error = format!("Failed to download {} from the remote.", hash);
+        let response = wrap(self.request("GET", hash).call(), &error)?;
+        let mut body = Vec::new();
+        wrap(response.into_reader().read_to_end(&mut body), &error)?;
+        Ok(body)
+    }
+}
+
+/// Stores the outputs larger than a threshold in a remote, the downloaded
+/// ones are cached.
+pub struct RemoteStore {
+    remote: Box<dyn Remote>,
+    cache: PathBuf,
+    /// Outputs larger than this many bytes are stored in the remote.
+    pub threshold: usize,
+}
+
+impl RemoteStore {
+    /// Initialize a new RemoteStore for the remote at `url`, an HTTP URL or a
+    /// directory, relative to `root` if it is a relative path.
+    pub fn new(url: &str, root: PathBuf, cache: PathBuf, threshold: usize) -> RemoteStore {
+        let remote: Box<dyn Remote> = if url.starts_with("http://") || url.starts_with("https://") {
+            Box::new(HttpRemote {
+                url: url.trim_end_matches('/').to_owned(),
+                token: std::env::var(TOKEN_VARIABLE).ok(),
+            })
+        } else {
+            let path = url.strip_prefix("file://").unwrap_or(url);
+            Box::new(DirectoryRemote { path: root.join(path) })
+        };
+        RemoteStore {
+            remote,
+            cache,
+            threshold,
+        }
+    }
+
+    /// Uploads a body and returns its hash, it is kept in the cache.
+    pub fn store(&self, body: &[u8]) -> Result<String, Error> {
+        let hash = address(body);
+        if self.cached(&hash).is_none() {
+            self.remote.put(&hash, body)?;
+            self.cache(&hash, body)?;
+        }
+        Ok(hash)
+    }
+
+    /// Returns the body of a hash, from the cache or else from the remote.
+    pub fn fetch(&self, hash: &str) -> Result<Vec<u8>, Error> {
+        if let Some(body) = self.cached(hash) {
+            return Ok(body);
+        }
+        let body = self.remote.get(hash)?;
+        if address(&body) != hash {
+            return Error::from_str(&format!("The remote holds a corrupted output for {}.", hash));
+        }
+        self.cache(hash, &body)?;
+        Ok(body)
+    }
+
+    /// Returns the cached body of a hash, unless it is missing or corrupted.
+    fn cached(&self, hash: &str) -> Option<Vec<u8>> {
+        fs::read(self.cache.join(hash)).ok().filter(|body| address(body) == hash)
+    }
+
+    /// Keeps a body in the cache.
+    fn cache(&self, hash: &str, body: &[u8]) -> Result<(), Error> {
+        wrap(fs::create_dir_all(&self.cache), "Failed to create the remote cache.")?;
+        wrap(write_atomic(&self.cache.join(hash), body), "Failed to write the remote cache.")
+    }
+}
+
+/// Returns the hash a body is stored under in the remote, the SHA-256 of its
+/// content, so that a body can't be mistaken for another.
+fn address(body: &[u8]) -> String {
+    Sha256::digest(body).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,121 +1,555 @@
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::io::prelude::{Read, Write};
-use std::path::PathBuf;
+use std::io::prelude::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use super::{Snapshot, SnapshotData};
+use super::metadata::{join_front_matter, split_front_matter};
+use super::{
+    checksum, write_atomic, Layout, SnapshotData, BLOBS_INDEX_PATH, BLOBS_PATH, FILES_PATH, META_FILE, SNAPSHOT_PATH,
+    SNAPS_PATH, SNAP_FILE_EXTENSION,
+};
+use super::encryption::{encrypted, Encryption, ENCRYPTED_EXTENSION};
+use super::remote::RemoteStore;
+use super::store::SnapshotStore;
 use crate::error::{wrap, Error};
 
-const FILE_EXTENSION: &'static str = ".txt";
+const FILE_EXTENSION: &str = ".txt";
+/// Appended to the files of the bodies stored compressed.
+const COMPRESSED_EXTENSION: &str = ".zst";
+/// Bodies larger than this many bytes are compressed with zstd.
+const COMPRESSION_THRESHOLD: usize = 1 << 20;
+/// The zstd compression level, the default one.
+const COMPRESSION_LEVEL: i32 = 3;
+/// Appended to the files pointing to a body stored in the remote.
+const REMOTE_EXTENSION: &str = ".remote";
+
+/// The file names of the outputs in the directory of a snapshot, by the
+/// extension of their data path.
+const DIRECTORY_FILES: [(&str, &str); 3] = [("out", "stdout"), ("err", "stderr"), ("in", "stdin")];
+
+/// The data paths of the snapshot files, mapped to the hash of the blob
+/// holding their body. The number of paths mapped to a blob is its reference
+/// count.
+type BlobIndex = BTreeMap<String, String>;
 
 pub struct SnapshotsManager {
     path: PathBuf,
+    /// Whether the files of each snapshot are in a directory of their own.
+    directories: bool,
+    /// Whether stdout is stored in the file of its snapshot, after the front
+    /// matter holding its metadata.
+    front_matter: bool,
+    /// Whether the metadata is stored in the outputs folder, in the
+    /// directories or the snapshot files.
+    holds_metadata: bool,
+    /// The index of the blobs, when the bodies are stored by the hash of their
+    /// content so that identical bodies are stored once.
+    index: Option<PathBuf>,
+    /// Where the large bodies are stored, only a pointer to them is kept in
+    /// their files.
+    remote: Option<Rc<RemoteStore>>,
+    encryption: Rc<Encryption>,
 }
 
 impl SnapshotsManager {
-    /// Initialize a new SnapshotsManager.
-    pub fn new(snapshots_path: PathBuf) -> SnapshotsManager {
+    /// Initialize a new SnapshotsManager, storing the files in the given
+    /// layout, or as blobs if `deduplicate` is set. Encrypted stdouts are not
+    /// stored after the front matter of the snapshot files.
+    pub fn new(
+        parrot_path: PathBuf,
+        layout: Layout,
+        deduplicate: bool,
+        remote: Option<Rc<RemoteStore>>,
+        encryption: Rc<Encryption>,
+    ) -> SnapshotsManager {
         SnapshotsManager {
-            path: snapshots_path,
+            path: parrot_path.join(outputs_folder(layout, deduplicate)),
+            directories: layout == Layout::Directories && !deduplicate,
+            front_matter: layout == Layout::Files && !deduplicate && !encryption.encrypts(),
+            holds_metadata: (layout == Layout::Directories || layout == Layout::Files) && !deduplicate,
+            index: Some(parrot_path.join(BLOBS_INDEX_PATH)).filter(|_| deduplicate),
+            remote,
+            encryption,
         }
     }
 
+    /// Returns the snapshot file holding a stdout after its front matter, if
+    /// the data path is one.
+    fn snap_file(&self, path: &str) -> Option<PathBuf> {
+        let name = path.strip_suffix(".out").filter(|_| self.front_matter)?;
+        Some(self.path.join(format!("{}{}", name, SNAP_FILE_EXTENSION)))
+    }
+
+    /// Reads the stdout of a snapshot file, empty if there is none.
+    fn read_snap_file(&self, file: &Path) -> Result<Vec<u8>, Error> {
+        if !file.exists() {
+            return Ok(Vec::new());
+        }
+        let error = format!("Could not open snapshot {}.", file.display());
+        let content = wrap(fs::read(file), &error)?;
+        match split_front_matter(&content) {
+            Some((_, body)) => Ok(body.to_owned()),
+            None => Error::from_str(&format!("Snapshot {} does not start with a front matter.", file.display())),
+        }
+    }
+
+    /// Writes the stdout of a snapshot file, keeping its front matter. A file
+    /// left without either is removed.
+    fn write_snap_file(&self, file: &Path, body: &[u8]) -> Result<(), Error> {
+        let content = fs::read(file).unwrap_or_default();
+        let front_matter = split_front_matter(&content).map_or("", |(front_matter, _)| front_matter);
+        if front_matter.is_empty() && body.is_empty() {
+            return self.delete_snap_file(file);
+        }
+        let content = join_front_matter(front_matter, body);
+        wrap(write_atomic(file, &content), "Failed to create a snapshot file")
+    }
+
+    fn delete_snap_file(&self, file: &Path) -> Result<(), Error> {
+        if file.exists() {
+            wrap(fs::remove_file(file), "Failed to delete snapshot data")?;
+        }
+        Ok(())
+    }
+
+    /// Reads the index of the blobs.
+    fn read_index(&self, index: &Path) -> Result<BlobIndex, Error> {
+        let file = wrap(fs::File::open(index), "Could not open blobs.json.")?;
+        wrap(serde_json::from_reader(file), "Failed to parse blobs.json.")
+    }
+
+    fn write_index(&self, blobs: &BlobIndex) -> Result<(), Error> {
+        if let Some(index) = &self.index {
+            let content = wrap(serde_json::to_vec_pretty(blobs), "Failed to write blobs.json.")?;
+            wrap(write_atomic(index, &content), "Failed to write blobs.json.")?;
+        }
+        Ok(())
+    }
+
+    /// Stores a body as a blob named after the hash of its content, unless
+    /// there already is one, and returns its hash.
+    fn store_blob(&self, body: &[u8]) -> Result<String, Error> {
+        let hash = checksum(body);
+        let path = self.path.join(&hash);
+        // A blob stored in clear is encrypted once outputs are
+        if !forms(&path).iter().any(|file| file.exists()) || encrypted(&path).is_file() != self.encryption.encrypts() {
+            self.write_body(&path, body, "Failed to create a blob")?;
+        } else if self.read_body(&path, &hash)? != body {
+            return Error::from_str(&format!("Two different outputs have the same hash: {}.", hash));
+        }
+        Ok(hash)
+    }
+
+    /// Returns the location of the file of a data path. In the directory
+    /// layout, `name.out` is stored as `name/stdout`.
+    fn file(&self, path: &str) -> PathBuf {
+        if !self.directories {
+            let mut path = path.to_owned();
+            path.push_str(FILE_EXTENSION);
+            return self.path.join(path);
+        }
+        match path.rfind('.') {
+            Some(idx) => {
+                let extension = &path[idx + 1..];
+                let file = DIRECTORY_FILES
+                    .iter()
+                    .find(|(ext, _)| *ext == extension)
+                    .map_or(extension, |(_, file)| file);
+                self.path.join(&path[..idx]).join(file)
+            }
+            None => self.path.join(path).join(path),
+        }
+    }
+
+    /// Returns the path of the body of a snapshot file, the one of its blob
+    /// when deduplicated.
+    fn body_path(&self, name: &str) -> Option<PathBuf> {
+        match &self.index {
+            Some(index) => Some(self.path.join(self.read_index(index).ok()?.get(name)?)),
+            None => Some(self.file(name)),
+        }
+    }
+
+    /// Reads the body of a snapshot file, decompressing it, decrypting it or
+    /// fetching it from the remote if needed.
+    fn read_body(&self, path: &Path, name: &str) -> Result<Vec<u8>, Error> {
+        if encrypted(path).is_file() {
+            let error = format!("Could not open snapshot {}.", name);
+            return self.encryption.decrypt(&wrap(fs::read(encrypted(path)), &error)?, name);
+        }
+        if !pointer(path).is_file() {
+            return read_body(path, name);
+        }
+        let (hash, _, is_encrypted) = read_pointer(&pointer(path))?;
+        let body = match &self.remote {
+            Some(remote) => remote.fetch(&hash)?,
+            None => {
+                return Error::from_str(&format!(
+                    "Snapshot {} is stored in a remote, set \"remote\" in config.json to fetch it.",
+                    name
+                ))
+            }
+        };
+        if is_encrypted {
+            return self.encryption.decrypt(&body, name);
+        }
+        Ok(body)
+    }
+
+    /// Writes a body to its file, encrypted if there are recipients, else
+    /// compressed if it is large, or to the remote if it is larger than its
+    /// threshold. The files of the other forms are removed, so that a body is
+    /// never stored twice.
+    fn write_body(&self, path: &Path, body: &[u8], error: &str) -> Result<(), Error> {
+        let remote = self.remote.as_ref().filter(|remote| body.len() > remote.threshold);
+        let encrypts = self.encryption.encrypts();
+        let [plain, compressed, pointer, encrypted] = forms(path);
+        let (path, stale) = if remote.is_some() {
+            (pointer, [plain, compressed, encrypted])
+        } else if encrypts {
+            (encrypted, [plain, compressed, pointer])
+        } else if body.len() > COMPRESSION_THRESHOLD {
+            (compressed, [plain, pointer, encrypted])
+        } else {
+            (plain, [compressed, pointer, encrypted])
+        };
+        let content = if let Some(remote) = remote {
+            // The remote only ever holds the encrypted outputs
+            let (hash, encrypted) = if encrypts {
+                (remote.store(&self.encryption.encrypt(body)?)?, "encrypted\n")
+            } else {
+                (remote.store(body)?, "")
+            };
+            format!("hash {}\nsize {}\n{}", hash, body.len(), encrypted).into_bytes()
+        } else if encrypts {
+            self.encryption.encrypt(body)?
+        } else if body.len() > COMPRESSION_THRESHOLD {
+            wrap(zstd::encode_all(body, COMPRESSION_LEVEL), "Failed to compress the snapshot")?
+        } else {
+            body.to_vec()
+        };
+        wrap(write_atomic(&path, &content), error)?;
+        for stale in stale.iter().filter(|stale| stale.is_file()) {
+            wrap(fs::remove_file(stale), "Failed to delete snapshot data")?;
+        }
+        Ok(())
+    }
+
+    /// Creates the directory of a snapshot, in the directory layout.
+    fn create_parent(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent().filter(|_| self.directories) {
+            wrap(fs::create_dir_all(parent), "Failed to create the directory of a snapshot")?;
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotStore for SnapshotsManager {
     /// Create an empty snapshot folder.
-    pub fn create_empty(&self) -> Result<(), Error> {
+    fn create_empty(&self) -> Result<(), Error> {
+        // The metadata may already live in the folder of the directories
         wrap(
-            fs::create_dir(&self.path),
+            fs::create_dir_all(&self.path),
             "Failed to create a snapshots folder.",
         )?;
+        if self.index.is_some() {
+            self.write_index(&BlobIndex::new())?;
+        }
         Ok(())
     }
 
-    /// Create a new snapshot file, abort if the file already exists.
-    pub fn create(&self, snap: &Snapshot) -> Result<(), Error> {
-        if let Some(stdout) = &snap.stdout {
-            self.write_snapshot(stdout)?;
+    /// Deletes the blobs no snapshot file refers to anymore, unless `dry_run`
+    /// is set. Returns their names along with their sizes on disk.
+    fn gc(&self, dry_run: bool) -> Result<Vec<(String, u64)>, Error> {
+        let index = match &self.index {
+            Some(index) => self.read_index(index)?,
+            None => return Ok(Vec::new()),
+        };
+        let referenced: HashSet<&String> = index.values().collect();
+        let mut unreferenced = Vec::new();
+        for entry in wrap(fs::read_dir(&self.path), "Could not read the blobs folder.")? {
+            let entry = wrap(entry, "Could not read the blobs folder.")?;
+            let file = entry.file_name().to_string_lossy().into_owned();
+            let hash = base_name(&file).to_owned();
+            if !referenced.contains(&hash) {
+                let size = entry.metadata().map_or(0, |metadata| metadata.len());
+                if !dry_run {
+                    wrap(fs::remove_file(entry.path()), "Failed to delete a blob")?;
+                }
+                unreferenced.push((hash, size));
+            }
         }
-        if let Some(stderr) = &snap.stderr {
-            self.write_snapshot(stderr)?;
-        }
-        Ok(())
+        unreferenced.sort();
+        Ok(unreferenced)
     }
 
-    /// Update a snapshot, will truncate any already existing file.
-    pub fn update(&self, snap: &Snapshot) -> Result<(), Error> {
-        if let Some(stdout) = &snap.stdout {
-            self.update_snapshot(stdout)?;
+    /// Move a single snapshot file, abort if the destination already exists.
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        if let Some(index) = &self.index {
+            let mut blobs = self.read_index(index)?;
+            if blobs.contains_key(to) {
+                return Error::from_str("A snapshot with that name already exists");
+            }
+            match blobs.remove(from) {
+                Some(hash) => blobs.insert(to.to_owned(), hash),
+                None => return Error::from_str("Failed to move snapshot data"),
+            };
+            return self.write_index(&blobs);
+        }
+        if let (Some(from), Some(to)) = (self.snap_file(from), self.snap_file(to)) {
+            if !self.read_snap_file(&to)?.is_empty() {
+                return Error::from_str("A snapshot with that name already exists");
+            }
+            self.write_snap_file(&to, &self.read_snap_file(&from)?)?;
+            return self.write_snap_file(&from, &[]);
         }
-        if let Some(stderr) = &snap.stderr {
-            self.update_snapshot(stderr)?;
+        let (from, to) = (self.file(from), self.file(to));
+        if forms(&to).iter().any(|file| file.exists()) {
+            return Error::from_str("A snapshot with that name already exists");
+        }
+        self.create_parent(&to)?;
+        for (from, to) in forms(&from).iter().zip(forms(&to).iter()).filter(|(from, _)| from.exists()) {
+            wrap(fs::rename(from, to), "Failed to move snapshot data")?;
         }
         Ok(())
     }
 
-    /// Delete a snapshot's datas.
-    pub fn delete(&self, snap: &Snapshot) -> Result<(), Error> {
-        if let Some(stdout) = &snap.stdout {
-            self.delete_snapshot(stdout)?;
+    /// Returns the size of the body of a snapshot file, or None if it does not
+    /// exist.
+    fn file_size(&self, name: &str) -> Option<u64> {
+        if let Some(file) = self.snap_file(name) {
+            return self.read_snap_file(&file).ok().filter(|body| !body.is_empty()).map(|body| body.len() as u64);
         }
-        if let Some(stderr) = &snap.stderr {
-            self.delete_snapshot(stderr)?;
+        let path = self.body_path(name)?;
+        // The size recorded in the metadata is the one of the body
+        if pointer(&path).is_file() {
+            return read_pointer(&pointer(&path)).ok().map(|(_, size, _)| size);
+        }
+        if compressed(&path).is_file() || encrypted(&path).is_file() {
+            return self.read_body(&path, name).ok().map(|body| body.len() as u64);
+        }
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => Some(metadata.len()),
+            _ => None,
         }
-        Ok(())
     }
 
-    /// Read a snapshot from file.
-    pub fn get(&self, name: &str) -> Result<Vec<u8>, Error> {
-        let mut snap = Vec::new();
-        let mut name = name.to_owned();
-        name.push_str(FILE_EXTENSION);
-        let path = self.path.join(&name);
-        let mut file = wrap(
-            fs::File::open(path),
-            &format!("Could not open snapshot {}.", name),
-        )?;
-        wrap(
-            file.read_to_end(&mut snap),
-            &format!("Failed to open snapshot {}.", name),
-        )?;
-        Ok(snap)
+    /// Whether the body of a snapshot file is encrypted and there is no key to
+    /// decrypt it with, so that its size cannot be checked.
+    fn is_locked(&self, name: &str) -> bool {
+        !self.encryption.has_key() && self.body_path(name).is_some_and(|path| encrypted(&path).is_file())
+    }
+
+    /// Read a snapshot from file, decompressing it if needed.
+    fn get(&self, name: &str) -> Result<Vec<u8>, Error> {
+        if let Some(file) = self.snap_file(name) {
+            return self.read_snap_file(&file);
+        }
+        if let Some(index) = &self.index {
+            return match self.read_index(index)?.get(name) {
+                Some(hash) => self.read_body(&self.path.join(hash), hash),
+                None => Error::from_str(&format!("Could not open snapshot {}.", name)),
+            };
+        }
+        let path = self.file(name);
+        let name = path.strip_prefix(&self.path).unwrap_or(&path).to_string_lossy().into_owned();
+        self.read_body(&path, &name)
     }
 
     /// Update a single snapshot, will not rise any errors if the snapshot already
     /// exists.
     fn update_snapshot(&self, snap: &SnapshotData) -> Result<(), Error> {
-        let mut path = snap.path.to_owned();
-        path.push_str(FILE_EXTENSION);
-        let path = self.path.join(path);
-        let mut file = wrap(fs::File::create(path), "Failed to create a snapshot file")?;
-        wrap(
-            file.write_all(&snap.body),
-            "Faile to write down the snapshot",
-        )?;
-        Ok(())
+        if let Some(index) = &self.index {
+            let mut blobs = self.read_index(index)?;
+            blobs.insert(snap.path.clone(), self.store_blob(&snap.body)?);
+            return self.write_index(&blobs);
+        }
+        if let Some(file) = self.snap_file(&snap.path) {
+            return self.write_snap_file(&file, &snap.body);
+        }
+        let path = self.file(&snap.path);
+        self.create_parent(&path)?;
+        self.write_body(&path, &snap.body, "Failed to create a snapshot file")
     }
 
     /// Write a single snapshot.
     fn write_snapshot(&self, snap: &SnapshotData) -> Result<(), Error> {
-        let mut path = snap.path.to_owned();
-        path.push_str(FILE_EXTENSION);
-        let path = self.path.join(path);
-        if path.exists() {
+        if let Some(index) = &self.index {
+            let mut blobs = self.read_index(index)?;
+            if blobs.contains_key(&snap.path) {
+                return Error::from_str("A snapshot with that name already exists");
+            }
+            blobs.insert(snap.path.clone(), self.store_blob(&snap.body)?);
+            return self.write_index(&blobs);
+        }
+        if let Some(file) = self.snap_file(&snap.path) {
+            if !self.read_snap_file(&file)?.is_empty() {
+                return Error::from_str("A snapshot with that name already exists");
+            }
+            return self.write_snap_file(&file, &snap.body);
+        }
+        let path = self.file(&snap.path);
+        if forms(&path).iter().any(|file| file.exists()) {
             return Error::from_str("A snapshot with that name already exists");
         }
-        let mut file = wrap(fs::File::create(path), "Failed to create a snapshot file, try using `parrot init` first.")?;
-        wrap(
-            file.write_all(&snap.body),
-            "Faile to write down the snapshot",
-        )?;
-        Ok(())
+        self.create_parent(&path)?;
+        self.write_body(&path, &snap.body, "Failed to create a snapshot file, try using `parrot init` first.")
     }
 
-    /// Delete a single snapshot.
-    fn delete_snapshot(&self, snap: &SnapshotData) -> Result<(), Error> {
-        let mut path = snap.path.to_owned();
-        path.push_str(FILE_EXTENSION);
-        let path = self.path.join(path);
-        if path.exists() && path.is_file() {
-            wrap(fs::remove_file(path), "Failed to delete snapshot data")?;
+    /// List the names of all the snapshot files.
+    fn list(&self) -> Result<Vec<String>, Error> {
+        if let Some(index) = &self.index {
+            return Ok(self.read_index(index)?.into_keys().collect());
+        }
+        let mut names = Vec::new();
+        for entry in wrap(fs::read_dir(&self.path), "Could not read the snapshots folder.")? {
+            let entry = wrap(entry, "Could not read the snapshots folder.")?;
+            if self.directories {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.path().is_dir() {
+                    for file in wrap(fs::read_dir(entry.path()), "Could not read the snapshots folder.")? {
+                        let file = wrap(file, "Could not read the snapshots folder.")?.file_name();
+                        let file = file.to_string_lossy();
+                        let file = base_name(&file);
+                        if file == META_FILE {
+                            continue;
+                        }
+                        let extension = DIRECTORY_FILES
+                            .iter()
+                            .find(|(_, name)| *name == file)
+                            .map_or(file, |(ext, _)| ext);
+                        names.push(format!("{}.{}", name, extension));
+                    }
+                }
+            } else if let Some(name) = entry.file_name().to_str() {
+                if let Some(name) = base_name(name).strip_suffix(FILE_EXTENSION) {
+                    names.push(name.to_owned());
+                } else if let Some(name) = name.strip_suffix(SNAP_FILE_EXTENSION).filter(|_| self.front_matter) {
+                    if !self.read_snap_file(&entry.path())?.is_empty() {
+                        names.push(format!("{}.out", name));
+                    }
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Delete a single snapshot file.
+    fn delete_file(&self, name: &str) -> Result<(), Error> {
+        if let Some(index) = &self.index {
+            // The blob is left for `parrot gc`, other files may refer to it
+            let mut blobs = self.read_index(index)?;
+            blobs.remove(name);
+            return self.write_index(&blobs);
+        }
+        if let Some(file) = self.snap_file(name) {
+            // The front matter holds the metadata, removed along with it
+            return self.write_snap_file(&file, &[]);
+        }
+        let path = self.file(name);
+        for file in forms(&path) {
+            if file.exists() && file.is_file() {
+                wrap(fs::remove_file(&file), "Failed to delete snapshot data")?;
+                if let Some(parent) = file.parent().filter(|_| self.directories) {
+                    // The directory is kept as long as the metadata is in it
+                    let _ = fs::remove_dir(parent);
+                }
+            }
         }
         Ok(())
     }
+
+    /// Delete all the snapshot files, once moved to another layout.
+    fn remove_all(&self) -> Result<(), Error> {
+        if self.holds_metadata {
+            // The directories or the snapshot files may still hold the
+            // metadata, or have been removed along with it
+            if !self.path.is_dir() {
+                return Ok(());
+            }
+            for file in self.list()? {
+                self.delete_file(&file)?;
+            }
+            let _ = fs::remove_dir(&self.path);
+            return Ok(());
+        }
+        if let Some(index) = &self.index {
+            wrap(fs::remove_file(index), "Failed to remove blobs.json.")?;
+        }
+        wrap(fs::remove_dir_all(&self.path), "Failed to remove the snapshots folder.")
+    }
+}
+
+/// Reads the body of a snapshot file, decompressing it if needed.
+fn read_body(path: &Path, name: &str) -> Result<Vec<u8>, Error> {
+    let mut snap = Vec::new();
+    let is_compressed = compressed(path).exists();
+    let path = if is_compressed { compressed(path) } else { path.to_owned() };
+    let mut file = wrap(
+        fs::File::open(&path),
+        &format!("Could not open snapshot {}.", name),
+    )?;
+    wrap(
+        file.read_to_end(&mut snap),
+        &format!("Failed to open snapshot {}.", name),
+    )?;
+    if is_compressed {
+        snap = wrap(zstd::decode_all(&snap[..]), &format!("Failed to decompress snapshot {}.", name))?;
+    }
+    Ok(snap)
+}
+
+/// Returns the folder of the parrot folder the outputs are stored in, in a
+/// layout.
+pub fn outputs_folder(layout: Layout, deduplicate: bool) -> &'static str {
+    match layout {
+        _ if deduplicate => BLOBS_PATH,
+        Layout::Directories => SNAPS_PATH,
+        Layout::Files => FILES_PATH,
+        _ => SNAPSHOT_PATH,
+    }
+}
+
+/// Returns the hash and the size of the body a remote pointer refers to, and
+/// whether it is stored encrypted.
+fn read_pointer(path: &Path) -> Result<(String, u64, bool), Error> {
+    let error = format!("Invalid remote pointer: {}.", path.display());
+    let content = wrap(fs::read_to_string(path), &error)?;
+    let field = |name: &str| {
+        content.lines().find_map(|line| line.strip_prefix(name)).map(|value| value.trim().to_owned())
+    };
+    match (field("hash "), field("size ").and_then(|size| size.parse().ok())) {
+        (Some(hash), Some(size)) => Ok((hash, size, content.lines().any(|line| line == "encrypted"))),
+        _ => Error::from_str(&error),
+    }
+}
+
+/// Returns the paths of a snapshot file in every form it can be stored in:
+/// plain, compressed, pointing to the remote and encrypted.
+fn forms(path: &Path) -> [PathBuf; 4] {
+    [path.to_owned(), compressed(path), pointer(path), encrypted(path)]
+}
+
+/// Returns the name of a file without the extension of its form.
+fn base_name(file: &str) -> &str {
+    [COMPRESSED_EXTENSION, REMOTE_EXTENSION, ENCRYPTED_EXTENSION]
+        .iter()
+        .find_map(|extension| file.strip_suffix(extension))
+        .unwrap_or(file)
+}
+
+/// Returns the path of the remote pointer form of a snapshot file.
+fn pointer(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(REMOTE_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Returns the path of the compressed form of a snapshot file.
+fn compressed(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(COMPRESSED_EXTENSION);
+    PathBuf::from(path)
 }
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use super::{write_atomic, RunRecord, Snapshot, SnapshotStatus};
+use crate::error::{wrap, Error};
+
+/// The state of the last runs. It is kept apart from the metadata as it
+/// changes on every run and is not meant to be versioned.
+#[derive(Serialize, Deserialize, Default)]
+pub struct State {
+    pub run_count: u64,
+    pub snapshots: BTreeMap<String, SnapshotState>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotState {
+    pub status: SnapshotStatus,
+    pub last_run: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<RunRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_key: Option<String>,
+}
+
+pub struct StateManager {
+    path: PathBuf,
+}
+
+impl StateManager {
+    /// Initialize a new StateManager.
+    pub fn new(state_path: PathBuf) -> StateManager {
+        StateManager { path: state_path }
+    }
+
+    /// Reads and return the state from file system, an empty state is returned
+    /// if there is none yet.
+    pub fn get_state(&self) -> Result<State, Error> {
+        if !self.path.exists() {
+            return Ok(State::default());
+        }
+        let file = wrap(fs::File::open(&self.path), "Could not open state.json.")?;
+        let state = wrap(serde_json::from_reader(file), "Failed to parse state.json.")?;
+        Ok(state)
+    }
+
+    /// Persists the state to the file system from the list of snapshots.
+    /// Borrows an immutable reference to the snapshots.
+    pub fn persist(&self, run_count: u64, snaps: &[Rc<RefCell<Snapshot>>]) -> Result<(), Error> {
+        let mut snapshots = BTreeMap::new();
+        for snap in snaps {
+            let snap = snap.borrow();
+            if snap.deleted {
+                continue;
+            }
+            snapshots.insert(
+                snap.name.clone(),
+                SnapshotState {
+                    status: snap.status,
+                    last_run: snap.last_run,
+                    history: snap.history.clone(),
+                    cache_key: snap.cache_key.clone(),
+                },
+            );
+        }
+        let content = wrap(serde_json::to_vec_pretty(&State { run_count, snapshots }), "Failed to write state.json.")?;
+        wrap(write_atomic(&self.path, &content), "Failed to write state.json.")
+    }
+}
@@ -0,0 +1,197 @@
+use super::{Snapshot, SnapshotData};
+use crate::error::Error;
+
+/// Where the outputs of the snapshots are stored, by their data path, such as
+/// `name.out`. The metadata is stored separately, so that a store only holds
+/// bodies: the `SnapshotsManager` keeps them in the parrot folder, in the
+/// configured layout and possibly in a remote.
+pub trait SnapshotStore {
+    /// Prepares an empty store.
+    fn create_empty(&self) -> Result<(), Error>;
+
+    /// Stores a new output, fails if there already is one at its path.
+    fn write_snapshot(&self, snap: &SnapshotData) -> Result<(), Error>;
+
+    /// Stores an output, replacing the one at its path if any.
+    fn update_snapshot(&self, snap: &SnapshotData) -> Result<(), Error>;
+
+    /// Returns the body of an output.
+    fn get(&self, name: &str) -> Result<Vec<u8>, Error>;
+
+    /// Moves an output, fails if there already is one at the destination.
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error>;
+
+    /// Deletes an output, if there is one.
+    fn delete_file(&self, name: &str) -> Result<(), Error>;
+
+    /// Returns the size of an output, or None if there is none.
+    fn file_size(&self, name: &str) -> Option<u64>;
+
+    /// Whether an output cannot be read for lack of a key, so that its size
+    /// cannot be checked.
+    fn is_locked(&self, _name: &str) -> bool {
+        false
+    }
+
+    /// Lists the paths of all the outputs.
+    fn list(&self) -> Result<Vec<String>, Error>;
+
+    /// Deletes all the outputs, once moved to another store.
+    fn remove_all(&self) -> Result<(), Error>;
+
+    /// Deletes what no output refers to anymore, unless `dry_run` is set.
+    /// Returns the names of what is deleted along with their sizes.
+    fn gc(&self, _dry_run: bool) -> Result<Vec<(String, u64)>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Stores the outputs of a new snapshot.
+    fn create(&self, snap: &Snapshot) -> Result<(), Error> {
+        for data in snap.data() {
+            self.write_snapshot(data)?;
+        }
+        Ok(())
+    }
+
+    /// Stores the outputs of a snapshot, replacing the previous ones.
+    fn update(&self, snap: &Snapshot) -> Result<(), Error> {
+        for data in snap.data() {
+            self.update_snapshot(data)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the outputs of a snapshot.
+    fn delete(&self, snap: &Snapshot) -> Result<(), Error> {
+        for data in snap.data() {
+            self.delete_file(&data.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the outputs in memory, to test what relies on a store without
+/// touching the disk.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore {
+    bodies: std::cell::RefCell<std::collections::BTreeMap<String, Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl SnapshotStore for MemoryStore {
+    fn create_empty(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn write_snapshot(&self, snap: &SnapshotData) -> Result<(), Error> {
+        if self.bodies.borrow().contains_key(&snap.path) {
+            return Error::from_str("A snapshot with that name already exists");
+        }
+        self.update_snapshot(snap)
+    }
+
+    fn update_snapshot(&self, snap: &SnapshotData) -> Result<(), Error> {
+        self.bodies.borrow_mut().insert(snap.path.clone(), snap.body.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Result<Vec<u8>, Error> {
+        match self.bodies.borrow().get(name) {
+            Some(body) => Ok(body.clone()),
+            None => Error::from_str(&format!("Could not open snapshot {}.", name)),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        let mut bodies = self.bodies.borrow_mut();
+        if bodies.contains_key(to) {
+            return Error::from_str("A snapshot with that name already exists");
+        }
+        match bodies.remove(from) {
+            Some(body) => bodies.insert(to.to_owned(), body),
+            None => return Error::from_str("Failed to move snapshot data"),
+        };
+        Ok(())
+    }
+
+    fn delete_file(&self, name: &str) -> Result<(), Error> {
+        self.bodies.borrow_mut().remove(name);
+        Ok(())
+    }
+
+    fn file_size(&self, name: &str) -> Option<u64> {
+        self.bodies.borrow().get(name).map(|body| body.len() as u64)
+    }
+
+    fn list(&self) -> Result<Vec<String>, Error> {
+        Ok(self.bodies.borrow().keys().cloned().collect())
+    }
+
+    fn remove_all(&self) -> Result<(), Error> {
+        self.bodies.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::encryption::Encryption;
+    use crate::data::snapshots::SnapshotsManager;
+    use crate::data::Layout;
+    use std::fs;
+    use std::rc::Rc;
+
+    fn data(path: &str, body: &str) -> SnapshotData {
+        SnapshotData {
+            path: path.to_owned(),
+            body: body.as_bytes().to_vec().into(),
+        }
+    }
+
+    /// Checks the behavior every store shares.
+    fn check_store(store: &dyn SnapshotStore) {
+        store.create_empty().unwrap();
+        store.write_snapshot(&data("a.out", "a\n")).unwrap();
+        store.write_snapshot(&data("a.err", "error\n")).unwrap();
+        store.write_snapshot(&data("b.err", "error\n")).unwrap();
+        assert!(store.write_snapshot(&data("a.out", "b\n")).is_err());
+        store.update_snapshot(&data("a.out", "b\n")).unwrap();
+        assert_eq!(store.get("a.out").unwrap(), b"b\n");
+        assert_eq!(store.file_size("a.err"), Some(6));
+        assert_eq!(store.file_size("c.out"), None);
+
+        store.rename("a.out", "c.out").unwrap();
+        assert!(store.rename("a.err", "b.err").is_err());
+        assert_eq!(store.list().unwrap(), ["a.err", "b.err", "c.out"]);
+        store.delete_file("a.err").unwrap();
+        assert_eq!(store.list().unwrap(), ["b.err", "c.out"]);
+        assert!(!store.is_locked("c.out"));
+        store.remove_all().unwrap();
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::default();
+        check_store(&store);
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshots_manager() {
+        let layouts = [
+            (Layout::Single, false),
+            (Layout::Directories, false),
+            (Layout::Files, false),
+            (Layout::Single, true),
+        ];
+        for (idx, (layout, deduplicate)) in layouts.iter().enumerate() {
+            let dir = std::env::temp_dir().join(format!("parrot-store-{}-{}", std::process::id(), idx));
+            fs::create_dir_all(&dir).unwrap();
+            let encryption = Rc::new(Encryption::new(Vec::new(), None, &dir));
+            check_store(&SnapshotsManager::new(dir.clone(), *layout, *deduplicate, None, encryption));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::encryption::Encryption;
+use super::metadata::Metadata;
+use super::write_atomic;
+use crate::error::{wrap, Error};
+
+const METADATA_PATH: &str = "metadata.json";
+
+/// The outputs of a snapshot, by their data paths.
+type Outputs = Vec<(String, Vec<u8>)>;
+
+/// A removed snapshot, kept in the trash.
+pub struct Trashed {
+    pub name: String,
+    /// When it was removed, in seconds since the Unix epoch.
+    pub removed_at: u64,
+    folder: PathBuf,
+}
+
+/// Keeps the removed snapshots for a while, in a `<name>.<removed at>` folder
+/// per removal, numbered if it already exists, holding their metadata and their outputs, named after their
+/// data paths. The outputs are encrypted as the stored ones are.
+pub struct TrashManager {
+    path: PathBuf,
+    encryption: Rc<Encryption>,
+}
+
+impl TrashManager {
+    /// Initialize a new TrashManager.
+    pub fn new(trash_path: PathBuf, encryption: Rc<Encryption>) -> TrashManager {
+        TrashManager {
+            path: trash_path,
+            encryption,
+        }
+    }
+
+    /// Moves a removed snapshot to the trash, along with its outputs.
+    pub fn put(&self, metadata: &Metadata, outputs: &[(String, Vec<u8>)]) -> Result<(), Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let mut path = self.path.join(format!("{}.{}", metadata.name, now));
+        // A snapshot removed twice in the same second is numbered
+        let mut count = 1;
+        while path.exists() {
+            path = self.path.join(format!("{}.{}-{}", metadata.name, now, count));
+            count += 1;
+        }
+        let error = format!("Failed to move {} to the trash.", metadata.name);
+        wrap(fs::create_dir_all(&path), &error)?;
+        for (file, body) in outputs {
+            self.encryption.write(&path.join(file), body, &error)?;
+        }
+        let content = wrap(serde_json::to_vec_pretty(metadata), &error)?;
+        wrap(write_atomic(&path.join(METADATA_PATH), &content), &error)
+    }
+
+    /// Returns the snapshots in the trash, the most recently removed last.
+    pub fn list(&self) -> Result<Vec<Trashed>, Error> {
+        if !self.path.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut trashed = Vec::new();
+        for entry in wrap(fs::read_dir(&self.path), "Could not read the trash.")? {
+            let entry = wrap(entry, "Could not read the trash.")?;
+            let file = entry.file_name().to_string_lossy().into_owned();
+            if let Some((name, removed_at)) = file.rsplit_once('.') {
+                if let Ok(removed_at) = removed_at.split('-').next().unwrap_or_default().parse() {
+                    trashed.push(Trashed {
+                        name: name.to_owned(),
+                        removed_at,
+                        folder: entry.path(),
+                    });
+                }
+            }
+        }
+        trashed.sort_by(|a, b| (a.removed_at, &a.folder).cmp(&(b.removed_at, &b.folder)));
+        Ok(trashed)
+    }
+
+    /// Returns the most recently removed snapshot of a name, along with its
+    /// metadata and its outputs.
+    pub fn get(&self, name: &str) -> Result<(Trashed, Metadata, Outputs), Error> {
+        let trashed = match self.list()?.into_iter().rev().find(|trashed| trashed.name == name) {
+            Some(trashed) => trashed,
+            None => return Error::from_str(&format!("No snapshot '{}' in the trash.", name)),
+        };
+        let error = format!("Could not read {} in the trash.", name);
+        let file = wrap(fs::File::open(trashed.folder.join(METADATA_PATH)), &error)?;
+        let metadata: Metadata = wrap(serde_json::from_reader(file), &error)?;
+        let mut outputs = Vec::new();
+        for (path, _) in metadata.data_paths() {
+            outputs.push((path.clone(), self.encryption.read(&trashed.folder.join(path), name, &error)?));
+        }
+        Ok((trashed, metadata, outputs))
+    }
+
+    /// Rewrites the outputs in the trash for the recipients of another
+    /// encryption.
+    pub fn rewrite(&self, encryption: &Encryption) -> Result<(), Error> {
+        for trashed in self.list()? {
+            let error = format!("Could not read {} in the trash.", trashed.name);
+            let file = wrap(fs::File::open(trashed.folder.join(METADATA_PATH)), &error)?;
+            let metadata: Metadata = wrap(serde_json::from_reader(file), &error)?;
+            for (path, _) in metadata.data_paths() {
+                self.encryption.rewrite(&trashed.folder.join(path), &trashed.name, encryption)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes a snapshot from the trash, once restored.
+    pub fn remove(&self, trashed: &Trashed) -> Result<(), Error> {
+        let error = format!("Failed to take {} out of the trash.", trashed.name);
+        wrap(fs::remove_dir_all(&trashed.folder), &error)
+    }
+
+    /// Deletes the snapshots removed more than `retention` seconds ago, or all
+    /// of them if it is not set. Returns how many were deleted.
+    pub fn purge(&self, retention: Option<u64>) -> Result<usize, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let mut count = 0;
+        for trashed in self.list()? {
+            if retention.is_none_or(|retention| now.saturating_sub(trashed.removed_at) > retention) {
+                wrap(fs::remove_dir_all(&trashed.folder), "Failed to empty the trash.")?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
@@ -100,6 +100,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::byte_char_slices, clippy::useless_vec)]
     fn test_diff() {
         // Examples taken from https://blog.jcoglan.com/2017/02/12/the-myers-diff-algorithm-part-1/
         let old = vec![
@@ -1,12 +1,726 @@
-use std::process::{Command, Output};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
 use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::error::{wrap, Error};
 
-/// Execute a command from a string.
-pub fn execute<P: AsRef<Path>>(cmd: &str, dir: P) -> Result<Output, Error> {
+/// Size of the pseudo-terminals, in columns and rows.
+const PTY_SIZE: (u16, u16) = (80, 24);
+
+/// Pseudo-terminals names are read with `ptsname`, which is not thread safe.
+static PTY_LOCK: Mutex<()> = Mutex::new(());
+
+/// `PATH` of the commands run in a scrubbed environment, unless passed through.
+const HERMETIC_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Appended to outputs exceeding their size limit.
+const TRUNCATION_MARKER: &[u8] = b"\n[output truncated by parrot]\n";
+
+/// The result of a command execution.
+pub struct Execution {
+    pub output: Output,
+    /// Whether the command was killed for exceeding its timeout.
+    pub timed_out: bool,
+    /// Whether the command was killed for exceeding the output size limit,
+    /// its output is then truncated.
+    pub output_exceeded: bool,
+    /// Wall-clock duration of the command, `before` and `after` excluded.
+    pub duration: Duration,
+    /// Content of the job's artifacts, `None` for the files the command did
+    /// not write.
+    pub artifacts: Vec<Option<Vec<u8>>>,
+}
+
+/// A command to execute, along with its input, environment and timeout.
+pub struct Job {
+    pub cmd: String,
+    pub stdin: Option<Vec<u8>>,
+    pub env: BTreeMap<String, String>,
+    pub timeout: Option<Duration>,
+    /// Run the command in a pseudo-terminal, stdout and stderr are then merged.
+    pub pty: bool,
+    /// Merge stderr into stdout, keeping the order in which they are written.
+    pub combined: bool,
+    /// Image of the container the command runs in, prefixed by its runtime,
+    /// such as `docker:alpine:3.19` or `podman:alpine:3.19`. The runtime is
+    /// `docker` without prefix.
+    pub runner: Option<String>,
+    /// Jobs of the same serial group never run in parallel.
+    pub serial: Option<String>,
+    /// How many times the job can be executed again when it fails, its serial
+    /// group is held meanwhile.
+    pub retries: u32,
+    /// Run the command in a scrubbed environment, only the listed variables
+    /// are passed through (along with a default `PATH`).
+    pub hermetic: Option<Vec<String>>,
+    /// Maximum size of stdout and stderr, in bytes.
+    pub max_output: usize,
+    /// Commands run before and after the main one, their output is ignored.
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Files written by the command, read once it exits.
+    pub artifacts: Vec<String>,
+    /// The artifacts the snapshot recorded, removed before the command runs.
+    pub recorded_artifacts: Vec<String>,
+}
+
+/// Execute a job's command, its input is fed to the process if any and its
+/// variables are added to the environment. If the command is still running
+/// after the timeout, its whole process group is killed. It is killed as well if
+/// it writes more than the size limit on either stdout or stderr.
+/// A failure of the `before` or `after` commands is an error, whose message holds
+/// their outputs.
+/// The recorded artifacts are removed beforehand, so that the files left by a
+/// previous run are not mistaken for the ones of this run, and the artifacts
+/// are read before the `after` command.
+pub fn execute<P: AsRef<Path>>(job: &Job, dir: P) -> Result<Execution, Error> {
+    for artifact in &job.recorded_artifacts {
+        let path = dir.as_ref().join(artifact);
+        if path.is_file() {
+            wrap(fs::remove_file(path), &format!("Could not remove the artifact '{}'.", artifact))?;
+        }
+    }
+    if let Some(before) = &job.before {
+        execute_hook(job, before, "before", &dir)?;
+    }
+    let start = Instant::now();
+    let execution = execute_command(job, &dir).map(|execution| Execution {
+        duration: start.elapsed(),
+        artifacts: job.artifacts.iter().map(|artifact| fs::read(dir.as_ref().join(artifact)).ok()).collect(),
+        ..execution
+    });
+    if let Some(after) = &job.after {
+        execute_hook(job, after, "after", &dir)?;
+    }
+    execution
+}
+
+/// Executes a hook of the whole suite. Its outputs are forwarded to stderr
+/// rather than captured, so that the processes it starts in the background
+/// (a server for instance) don't keep us waiting.
+pub fn execute_suite_hook<P: AsRef<Path>>(cmd: &str, kind: &str, dir: P) -> Result<(), Error> {
+    let mut process = shell(cmd);
+    process.current_dir(dir).stdin(Stdio::null()).stdout(io::stderr());
+    let status = wrap(process.status(), "Could not run command")?;
+    if !status.success() {
+        return Error::from_str(&format!("The {} command '{}' failed.", kind, cmd));
+    }
+    Ok(())
+}
+
+/// The verdict of a comparator command on an output.
+pub struct Verdict {
+    pub same: bool,
+    /// Why the outputs differ, the stdout of the comparator.
+    pub explanation: Vec<u8>,
+}
+
+/// Runs a comparator command with the paths of files holding the expected and
+/// actual outputs as arguments, and `PARROT_OUTPUT` set to the name of the
+/// output. The paths are positional parameters of the shell, so that they are
+/// never parsed as part of the command. It succeeds if the outputs are the same, and explains why they
+/// differ on its stdout otherwise.
+pub fn execute_comparator<P: AsRef<Path>>(
+    cmd: &str,
+    output: &str,
+    expected: &[u8],
+    actual: &[u8],
+    dir: P,
+) -> Result<Verdict, Error> {
+    // The directory is private to us, and removed once dropped
+    let tmp = tempfile::Builder::new().prefix("parrot-compare-").tempdir();
+    let tmp = wrap(tmp, "Could not create a temporary directory")?;
+    let expected_path = tmp.path().join(format!("expected.{}", output));
+    let actual_path = tmp.path().join(format!("actual.{}", output));
+    let written = fs::write(&expected_path, expected).and_then(|_| fs::write(&actual_path, actual));
+    wrap(written, "Could not write the outputs to compare")?;
+    let mut process = shell(&format!("{} \"$1\" \"$2\"", cmd));
+    process.arg("sh").arg(&expected_path).arg(&actual_path);
+    process.current_dir(dir).env("PARROT_OUTPUT", output).stdin(Stdio::null()).stderr(Stdio::inherit());
+    let result = wrap(process.output(), &format!("Could not run the comparator '{}'", cmd))?;
+    Ok(Verdict {
+        same: result.status.success(),
+        explanation: result.stdout,
+    })
+}
+
+/// Returns a process running a command line through `sh`.
+fn shell(cmd: &str) -> Command {
     let mut process = Command::new("sh");
-    process.arg("-c").arg(cmd).current_dir(dir);
-    let output = wrap(process.output(), "Could not run command")?;
-    Ok(output)
+    process.arg("-c").arg(cmd);
+    process
+}
+
+/// Executes a `before` or `after` command, with the environment and limits of
+/// its job.
+fn execute_hook<P: AsRef<Path>>(job: &Job, cmd: &str, kind: &str, dir: P) -> Result<(), Error> {
+    let hook = Job {
+        cmd: cmd.to_owned(),
+        stdin: None,
+        env: job.env.clone(),
+        timeout: job.timeout,
+        pty: false,
+        combined: false,
+        runner: job.runner.clone(),
+        serial: None,
+        retries: 0,
+        hermetic: job.hermetic.clone(),
+        max_output: job.max_output,
+        before: None,
+        after: None,
+        artifacts: Vec::new(),
+        recorded_artifacts: Vec::new(),
+    };
+    let execution = execute_command(&hook, dir)?;
+    let failure = if execution.timed_out {
+        "timed out"
+    } else if execution.output_exceeded {
+        "exceeded the output size limit"
+    } else if !execution.output.status.success() {
+        "failed"
+    } else {
+        return Ok(());
+    };
+    let mut message = format!("The {} command '{}' {}.", kind, cmd, failure);
+    for output in [&execution.output.stdout, &execution.output.stderr].iter() {
+        let output = String::from_utf8_lossy(output);
+        if !output.trim().is_empty() {
+            message.push('\n');
+            message.push_str(output.trim_end());
+        }
+    }
+    Error::from_str(&message)
+}
+
+/// A container a job's command runs in, named so that it can be killed: the
+/// process we run is only a client of its runtime.
+struct Container {
+    runtime: String,
+    image: String,
+    name: String,
+}
+
+impl Container {
+    /// Initialize a new Container for a runner, the image prefixed by its
+    /// runtime, `docker` if there is none.
+    fn new(runner: &str) -> Container {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let (runtime, image) = match runner.split_once(':') {
+            Some((runtime, image)) if runtime == "docker" || runtime == "podman" => (runtime, image),
+            _ => ("docker", runner),
+        };
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        Container {
+            runtime: runtime.to_owned(),
+            image: image.to_owned(),
+            name: format!("parrot-{}-{}", std::process::id(), count),
+        }
+    }
+
+    /// Kills the container, once its client was killed.
+    fn kill(&self) {
+        let mut process = Command::new(&self.runtime);
+        process.args(["kill", &self.name]).stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        // The container may have exited already
+        let _ = process.status();
+    }
+}
+
+/// Returns a process running a job's command in a new container. The
+/// directory is mounted at the same path, so that outputs don't depend on the
+/// runner, and the environment of the container is always scrubbed: variables
+/// passed through are read from ours.
+fn container(container: &Container, job: &Job, dir: &Path) -> Result<Command, Error> {
+    let dir = wrap(dir.canonicalize(), "Could not find the directory of the snapshots")?;
+    let mut process = Command::new(&container.runtime);
+    process.args(["run", "--rm", "--init", "-i", "--name", &container.name]);
+    if job.pty {
+        process.arg("-t");
+    }
+    process.arg("-v").arg(format!("{0}:{0}", dir.display())).arg("-w").arg(&dir);
+    for var in job.hermetic.iter().flatten() {
+        process.arg("-e").arg(var);
+    }
+    for (var, value) in &job.env {
+        process.arg("-e").arg(format!("{}={}", var, value));
+    }
+    process.arg(&container.image).args(["sh", "-c", &job.cmd]);
+    Ok(process)
+}
+
+/// Executes a job's command, the container it runs in is killed along with it
+/// if it exceeds its timeout or the output size limit.
+fn execute_command<P: AsRef<Path>>(job: &Job, dir: P) -> Result<Execution, Error> {
+    let runner = job.runner.as_deref().map(Container::new);
+    let mut process = match &runner {
+        Some(runner) => container(runner, job, dir.as_ref())?,
+        None => shell(&job.cmd),
+    };
+    process.current_dir(dir);
+    if job.runner.is_none() {
+        if let Some(allowed) = &job.hermetic {
+            process.env_clear().env("PATH", HERMETIC_PATH);
+            for var in allowed {
+                if let Some(value) = std::env::var_os(var) {
+                    process.env(var, value);
+                }
+            }
+        }
+        process.envs(&job.env);
+    }
+    let execution = if job.pty { execute_pty(process, job)? } else { execute_piped(process, job)? };
+    if execution.timed_out || execution.output_exceeded {
+        if let Some(runner) = &runner {
+            runner.kill();
+        }
+    }
+    Ok(execution)
+}
+
+/// Executes a command with its outputs read from pipes.
+fn execute_piped(mut process: Command, job: &Job) -> Result<Execution, Error> {
+    // A single pipe keeps the order in which stdout and stderr are written
+    let merged = if job.combined {
+        let (reader, writer) = wrap(open_pipe(), "Could not create a pipe")?;
+        process.stdout(wrap(writer.try_clone(), "Could not create a pipe")?).stderr(writer);
+        Some(reader)
+    } else {
+        process.stdout(Stdio::piped()).stderr(Stdio::piped());
+        None
+    };
+    if job.stdin.is_some() {
+        process.stdin(Stdio::piped());
+    } else {
+        process.stdin(Stdio::null());
+    }
+    // Run in a new process group, so that children can be killed too
+    process.process_group(0);
+    let mut child = wrap(process.spawn(), "Could not run command")?;
+    let _group = RunningGroup::register(&child);
+    drop(process); // Close our side of the merged pipe, so that reading ends with the command
+    // Stdin is written from another thread, otherwise the process could block
+    // on a full stdout while we block on a full stdin.
+    let writer = match (child.stdin.take(), &job.stdin) {
+        (Some(child_stdin), Some(input)) => Some(write_input(child_stdin, input)),
+        _ => None,
+    };
+    let watchdog = job.timeout.map(|timeout| Watchdog::new(&child, timeout));
+    let target = -(child.id() as libc::pid_t);
+    let stdout = match merged {
+        Some(reader) => Some(read_output(reader, job.max_output, target)),
+        None => child.stdout.take().map(|stdout| read_output(stdout, job.max_output, target)),
+    };
+    let stderr = child.stderr.take().map(|stderr| read_output(stderr, job.max_output, target));
+    let status = wrap(child.wait(), "Could not run command")?;
+    let (stdout, stdout_exceeded) = join_output(stdout);
+    let (stderr, stderr_exceeded) = join_output(stderr);
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let timed_out = watchdog.is_some_and(Watchdog::stop);
+    Ok(Execution {
+        output: Output { status, stdout, stderr },
+        timed_out,
+        output_exceeded: stdout_exceeded || stderr_exceeded,
+        duration: Duration::default(),
+        artifacts: Vec::new(),
+    })
+}
+
+/// Executes a command in a new session, attached to a pseudo-terminal.
+fn execute_pty(mut process: Command, job: &Job) -> Result<Execution, Error> {
+    let error = "Could not open a pseudo-terminal";
+    let (master, slave) = wrap(open_pty(), error)?;
+    process
+        .stdin(wrap(slave.try_clone(), error)?)
+        .stdout(wrap(slave.try_clone(), error)?)
+        .stderr(slave);
+    unsafe {
+        process.pre_exec(|| {
+            // The new session is also a new process group, as for other commands
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = wrap(process.spawn(), "Could not run command")?;
+    let _group = RunningGroup::register(&child);
+    drop(process); // Close our side of the slave, so that reading ends with the command
+    let writer = match &job.stdin {
+        Some(input) => Some(write_input(wrap(master.try_clone(), error)?, input)),
+        None => None,
+    };
+    let watchdog = job.timeout.map(|timeout| Watchdog::new(&child, timeout));
+    // Reading fails once no process has the terminal open anymore
+    let reader = read_output(master, job.max_output, -(child.id() as libc::pid_t));
+    let status = wrap(child.wait(), "Could not run command")?;
+    let (stdout, output_exceeded) = join_output(Some(reader));
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    let timed_out = watchdog.is_some_and(Watchdog::stop);
+    let output = Output {
+        status,
+        stdout,
+        stderr: Vec::new(),
+    };
+    Ok(Execution {
+        output,
+        timed_out,
+        output_exceeded,
+        duration: Duration::default(),
+        artifacts: Vec::new(),
+    })
+}
+
+/// Opens a pipe, returns its reading and writing ends, both closed on exec.
+fn open_pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let (reader, writer) = (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1]));
+        for fd in &fds {
+            if libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok((reader, writer))
+    }
+}
+
+/// Opens a pseudo-terminal of size `PTY_SIZE`, returns its master and slave
+/// sides.
+fn open_pty() -> io::Result<(File, File)> {
+    let _lock = PTY_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if master == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(master);
+        if libc::grantpt(master.as_raw_fd()) == -1 || libc::unlockpt(master.as_raw_fd()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = libc::ptsname(master.as_raw_fd());
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = libc::open(name, libc::O_RDWR | libc::O_NOCTTY);
+        if slave == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = File::from_raw_fd(slave);
+        let size = libc::winsize {
+            ws_col: PTY_SIZE.0,
+            ws_row: PTY_SIZE.1,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if libc::ioctl(slave.as_raw_fd(), libc::TIOCSWINSZ, &size) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((master, slave))
+    }
+}
+
+/// Writes the input of a process from another thread.
+fn write_input<W: Write + Send + 'static>(mut writer: W, input: &[u8]) -> thread::JoinHandle<()> {
+    let input = input.to_owned();
+    thread::spawn(move || {
+        // The process may exit without reading its whole input
+        let _ = writer.write_all(&input);
+    })
+}
+
+/// Reads an output from another thread until the end of the stream or an
+/// error, at most `limit` bytes are kept. Once the limit is exceeded `target`
+/// is killed and the output truncated; the stream is closed as well, so that
+/// children of `target` get a broken pipe.
+fn read_output<R: Read + Send + 'static>(
+    mut reader: R,
+    limit: usize,
+    target: libc::pid_t,
+) -> thread::JoinHandle<(Vec<u8>, bool)> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut buffer = [0; 8192];
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let room = limit - output.len();
+            if n > room {
+                output.extend_from_slice(&buffer[..room]);
+                output.extend_from_slice(TRUNCATION_MARKER);
+                unsafe { libc::kill(target, libc::SIGKILL) };
+                return (output, true);
+            }
+            output.extend_from_slice(&buffer[..n]);
+        }
+        (output, false)
+    })
+}
+
+/// Waits for an output read by `read_output`.
+fn join_output(reader: Option<thread::JoinHandle<(Vec<u8>, bool)>>) -> (Vec<u8>, bool) {
+    reader.and_then(|reader| reader.join().ok()).unwrap_or_default()
+}
+
+/// Kills the process group of a child once its timeout is exceeded.
+struct Watchdog {
+    done: mpsc::Sender<()>,
+    thread: thread::JoinHandle<bool>,
+}
+
+impl Watchdog {
+    fn new(child: &Child, timeout: Duration) -> Watchdog {
+        let pgid = child.id() as libc::pid_t;
+        let (done, wait) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            if wait.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout) {
+                unsafe { libc::kill(-pgid, libc::SIGKILL) };
+                true
+            } else {
+                false
+            }
+        });
+        Watchdog { done, thread }
+    }
+
+    /// Stops the watchdog, returns true if the process group was killed.
+    fn stop(self) -> bool {
+        drop(self.done); // Wakes the watchdog up
+        self.thread.join().unwrap_or(false)
+    }
+}
+
+/// Maximum number of jobs run in parallel, a job runs a single process group
+/// at a time.
+pub const MAX_JOBS: usize = 64;
+
+/// Process groups of the running commands, killed along with us if we are
+/// interrupted. Empty slots are zeros, they are atomics so that the signal
+/// handler can read them.
+static RUNNING_GROUPS: [AtomicI32; MAX_JOBS] = [const { AtomicI32::new(0) }; MAX_JOBS];
+
+/// Installs the interrupt handler once.
+static INTERRUPT_HANDLER: Once = Once::new();
+
+/// The process group of a running command, registered for as long as it
+/// lives.
+struct RunningGroup(Option<usize>);
+
+impl RunningGroup {
+    /// Registers the process group of a child, if there is a free slot.
+    fn register(child: &Child) -> RunningGroup {
+        INTERRUPT_HANDLER.call_once(|| unsafe {
+            libc::signal(libc::SIGINT, on_interrupt as *const () as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, on_interrupt as *const () as libc::sighandler_t);
+        });
+        let pgid = child.id() as i32;
+        let slot = RUNNING_GROUPS
+            .iter()
+            .position(|slot| slot.compare_exchange(0, pgid, Ordering::SeqCst, Ordering::SeqCst).is_ok());
+        RunningGroup(slot)
+    }
+}
+
+impl Drop for RunningGroup {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0 {
+            RUNNING_GROUPS[slot].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Kills the running commands, along with their children, before exiting as
+/// if we were killed by the signal. Only async-signal-safe functions are
+/// called.
+extern "C" fn on_interrupt(signal: libc::c_int) {
+    for slot in &RUNNING_GROUPS {
+        let pgid = slot.load(Ordering::SeqCst);
+        if pgid > 0 {
+            unsafe { libc::kill(-pgid, libc::SIGKILL) };
+        }
+    }
+    unsafe { libc::_exit(128 + signal) };
+}
+
+/// Jobs waiting to be executed, along with the serial groups of the running
+/// ones.
+struct Queue<'a> {
+    pending: Vec<usize>,
+    busy: HashSet<&'a str>,
+}
+
+impl<'a> Queue<'a> {
+    /// Takes the first pending job whose serial group is not busy, if any.
+    fn take(&mut self, jobs: &'a [Job]) -> Option<usize> {
+        let busy = &self.busy;
+        let available = |idx: &usize| jobs[*idx].serial.as_ref().is_none_or(|group| !busy.contains(&group[..]));
+        let pos = self.pending.iter().position(available)?;
+        let idx = self.pending.remove(pos);
+        if let Some(group) = &jobs[idx].serial {
+            self.busy.insert(group);
+        }
+        Some(idx)
+    }
+}
+
+/// What to do once the result of a job is handled.
+pub enum Next {
+    /// Execute the job again, if it has retries left.
+    Retry,
+    /// Go on with the next jobs.
+    Continue,
+    /// Start no new job.
+    Stop,
+}
+
+/// Executes jobs on `workers` threads, jobs of the same serial group are never
+/// executed at the same time. Results are passed to `handle` along with the
+/// index of their job and the execution time, in the order of the jobs. The
+/// results of each attempt of a retried job are passed in turn, its serial
+/// group is released only after the last one.
+pub fn execute_parallel<P, F>(jobs: &[Job], dir: P, workers: usize, mut handle: F)
+where
+    P: AsRef<Path> + Sync,
+    F: FnMut(usize, Result<Execution, Error>, Duration) -> Next,
+{
+    let queue = Mutex::new(Queue {
+        pending: (0..jobs.len()).collect(),
+        busy: HashSet::new(),
+    });
+    // Notified each time a job of a serial group is done
+    let released = Condvar::new();
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            let sender = sender.clone();
+            let (queue, released, stop, dir) = (&queue, &released, &stop, &dir);
+            scope.spawn(move || loop {
+                let idx = {
+                    let mut queue = queue.lock().unwrap();
+                    loop {
+                        if stop.load(Ordering::Relaxed) || queue.pending.is_empty() {
+                            return;
+                        }
+                        match queue.take(jobs) {
+                            Some(idx) => break idx,
+                            None => queue = released.wait(queue).unwrap(),
+                        }
+                    }
+                };
+                let job = &jobs[idx];
+                let mut sent = true;
+                for attempt in 0..=job.retries {
+                    let start = Instant::now();
+                    let result = execute(job, dir);
+                    // A retry is requested through this channel, its sender is
+                    // dropped otherwise
+                    let (retry, requested) = mpsc::channel();
+                    let retry = Some(retry).filter(|_| attempt < job.retries);
+                    sent = sender.send((idx, result, start.elapsed(), retry)).is_ok();
+                    if !sent || requested.recv().is_err() {
+                        break;
+                    }
+                }
+                if let Some(group) = &job.serial {
+                    queue.lock().unwrap().busy.remove(&group[..]);
+                    released.notify_all();
+                }
+                if !sent {
+                    return;
+                }
+            });
+        }
+        drop(sender); // The channel closes once all workers are done
+
+        // Results arrive in any order, hold them back until their turn
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (idx, result, duration, retry) in receiver {
+            pending.insert(idx, (result, duration, retry));
+            while let Some((result, duration, retry)) = pending.remove(&expected) {
+                match (handle(expected, result, duration), retry) {
+                    // The next attempt is sent under the same index
+                    (Next::Retry, Some(retry)) => {
+                        retry.send(()).ok();
+                        break;
+                    }
+                    (Next::Stop, _) => {
+                        stop.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                    _ => expected += 1,
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container() {
+        let job = Job {
+            cmd: String::from("ls"),
+            stdin: None,
+            env: vec![(String::from("TZ"), String::from("UTC"))].into_iter().collect(),
+            timeout: None,
+            pty: false,
+            combined: false,
+            runner: Some(String::from("podman:alpine:3.19")),
+            serial: None,
+            retries: 0,
+            hermetic: Some(vec![String::from("HOME")]),
+            max_output: 0,
+            before: None,
+            after: None,
+            artifacts: Vec::new(),
+            recorded_artifacts: Vec::new(),
+        };
+        let dir = std::env::current_dir().unwrap();
+        let runner = Container::new("podman:alpine:3.19");
+        let process = container(&runner, &job, &dir).unwrap();
+        assert_eq!(process.get_program(), "podman");
+        let args: Vec<_> = process.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        let mount = format!("{0}:{0}", dir.display());
+        let expected = [
+            "run", "--rm", "--init", "-i", "--name", &runner.name, "-v", &mount, "-w", &dir.to_string_lossy(), "-e",
+            "HOME", "-e", "TZ=UTC", "alpine:3.19", "sh", "-c", "ls",
+        ];
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_container_runtime() {
+        let runtime = |runner: &str| {
+            let container = Container::new(runner);
+            (container.runtime, container.image)
+        };
+        assert_eq!(runtime("docker:alpine:3.19"), (String::from("docker"), String::from("alpine:3.19")));
+        assert_eq!(runtime("podman:alpine"), (String::from("podman"), String::from("alpine")));
+        assert_eq!(runtime("alpine:3.19"), (String::from("docker"), String::from("alpine:3.19")));
+        assert_ne!(Container::new("alpine").name, Container::new("alpine").name);
+    }
 }
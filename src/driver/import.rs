@@ -0,0 +1,420 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{wrap, Error};
+use crate::formats::{toml, yaml};
+
+/// The snapshot formats of other tools that can be imported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// The `.snap` files of insta, recorded with insta-cmd.
+    Insta,
+    /// The `.t` files of cram, a shell session with the expected outputs.
+    Cram,
+    /// The test files of turnt, with their expected outputs in `.out` files.
+    Turnt,
+}
+
+impl std::str::FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<ImportFormat, String> {
+        match format {
+            "insta" => Ok(ImportFormat::Insta),
+            "cram" => Ok(ImportFormat::Cram),
+            "turnt" => Ok(ImportFormat::Turnt),
+            _ => Err(format!("unknown import format '{}'", format)),
+        }
+    }
+}
+
+/// A snapshot read from the files of another tool, along with the outputs it
+/// expects.
+pub struct Imported {
+    pub name: String,
+    pub description: Option<String>,
+    pub cmd: String,
+    pub env: BTreeMap<String, String>,
+    pub stdin: Option<Vec<u8>>,
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// The expected stderr is interleaved with the expected stdout.
+    pub combined: bool,
+    /// No stderr is expected, so it is not compared.
+    pub ignore_stderr: bool,
+}
+
+impl Imported {
+    fn new(name: String, cmd: String) -> Imported {
+        Imported {
+            name,
+            description: None,
+            cmd,
+            env: BTreeMap::new(),
+            stdin: None,
+            exit_code: 0,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            combined: false,
+            ignore_stderr: false,
+        }
+    }
+}
+
+/// Reads the snapshots of a format in a file, or in the files under a folder.
+/// The commands run from the `project` folder. Returns them along with
+/// warnings about the files skipped and the expectations converted loosely.
+pub fn import(format: ImportFormat, path: &Path, project: &Path) -> Result<(Vec<Imported>, Vec<String>), Error> {
+    let mut files = Vec::new();
+    find_files(path, &mut files)?;
+    let mut imported = Vec::new();
+    let mut warnings = Vec::new();
+    for file in files {
+        let stem = file.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let extension = file.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+        let read = || wrap(fs::read_to_string(&file), &format!("Could not read {}.", file.display()));
+        match format {
+            ImportFormat::Insta if extension == "snap" => match parse_insta(&stem, &read()?) {
+                Ok(snap) => imported.push(snap),
+                Err(reason) => warnings.push(format!("Skipped {}: {}.", file.display(), reason)),
+            },
+            ImportFormat::Cram if extension == "t" => {
+                let name = file.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                let dir = relative(file.parent().unwrap_or_else(|| Path::new(".")), project);
+                let testdir = match dir.as_str() {
+                    "." => "\"$PWD\"".to_owned(),
+                    dir if Path::new(dir).is_absolute() => quote(dir),
+                    dir => format!("\"$PWD\"/{}", quote(dir)),
+                };
+                match parse_cram(&name, &testdir, &read()?, &mut warnings) {
+                    Ok(snap) => imported.push(snap),
+                    Err(reason) => warnings.push(format!("Skipped {}: {}.", file.display(), reason)),
+                }
+            }
+            ImportFormat::Turnt
+                if !["out", "err", "toml"].contains(&&extension[..]) && file.with_extension("out").is_file() =>
+            {
+                imported.push(parse_turnt(&file, &stem, project)?);
+            }
+            _ => (),
+        }
+    }
+    Ok((imported, warnings))
+}
+
+/// Lists the files under a path, sorted.
+fn find_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    if !path.is_dir() {
+        files.push(path.to_owned());
+        return Ok(());
+    }
+    let error = format!("Could not read {}.", path.display());
+    let mut entries = Vec::new();
+    for entry in wrap(fs::read_dir(path), &error)? {
+        entries.push(wrap(entry, &error)?.path());
+    }
+    entries.sort();
+    for entry in entries {
+        find_files(&entry, files)?;
+    }
+    Ok(())
+}
+
+/// Parses an insta snapshot: its front matter holds the command recorded by
+/// insta-cmd, followed by the exit code and the outputs.
+fn parse_insta(name: &str, content: &str) -> Result<Imported, String> {
+    let rest = content.strip_prefix("---\n").ok_or("no front matter")?;
+    let end = rest.find("\n---\n").ok_or("no front matter")?;
+    let header = yaml::parse(&rest[..end]).ok_or("invalid front matter")?;
+    let body = &rest[end + 5..];
+    let info = header.get("info");
+    let program = info
+        .and_then(|info| info.get("program"))
+        .and_then(Value::as_str)
+        .ok_or("not a command snapshot, recorded with insta-cmd")?;
+    let mut cmd = quote(program);
+    for arg in info.and_then(|info| info.get("args")).and_then(Value::as_array).into_iter().flatten() {
+        cmd.push(' ');
+        cmd.push_str(&quote(&scalar(arg)));
+    }
+    let mut snap = Imported::new(name.to_owned(), cmd);
+    snap.description = header.get("description").map(scalar);
+    if let Some(env) = info.and_then(|info| info.get("env")).and_then(Value::as_object) {
+        snap.env = env.iter().map(|(key, value)| (key.clone(), scalar(value))).collect();
+    }
+    snap.stdin = info.and_then(|info| info.get("stdin")).map(|stdin| scalar(stdin).into_bytes());
+    let start = body.find("----- stdout -----\n").ok_or("no stdout section")?;
+    for line in body[..start].lines() {
+        if let Some(code) = line.strip_prefix("exit_code: ") {
+            snap.exit_code = code.trim().parse().map_err(|_| "invalid exit code")?;
+        }
+    }
+    let outputs = &body[start + 19..];
+    let (stdout, stderr) = match outputs.find("----- stderr -----\n") {
+        Some(end) => (&outputs[..end], &outputs[end + 19..]),
+        None => (outputs, ""),
+    };
+    snap.stdout = output(stdout);
+    snap.stderr = output(stderr);
+    Ok(snap)
+}
+
+/// Parses a cram test as a single snapshot named after the file, described
+/// by its comments. Its commands run one after the other in the same shell,
+/// as cram runs them: from a temporary folder, with `TESTDIR` set to `testdir`,
+/// the folder of the test, and the exit code of a failing command printed
+/// after its output.
+fn parse_cram(file: &str, testdir: &str, content: &str, warnings: &mut Vec<String>) -> Result<Imported, String> {
+    let stem = file.strip_suffix(".t").unwrap_or(file);
+    let mut snap = Imported::new(stem.to_owned(), String::new());
+    let mut commands: Vec<String> = Vec::new();
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut comment: Vec<&str> = Vec::new();
+    // Whether the lines belong to the last command, and whether its output
+    // started
+    let mut in_command = false;
+    let mut in_output = false;
+    for line in content.lines() {
+        if let Some(cmd) = line.strip_prefix("  $ ") {
+            commands.push(cmd.to_owned());
+            in_command = true;
+            in_output = false;
+            continue;
+        }
+        if in_command && line.starts_with("  ") {
+            match (line.strip_prefix("  > ").filter(|_| !in_output), commands.last_mut()) {
+                (Some(cmd), Some(command)) => {
+                    command.push('\n');
+                    command.push_str(cmd);
+                }
+                _ => {
+                    in_output = true;
+                    cram_line(stem, &line[2..], &mut snap.stdout, warnings);
+                }
+            }
+            continue;
+        }
+        in_command = false;
+        if !line.trim().is_empty() {
+            comment.push(line.trim());
+        } else if !comment.is_empty() {
+            paragraphs.push(comment.join(" "));
+            comment.clear();
+        }
+    }
+    if commands.is_empty() {
+        return Err("no command".to_owned());
+    }
+    if !comment.is_empty() {
+        paragraphs.push(comment.join(" "));
+    }
+    snap.description = Some(paragraphs.join("\n")).filter(|description| !description.is_empty());
+    snap.cmd = format!(
+        "export TESTDIR={} TESTFILE={} CRAMTMP=\"$(mktemp -d)\" && trap 'rm -rf \"$CRAMTMP\"' EXIT && \
+         mkdir \"$CRAMTMP\"/{} && cd \"$CRAMTMP\"/{} || exit 2",
+        testdir,
+        quote(file),
+        quote(file),
+        quote(file)
+    );
+    for command in commands {
+        snap.cmd.push_str(&format!("\n{}\ncram_status=$?; [ $cram_status = 0 ] || echo \"[$cram_status]\"", command));
+    }
+    for (var, value) in [("LANG", "C"), ("LC_ALL", "C"), ("LANGUAGE", "C"), ("TZ", "GMT"), ("COLUMNS", "80")] {
+        snap.env.insert(var.to_owned(), value.to_owned());
+    }
+    snap.combined = true;
+    Ok(snap)
+}
+
+/// Appends an expected line of a cram test to an output: escaped lines are
+/// unescaped and globs become placeholders, regular expressions can't be
+/// converted and are kept as is.
+fn cram_line(name: &str, line: &str, output: &mut Vec<u8>, warnings: &mut Vec<String>) {
+    let mut line = line.to_owned();
+    let mut escaped = false;
+    let mut eol = true;
+    loop {
+        if let Some(rest) = line.strip_suffix(" (no-eol)") {
+            eol = false;
+            line = rest.to_owned();
+        } else if let Some(rest) = line.strip_suffix(" (esc)") {
+            escaped = true;
+            line = rest.to_owned();
+        } else if let Some(rest) = line.strip_suffix(" (glob)") {
+            if rest.contains('?') {
+                warnings.push(format!("'{}' expects a line matching a glob with '?', kept as is.", name));
+            }
+            line = rest.replace('*', "[..]");
+        } else if let Some(rest) = line.strip_suffix(" (re)") {
+            warnings.push(format!("'{}' expects a line matching a regular expression, kept as is.", name));
+            line = rest.to_owned();
+        } else {
+            break;
+        }
+    }
+    output.extend(if escaped { unescape(&line) } else { line.into_bytes() });
+    if eol {
+        output.push(b'\n');
+    }
+}
+
+/// Unescapes the `\\`, `\t`, `\r` and `\xNN` escape sequences of a cram line.
+fn unescape(line: &str) -> Vec<u8> {
+    let mut body = Vec::with_capacity(line.len());
+    let mut bytes = line.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            body.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b't') => body.push(b'\t'),
+            Some(b'r') => body.push(b'\r'),
+            Some(b'x') => {
+                let digits: Vec<u8> = bytes.by_ref().take(2).collect();
+                match std::str::from_utf8(&digits).ok().and_then(|digits| u8::from_str_radix(digits, 16).ok()) {
+                    Some(byte) => body.push(byte),
+                    None => body.extend(b"\\x".iter().chain(&digits)),
+                }
+            }
+            Some(byte) => body.push(byte),
+            None => body.push(b'\\'),
+        }
+    }
+    body
+}
+
+/// Parses a turnt test: its command comes from the `CMD:` line of the test
+/// or from the closest `turnt.toml`, and runs in the folder of the test. The
+/// expected stdout is in the `.out` file next to it, and the expected stderr
+/// in the `.err` one if any.
+fn parse_turnt(file: &Path, stem: &str, project: &Path) -> Result<Imported, Error> {
+    let error = format!("Could not read {}.", file.display());
+    let content = String::from_utf8_lossy(&wrap(fs::read(file), &error)?).into_owned();
+    let dir = file.parent().unwrap_or_else(|| Path::new("."));
+    let config = dir
+        .ancestors()
+        .map(|dir| dir.join("turnt.toml"))
+        .find(|config| config.is_file())
+        .and_then(|config| fs::read_to_string(config).ok())
+        .and_then(|config| toml::parse(&config));
+    let setting = |key: &str| -> Option<String> {
+        let line = content.lines().find_map(|line| line.split_once(&format!("{}:", key.to_uppercase())));
+        match line {
+            Some((_, value)) => Some(value.trim().to_owned()),
+            None => config.as_ref().and_then(|config| config.get(key)).map(scalar),
+        }
+    };
+    let command = match setting("command").or_else(|| setting("cmd")) {
+        Some(command) => command,
+        None => return Error::from_str(&format!("No turnt command for {}.", file.display())),
+    };
+    let name = file.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let command = command
+        .replace("{filename}", &quote(&name))
+        .replace("{base}", stem)
+        .replace("{args}", &setting("args").unwrap_or_default());
+    let mut snap = Imported::new(stem.to_owned(), format!("cd {} && {}", quote(&relative(dir, project)), command));
+    let code = setting("return_code").or_else(|| setting("return"));
+    snap.exit_code = code.and_then(|code| code.parse().ok()).unwrap_or(0);
+    snap.stdout = wrap(fs::read(file.with_extension("out")), &error)?;
+    match fs::read(file.with_extension("err")) {
+        Ok(stderr) => snap.stderr = stderr,
+        Err(_) => snap.ignore_stderr = true,
+    }
+    Ok(snap)
+}
+
+/// Returns a path relative to the project folder, or absolute if it is out
+/// of it.
+fn relative(path: &Path, project: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let project = fs::canonicalize(project).unwrap_or_else(|_| project.to_owned());
+    match path.strip_prefix(&project) {
+        Ok(path) if path.as_os_str().is_empty() => ".".to_owned(),
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Returns a YAML or TOML scalar as a string.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// An output of an insta snapshot, which trims the trailing newlines: a non
+/// empty output ends with a single one.
+fn output(section: &str) -> Vec<u8> {
+    let mut output = section.trim_end_matches('\n').as_bytes().to_vec();
+    if !output.is_empty() {
+        output.push(b'\n');
+    }
+    output
+}
+
+/// Quotes a word for the shell, if needed.
+fn quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_insta() {
+        let content = "---\nsource: tests/cli.rs\ninfo:\n  program: echo\n  args:\n    - hello world\n---\n\
+                       success: false\nexit_code: 2\n----- stdout -----\nhello world\n\n----- stderr -----\n\n";
+        let snap = parse_insta("cli__echo", content).unwrap();
+        assert_eq!(snap.cmd, "echo 'hello world'");
+        assert_eq!(snap.exit_code, 2);
+        assert_eq!(snap.stdout, b"hello world\n");
+        assert!(snap.stderr.is_empty());
+        assert!(parse_insta("value", "---\nexpression: value\n---\n42\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_cram() {
+        let content = [
+            "Greets:",
+            "",
+            "  $ echo hi \\",
+            "  > there",
+            "  hi there",
+            "  $ printf 'a\\tb'; false",
+            "  a\\tb (no-eol) (esc)",
+            "  [1]",
+            "",
+            "Globs:",
+            "  $ ls",
+            "  *.t (glob)",
+        ];
+        let mut warnings = Vec::new();
+        let snap = parse_cram("greet.t", "\"$PWD\"/tests", &content.join("\n"), &mut warnings).unwrap();
+        assert_eq!(snap.name, "greet");
+        assert_eq!(snap.description.as_deref(), Some("Greets:\nGlobs:"));
+        let mut lines = snap.cmd.lines();
+        assert!(lines.next().unwrap().starts_with("export TESTDIR=\"$PWD\"/tests TESTFILE=greet.t CRAMTMP="));
+        assert_eq!(lines.next(), Some("echo hi \\"));
+        assert_eq!(lines.next(), Some("there"));
+        assert_eq!(lines.nth(1), Some("printf 'a\\tb'; false"));
+        assert_eq!(lines.nth(1), Some("ls"));
+        assert_eq!(snap.stdout, b"hi there\na\tb[1]\n[..].t\n");
+        assert_eq!(snap.exit_code, 0);
+        assert_eq!(snap.env.get("LC_ALL").map(String::as_str), Some("C"));
+        assert!(warnings.is_empty());
+        assert!(parse_cram("empty.t", "\"$PWD\"", "Nothing to run.\n", &mut warnings).is_err());
+    }
+}
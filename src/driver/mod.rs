@@ -1,20 +1,39 @@
-use std::io::{stdin, stdout, Write};
-use std::path::PathBuf;
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{sink, stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Output;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::data::{DataManager, Snapshot, SnapshotStatus};
+use crate::compare::{
+    mask_placeholders, missing_sections, Assertion, Compare, ExitCodes, LineOrder, Normalize, Tolerance, Transform,
+};
+use crate::data::{check_name, DataManager, Problem, Redaction, RunRecord, Snapshot, SnapshotStatus, SCHEMA_VERSION};
 use crate::editor;
-use crate::error::{Error, Log};
+use crate::error::{wrap, Error, Log};
 use crate::parser;
+use crate::report::{
+    write_annotations, write_html, write_json, write_json_report, write_junit, write_markdown, DiffSummary,
+    ReportFormat, RunReport, SnapshotEntry, SnapshotReport, StatsReport, StatusReport,
+};
 use crate::term;
-use crate::term::{BoxedWriter, Input, SeparatorKind};
+use crate::term::{BoxedWriter, ColorChoice, Input, SeparatorKind};
 
+use cmd::{Execution, Verdict};
 use parser::{parse, Command, Filter, Target};
 use util::*;
 
 mod cmd;
+mod import;
 mod repl;
 mod util;
 
+pub use cmd::MAX_JOBS;
+pub use import::ImportFormat;
 pub use repl::View;
 
 /// The result of a command execution, which may ask for termination or not.
@@ -23,20 +42,209 @@ pub enum ReplStatus {
     Continue,
 }
 
+/// Options of the run subcommand.
+pub struct RunOptions {
+    /// Only run snapshots matching this pattern.
+    pub pattern: Option<String>,
+    /// Stop the run after this number of failures.
+    pub max_failures: Option<usize>,
+    /// Number of failures tolerated before the run is considered failed.
+    pub allow_failures: usize,
+    /// Only run snapshots that failed during the last run.
+    pub last_failed: bool,
+    /// Only run snapshots with all these tags.
+    pub tags: Vec<String>,
+    /// Skip snapshots with any of these tags.
+    pub exclude_tags: Vec<String>,
+    /// Only run snapshots whose name matches this regex.
+    pub name_regex: Option<String>,
+    /// Only list the snapshots that would be run.
+    pub dry_run: bool,
+    /// Number of snapshots executed in parallel.
+    pub jobs: usize,
+    /// Run the snapshots this number of times, stops at the first failure.
+    pub repeat: Option<usize>,
+    /// Run the snapshots again until one fails, up to `repeat` times if set.
+    pub until_failure: bool,
+    /// Keep the outputs of the failing snapshots as pending ones.
+    pub pending: bool,
+    /// The report files written after the run, by format.
+    pub reports: Vec<(ReportFormat, PathBuf)>,
+    /// Print GitHub Actions annotations for the failing snapshots.
+    pub annotations: bool,
+    /// How much is printed during the run.
+    pub verbosity: Verbosity,
+}
+
+/// How much `run` prints, from the least to the most.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only the verdict.
+    Quiet,
+    /// The failures and a summary.
+    Normal,
+    /// Also a line per snapshot, with its command and duration.
+    Verbose,
+    /// Also the outputs of the snapshots that pass.
+    Outputs,
+}
+
+/// Options of the add subcommand.
+pub struct AddOptions {
+    /// Name of the snapshot, asked in the editor otherwise.
+    pub name: Option<String>,
+    /// Input fed to the command.
+    pub stdin: Option<String>,
+    /// A file whose content is fed to the command.
+    pub stdin_file: Option<PathBuf>,
+    /// Write the input fed to the command in the editor.
+    pub edit_stdin: bool,
+    /// Environment variables set when running the command.
+    pub env: Vec<(String, String)>,
+    /// Environment variables whose current value is recorded.
+    pub capture_env: Vec<String>,
+    /// Timeout in seconds.
+    pub timeout: Option<u64>,
+    /// Duration above which the snapshot fails, in milliseconds.
+    pub max_duration_ms: Option<u64>,
+    /// Run the command in a pseudo-terminal.
+    pub pty: bool,
+    /// Record stdout and stderr interleaved, as stdout.
+    pub combined: bool,
+    /// Container runtime and image the command runs in.
+    pub runner: Option<String>,
+    /// Run the command in a scrubbed environment.
+    pub hermetic: bool,
+    /// Environment variables passed through the scrubbed environment.
+    pub pass_env: Vec<String>,
+    /// Commands run before and after the snapshot's command.
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Number of times the command is executed again if the snapshot fails.
+    pub retries: u32,
+    /// Environment variables along with their values, a snapshot is added
+    /// for each combination.
+    pub matrix: Vec<(String, Vec<String>)>,
+    /// Names of the snapshots that must pass before this one is run.
+    pub needs: Vec<String>,
+    /// Serial group of the snapshot.
+    pub serial: Option<String>,
+    /// Files the outputs of the command depend on.
+    pub inputs: Vec<String>,
+    /// Remove ANSI escape sequences from the outputs.
+    pub strip_ansi: bool,
+    /// Built-in filters redacting the outputs.
+    pub filters: Vec<String>,
+    /// Patterns replaced in the outputs, along with their replacement.
+    pub redact: Vec<(String, String)>,
+    /// Steps transforming the outputs, in order, before they are stored.
+    pub pipeline: Vec<Transform>,
+    /// How outputs are normalized when they are compared.
+    pub normalize: Normalize,
+    /// Only compare the exit code, the outputs are not recorded.
+    pub exit_code_only: bool,
+    /// Neither record nor compare stdout, or stderr.
+    pub ignore_stdout: bool,
+    pub ignore_stderr: bool,
+    /// Show the outputs as binary data.
+    pub binary: bool,
+    /// How to compare stdout.
+    pub compare: Compare,
+    /// How close numbers must be in the outputs.
+    pub tolerance: Tolerance,
+    /// Only check that the outputs contain the stored sections.
+    pub contains: Option<LineOrder>,
+    /// The accepted exit codes, instead of the recorded one.
+    pub exit_codes: Option<ExitCodes>,
+    /// Properties the outputs must have, instead of their content.
+    pub assertions: Vec<Assertion>,
+    /// A command judging whether the outputs match.
+    pub comparator: Option<String>,
+    /// The snapshot is expected to fail.
+    pub xfail: bool,
+    /// Files written by the command, captured along with its outputs.
+    pub artifacts: Vec<String>,
+    /// A file of the project holding the expected stdout.
+    pub expected_stdout: Option<String>,
+    /// Save the snapshot without confirmation.
+    pub yes: bool,
+}
+
+/// Options of the list subcommand.
+pub struct ListOptions {
+    /// Only list snapshots with all these tags.
+    pub tags: Vec<String>,
+    /// Only list snapshots matching this pattern.
+    pub name: Option<String>,
+    /// Only print the snapshot names.
+    pub names_only: bool,
+    /// List archived snapshots instead of active ones.
+    pub archived: bool,
+    /// Only list snapshots last changed more than this many seconds ago.
+    pub older_than: Option<u64>,
+    /// Only list snapshots last changed less than this many seconds ago.
+    pub newer_than: Option<u64>,
+}
+
 pub struct Context {
     path: PathBuf,
     data: DataManager,
     theme: term::Theme,
+    /// Write JSON instead of human readable output.
+    json: bool,
+    /// When to color the output, diffs are plain when it is never colored.
+    color: ColorChoice,
+    /// Timeout in seconds of snapshots without their own.
+    timeout: Option<u64>,
+    /// Maximum size of the outputs of commands, in bytes.
+    max_output: usize,
+    /// Placeholders replacing the paths that differ across machines in the
+    /// outputs of all snapshots, before any other redaction.
+    paths: Vec<Redaction>,
+    /// Patterns replaced in the outputs of all snapshots, including the ones
+    /// of the configured filters.
+    redact: Vec<Redaction>,
+    /// How the outputs of all snapshots are normalized when they are compared.
+    normalize: Normalize,
+    /// Keep the plain diffs of the failed snapshots in their reports, for the
+    /// report files.
+    keep_diffs: bool,
+    /// How much is printed while snapshots run.
+    verbosity: Verbosity,
 }
 
 impl Context {
     /// Creates a new context.
-    pub fn new(path: PathBuf) -> Result<Context, Error> {
-        let data = DataManager::new(&path)?;
+    #[allow(clippy::too_many_arguments)] // Called once, with the global options of the command line
+    pub fn new(
+        path: PathBuf,
+        json: bool,
+        color: ColorChoice,
+        timeout: Option<u64>,
+        max_output: usize,
+        store: Option<PathBuf>,
+        suite: Option<String>,
+        wait: bool,
+    ) -> Result<Context, Error> {
+        let data = DataManager::new(&path, store.as_deref(), suite.as_deref(), wait)?;
+        let config = data.get_config()?;
+        let paths = if config.keep_paths { Vec::new() } else { path_redactions(&path) };
+        let mut redact = filter_redactions(&config.filters)?;
+        redact.extend(config.redact);
+        let normalize = config.normalize;
         Ok(Context {
             path,
             data,
-            theme: term::Theme::new(),
+            theme: term::Theme::new(color.enabled()),
+            json,
+            color,
+            timeout,
+            max_output,
+            paths,
+            redact,
+            normalize,
+            keep_diffs: false,
+            verbosity: Verbosity::Normal,
         })
     }
 
@@ -47,27 +255,108 @@ impl Context {
     }
 
     /// Handles add subcommand.
-    pub fn add(&mut self, cmd: &str, name: &Option<String>, yes: bool) {
-        let snap = cmd::execute(&cmd, &self.path).unwrap_log();
+    pub fn add(&mut self, cmd: &str, options: &AddOptions) {
+        self.data.create_suite().unwrap_log();
+        let yes = options.yes;
+        if let Some(name) = &options.name {
+            check_name(name).unwrap_log();
+        }
+        for needs in &options.needs {
+            self.data.get_snapshot(needs).unwrap_log();
+        }
+        let stdin = if let Some(stdin) = &options.stdin {
+            Some(stdin.as_bytes().to_owned())
+        } else if let Some(path) = &options.stdin_file {
+            Some(wrap(fs::read(path), "Could not read the stdin file.").unwrap_log())
+        } else if options.edit_stdin {
+            Some(editor::open_stdin(self.data.store_path(), cmd).unwrap_log())
+        } else {
+            None
+        };
+        let mut env: BTreeMap<String, String> = options.env.iter().cloned().collect();
+        for var in &options.capture_env {
+            match std::env::var(var) {
+                Ok(value) => env.insert(var.to_owned(), value),
+                Err(_) => Error::from_str(&format!("Environment variable '{}' is not set.", var)).unwrap_log(),
+            };
+        }
+        let hermetic = if options.hermetic { Some(options.pass_env.clone()) } else { None };
+        let redact: Vec<Redaction> = options
+            .redact
+            .iter()
+            .map(|(pattern, replacement)| Redaction {
+                pattern: pattern.to_owned(),
+                replacement: replacement.to_owned(),
+            })
+            .collect();
+        for file in &options.artifacts {
+            check_artifact(&self.path, file).unwrap_log();
+        }
+        // Each combination of the matrix values is a snapshot of its own
+        let mut runs = Vec::new();
+        for (suffix, vars) in expand_matrix(&options.matrix) {
+            let mut env = env.clone();
+            env.extend(vars);
+            let mut execution = self.execute_new(cmd::Job {
+                cmd: cmd.to_owned(),
+                stdin: stdin.clone(),
+                env: env.clone(),
+                timeout: options.timeout.map(Duration::from_secs),
+                pty: options.pty,
+                combined: options.combined,
+                runner: options.runner.clone(),
+                serial: None,
+                retries: 0,
+                hermetic: hermetic.clone(),
+                max_output: self.max_output,
+                before: options.before.clone(),
+                after: options.after.clone(),
+                artifacts: options.artifacts.clone(),
+                // The files of the previous combinations, the artifacts not existing beforehand
+                recorded_artifacts: options.artifacts.clone(),
+            });
+            let (filters, pipeline) = (&options.filters, &options.pipeline);
+            self.redact_output(options.strip_ansi, filters, &redact, pipeline, &mut execution).unwrap_log();
+            runs.push((suffix, env, execution));
+        }
         let save = if yes {
             true
-        } else {
-            term::snap_preview(&snap, &mut stdout(), &self.theme);
+        } else if runs.len() == 1 {
+            term::snap_preview(&runs[0].2.output, &mut stdout(), &self.theme);
             term::binary_qestion("Save this snapshot?").unwrap_log()
+        } else {
+            for (suffix, _, execution) in &runs {
+                term::writeln(&format!("@{}", suffix), &mut stdout());
+                term::snap_preview(&execution.output, &mut stdout(), &self.theme);
+            }
+            term::binary_qestion(&format!("Save these {} snapshots?", runs.len())).unwrap_log()
         };
         if save {
             // Get snapshot name
             let mut description = None;
             let mut tags = Vec::new();
-            let name = if let Some(name) = name {
+            let mut exit_code_only = options.exit_code_only;
+            let mut pipeline = options.pipeline.clone();
+            let name = if let Some(name) = &options.name {
                 name.to_owned()
             } else {
                 if yes {
                     get_random_name()
                 } else {
-                    let edit_result = editor::open_empty(&self.path, cmd).unwrap_log();
+                    let edit_result = editor::open_empty(self.data.store_path(), cmd).unwrap_log();
                     description = edit_result.description;
                     tags = edit_result.tags;
+                    exit_code_only |= edit_result.exit_code_only;
+                    // The steps added in the editor run after the ones already applied
+                    let steps = parse_pipeline(&edit_result.pipeline).unwrap_log();
+                    for (_, _, execution) in &mut runs {
+                        let output = &mut execution.output;
+                        let artifacts = execution.artifacts.iter_mut().flatten();
+                        for body in vec![&mut output.stdout, &mut output.stderr].into_iter().chain(artifacts) {
+                            *body = apply_pipeline(body, &steps).unwrap_log();
+                        }
+                    }
+                    pipeline.extend(steps);
                     if let Some(name) = edit_result.name {
                         normalize_name(&name)
                     } else {
@@ -75,26 +364,1231 @@ impl Context {
                     }
                 }
             };
-            let snapshot = to_snapshot(name, description, tags, cmd.to_owned(), snap);
-            self.data.add_snapshot(snapshot).unwrap_log();
+            for (suffix, env, execution) in runs {
+                let name = if suffix.is_empty() { name.clone() } else { format!("{}@{}", name, suffix) };
+                let cmd = cmd.to_owned();
+                let stdin = stdin.clone();
+                let mut snapshot = to_snapshot(name, description.clone(), tags.clone(), cmd, stdin, execution.output);
+                snapshot.artifacts = to_artifacts(&snapshot.name, &options.artifacts, execution.artifacts);
+                self.set_golden(&mut snapshot, &options.expected_stdout);
+                snapshot.env = env;
+                snapshot.timeout = options.timeout;
+                snapshot.max_duration_ms = options.max_duration_ms;
+                snapshot.pty = options.pty;
+                snapshot.combined = options.combined;
+                snapshot.runner = options.runner.clone();
+                snapshot.hermetic = hermetic.clone();
+                snapshot.before = options.before.clone();
+                snapshot.after = options.after.clone();
+                snapshot.retries = options.retries;
+                snapshot.needs = options.needs.clone();
+                snapshot.serial = options.serial.clone();
+                snapshot.inputs = options.inputs.clone();
+                snapshot.strip_ansi = options.strip_ansi;
+                snapshot.filters = options.filters.clone();
+                snapshot.redact = redact.clone();
+                snapshot.pipeline = pipeline.clone();
+                snapshot.normalize = options.normalize;
+                snapshot.exit_code_only = exit_code_only;
+                snapshot.ignore_stdout = options.ignore_stdout;
+                snapshot.ignore_stderr = options.ignore_stderr;
+                snapshot.binary = options.binary;
+                snapshot.compare = options.compare;
+                snapshot.tolerance = options.tolerance;
+                snapshot.contains = options.contains;
+                snapshot.exit_codes = options.exit_codes.clone();
+                snapshot.assertions = options.assertions.clone();
+                snapshot.comparator = options.comparator.clone();
+                snapshot.xfail = options.xfail;
+                snapshot.drop_ignored_outputs();
+                self.data.add_snapshot(snapshot).unwrap_log();
+            }
+        }
+    }
+
+    /// Handles add subcommand with a commands file.
+    /// All commands are executed and previewed, then saved at once after a
+    /// single confirmation.
+    pub fn add_from_file(&mut self, path: &Path, yes: bool) {
+        self.data.create_suite().unwrap_log();
+        let mut stdout = stdout();
+        let content = wrap(fs::read_to_string(path), "Could not read the commands file.").unwrap_log();
+        let is_json = path.extension().is_some_and(|ext| ext == "json");
+        let entries = parse_commands(&content, is_json).unwrap_log();
+        if entries.is_empty() {
+            term::writeln("No command to add.", &mut stdout);
+            return;
+        }
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        for entry in entries {
+            let name = entry.name.as_deref().map_or_else(get_random_name, normalize_name);
+            check_name(&name).unwrap_log();
+            if self.data.get_snapshot(&name).is_ok() || snapshots.iter().any(|snap| snap.name == name) {
+                Error::from_str::<()>(&format!("A snapshot named '{}' already exists.", name)).unwrap_log();
+            }
+            for needs in &entry.needs {
+                if self.data.get_snapshot(needs).is_err() && !snapshots.iter().any(|snap| &snap.name == needs) {
+                    Error::from_str::<()>(&format!("'{}' needs '{}', which does not exist.", name, needs)).unwrap_log();
+                }
+            }
+            if let Some(tag) = entry.tags.iter().find(|tag| !is_valid_tag(tag.trim_start_matches('#'))) {
+                Error::from_str::<()>(&format!("Invalid tag '{}' for '{}'.", tag, name)).unwrap_log();
+            }
+            for file in &entry.artifacts {
+                check_artifact(&self.path, file).unwrap_log();
+            }
+            let mut execution = self.execute_new(cmd::Job {
+                cmd: entry.cmd.to_owned(),
+                stdin: None,
+                env: entry.env.clone(),
+                timeout: entry.timeout.map(Duration::from_secs),
+                pty: entry.pty,
+                combined: entry.combined,
+                runner: entry.runner.clone(),
+                serial: None,
+                retries: 0,
+                hermetic: entry.hermetic.clone(),
+                max_output: self.max_output,
+                before: entry.before.clone(),
+                after: entry.after.clone(),
+                artifacts: entry.artifacts.clone(),
+                recorded_artifacts: Vec::new(),
+            });
+            let (filters, pipeline) = (&entry.filters, &entry.pipeline);
+            self.redact_output(entry.strip_ansi, filters, &entry.redact, pipeline, &mut execution).unwrap_log();
+            let mut snap = to_snapshot(name, entry.description, Vec::new(), entry.cmd, None, execution.output);
+            snap.artifacts = to_artifacts(&snap.name, &entry.artifacts, execution.artifacts);
+            self.set_golden(&mut snap, &entry.expected_stdout);
+            snap.env = entry.env;
+            snap.timeout = entry.timeout;
+            snap.max_duration_ms = entry.max_duration_ms;
+            snap.pty = entry.pty;
+            snap.combined = entry.combined;
+            snap.runner = entry.runner;
+            snap.hermetic = entry.hermetic;
+            snap.before = entry.before;
+            snap.after = entry.after;
+            snap.retries = entry.retries;
+            snap.needs = entry.needs;
+            snap.serial = entry.serial;
+            snap.inputs = entry.inputs;
+            snap.strip_ansi = entry.strip_ansi;
+            snap.filters = entry.filters;
+            snap.redact = entry.redact;
+            snap.normalize = entry.normalize;
+            snap.exit_code_only = entry.exit_code_only;
+            snap.ignore_stdout = entry.ignore_stdout;
+            snap.ignore_stderr = entry.ignore_stderr;
+            snap.binary = entry.binary;
+            snap.compare = entry.compare;
+            snap.tolerance = entry.tolerance;
+            snap.contains = entry.contains;
+            snap.exit_codes = entry.exit_codes;
+            snap.pipeline = entry.pipeline;
+            snap.assertions = entry.assertions;
+            snap.comparator = entry.comparator;
+            snap.xfail = entry.xfail;
+            snap.drop_ignored_outputs();
+            snap.tags = snap.description.as_deref().map(editor::parse_tags).unwrap_or_default();
+            for tag in &entry.tags {
+                add_tag(&mut snap, tag.trim_start_matches('#'));
+            }
+            snapshots.push(snap);
+        }
+        let name_width = snapshots.iter().map(|snap| snap.name.len()).max().unwrap_or(0);
+        for snap in &snapshots {
+            term::list_item(snap, name_width, false, &mut stdout, &self.theme);
+        }
+        let count = snapshots.len();
+        let save = yes || term::binary_qestion(&format!("Save these {} snapshots?", count)).unwrap_log();
+        if save {
+            for snap in snapshots {
+                self.data.add_snapshot(snap).unwrap_log();
+            }
+            term::writeln(&format!("Added {} snapshot(s).", count), &mut stdout);
         }
     }
 
     /// Handles run subcommand.
     /// Returns true in case of success, false otherwise.
-    pub fn run(&mut self) -> bool {
+    pub fn run(&mut self, options: &RunOptions) -> bool {
         let mut stdout = stdout();
-        let snapshots = self.data.get_all_snapshots().unwrap_log();
-        let view = repl::View::new(snapshots);
-        if self.run_view(&view, &mut stdout) {
-            term::success(&mut stdout);
+        let snapshots = self.data.get_active_snapshots().unwrap_log();
+        let mut view = repl::View::new(snapshots);
+        if let Some(pattern) = &options.pattern {
+            view.apply_filter(Filter::Name(pattern.to_owned()));
+        }
+        for tag in &options.tags {
+            view.apply_filter(Filter::Tag(tag.trim_start_matches('#').to_owned()));
+        }
+        for tag in &options.exclude_tags {
+            let tag = tag.trim_start_matches('#');
+            view.retain(|snap| !snap.tags.iter().any(|t| t == tag));
+        }
+        if let Some(regex) = &options.name_regex {
+            let regex = wrap(Regex::new(regex), "Invalid name regex.").unwrap_log();
+            view.retain(|snap| regex.is_match(&snap.name));
+        }
+        if options.last_failed {
+            view.apply_filter(Filter::Failed);
+            if view.get_view().is_empty() && !self.json {
+                term::writeln("No snapshot failed during the last run.", &mut stdout);
+            }
+        }
+        if options.dry_run {
+            self.list_view(&view, "run", &mut stdout);
+            return true;
+        }
+        let config = self.data.get_config().unwrap_log();
+        if let Some(before_all) = &config.before_all {
+            cmd::execute_suite_hook(before_all, "before-all", &self.path).unwrap_log();
+        }
+        let start = Instant::now();
+        self.keep_diffs = !options.reports.is_empty() || options.annotations;
+        self.verbosity = options.verbosity;
+        let keep_pending = options.pending || config.pending;
+        let repeated = options.repeat.unwrap_or(1) > 1 || options.until_failure;
+        // The after-all command runs even if the run fails, its own failure is
+        // reported last
+        let outcome = self.run_iterations(&view, options, keep_pending, repeated);
+        let after_all = config.after_all.as_ref().map(|cmd| cmd::execute_suite_hook(cmd, "after-all", &self.path));
+        let (reports, iteration, pending, divergent) = outcome.unwrap_log();
+        after_all.transpose().unwrap_log();
+        let failures = reports.iter().filter(|report| !report.passed()).count();
+        let names = |keep: fn(&SnapshotReport) -> bool| -> Vec<String> {
+            reports.iter().filter(|report| keep(report)).map(|report| report.name.to_owned()).collect()
+        };
+        let flaky = names(|report| report.flaky);
+        let expected_failures = names(|report| report.expected_failure);
+        let skipped = reports.iter().filter(|report| report.skipped).count();
+        let success = failures <= options.allow_failures;
+        let report = RunReport {
+            success,
+            failures,
+            flaky: flaky.len(),
+            expected_failures: expected_failures.len(),
+            skipped,
+            iterations: iteration,
+            duration_ms: start.elapsed().as_millis() as u64,
+            snapshots: reports,
+        };
+        for (format, path) in &options.reports {
+            let error = format!("Failed to write the report {}.", path.display());
+            let mut file = wrap(fs::File::create(path), &error).unwrap_log();
+            let written = match format {
+                ReportFormat::JUnit => write_junit(&report.snapshots, report.duration_ms, &mut file),
+                ReportFormat::Json => write_json_report(&report, &mut file),
+                ReportFormat::Html => write_html(&report.snapshots, report.duration_ms, &mut file),
+                ReportFormat::Markdown => write_markdown(&report.snapshots, report.duration_ms, &mut file),
+            };
+            wrap(written, &error).unwrap_log();
+        }
+        if self.json {
+            write_json(&report, &mut stdout);
+            return success;
+        }
+        // The quiet mode only prints the verdict
+        let mut summary: Box<dyn Write> =
+            if options.verbosity == Verbosity::Quiet { Box::new(sink()) } else { Box::new(std::io::stdout()) };
+        if let Some(path) = divergent {
+            let message = format!("Diverged at iteration {}, outputs kept in {}.", iteration, path.display());
+            term::writeln(&message, &mut summary);
+        } else if repeated {
+            term::writeln(&format!("No divergence in {} iterations.", iteration), &mut summary);
+        }
+        if let Some(max_failures) = options.max_failures {
+            if failures >= max_failures && failures > 0 {
+                term::writeln(&format!("Stopped after {} failure(s).", failures), &mut summary);
+            }
+        }
+        if !flaky.is_empty() {
+            term::writeln(
+                &format!("{} flaky snapshot(s) passed after a retry: {}", flaky.len(), flaky.join(", ")),
+                &mut summary,
+            );
+        }
+        if !expected_failures.is_empty() {
+            let names = expected_failures.join(", ");
+            let message = format!("{} snapshot(s) failed as expected: {}", expected_failures.len(), names);
+            term::writeln(&message, &mut summary);
+        }
+        if skipped > 0 {
+            term::writeln(
+                &format!("{} snapshot(s) skipped because a snapshot they need failed.", skipped),
+                &mut summary,
+            );
+        }
+        if pending > 0 {
+            let message = format!(
+                "{} snapshot(s) pending, review them with `parrot accept` or `parrot reject`.",
+                pending
+            );
+            term::writeln(&message, &mut summary);
+        }
+        if failures > 0 && failures <= options.allow_failures {
+            term::writeln(
+                &format!("{} failure(s), up to {} allowed.", failures, options.allow_failures),
+                &mut summary,
+            );
+        }
+        if options.annotations {
+            write_annotations(&report.snapshots, &mut stdout);
+        }
+        if success {
+            term::success(&mut stdout, &self.theme);
+        } else {
+            term::failure(&mut stdout, &self.theme);
+        }
+        success
+    }
+
+    /// Runs the snapshots of a view as many times as asked, until one of them
+    /// fails if the last iteration is not known, then persists their state
+    /// and pending outputs, and the outputs of the divergent snapshots of a
+    /// repeated run.
+    /// Returns the reports of the last iteration, the number of iterations, the
+    /// number of pending snapshots and the folder of the divergent outputs, if
+    /// any.
+    fn run_iterations(
+        &mut self,
+        view: &View,
+        options: &RunOptions,
+        keep_pending: bool,
+        repeated: bool,
+    ) -> Result<(Vec<SnapshotReport>, usize, usize, Option<PathBuf>), Error> {
+        let mut stdout = stdout();
+        let mut iteration = 0;
+        let reports = loop {
+            iteration += 1;
+            self.data.start_run()?;
+            let reports = if self.json || options.verbosity == Verbosity::Quiet {
+                self.run_view(view, options.max_failures, options.jobs, &mut sink())?
+            } else {
+                self.run_view(view, options.max_failures, options.jobs, &mut stdout)?
+            };
+            let failed = reports.iter().any(|report| !report.passed());
+            let last = options.repeat.map_or(!options.until_failure, |repeat| iteration >= repeat);
+            if failed || last {
+                break reports;
+            }
+        };
+        self.data.persist_state()?;
+        // Outputs edited by hand, with placeholders for instance, are adopted
+        // once they pass: their checksums are recorded again
+        let mut adopted = false;
+        for snap in view.get_view() {
+            let mut snap = snap.borrow_mut();
+            if snap.status == SnapshotStatus::Passed && !snap.corrupted().is_empty() {
+                snap.adopt_outputs();
+                adopted = true;
+            }
+        }
+        if adopted {
+            self.data.persist_metadata()?;
+        }
+        // Pending outputs are replaced by the ones of the latest run, and
+        // discarded once the snapshot passes again
+        let mut pending = 0;
+        for report in &reports {
+            match &report.pending {
+                Some(expectation) if keep_pending => {
+                    self.data.keep_pending(&report.name, expectation)?;
+                    pending += 1;
+                }
+                _ if report.passed() => self.data.remove_pending(&report.name)?,
+                _ => (),
+            }
+        }
+        let mut divergent = None;
+        if repeated {
+            for report in &reports {
+                if let Some(output) = &report.output {
+                    divergent = Some(self.data.keep_divergent(&report.name, output)?);
+                }
+            }
+        }
+        Ok((reports, iteration, pending, divergent))
+    }
+
+    /// Handles list subcommand.
+    pub fn list(&mut self, options: &ListOptions, verbose: bool) {
+        let mut stdout = stdout();
+        let mut snapshots = self.data.get_all_snapshots().unwrap_log();
+        snapshots.retain(|snap| snap.borrow().archived == options.archived);
+        if options.older_than.is_some() || options.newer_than.is_some() {
+            // Snapshots recorded before the stamps count as the oldest
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+            snapshots.retain(|snap| {
+                let snap = snap.borrow();
+                let changed = snap.updated.as_ref().or(snap.created.as_ref()).map_or(0, |stamp| stamp.at);
+                let age = now.saturating_sub(changed);
+                options.older_than.is_none_or(|older| age > older) && options.newer_than.is_none_or(|newer| age < newer)
+            });
+        }
+        let mut view = repl::View::new(snapshots);
+        for tag in &options.tags {
+            view.apply_filter(Filter::Tag(tag.trim_start_matches('#').to_owned()));
+        }
+        if let Some(name) = &options.name {
+            view.apply_filter(Filter::Name(name.to_owned()));
+        }
+        let snaps = view.get_view();
+        if self.json {
+            write_entries(snaps, &mut stdout);
+            return;
+        }
+        if options.names_only {
+            for snap in snaps {
+                println!("{}", snap.borrow().name);
+            }
+            return;
+        }
+        if snaps.is_empty() {
+            term::writeln("No snapshot to list.", &mut stdout);
+            return;
+        }
+        let name_width = snaps.iter().map(|snap| snap.borrow().name.len()).max().unwrap_or(0);
+        for snap in snaps {
+            term::list_item(&snap.borrow(), name_width, verbose, &mut stdout, &self.theme);
+        }
+    }
+
+    /// Handles copy subcommand.
+    pub fn copy(&mut self, name: &str, new_name: &str, cmd: &Option<String>) {
+        let new_name = normalize_name(new_name);
+        self.data.copy_snapshot(name, &new_name, cmd.clone()).unwrap_log();
+        println!("Copied '{}' to '{}'.", name, new_name);
+    }
+
+    /// Handles diff subcommand.
+    /// The snapshot status is left untouched.
+    /// Returns true if the output did not change, false otherwise.
+    pub fn diff(&mut self, name: &str) -> bool {
+        let mut stdout = stdout();
+        let snap = self.data.get_snapshot(name).unwrap_log();
+        let snap = snap.borrow();
+        let execution = self.execute(&snap).unwrap_log();
+        if self.color != ColorChoice::Never {
+            let same = self.draw_diff(&snap, &execution, &mut stdout);
+            if same {
+                term::writeln("No difference.", &mut stdout);
+            }
+            return same;
+        }
+        self.write_plain_diff(&snap, &execution, &mut stdout)
+    }
+
+    /// Writes the differences between a snapshot and the result of an
+    /// execution as a plain diff, returns true if there are none.
+    fn write_plain_diff<B: Write>(&self, snap: &Snapshot, execution: &Execution, buffer: &mut B) -> bool {
+        let empty_body = Vec::new();
+        let old_stdout = snap.stdout.as_ref().map_or(&empty_body, |data| &data.body);
+        let old_stderr = snap.stderr.as_ref().map_or(&empty_body, |data| &data.body);
+        let result = &execution.output;
+        let normalize = self.normalize.or(&snap.normalize);
+        let [stdout_verdict, stderr_verdict] = self.judge_outputs(snap, old_stdout, old_stderr, result);
+        let stdout_eq = stdout_verdict.as_ref().map_or_else(
+            || same_stdout(snap, &normalize, old_stdout, &result.stdout),
+            |verdict| verdict.same,
+        );
+        let stderr_eq = stderr_verdict.as_ref().map_or_else(
+            || same_stderr(snap, &normalize, old_stderr, &result.stderr),
+            |verdict| verdict.same,
+        );
+        if normalize.sort_lines && !(stdout_eq && stderr_eq) {
+            writeln!(buffer, "line order ignored").unwrap();
+        }
+        if execution.timed_out {
+            writeln!(buffer, "timed out").unwrap();
+        }
+        if execution.output_exceeded {
+            writeln!(buffer, "output exceeded limit").unwrap();
+        }
+        let too_slow = is_too_slow(snap, execution);
+        if let (true, Some(max)) = (too_slow, snap.max_duration_ms) {
+            let duration = execution.duration.as_millis() as u64;
+            writeln!(buffer, "too slow: {} > {}", term::format_duration(duration), term::format_duration(max)).unwrap();
+        }
+        let code_eq = snap.accepts_code(result.status.code()) && snap.signal == result.status.signal();
+        let code = |code: Option<i32>| code.map_or(String::from("None"), |code| code.to_string());
+        if !snap.accepts_code(result.status.code()) {
+            writeln!(buffer, "exit code: {} -> {}", snap.expected_code(), code(result.status.code())).unwrap();
+        }
+        if snap.signal != result.status.signal() {
+            let signal = |signal: Option<i32>| signal.map_or(String::from("None"), term::format_signal);
+            writeln!(buffer, "signal: {} -> {}", signal(snap.signal), signal(result.status.signal())).unwrap();
+        }
+        let outputs = [
+            ("stdout", stdout_eq, old_stdout, &result.stdout, stdout_verdict),
+            ("stderr", stderr_eq, old_stderr, &result.stderr, stderr_verdict),
+        ];
+        for (title, eq, old, new, verdict) in &outputs {
+            if !eq {
+                writeln!(buffer, "--- {}", title).unwrap();
+                if only_line_endings_differ(old, new) {
+                    writeln!(buffer, "only line endings differ").unwrap();
+                }
+                let structural = if *title == "stdout" { structural_differences(snap, old, new) } else { None };
+                let explanation = verdict.as_ref().map(|verdict| &verdict.explanation);
+                if let Some(failures) = snap.failed_assertions(*title == "stderr", new) {
+                    for failure in failures {
+                        writeln!(buffer, "{}", failure).unwrap();
+                    }
+                } else if let Some(explanation) = explanation.filter(|explanation| !explanation.is_empty()) {
+                    buffer.write_all(explanation).unwrap();
+                } else if let Some(differences) = structural {
+                    term::write_plain_structural_diff(snap.compare.name(), &differences, buffer);
+                } else if is_binary_output(snap, old, new) {
+                    term::write_plain_binary_diff(old, new, buffer);
+                } else if let Some(order) = snap.contains {
+                    let (old, new) = (normalize.apply(old), normalize.apply(new));
+                    term::write_plain_missing_sections(&missing_sections(&old, &new, order), buffer);
+                } else if normalize.sort_lines {
+                    let (old, new) = (normalize.apply(old), normalize.apply(new));
+                    term::write_plain_diff(&old, &mask_placeholders(&old, &new), buffer);
+                } else {
+                    term::write_plain_diff(old, &mask_placeholders(old, new), buffer);
+                }
+            }
+        }
+        !too_slow && code_eq && stdout_eq && stderr_eq
+    }
+
+    /// Handles archive subcommand.
+    pub fn archive(&mut self, name: &str) {
+        self.set_archived(name, true);
+        println!("Archived '{}'.", name);
+    }
+
+    /// Handles restore subcommand.
+    pub fn restore(&mut self, name: &str) {
+        self.set_archived(name, false);
+        println!("Restored '{}'.", name);
+    }
+
+    /// Handles history subcommand.
+    pub fn history(&mut self, name: &str) {
+        let mut stdout = stdout();
+        let versions = self.data.versions(name).unwrap_log();
+        if versions.is_empty() {
+            term::writeln(&format!("No previous version of '{}'.", name), &mut stdout);
+            return;
+        }
+        term::writeln(&format!("Previous versions of '{}', most recent first:", name), &mut stdout);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        for version in versions.iter().rev() {
+            let mut line = format!(
+                "{:>3}  replaced {}  code: {}",
+                version.number,
+                term::format_age(now.saturating_sub(version.replaced_at)),
+                version.exit_code.map_or("none".to_owned(), |code| code.to_string())
+            );
+            if let Some(signal) = version.signal {
+                line.push_str(&format!("  signal: {}", term::format_signal(signal)));
+            }
+            let outputs = vec![("stdout", version.stdout_len), ("stderr", version.stderr_len)];
+            for (output, len) in outputs.into_iter().filter_map(|(output, len)| Some((output, len?))) {
+                line.push_str(&format!("  {}: {}", output, term::format_size(len)));
+            }
+            for (file, len) in &version.artifacts {
+                match len {
+                    Some(len) => line.push_str(&format!("  {}: {}", file, term::format_size(*len))),
+                    None => line.push_str(&format!("  {}: not written", file)),
+                }
+            }
+            term::writeln(&line, &mut stdout);
+        }
+    }
+
+    /// Handles rollback subcommand, restores the most recent previous version
+    /// unless one is given. The replaced expectation is kept as a version in
+    /// turn.
+    pub fn rollback(&mut self, name: &str, to: Option<u32>) {
+        let number = match to {
+            Some(number) => number,
+            None => match self.data.versions(name).unwrap_log().last() {
+                Some(version) => version.number,
+                None => Error::from_str(&format!("No previous version of '{}'.", name)).unwrap_log(),
+            },
+        };
+        let expectation = self.data.version(name, number).unwrap_log();
+        let snap = self.data.get_snapshot(name).unwrap_log();
+        let mut snap = snap.borrow_mut();
+        if snap.corrupted().is_empty() {
+            self.data.keep_version(name, &snap.expectation()).unwrap_log();
+        }
+        util::set_expectation(&mut snap, expectation);
+        self.data.persist_snapshot_data(&mut snap).unwrap_log();
+        drop(snap); // Release the mutable borrow before persisting
+        self.data.persist_metadata().unwrap_log();
+        println!("Rolled '{}' back to version {}.", name, number);
+    }
+
+    /// Handles accept subcommand: the pending outputs of the snapshot, or of
+    /// all snapshots with pending outputs, become their expectation.
+    pub fn accept(&mut self, name: &Option<String>) {
+        for name in self.pending_names(name) {
+            let expectation = self.data.get_pending(&name).unwrap_log();
+            let snap = self.data.get_snapshot(&name).unwrap_log();
+            let mut snap = snap.borrow_mut();
+            if snap.corrupted().is_empty() {
+                self.data.keep_version(&name, &snap.expectation()).unwrap_log();
+            }
+            util::set_expectation(&mut snap, expectation);
+            snap.status = SnapshotStatus::Passed;
+            self.data.persist_snapshot_data(&mut snap).unwrap_log();
+            drop(snap); // Release the mutable borrow before persisting
+            self.data.persist_metadata().unwrap_log();
+            self.data.remove_pending(&name).unwrap_log();
+            println!("Accepted '{}'.", name);
+        }
+    }
+
+    /// Handles reject subcommand: the pending outputs of the snapshot, or of
+    /// all snapshots with pending outputs, are discarded.
+    pub fn reject(&mut self, name: &Option<String>) {
+        for name in self.pending_names(name) {
+            self.data.remove_pending(&name).unwrap_log();
+            println!("Rejected '{}'.", name);
+        }
+    }
+
+    /// Returns the given snapshot if it has pending outputs, or else all the
+    /// snapshots with pending outputs.
+    fn pending_names(&self, name: &Option<String>) -> Vec<String> {
+        let names = self.data.pending().unwrap_log();
+        match name {
+            Some(name) if !names.contains(name) => {
+                Error::from_str(&format!("No pending snapshot for '{}'.", name)).unwrap_log()
+            }
+            Some(name) => vec![name.to_owned()],
+            None if names.is_empty() => {
+                println!("No pending snapshot.");
+                names
+            }
+            None => names,
+        }
+    }
+
+    /// Handles import subcommand: the snapshots of another tool become parrot
+    /// snapshots, expecting the outputs they recorded.
+    pub fn import(&mut self, format: ImportFormat, path: &Path, yes: bool) {
+        self.data.create_suite().unwrap_log();
+        let mut stdout = stdout();
+        let (entries, warnings) = import::import(format, path, &self.path).unwrap_log();
+        for warning in &warnings {
+            term::writeln(warning, &mut stdout);
+        }
+        if entries.is_empty() {
+            term::writeln("No snapshot to import.", &mut stdout);
+            return;
+        }
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        for entry in entries {
+            let name = normalize_name(&entry.name);
+            if self.data.get_snapshot(&name).is_ok() || snapshots.iter().any(|snap| snap.name == name) {
+                Error::from_str::<()>(&format!("A snapshot named '{}' already exists.", name)).unwrap_log();
+            }
+            let output = Output {
+                status: std::process::ExitStatus::from_raw((entry.exit_code & 0xff) << 8),
+                stdout: entry.stdout,
+                stderr: entry.stderr,
+            };
+            let mut snap = to_snapshot(name, entry.description, Vec::new(), entry.cmd, entry.stdin, output);
+            snap.env = entry.env;
+            snap.combined = entry.combined;
+            snap.ignore_stderr = entry.ignore_stderr;
+            snap.drop_ignored_outputs();
+            snap.tags = snap.description.as_deref().map(editor::parse_tags).unwrap_or_default();
+            snapshots.push(snap);
+        }
+        let name_width = snapshots.iter().map(|snap| snap.name.len()).max().unwrap_or(0);
+        for snap in &snapshots {
+            term::list_item(snap, name_width, false, &mut stdout, &self.theme);
+        }
+        let count = snapshots.len();
+        let save = yes || term::binary_qestion(&format!("Import these {} snapshots?", count)).unwrap_log();
+        if save {
+            for snap in snapshots {
+                self.data.add_snapshot(snap).unwrap_log();
+            }
+            term::writeln(&format!("Imported {} snapshot(s).", count), &mut stdout);
+        }
+    }
+
+    /// Moves a snapshot in or out of the archive.
+    fn set_archived(&mut self, name: &str, archived: bool) {
+        let snap = self.data.get_snapshot(name).unwrap_log();
+        if snap.borrow().archived == archived {
+            let state = if archived { "already archived" } else { "not archived" };
+            Error::from_str::<()>(&format!("Snapshot '{}' is {}.", name, state)).unwrap_log();
+        }
+        snap.borrow_mut().archived = archived;
+        self.data.persist_metadata().unwrap_log();
+    }
+
+    /// Handles edit subcommand.
+    pub fn edit(&mut self, name: &str) {
+        let snap = self.data.get_snapshot(name).unwrap_log();
+        if self.edit_snapshot(&mut snap.borrow_mut(), &mut stdout()) {
+            self.data.persist_metadata().unwrap_log();
+        }
+    }
+
+    /// Handles rm subcommand.
+    pub fn remove(&mut self, name: &str, yes: bool) {
+        let snap = self.data.get_snapshot(name).unwrap_log();
+        let delete = if yes {
             true
         } else {
-            term::failure(&mut stdout);
-            false
+            self.show_snapshot(&snap.borrow(), &mut stdout());
+            term::binary_qestion("Delete this snapshot?").unwrap_log()
+        };
+        if delete {
+            self.data.remove_snapshot(name).unwrap_log();
+            println!("Deleted 1 snapshot.");
+        }
+    }
+
+    /// Handles rename subcommand.
+    pub fn rename(&mut self, name: &str, new_name: &str) {
+        let new_name = normalize_name(new_name);
+        self.data.rename_snapshot(name, &new_name).unwrap_log();
+        println!("Renamed '{}' to '{}'.", name, new_name);
+    }
+
+    /// Handles show subcommand.
+    /// In raw mode only the stored stdout is written, without any formatting.
+    pub fn show(&mut self, name: &str, raw: bool) {
+        let mut stdout = stdout();
+        let snap = self.data.get_snapshot(name).unwrap_log();
+        let snap = snap.borrow();
+        if raw {
+            if let Some(data) = &snap.stdout {
+                wrap(stdout.write_all(&data.body), "Unable to write to stdout").unwrap_log();
+            }
+        } else if self.json {
+            write_json(&SnapshotEntry::new(&snap, true), &mut stdout);
+        } else {
+            self.show_snapshot(&snap, &mut stdout);
+        }
+    }
+
+    /// Handles stats subcommand.
+    /// The statistics are computed over the active snapshots, from the history
+    /// of their last runs.
+    pub fn stats(&mut self, top: usize) {
+        let mut stdout = stdout();
+        let snapshots = self.data.get_active_snapshots().unwrap_log();
+        let snaps: Vec<_> = snapshots.iter().map(|snap| snap.borrow()).collect();
+        let snaps: Vec<&Snapshot> = snaps.iter().map(|snap| &**snap).collect();
+        let stats = StatsReport::new(&snaps, top);
+        if self.json {
+            write_json(&stats, &mut stdout);
+            return;
+        }
+        if snaps.is_empty() {
+            term::writeln("No snapshot.", &mut stdout);
+            return;
+        }
+        let tag_width = stats.by_tag.keys().map(|tag| tag.len() + 1).max().unwrap_or(0);
+        let width = tag_width.max("untagged".len());
+        term::writeln("By status", &mut stdout);
+        for (status, group) in &stats.by_status {
+            term::writeln(&format!("  {:<width$}  {}", status, group.summary(), width = width), &mut stdout);
+        }
+        term::writeln("By tag", &mut stdout);
+        for (tag, group) in &stats.by_tag {
+            let tag = format!("#{}", tag);
+            term::writeln(&format!("  {:<width$}  {}", tag, group.summary(), width = width), &mut stdout);
+        }
+        if stats.untagged.snapshots > 0 {
+            let untagged = stats.untagged.summary();
+            term::writeln(&format!("  {:<width$}  {}", "untagged", untagged, width = width), &mut stdout);
+        }
+        if !stats.slowest.is_empty() {
+            let width = stats.slowest.iter().map(|snap| snap.name.len()).max().unwrap_or(0);
+            term::writeln("Slowest (average runtime)", &mut stdout);
+            for snap in &stats.slowest {
+                let duration = term::format_duration(snap.average_ms);
+                term::writeln(&format!("  {:<width$}  {}", snap.name, duration, width = width), &mut stdout);
+            }
+        }
+        let width = stats.largest.iter().map(|snap| snap.name.len()).max().unwrap_or(0);
+        term::writeln("Largest", &mut stdout);
+        for snap in &stats.largest {
+            let size = term::format_size(snap.size);
+            term::writeln(&format!("  {:<width$}  {}", snap.name, size, width = width), &mut stdout);
+        }
+    }
+
+    /// Handles status subcommand.
+    pub fn status(&mut self) {
+        let mut stdout = stdout();
+        let snapshots = self.data.get_all_snapshots().unwrap_log();
+        let current_run = self.data.current_run();
+        let (mut passed, mut failed, mut waiting, mut stale, mut archived) = (0, 0, 0, 0, 0);
+        for snap in &snapshots {
+            let snap = snap.borrow();
+            if snap.archived {
+                archived += 1;
+                continue;
+            }
+            match snap.status {
+                SnapshotStatus::Passed => passed += 1,
+                SnapshotStatus::Failed => failed += 1,
+                SnapshotStatus::Waiting => waiting += 1,
+            }
+            if let Some(run) = snap.last_run {
+                if run < current_run {
+                    stale += 1;
+                }
+            }
+        }
+        let size = self.data.disk_usage().unwrap_log();
+        if self.json {
+            let report = StatusReport {
+                snapshots: snapshots.len(),
+                passed,
+                failed,
+                never_run: waiting,
+                stale,
+                archived,
+                disk_usage: size,
+            };
+            write_json(&report, &mut stdout);
+            return;
+        }
+        term::writeln(&format!("Snapshots:  {}", snapshots.len()), &mut stdout);
+        term::writeln(&format!("Passed:     {}", passed), &mut stdout);
+        term::writeln(&format!("Failed:     {}", failed), &mut stdout);
+        term::writeln(&format!("Never run:  {}", waiting), &mut stdout);
+        term::writeln(&format!("Stale:      {} (not executed by the last run)", stale), &mut stdout);
+        term::writeln(&format!("Archived:   {}", archived), &mut stdout);
+        term::writeln(&format!("Disk usage: {}", term::format_size(size)), &mut stdout);
+    }
+
+    /// Handles tag add subcommand.
+    pub fn tag_add(&mut self, tag: &str, name: &Option<String>, all: bool) {
+        let count = self.edit_tags(tag, name, all, add_tag);
+        println!("Tagged {} snapshot(s) with #{}.", count, tag.trim_start_matches('#'));
+    }
+
+    /// Handles tag rm subcommand.
+    pub fn tag_remove(&mut self, tag: &str, name: &Option<String>, all: bool) {
+        let count = self.edit_tags(tag, name, all, remove_tag);
+        println!("Removed #{} from {} snapshot(s).", tag.trim_start_matches('#'), count);
+    }
+
+    /// Handles tag list subcommand.
+    pub fn tag_list(&mut self) {
+        let mut stdout = stdout();
+        let snapshots = self.data.get_all_snapshots().unwrap_log();
+        let mut tags = BTreeMap::new();
+        for snap in &snapshots {
+            for tag in &snap.borrow().tags {
+                *tags.entry(tag.to_owned()).or_insert(0) += 1;
+            }
+        }
+        if tags.is_empty() {
+            term::writeln("No tag.", &mut stdout);
+        }
+        for (tag, count) in tags {
+            term::writeln(&format!("#{} ({})", tag, count), &mut stdout);
+        }
+    }
+
+    /// Handles trash list subcommand.
+    pub fn trash_list(&self) {
+        let mut stdout = stdout();
+        let trashed = self.data.trash().unwrap_log();
+        if trashed.is_empty() {
+            term::writeln("No snapshot in the trash.", &mut stdout);
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        for snap in trashed {
+            let age = term::format_age(now.saturating_sub(snap.removed_at));
+            term::writeln(&format!("{}  removed {}", snap.name, age), &mut stdout);
+        }
+    }
+
+    /// Handles trash restore subcommand.
+    pub fn trash_restore(&mut self, name: &str) {
+        self.data.restore_snapshot(name).unwrap_log();
+        println!("Restored '{}'.", name);
+    }
+
+    /// Handles trash empty subcommand.
+    pub fn trash_empty(&self) {
+        let count = self.data.empty_trash().unwrap_log();
+        println!("Deleted {} snapshot(s) from the trash.", count);
+    }
+
+    /// Applies a tag edition to the snapshots matching the name pattern, or to
+    /// all of them if `all` is set.
+    /// Returns the number of modified snapshots.
+    fn edit_tags(
+        &mut self,
+        tag: &str,
+        name: &Option<String>,
+        all: bool,
+        edit: fn(&mut Snapshot, &str) -> bool,
+    ) -> usize {
+        let tag = tag.trim_start_matches('#');
+        if !is_valid_tag(tag) {
+            let message = format!("Invalid tag '{}', only use letters, digits, '-' and '_'.", tag);
+            Error::from_str::<()>(&message).unwrap_log();
+        }
+        if name.is_none() && !all {
+            Error::from_str::<()>("No snapshot selected, pass --name or --all.").unwrap_log();
+        }
+        let snapshots = self.data.get_active_snapshots().unwrap_log();
+        let mut view = repl::View::new(snapshots);
+        if let Some(name) = name {
+            view.apply_filter(Filter::Name(name.to_owned()));
+        }
+        let mut count = 0;
+        for snap in view.get_view() {
+            if edit(&mut snap.borrow_mut(), tag) {
+                count += 1;
+            }
+        }
+        if count > 0 {
+            self.data.persist_metadata().unwrap_log();
+        }
+        count
+    }
+
+    /// Handles update subcommand.
+    /// Either a pattern or `all` must be provided, if `failed_only` is set the
+    /// snapshots are checked first and only the failing ones are updated.
+    /// In dry run mode nothing is executed, `failed_only` then relies on the
+    /// status of the last run.
+    pub fn update(&mut self, pattern: &Option<String>, all: bool, failed_only: bool, dry_run: bool, no_cache: bool) {
+        if pattern.is_none() && !all {
+            Error::from_str::<()>("Nothing to update, pass a pattern or --all.").unwrap_log();
+        }
+        let mut stdout = stdout();
+        let snapshots = self.data.get_active_snapshots().unwrap_log();
+        let mut view = repl::View::new(snapshots);
+        if let Some(pattern) = pattern {
+            view.apply_filter(Filter::Name(pattern.to_owned()));
+        }
+        if dry_run {
+            if failed_only {
+                view.apply_filter(Filter::Failed);
+            }
+            self.list_view(&view, "updated", &mut stdout);
+            return;
+        }
+        let mut count = 0;
+        let mut cached = 0;
+        for snap in view.get_view() {
+            let mut snap = snap.borrow_mut();
+            // The input is read first, an output failing to be read is
+            // reported like a command failing to be executed
+            let loaded = snap.load();
+            // The outputs can't have changed since the last successful run
+            let key = loaded.as_ref().ok().and_then(|_| cache_key(&snap, &self.redact, &self.path));
+            if !no_cache && key.is_some() && key == snap.cache_key {
+                cached += 1;
+                continue;
+            }
+            let execution = match loaded.and_then(|_| self.execute(&snap)) {
+                Ok(execution) => execution,
+                Err(err) => {
+                    term::write_lines(&format!("{} not updated: {}", snap.name, describe_error(&err)), &mut stdout);
+                    continue;
+                }
+            };
+            if failed_only && self.check_snapshot(&mut snap, &execution, &mut stdout) {
+                continue;
+            }
+            if execution.timed_out {
+                term::writeln(&format!("{} timed out, not updated.", snap.name), &mut stdout);
+                continue;
+            }
+            if execution.output_exceeded {
+                term::writeln(&format!("{} output exceeded the size limit, not updated.", snap.name), &mut stdout);
+                continue;
+            }
+            snap.cache_key = key;
+            let changes = self.apply_result(&mut snap, execution);
+            if !changes.is_empty() {
+                self.data.persist_snapshot_data(&mut snap).unwrap_log();
+                term::writeln(&format!("Updated {}: {}", snap.name, changes.join(", ")), &mut stdout);
+                count += 1;
+            }
+        }
+        self.data.persist_state().unwrap_log();
+        if cached > 0 {
+            term::writeln(
+                &format!("{} snapshot(s) skipped, their inputs did not change since they last passed.", cached),
+                &mut stdout,
+            );
+        }
+        if count > 0 {
+            self.data.persist_metadata().unwrap_log();
+            if count == 1 {
+                term::writeln("Updated 1 snapshot.", &mut stdout);
+            } else {
+                term::writeln(&format!("Updated {} snapshots.", count), &mut stdout);
+            }
+        } else {
+            term::writeln("Nothing to do.", &mut stdout);
+        }
+    }
+
+    /// Handles review subcommand.
+    /// All snapshots are run, then failing snapshots are reviewed one at a time.
+    pub fn review(&mut self) {
+        let mut stdout = stdout();
+        let snapshots = self.data.get_active_snapshots().unwrap_log();
+        self.data.start_run().unwrap_log();
+        let mut failures = Vec::new();
+        for snap in snapshots {
+            let result = self.execute(&snap.borrow());
+            match result {
+                Ok(execution) => {
+                    if !self.check_snapshot(&mut snap.borrow_mut(), &execution, &mut sink()) {
+                        failures.push((snap, execution));
+                    }
+                }
+                // There is no output to accept
+                Err(err) => self.check_error(&mut snap.borrow_mut(), &describe_error(&err), &mut stdout),
+            }
+        }
+        self.data.persist_state().unwrap_log();
+        if failures.is_empty() {
+            term::success(&mut stdout, &self.theme);
+            return;
+        }
+        let n = failures.len();
+        let (mut accepted, mut rejected, mut skipped) = (0, 0, 0);
+        for (idx, (snap, execution)) in failures.into_iter().enumerate() {
+            let mut snap = snap.borrow_mut();
+            self.check_snapshot(&mut snap, &execution, &mut stdout);
+            let question = format!("[{}/{}] a(ccept), r(eject), s(kip) or q(uit)?", idx + 1, n);
+            match term::key_question(&question, &['a', 'r', 's', 'q']).unwrap_log() {
+                'a' => {
+                    self.apply_result(&mut snap, execution);
+                    self.data.persist_snapshot_data(&mut snap).unwrap_log();
+                    drop(snap); // Release the mutable borrow to allow data.persist
+                    self.data.persist_metadata().unwrap_log();
+                    accepted += 1;
+                }
+                'r' => rejected += 1,
+                's' => skipped += 1,
+                _ => break,
+            }
+        }
+        println!("{} accepted, {} rejected, {} skipped.", accepted, rejected, skipped);
+    }
+
+    /// Handles resolve subcommand.
+    /// Shows the diff of both versions of each conflicted snapshot against the
+    /// current output of its command, and asks which one to keep. Nothing is
+    /// changed until all of them are resolved.
+    pub fn resolve(&mut self) {
+        let mut stdout = stdout();
+        let conflicts = self.data.conflicts().unwrap_log();
+        if conflicts.is_empty() {
+            term::writeln("No conflict in the snapshots.", &mut stdout);
+            return;
+        }
+        let n = conflicts.len();
+        let (mut resolved, mut rerecorded) = (Vec::new(), Vec::new());
+        for (idx, conflict) in conflicts.into_iter().enumerate() {
+            let sides = [
+                ("the current branch (ours)", &conflict.ours),
+                ("the merged branch (theirs)", &conflict.theirs),
+            ];
+            for (side, snap) in sides.iter() {
+                match snap {
+                    Some(snap) => {
+                        term::writeln(&format!("{}, in {}:", conflict.name, side), &mut stdout);
+                        match self.execute(snap) {
+                            Ok(execution) => {
+                                if self.draw_diff(snap, &execution, &mut stdout) {
+                                    term::writeln("No difference with the current output.", &mut stdout);
+                                }
+                            }
+                            Err(err) => term::write_lines(&describe_error(&err), &mut stdout),
+                        }
+                    }
+                    None => term::writeln(&format!("{}, removed in {}.", conflict.name, side), &mut stdout),
+                }
+            }
+            let question = format!("[{}/{}] o(urs), t(heirs), r(e-record) or q(uit)?", idx + 1, n);
+            match term::key_question(&question, &['o', 't', 'r', 'q']).unwrap_log() {
+                'o' => resolved.push((conflict.name, conflict.ours)),
+                't' => resolved.push((conflict.name, conflict.theirs)),
+                'r' => {
+                    rerecorded.push(conflict.name.clone());
+                    resolved.push((conflict.name, conflict.ours.or(conflict.theirs)));
+                }
+                _ => {
+                    term::writeln("Nothing resolved.", &mut stdout);
+                    return;
+                }
+            }
+        }
+        self.data.resolve_conflicts(resolved).unwrap_log();
+        self.data.start_run().unwrap_log();
+        for name in rerecorded {
+            let snap = self.data.get_snapshot(&name).unwrap_log();
+            let mut snap = snap.borrow_mut();
+            if self.update_snapshot(&mut snap, &mut stdout) {
+                self.data.persist_snapshot_data(&mut snap).unwrap_log();
+            }
+        }
+        self.data.persist_metadata().unwrap_log();
+        let message = format!("Resolved {} snapshot(s), add the parrot folder to mark the conflicts as resolved.", n);
+        term::writeln(&message, &mut stdout);
+    }
+
+    /// Handles clean subcommand.
+    pub fn clean(&mut self, dry_run: bool) {
+        let mut stdout = stdout();
+        let orphans = self.data.clean(dry_run).unwrap_log();
+        let (file_msg, snap_msg) = if dry_run {
+            ("Would delete orphan file", "Would remove snapshot with missing files")
+        } else {
+            ("Deleted orphan file", "Removed snapshot with missing files")
+        };
+        for snap in &orphans.snapshots {
+            term::writeln(&format!("{}: {}", snap_msg, snap), &mut stdout);
+        }
+        for file in &orphans.files {
+            term::writeln(&format!("{}: {}", file_msg, file), &mut stdout);
+        }
+        if orphans.snapshots.is_empty() && orphans.files.is_empty() {
+            term::writeln("Nothing to clean.", &mut stdout);
         }
     }
 
+    /// Handles gc subcommand.
+    pub fn gc(&mut self, dry_run: bool, aggressive: bool, keep_history: Option<usize>) {
+        let mut stdout = stdout();
+        let garbage = self.data.gc(dry_run, aggressive, keep_history).unwrap_log();
+        let categories = vec![
+            (&garbage.files, "orphan file(s)"),
+            (&garbage.divergent, "divergent output(s)"),
+            (&garbage.blobs, "unreferenced output(s)"),
+            (&garbage.versions, "version(s) from the history"),
+        ];
+        let verb = if dry_run { "Would delete" } else { "Deleted" };
+        let (mut count, mut total) = (0, 0);
+        for (collected, what) in categories.into_iter().filter(|(collected, _)| collected.count > 0) {
+            let size = term::format_size(collected.size);
+            term::writeln(&format!("{} {} {}, {}.", verb, collected.count, what, size), &mut stdout);
+            count += collected.count;
+            total += collected.size;
+        }
+        if count == 0 {
+            term::writeln("Nothing to collect.", &mut stdout);
+        } else {
+            let verb = if dry_run { "Would free" } else { "Freed" };
+            term::writeln(&format!("{} {} in total.", verb, term::format_size(total)), &mut stdout);
+        }
+    }
+
+    /// Handles migrate subcommand.
+    pub fn migrate(&mut self) {
+        let version = self.data.migrate().unwrap_log();
+        let message = if version < SCHEMA_VERSION {
+            format!("Migrated the parrot folder from format {} to {}.", version, SCHEMA_VERSION)
+        } else {
+            format!("The parrot folder is up to date (format {}).", SCHEMA_VERSION)
+        };
+        term::writeln(&message, &mut stdout());
+    }
+
+    /// Handles doctor subcommand.
+    /// Returns true if no problem was found, false otherwise.
+    pub fn doctor(&mut self) -> bool {
+        let mut stdout = stdout();
+        let (names, problems) = match self.data.check() {
+            Ok(result) => result,
+            Err(err) => {
+                term::writeln(&format!("✗ {}", err.message), &mut stdout);
+                if let Some(cause) = err.cause {
+                    term::writeln(&format!("  cause: {}", cause), &mut stdout);
+                }
+                term::writeln(
+                    "  Restore .parrot/metadata.json from version control, or run `parrot init` in a new folder.",
+                    &mut stdout,
+                );
+                return false;
+            }
+        };
+        let mut messages = Vec::new();
+        for name in &names {
+            let normalized = normalize_name(name);
+            if normalized.is_empty() {
+                messages.push(String::from("A snapshot has an empty name, give it one in metadata.json."));
+            } else if check_name(name).is_err() {
+                messages.push(format!("Snapshot name '{}' is invalid, give it a file name in metadata.json.", name));
+            } else if &normalized != name {
+                messages.push(format!(
+                    "Snapshot name '{}' is not normalized, fix it with `parrot rename '{}' {}`.",
+                    name, name, normalized
+                ));
+            }
+        }
+        for problem in problems {
+            messages.push(match problem {
+                Problem::MissingFile { snapshot, file } => format!(
+                    "File '{}' of snapshot '{}' is missing, record it again with `parrot update '{}'` or drop the snapshot with `parrot clean`.",
+                    file, snapshot, snapshot
+                ),
+                Problem::MissingGolden { snapshot, file } => format!(
+                    "Golden file '{}' of snapshot '{}' is missing, record it again with `parrot update '{}'`.",
+                    file, snapshot, snapshot
+                ),
+                Problem::SizeMismatch {
+                    snapshot,
+                    file,
+                    expected,
+                    actual,
+                } => format!(
+                    "File '{}' of snapshot '{}' is {} bytes long but {} bytes were recorded, it may be corrupted: restore it from version control or record it again with `parrot update '{}'`.",
+                    file, snapshot, actual, expected, snapshot
+                ),
+                Problem::ChecksumMismatch { snapshot, file } => format!(
+                    "File '{}' of snapshot '{}' does not match its checksum: unless it was edited on purpose, which a passing `parrot run` adopts, it is corrupted, re-record it with `parrot update '{}'` or restore it from the history with `parrot rollback '{}'`.",
+                    file, snapshot, snapshot, snapshot
+                ),
+                Problem::DuplicateName { name, count } => format!(
+                    "{} snapshots are named '{}', rename all but one in metadata.json.",
+                    count, name
+                ),
+                Problem::MissingDependency { snapshot, needs } => format!(
+                    "Snapshot '{}' needs '{}', which does not exist: fix its `needs` in metadata.json.",
+                    snapshot, needs
+                ),
+                Problem::UnreferencedFile(file) => format!(
+                    "File '{}' does not belong to any snapshot, delete it with `parrot clean`.",
+                    file
+                ),
+                Problem::InvalidState(err) => format!("{} Delete .parrot/state.json to reset it.", err.message),
+                Problem::OutdatedSchema(version) => format!(
+                    "The parrot folder is in format {}, upgrade it to format {} with `parrot migrate`.",
+                    version, SCHEMA_VERSION
+                ),
+            });
+        }
+        for message in &messages {
+            term::writeln(&format!("✗ {}", message), &mut stdout);
+        }
+        match messages.len() {
+            0 => term::writeln("No problem found.", &mut stdout),
+            1 => term::writeln("Found 1 problem.", &mut stdout),
+            n => term::writeln(&format!("Found {} problems.", n), &mut stdout),
+        }
+        messages.is_empty()
+    }
+
     /// Hnadles the exec subcommand.
     pub fn exec(&mut self, commands: &str) {
         let (mut view, mut repl) = self.get_view_and_repl();
@@ -114,6 +1608,7 @@ impl Context {
                     ReplStatus::Continue => (),
                 },
             }
+            self.data.release_outputs().unwrap_log();
         }
         // Clear the REPL befor exiting
         repl.suspend();
@@ -121,11 +1616,11 @@ impl Context {
 
     /// Returns a new View and Repl.
     fn get_view_and_repl(&mut self) -> (View, term::Repl) {
-        let snapshots = self.data.get_all_snapshots().unwrap_log();
+        let snapshots = self.data.get_active_snapshots().unwrap_log();
         let view = repl::View::new(snapshots);
         let stdout = stdout();
         let stdin = stdin();
-        let repl = term::Repl::new(stdin, stdout);
+        let repl = term::Repl::new(stdin, stdout, self.theme.clone());
         (view, repl)
     }
 
@@ -157,7 +1652,7 @@ impl Context {
 
     /// Executes the help command.
     fn execute_help(&self, repl: &mut term::Repl) {
-        term::help::write_help(&mut repl.stdout);
+        term::help::write_help(&mut repl.stdout, &self.theme);
     }
 
     /// Executes the edit command.
@@ -176,17 +1671,22 @@ impl Context {
     /// Executes the run command.
     fn execute_run(&mut self, repl: &mut term::Repl, view: &View, target: Target) {
         repl.suspend();
+        self.data.start_run().unwrap_log();
         let success = match target {
-            Target::All => self.run_view(&view, &mut repl.stdout),
+            Target::All => {
+                let reports = self.run_view(view, None, 1, &mut repl.stdout).unwrap_log();
+                reports.iter().all(|report| report.passed())
+            }
             Target::Selected => match view.get_selected_mut() {
-                Some(mut snap) => self.run_snapshot(&mut snap, &mut repl.stdout),
+                Some(mut snap) => self.run_snapshot(&mut snap, &mut repl.stdout).passed(),
                 None => true,
             },
         };
+        self.data.persist_state().unwrap_log();
         if success {
-            term::success(&mut repl.stdout);
+            term::success(&mut repl.stdout, &self.theme);
         } else {
-            term::failure(&mut repl.stdout);
+            term::failure(&mut repl.stdout, &self.theme);
         }
     }
 
@@ -203,10 +1703,11 @@ impl Context {
     fn execute_show(&self, repl: &mut term::Repl, view: &View, target: Target) {
         repl.suspend();
         match target {
-            Target::Selected => match view.get_selected() {
-                Some(snap) => self.show_snapshot(&snap, &mut repl.stdout),
-                None => (),
-            },
+            Target::Selected => {
+                if let Some(snap) = view.get_selected() {
+                    self.show_snapshot(&snap, &mut repl.stdout);
+                }
+            }
             Target::All => {
                 for snap in view.get_view() {
                     self.show_snapshot(&snap.borrow(), &mut repl.stdout);
@@ -244,21 +1745,378 @@ impl Context {
         view.apply_filter(Filter::Deleted);
     }
 
-    /// Runs only commands from the given view.
-    fn run_view<B: Write>(&mut self, view: &View, buffer: &mut B) -> bool {
-        let mut success = true;
-        for snap in view.get_view() {
-            let pass = self.run_snapshot(&mut snap.borrow_mut(), buffer);
-            success = success && pass;
+    /// Lists the snapshots of a view, followed by a count of the snapshots that
+    /// would be run or updated (depending on `action`).
+    fn list_view<B: Write>(&self, view: &View, action: &str, buffer: &mut B) {
+        let snaps = view.get_view();
+        if self.json {
+            write_entries(snaps, buffer);
+            return;
         }
-        success
+        let name_width = snaps.iter().map(|snap| snap.borrow().name.len()).max().unwrap_or(0);
+        for snap in snaps {
+            term::list_item(&snap.borrow(), name_width, false, buffer, &self.theme);
+        }
+        term::writeln(&format!("{} snapshot(s) would be {}.", snaps.len(), action), buffer);
+    }
+
+    /// Runs only commands from the given view on `jobs` threads, stops after
+    /// `max_failures` failures if any.
+    /// Snapshots are run after the ones they need, and skipped if one of them
+    /// failed. Otherwise they are checked in the order of the view, whatever
+    /// the order in which their commands complete, so that diffs are never
+    /// interleaved.
+    /// Returns the report of each executed or skipped snapshot, or an error if
+    /// the snapshots need each other.
+    fn run_view<B: Write>(
+        &mut self,
+        view: &View,
+        max_failures: Option<usize>,
+        jobs: usize,
+        buffer: &mut B,
+    ) -> Result<Vec<SnapshotReport>, Error> {
+        let snaps = view.get_view();
+        let needs: Vec<(String, Vec<String>)> = snaps
+            .iter()
+            .map(|snap| (snap.borrow().name.clone(), snap.borrow().needs.clone()))
+            .collect();
+        let mut tally = Tally::new(max_failures);
+        // The progress goes to stderr, it is left out of the JSON output
+        let mut progress = term::Progress::new(snaps.len(), !self.json && self.verbosity != Verbosity::Quiet);
+        'levels: for level in order_by_needs(&needs)? {
+            let mut runnable = Vec::new();
+            for idx in level {
+                let mut snap = snaps[idx].borrow_mut();
+                progress.clear();
+                let report = match snap.needs.iter().find(|needs| tally.failed.contains(*needs)) {
+                    Some(needs) => self.skip_snapshot(&snap, needs, buffer),
+                    // A snapshot whose stored outputs can't be read is not run
+                    None => match snap.load() {
+                        Ok(()) => {
+                            runnable.push(idx);
+                            continue;
+                        }
+                        Err(err) => self.check_result(&mut snap, Err(err), Duration::ZERO, 0, buffer),
+                    },
+                };
+                if !tally.record(report) {
+                    break 'levels;
+                }
+            }
+            if jobs > 1 {
+                let commands: Vec<cmd::Job> = runnable.iter().map(|&idx| self.to_job(&snaps[idx].borrow())).collect();
+                let mut stopped = false;
+                // The attempts of a snapshot are handled in a row
+                let (mut total, mut attempts) = (Duration::ZERO, 0);
+                progress.update(tally.reports.len(), tally.failures, None);
+                cmd::execute_parallel(&commands, &self.path, jobs, |idx, result, duration| {
+                    let mut snap = snaps[runnable[idx]].borrow_mut();
+                    let result = result.and_then(|mut execution| {
+                        let (filters, redact) = (&snap.filters, &snap.redact);
+                        self.redact_output(snap.strip_ansi, filters, redact, &snap.pipeline, &mut execution)?;
+                        Ok(execution)
+                    });
+                    total += duration;
+                    attempts += 1;
+                    if self.should_retry(&snap, &result, attempts) {
+                        return cmd::Next::Retry;
+                    }
+                    progress.clear();
+                    stopped = !tally.record(self.check_result(&mut snap, result, total, attempts, buffer));
+                    progress.update(tally.reports.len(), tally.failures, None);
+                    (total, attempts) = (Duration::ZERO, 0);
+                    if stopped {
+                        cmd::Next::Stop
+                    } else {
+                        cmd::Next::Continue
+                    }
+                });
+                if stopped {
+                    break;
+                }
+            } else {
+                for idx in runnable {
+                    let snap = &snaps[idx];
+                    progress.update(tally.reports.len(), tally.failures, Some(&snap.borrow().name));
+                    let (result, duration, attempts) = self.execute_attempts(&snap.borrow());
+                    progress.clear();
+                    let report = self.check_result(&mut snap.borrow_mut(), result, duration, attempts, buffer);
+                    if !tally.record(report) {
+                        break 'levels;
+                    }
+                }
+            }
+        }
+        progress.clear();
+        Ok(tally.reports)
+    }
+
+    /// Reports a snapshot that is not run because a snapshot it needs failed.
+    fn skip_snapshot<B: Write>(&self, snap: &Snapshot, needs: &str, buffer: &mut B) -> SnapshotReport {
+        let theme = &self.theme;
+        term::box_separator(&snap.name, SeparatorKind::Top, buffer, theme);
+        term::snap_summary(snap.description.as_ref(), &snap.cmd, snap.exit_code, snap.signal, buffer, theme);
+        term::skipped(needs, buffer, theme);
+        term::box_separator("", SeparatorKind::Bottom, buffer, theme);
+        SnapshotReport::skipped(snap)
     }
 
     /// Runs a single snapshot.
-    fn run_snapshot<B: Write>(&self, snap: &mut Snapshot, buffer: &mut B) -> bool {
+    fn run_snapshot<B: Write>(&self, snap: &mut Snapshot, buffer: &mut B) -> SnapshotReport {
+        let (result, duration, attempts) = self.execute_attempts(snap);
+        self.check_result(snap, result, duration, attempts, buffer)
+    }
+
+    /// Executes the command of a snapshot until it passes, up to its number of
+    /// retries.
+    /// Returns the result of the last attempt, along with the total duration
+    /// and the number of attempts.
+    fn execute_attempts(&self, snap: &Snapshot) -> (Result<Execution, Error>, Duration, u32) {
+        let (mut duration, mut attempts) = (Duration::ZERO, 0);
+        loop {
+            let start = Instant::now();
+            let result = self.execute(snap);
+            duration += start.elapsed();
+            attempts += 1;
+            if !self.should_retry(snap, &result, attempts) {
+                return (result, duration, attempts);
+            }
+        }
+    }
+
+    /// Returns true if a snapshot whose last attempt had this result is to be
+    /// executed again: it failed and has retries left.
+    fn should_retry(&self, snap: &Snapshot, result: &Result<Execution, Error>, attempts: u32) -> bool {
+        attempts <= snap.retries && result.as_ref().map_or(true, |execution| self.works(snap, execution) == snap.xfail)
+    }
+
+    /// Checks the result of the last attempt of a snapshot execution and
+    /// records it in the snapshot's history.
+    /// A snapshot which could not be executed fails, with the error as its
+    /// diff.
+    fn check_result<B: Write>(
+        &self,
+        snap: &mut Snapshot,
+        result: Result<Execution, Error>,
+        duration: Duration,
+        attempts: u32,
+        buffer: &mut B,
+    ) -> SnapshotReport {
+        let execution = match result {
+            Ok(execution) => execution,
+            Err(err) => {
+                let message = describe_error(&err);
+                if self.verbosity >= Verbosity::Verbose {
+                    term::run_line(&snap.name, &snap.cmd, duration.as_millis() as u64, buffer, &self.theme);
+                }
+                self.check_error(snap, &message, buffer);
+                let mut report = SnapshotReport::errored(snap, &message, duration, attempts);
+                if self.keep_diffs {
+                    report.diff = Some(message);
+                }
+                snap.record_run(RunRecord {
+                    run: self.data.current_run(),
+                    passed: false,
+                    duration_ms: report.duration_ms,
+                });
+                return report;
+            }
+        };
+        let mut report = SnapshotReport::new(snap, &execution.output, duration, &self.normalize.or(&snap.normalize));
+        report.timed_out = execution.timed_out;
+        report.output_exceeded = execution.output_exceeded;
+        report.too_slow = is_too_slow(snap, &execution);
+        report.attempts = attempts;
+        report.changed_artifacts = snap
+            .changed_artifacts(&self.normalize.or(&snap.normalize), &execution.artifacts)
+            .into_iter()
+            .map(|(artifact, _)| artifact.file.clone())
+            .collect();
+        if self.verbosity >= Verbosity::Verbose {
+            term::run_line(&snap.name, &snap.cmd, report.duration_ms, buffer, &self.theme);
+        }
+        let passed = self.check_snapshot(snap, &execution, buffer);
+        if passed && self.verbosity == Verbosity::Outputs {
+            term::snap_preview(&execution.output, buffer, &self.theme);
+        }
+        report.flaky = passed && attempts > 1;
+        report.expected_failure = passed && snap.xfail;
+        report.unexpected_pass = !passed && snap.xfail;
+        if passed && !snap.xfail && snap.comparator.is_some() {
+            // The comparator judged the outputs the same, even if they differ.
+            report.stdout = DiffSummary::same(report.stdout.expected_bytes, report.stdout.actual_bytes);
+            report.stderr = DiffSummary::same(report.stderr.expected_bytes, report.stderr.actual_bytes);
+        }
+        if !passed {
+            if self.keep_diffs {
+                let mut diff = Vec::new();
+                self.write_plain_diff(snap, &execution, &mut diff);
+                report.diff = Some(String::from_utf8_lossy(&diff).into_owned());
+            }
+            if !snap.xfail && !execution.timed_out && !execution.output_exceeded {
+                report.pending = Some(util::to_expectation(snap, &execution));
+            }
+            report.output = Some(execution.output);
+        }
+        snap.record_run(RunRecord {
+            run: self.data.current_run(),
+            passed,
+            duration_ms: report.duration_ms,
+        });
+        report.status = snap.status;
+        report
+    }
+
+    /// Executes the command of a snapshot, its outputs are redacted. Fails if
+    /// the stored outputs of the snapshot can't be read, as they are compared
+    /// to the new ones.
+    fn execute(&self, snap: &Snapshot) -> Result<Execution, Error> {
+        snap.load()?;
+        let mut execution = cmd::execute(&self.to_job(snap), &self.path)?;
+        self.redact_output(snap.strip_ansi, &snap.filters, &snap.redact, &snap.pipeline, &mut execution)?;
+        Ok(execution)
+    }
+
+    /// Replaces the paths with placeholders and the patterns of the
+    /// configuration, then the ones of the given filters and the given
+    /// patterns, in the outputs and artifacts of a command, then
+    /// applies the steps of the given pipeline. ANSI escape sequences are
+    /// removed first if `strip_ansi` is set.
+    fn redact_output(
+        &self,
+        strip_ansi: bool,
+        filters: &[String],
+        redact: &[Redaction],
+        pipeline: &[Transform],
+        execution: &mut Execution,
+    ) -> Result<(), Error> {
+        let filters = filter_redactions(filters)?;
+        let output = &mut execution.output;
+        let artifacts = execution.artifacts.iter_mut().flatten();
+        for body in vec![&mut output.stdout, &mut output.stderr].into_iter().chain(artifacts) {
+            if strip_ansi {
+                *body = term::strip_ansi(body);
+            }
+            for redactions in &[&self.paths[..], &self.redact[..], &filters, redact] {
+                *body = apply_redactions(body, redactions)?;
+            }
+            *body = apply_pipeline(body, pipeline)?;
+        }
+        Ok(())
+    }
+
+    /// Executes the command of a new snapshot, fails if it timed out.
+    /// The default timeout applies if the job has none.
+    fn execute_new(&self, mut job: cmd::Job) -> Execution {
+        if job.timeout.is_none() {
+            job.timeout = self.timeout.map(Duration::from_secs);
+        }
+        let execution = cmd::execute(&job, &self.path).unwrap_log();
+        if execution.timed_out {
+            Error::from_str::<()>(&format!("'{}' timed out.", job.cmd)).unwrap_log();
+        }
+        if execution.output_exceeded {
+            Error::from_str::<()>(&format!("The output of '{}' exceeded the size limit.", job.cmd)).unwrap_log();
+        }
+        execution
+    }
+
+    /// Makes a golden file hold the expected stdout of a new snapshot: an
+    /// existing file is the expected stdout, otherwise the actual stdout is
+    /// recorded in it.
+    fn set_golden(&self, snap: &mut Snapshot, file: &Option<String>) {
+        if let Some(file) = file {
+            if self.path.join(file).is_file() {
+                snap.stdout = self.data.read_golden(&snap.name, file);
+            }
+            snap.expected_stdout = Some(file.to_owned());
+        }
+    }
+
+    /// Returns the job executing a snapshot's command.
+    fn to_job(&self, snap: &Snapshot) -> cmd::Job {
+        cmd::Job {
+            cmd: snap.cmd.to_owned(),
+            stdin: snap.stdin.as_ref().map(|data| data.body.to_vec()),
+            env: snap.env.clone(),
+            timeout: snap.timeout.or(self.timeout).map(Duration::from_secs),
+            pty: snap.pty,
+            combined: snap.combined,
+            runner: snap.runner.clone(),
+            serial: snap.serial.clone(),
+            retries: snap.retries,
+            hermetic: snap.hermetic.clone(),
+            max_output: self.max_output,
+            before: snap.before.clone(),
+            after: snap.after.clone(),
+            artifacts: snap.artifacts.iter().map(|artifact| artifact.file.clone()).collect(),
+            recorded_artifacts: snap
+                .artifacts
+                .iter()
+                .filter(|artifact| artifact.data.is_some())
+                .map(|artifact| artifact.file.clone())
+                .collect(),
+        }
+    }
+
+    /// Compares the result of an execution against a snapshot and draws the
+    /// diff in case of failure.
+    /// Returns true if the snapshot passed, false otherwise.
+    fn check_snapshot<B: Write>(&self, snap: &mut Snapshot, execution: &Execution, buffer: &mut B) -> bool {
+        let passed = if snap.xfail {
+            let works = self.works(snap, execution);
+            if works {
+                let theme = &self.theme;
+                term::box_separator(&snap.name, SeparatorKind::Top, buffer, theme);
+                term::snap_summary(snap.description.as_ref(), &snap.cmd, snap.exit_code, snap.signal, buffer, theme);
+                buffer.boxed_write_str("passed, but is expected to fail (xfail)", theme).unwrap();
+                term::box_separator("", SeparatorKind::Bottom, buffer, theme);
+            }
+            !works
+        } else {
+            self.draw_diff(snap, execution, buffer)
+        };
+        if passed {
+            snap.status = SnapshotStatus::Passed;
+            snap.cache_key = cache_key(snap, &self.redact, &self.path);
+        } else {
+            snap.status = SnapshotStatus::Failed;
+            snap.cache_key = None;
+        }
+        snap.last_run = Some(self.data.current_run());
+        passed
+    }
+
+    /// Draws why a snapshot could not be executed, it then fails.
+    fn check_error<B: Write>(&self, snap: &mut Snapshot, message: &str, buffer: &mut B) {
+        let theme = &self.theme;
+        term::box_separator(&snap.name, SeparatorKind::Top, buffer, theme);
+        term::snap_summary(snap.description.as_ref(), &snap.cmd, snap.exit_code, snap.signal, buffer, theme);
+        term::errored(message, buffer, theme);
+        term::box_separator("", SeparatorKind::Bottom, buffer, theme);
+        snap.status = SnapshotStatus::Failed;
+        snap.cache_key = None;
+        snap.last_run = Some(self.data.current_run());
+    }
+
+    /// Returns true if the result of an execution matches a snapshot and its
+    /// command succeeded, which an expected failure must not.
+    fn works(&self, snap: &Snapshot, execution: &Execution) -> bool {
+        let same = self.draw_diff(snap, execution, &mut sink());
+        if snap.xfail {
+            same && execution.output.status.success()
+        } else {
+            same
+        }
+    }
+
+    /// Draws the diff between a snapshot and the result of an execution, if
+    /// there is any.
+    /// Returns true if they are the same, false otherwise.
+    fn draw_diff<B: Write>(&self, snap: &Snapshot, execution: &Execution, buffer: &mut B) -> bool {
         let theme = &self.theme;
+        let result = &execution.output;
         let empty_body = Vec::new();
-        let result = cmd::execute(&snap.cmd, &self.path).unwrap_log();
         let old_stdout = if let Some(ref stdout) = snap.stdout {
             &stdout.body
         } else {
@@ -269,44 +2127,283 @@ impl Context {
         } else {
             &empty_body
         };
-        let stdout_eq = &result.stdout == old_stdout;
-        let stderr_eq = &result.stderr == old_stderr;
-        let code_eq = snap.exit_code == result.status.code();
-        let failed = !stdout_eq || !stderr_eq || !code_eq;
+        let normalize = self.normalize.or(&snap.normalize);
+        let [stdout_verdict, stderr_verdict] = self.judge_outputs(snap, old_stdout, old_stderr, result);
+        let stdout_eq = stdout_verdict.as_ref().map_or_else(
+            || same_stdout(snap, &normalize, old_stdout, &result.stdout),
+            |verdict| verdict.same,
+        );
+        let stderr_eq = stderr_verdict.as_ref().map_or_else(
+            || same_stderr(snap, &normalize, old_stderr, &result.stderr),
+            |verdict| verdict.same,
+        );
+        let code_eq = snap.accepts_code(result.status.code()) && snap.signal == result.status.signal();
+        let changed_artifacts = snap.changed_artifacts(&normalize, &execution.artifacts);
+        let too_slow = is_too_slow(snap, execution);
+        let exceeded = execution.timed_out || execution.output_exceeded || too_slow;
+        let failed = exceeded || !stdout_eq || !stderr_eq || !code_eq || !changed_artifacts.is_empty();
         // Draw test summary
         if failed {
             term::box_separator(&snap.name, SeparatorKind::Top, buffer, theme);
-            term::snap_summary(snap.description.as_ref(), &snap.cmd, snap.exit_code, buffer, theme);
+            let (code, signal) = (snap.exit_code, snap.signal);
+            term::snap_summary(snap.description.as_ref(), &snap.cmd, code, signal, buffer, theme);
+            let redactions = self.redact.len() + snap.filters.len() + snap.redact.len() + snap.strip_ansi as usize;
+            if redactions > 0 {
+                buffer.boxed_write_str(&format!("outputs redacted by {} pattern(s)", redactions), theme).unwrap();
+            }
+            if normalize.sort_lines {
+                buffer.boxed_write_str("line order ignored, sorted lines are compared", theme).unwrap();
+            }
+            if !snap.tolerance.is_none() {
+                let tolerance = format!("numbers compared within {}", format_tolerance(&snap.tolerance));
+                buffer.boxed_write_str(&tolerance, theme).unwrap();
+            }
+            if let Some(comparator) = &snap.comparator {
+                buffer.boxed_write_str(&format!("outputs compared by '{}'", comparator), theme).unwrap();
+            }
+            let corrupted = snap.corrupted();
+            if !corrupted.is_empty() {
+                let message = format!(
+                    "the stored {} does not match its checksum, edited or corrupted: re-record it with `parrot \
+                     update {}` or restore it from the history with `parrot rollback {}`",
+                    corrupted.join(" and "),
+                    snap.name,
+                    snap.name
+                );
+                buffer.boxed_write_str(&message, theme).unwrap();
+            }
+        }
+        if !snap.accepts_code(result.status.code()) {
+            let code = result.status.code().map_or(String::from("None"), |code| code.to_string());
+            let actual = match &snap.exit_codes {
+                Some(codes) => format!("actual code: {}, expected {}", code, codes),
+                None => format!("actual code: {}", code),
+            };
+            buffer.boxed_write_str(&actual, theme).unwrap();
         }
-        if &result.stdout != old_stdout {
-            term::box_separator("stdout", SeparatorKind::Middle, buffer, theme);
-            term::write_diff(old_stdout, &result.stdout, buffer, theme);
+        if snap.signal != result.status.signal() {
+            let signal = result.status.signal().map_or(String::from("None"), term::format_signal);
+            buffer.boxed_write_str(&format!("actual signal: {}", signal), theme).unwrap();
         }
-        if &result.stderr != old_stderr {
-            term::box_separator("stderr", SeparatorKind::Middle, buffer, theme);
-            term::write_diff(old_stderr, &result.stderr, buffer, theme);
+        if execution.timed_out {
+            term::timed_out(snap.timeout.or(self.timeout), buffer, theme);
+        }
+        if execution.output_exceeded {
+            term::output_exceeded(self.max_output, buffer, theme);
+        }
+        if let (true, Some(max)) = (too_slow, snap.max_duration_ms) {
+            term::too_slow(execution.duration.as_millis() as u64, max, buffer, theme);
+        }
+        let outputs = [
+            ("stdout", stdout_eq, old_stdout, &result.stdout, stdout_verdict),
+            ("stderr", stderr_eq, old_stderr, &result.stderr, stderr_verdict),
+        ];
+        for (title, eq, old, new, verdict) in &outputs {
+            if !eq {
+                term::box_separator(title, SeparatorKind::Middle, buffer, theme);
+                if only_line_endings_differ(old, new) {
+                    buffer.boxed_write_str("only line endings differ, CRLF against LF", theme).unwrap();
+                }
+                let structural = if *title == "stdout" { structural_differences(snap, old, new) } else { None };
+                let explanation = verdict.as_ref().map(|verdict| &verdict.explanation);
+                if let Some(failures) = snap.failed_assertions(*title == "stderr", new) {
+                    for failure in failures {
+                        buffer.boxed_write_str(&failure, theme).unwrap();
+                    }
+                } else if let Some(explanation) = explanation.filter(|explanation| !explanation.is_empty()) {
+                    buffer.boxed_write(explanation, theme).unwrap();
+                } else if let Some(differences) = structural {
+                    term::write_structural_diff(snap.compare.name(), &differences, buffer, theme);
+                } else if is_binary_output(snap, old, new) {
+                    term::write_binary_diff(old, new, buffer, theme);
+                } else if let Some(order) = snap.contains {
+                    let (old, new) = (normalize.apply(old), normalize.apply(new));
+                    term::write_missing_sections(&missing_sections(&old, &new, order), buffer, theme);
+                } else if normalize.sort_lines {
+                    let (old, new) = (normalize.apply(old), normalize.apply(new));
+                    term::write_diff(&old, &mask_placeholders(&old, &new), buffer, theme);
+                } else {
+                    term::write_diff(old, &mask_placeholders(old, new), buffer, theme);
+                }
+            }
+        }
+        for (artifact, new) in changed_artifacts {
+            term::box_separator(&artifact.file, SeparatorKind::Middle, buffer, theme);
+            let old = artifact.data.as_ref().map_or(&empty_body, |data| &data.body);
+            match new {
+                None => buffer.boxed_write_str("the file was not written", theme).unwrap(),
+                Some(new) if is_binary_output(snap, old, new) => term::write_binary_diff(old, new, buffer, theme),
+                Some(new) => {
+                    if artifact.data.is_none() {
+                        buffer.boxed_write_str("the file was written, it was not when recorded", theme).unwrap();
+                    }
+                    term::write_diff(old, &mask_placeholders(old, new), buffer, theme);
+                }
+            }
         }
         if failed {
             term::box_separator("", SeparatorKind::Bottom, buffer, theme);
-            snap.status = SnapshotStatus::Failed;
-        } else {
-            snap.status = SnapshotStatus::Passed;
         }
         !failed
     }
 
+    /// Runs the comparator of a snapshot on its stdout and stderr, unless they
+    /// are ignored or unchanged. A comparator that can't be run fails with
+    /// the reason as explanation.
+    fn judge_outputs(
+        &self,
+        snap: &Snapshot,
+        old_stdout: &[u8],
+        old_stderr: &[u8],
+        result: &Output,
+    ) -> [Option<Verdict>; 2] {
+        let judge = |output: &str, ignored: bool, old: &[u8], new: &[u8]| {
+            let comparator = snap.comparator.as_ref().filter(|_| !ignored && old != new)?;
+            Some(cmd::execute_comparator(comparator, output, old, new, &self.path).unwrap_or_else(|err| Verdict {
+                same: false,
+                explanation: err.message.into_bytes(),
+            }))
+        };
+        let asserted = |stderr: bool| snap.assertions.iter().any(|assertion| assertion.stderr == stderr);
+        [
+            judge("stdout", snap.ignores_stdout() || asserted(false), old_stdout, &result.stdout),
+            judge("stderr", snap.ignores_stderr() || asserted(true), old_stderr, &result.stderr),
+        ]
+    }
+
     /// Shows a single test.
     fn show_snapshot<B: Write>(&self, snap: &Snapshot, buffer: &mut B) {
         let theme = &self.theme;
         term::box_separator(&snap.name, SeparatorKind::Top, buffer, theme);
-        term::snap_summary(snap.description.as_ref(), &snap.cmd, snap.exit_code, buffer, theme);
+        term::snap_summary(snap.description.as_ref(), &snap.cmd, snap.exit_code, snap.signal, buffer, theme);
+        if let Some(record) = snap.history.last() {
+            let duration = term::format_duration(record.duration_ms);
+            buffer.boxed_write_str(&format!("last run: {}", duration), theme).unwrap();
+        }
+        if let Some(max) = snap.max_duration_ms {
+            let max = term::format_duration(max);
+            buffer.boxed_write_str(&format!("max duration: {}", max), theme).unwrap();
+        }
+        if let Some(runner) = &snap.runner {
+            buffer.boxed_write_str(&format!("runner: {}", runner), theme).unwrap();
+        }
+        if !snap.needs.is_empty() {
+            buffer.boxed_write_str(&format!("needs: {}", snap.needs.join(", ")), theme).unwrap();
+        }
+        if let Some(serial) = &snap.serial {
+            buffer.boxed_write_str(&format!("serial: {}", serial), theme).unwrap();
+        }
+        if let Some(created) = &snap.created {
+            buffer.boxed_write_str(&format!("created: {}", term::format_stamp(created)), theme).unwrap();
+        }
+        if let Some(updated) = &snap.updated {
+            buffer.boxed_write_str(&format!("updated: {}", term::format_stamp(updated)), theme).unwrap();
+        }
+        let normalize = &snap.normalize;
+        let options = [
+            (normalize.trim_trailing_whitespace, "trailing whitespace"),
+            (normalize.collapse_blank_lines, "consecutive blank lines"),
+            (normalize.ignore_all_whitespace, "all whitespace"),
+            (normalize.sort_lines, "line order"),
+            (normalize.normalize_newlines, "line endings"),
+        ];
+        let ignored: Vec<&str> = options.iter().filter(|(set, _)| *set).map(|(_, option)| *option).collect();
+        if !ignored.is_empty() {
+            buffer.boxed_write_str(&format!("ignores: {}", ignored.join(", ")), theme).unwrap();
+        }
+        if snap.exit_code_only {
+            buffer.boxed_write_str("compares: exit code only", theme).unwrap();
+        } else if snap.ignore_stdout || snap.ignore_stderr {
+            let outputs = [(snap.ignore_stdout, "stdout"), (snap.ignore_stderr, "stderr")];
+            let ignored: Vec<&str> = outputs.iter().filter(|(set, _)| *set).map(|(_, output)| *output).collect();
+            buffer.boxed_write_str(&format!("not compared: {}", ignored.join(", ")), theme).unwrap();
+        }
+        if let Some(codes) = &snap.exit_codes {
+            buffer.boxed_write_str(&format!("accepted codes: {}", codes), theme).unwrap();
+        }
+        if let Some(order) = snap.contains {
+            let order = if order == LineOrder::InOrder { "in order" } else { "in any order" };
+            buffer.boxed_write_str(&format!("compares: stored sections are contained, {}", order), theme).unwrap();
+        }
+        if !snap.tolerance.is_none() {
+            buffer.boxed_write_str(&format!("tolerance: {}", format_tolerance(&snap.tolerance)), theme).unwrap();
+        }
+        if !snap.compare.is_text() && !snap.ignores_stdout() {
+            let compares = format!("compares: stdout as {}", snap.compare.name());
+            buffer.boxed_write_str(&compares, theme).unwrap();
+        }
+        if let Some(comparator) = &snap.comparator {
+            buffer.boxed_write_str(&format!("comparator: {}", comparator), theme).unwrap();
+        }
+        if snap.xfail {
+            buffer.boxed_write_str("expected to fail (xfail)", theme).unwrap();
+        }
+        if !snap.assertions.is_empty() {
+            let assertions: Vec<String> = snap.assertions.iter().map(Assertion::to_string).collect();
+            buffer.boxed_write_str(&format!("asserts: {}", assertions.join(", ")), theme).unwrap();
+        }
+        if !snap.env.is_empty() || snap.hermetic.is_some() {
+            let title = if snap.hermetic.is_some() { "env (hermetic)" } else { "env" };
+            term::box_separator(title, SeparatorKind::Middle, buffer, theme);
+            for var in snap.hermetic.iter().flatten() {
+                buffer.boxed_write_str(&format!("{} (passed through)", var), theme).unwrap();
+            }
+            for (var, value) in &snap.env {
+                buffer.boxed_write_str(&format!("{}={}", var, value), theme).unwrap();
+            }
+        }
+        if snap.strip_ansi || !snap.filters.is_empty() || !snap.redact.is_empty() {
+            term::box_separator("redact", SeparatorKind::Middle, buffer, theme);
+            if snap.strip_ansi {
+                buffer.boxed_write_str("ANSI escape sequences", theme).unwrap();
+            }
+            for filter in &snap.filters {
+                buffer.boxed_write_str(&format!("{} (filter)", filter), theme).unwrap();
+            }
+            for redaction in &snap.redact {
+                buffer.boxed_write_str(&format!("{} => {}", redaction.pattern, redaction.replacement), theme).unwrap();
+            }
+        }
+        if !snap.pipeline.is_empty() {
+            term::box_separator("pipeline", SeparatorKind::Middle, buffer, theme);
+            for step in &snap.pipeline {
+                buffer.boxed_write_str(&step.to_string(), theme).unwrap();
+            }
+        }
+        for (kind, hook) in &[("before", &snap.before), ("after", &snap.after)] {
+            if let Some(hook) = hook {
+                term::box_separator(kind, SeparatorKind::Middle, buffer, theme);
+                buffer.boxed_write_str(hook, theme).unwrap();
+            }
+        }
+        // The outputs are shown only if they can be read
+        if let Err(err) = snap.load() {
+            term::errored(&describe_error(&err), buffer, theme);
+            term::box_separator("", SeparatorKind::Bottom, buffer, theme);
+            return;
+        }
+        if let Some(stdin) = &snap.stdin {
+            term::box_separator("stdin", SeparatorKind::Middle, buffer, theme);
+            buffer.boxed_write(&stdin.body, theme).unwrap();
+        }
         if let Some(stdout) = &snap.stdout {
-            term::box_separator("stdout", SeparatorKind::Middle, buffer, theme);
-            buffer.boxed_write(&stdout.body, theme).unwrap();
+            let title = match &snap.expected_stdout {
+                Some(file) => format!("stdout ({})", file),
+                None => String::from("stdout"),
+            };
+            term::box_separator(&title, SeparatorKind::Middle, buffer, theme);
+            term::write_output(&stdout.body, snap.binary, buffer, theme);
         }
         if let Some(stderr) = &snap.stderr {
             term::box_separator("stderr", SeparatorKind::Middle, buffer, theme);
-            buffer.boxed_write(&stderr.body, theme).unwrap();
+            term::write_output(&stderr.body, snap.binary, buffer, theme);
+        }
+        for artifact in &snap.artifacts {
+            term::box_separator(&artifact.file, SeparatorKind::Middle, buffer, theme);
+            match &artifact.data {
+                Some(data) => term::write_output(&data.body, snap.binary, buffer, theme),
+                None => buffer.boxed_write_str("not written", theme).unwrap(),
+            }
         }
         term::box_separator("", SeparatorKind::Bottom, buffer, theme);
     }
@@ -318,10 +2415,23 @@ impl Context {
             Some(desc) => desc,
             None => "",
         };
-        match editor::open_snap(&self.path, &snap.name, description, &snap.cmd) {
+        let pipeline: Vec<String> = snap.pipeline.iter().map(Transform::to_string).collect();
+        let store = self.data.store_path();
+        match editor::open_snap(store, &snap.name, description, snap.exit_code_only, &pipeline, &snap.cmd) {
             Ok(edit) => {
+                let pipeline = match parse_pipeline(&edit.pipeline) {
+                    Ok(pipeline) => pipeline,
+                    Err(err) => {
+                        term::writeln(&err.message, buffer);
+                        return false;
+                    }
+                };
                 let mut has_changed = false;
                 if let Some(name) = edit.name {
+                    if let Err(err) = check_name(&name) {
+                        term::writeln(&err.message, buffer);
+                        return false;
+                    }
                     if name != snap.name {
                         snap.name = name;
                         has_changed = true;
@@ -332,7 +2442,27 @@ impl Context {
                     snap.tags = edit.tags;
                     has_changed = true;
                 }
+                if edit.exit_code_only != snap.exit_code_only {
+                    snap.exit_code_only = edit.exit_code_only;
+                    has_changed = true;
+                }
+                // The pipeline is left as is if the stored outputs can't be read
+                let loaded = if pipeline != snap.pipeline { snap.load() } else { Ok(()) };
+                if let Err(err) = &loaded {
+                    term::write_lines(&format!("Pipeline not updated: {}", describe_error(err)), buffer);
+                } else if pipeline != snap.pipeline {
+                    // The stored outputs go through the new pipeline, so that
+                    // they are compared against transformed outputs
+                    let artifacts = snap.artifacts.iter_mut().filter_map(|artifact| artifact.data.as_mut());
+                    for data in snap.stdout.iter_mut().chain(snap.stderr.iter_mut()).chain(artifacts) {
+                        data.body = apply_pipeline(&data.body, &pipeline).unwrap_log().into();
+                    }
+                    snap.pipeline = pipeline;
+                    self.data.persist_snapshot_data(snap).unwrap_log();
+                    has_changed = true;
+                }
                 if has_changed {
+                    snap.updated = Some(self.data.stamp());
                     term::writeln("Updated.", buffer);
                     true
                 } else {
@@ -352,8 +2482,8 @@ impl Context {
         let mut count = 0;
         for snap in view.get_view() {
             let mut snap = snap.borrow_mut();
-            if self.update_snapshot(&mut snap) {
-                self.data.persist_snapshot_data(&snap).unwrap_log();
+            if self.update_snapshot(&mut snap, &mut repl.stdout) {
+                self.data.persist_snapshot_data(&mut snap).unwrap_log();
                 count += 1;
             }
         }
@@ -373,8 +2503,8 @@ impl Context {
     fn update_selected(&self, repl: &mut term::Repl, view: &View) {
         match view.get_selected_mut() {
             Some(mut snap) => {
-                if self.update_snapshot(&mut snap) {
-                    self.data.persist_snapshot_data(&snap).unwrap_log();
+                if self.update_snapshot(&mut snap, &mut repl.stdout) {
+                    self.data.persist_snapshot_data(&mut snap).unwrap_log();
                     drop(snap); // Release mut ref before persisting
                     self.data.persist_metadata().unwrap_log();
                     repl.writeln("Updated 1 snapshot.")
@@ -389,25 +2519,103 @@ impl Context {
     /// Updates a single snapshot.
     /// Returns true if there was a change, false otherwise.
     /// The command will be run to get the new output, there is no caching for
-    /// now.
-    fn update_snapshot(&self, snap: &mut Snapshot) -> bool {
-        let result = cmd::execute(&snap.cmd, &self.path).unwrap_log();
-        let mut has_changed = false;
+    /// now. Why it could not be run is written to the buffer.
+    fn update_snapshot<B: Write>(&self, snap: &mut Snapshot, buffer: &mut B) -> bool {
+        let execution = match self.execute(snap) {
+            Ok(execution) => execution,
+            Err(err) => {
+                term::write_lines(&format!("{} not updated: {}", snap.name, describe_error(&err)), buffer);
+                return false;
+            }
+        };
+        // The output of a killed command is not worth saving
+        !execution.timed_out && !execution.output_exceeded && !self.apply_result(snap, execution).is_empty()
+    }
+
+    /// Replaces the snapshot's outputs by those of an execution result.
+    /// Returns the list of the parts that changed.
+    fn apply_result(&self, snap: &mut Snapshot, execution: Execution) -> Vec<&'static str> {
+        let previous = snap.expectation();
+        let corrupted = !snap.corrupted().is_empty();
+        let mut changes = Vec::new();
+        let mut result = execution.output;
+        if snap.ignores_stdout() {
+            result.stdout.clear();
+        }
+        if snap.ignores_stderr() {
+            result.stderr.clear();
+        }
         let new_stdout = util::to_snapshot_data(result.stdout, &snap.name, ".out");
         let new_stderr = util::to_snapshot_data(result.stderr, &snap.name, ".err");
         if snap.exit_code != result.status.code() {
             snap.exit_code = result.status.code();
-            has_changed = true;
+            changes.push("exit code");
+        }
+        if snap.signal != result.status.signal() {
+            snap.signal = result.status.signal();
+            changes.push("signal");
         }
         if snap.stdout != new_stdout {
             snap.stdout = new_stdout;
-            has_changed = true;
+            changes.push("stdout");
         }
         if snap.stderr != new_stderr {
             snap.stderr = new_stderr;
-            has_changed = true;
+            changes.push("stderr");
+        }
+        let files: Vec<String> = snap.artifacts.iter().map(|artifact| artifact.file.clone()).collect();
+        let new_artifacts = util::to_artifacts(&snap.name, &files, execution.artifacts);
+        if snap.artifacts != new_artifacts {
+            snap.artifacts = new_artifacts;
+            changes.push("artifacts");
+        }
+        // A corrupted expectation is not worth restoring
+        if !changes.is_empty() && !corrupted {
+            self.data.keep_version(&snap.name, &previous).unwrap_log();
         }
         snap.status = SnapshotStatus::Passed;
-        has_changed
+        snap.last_run = Some(self.data.current_run());
+        changes
     }
 }
+
+/// Reports of the snapshots of a run, along with its failures.
+struct Tally {
+    reports: Vec<SnapshotReport>,
+    /// Names of the snapshots that failed or were skipped.
+    failed: HashSet<String>,
+    /// Number of snapshots that failed, those skipped are not counted.
+    failures: usize,
+    max_failures: Option<usize>,
+}
+
+impl Tally {
+    fn new(max_failures: Option<usize>) -> Tally {
+        Tally {
+            reports: Vec::new(),
+            failed: HashSet::new(),
+            failures: 0,
+            max_failures,
+        }
+    }
+
+    /// Records a report, returns false once enough snapshots failed.
+    fn record(&mut self, report: SnapshotReport) -> bool {
+        if !report.passed() {
+            self.failed.insert(report.name.clone());
+            if !report.skipped {
+                self.failures += 1;
+            }
+        }
+        self.reports.push(report);
+        let failures = self.failures;
+        self.max_failures.is_none_or(|max_failures| failures < max_failures)
+    }
+}
+
+/// Writes the description of the snapshots as JSON.
+fn write_entries<B: Write>(snaps: &[Rc<RefCell<Snapshot>>], buffer: &mut B) {
+    let snaps: Vec<_> = snaps.iter().map(|snap| snap.borrow()).collect();
+    let entries: Vec<_> = snaps.iter().map(|snap| SnapshotEntry::new(snap, false)).collect();
+    write_json(&entries, buffer);
+}
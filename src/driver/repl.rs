@@ -1,8 +1,9 @@
 use std::cell::{Ref, RefCell, RefMut};
 use std::rc::Rc;
 
-use crate::parser::Filter;
+use super::util::name_matches;
 use crate::data::{Snapshot, SnapshotStatus};
+use crate::parser::Filter;
 
 /// Represents a view of the snapshots after filters have been applied.
 pub struct View {
@@ -40,8 +41,8 @@ impl View {
 
     /// Returns the selected item.
     /// Borrows an immutable ref to the snapshot.
-    pub fn get_selected(&self) -> Option<Ref<Snapshot>> {
-        if self.view.len() == 0 {
+    pub fn get_selected(&self) -> Option<Ref<'_, Snapshot>> {
+        if self.view.is_empty() {
             None
         } else {
             Some(self.view[self.window.0 + self.cursor].borrow())
@@ -50,8 +51,8 @@ impl View {
 
     /// Returns a mutable reference of the selected item.
     /// Borrows a mutable ref, use with care.
-    pub fn get_selected_mut(&self) -> Option<RefMut<Snapshot>> {
-        if self.view.len() == 0 {
+    pub fn get_selected_mut(&self) -> Option<RefMut<'_, Snapshot>> {
+        if self.view.is_empty() {
             None
         } else {
             Some(self.view[self.window.0 + self.cursor].borrow_mut())
@@ -72,7 +73,7 @@ impl View {
             let n = self.view.len();
             let h = self.height;
             let max = n;
-            let min = if max < h { 0 } else { max - h };
+            let min = max.saturating_sub(h);
             self.window = (min, max);
             if max > min {
                 self.cursor = max - min - 1;
@@ -112,11 +113,17 @@ impl View {
         self.update_window();
     }
 
+    /// Only keeps the snapshots of the view for which the predicate is true.
+    pub fn retain<F: Fn(&Snapshot) -> bool>(&mut self, predicate: F) {
+        self.view.retain(|snap| predicate(&snap.borrow()));
+        self.update_window();
+    }
+
     /// Remove any filter currently applied.
     pub fn clear_filters(&mut self) {
         let mut view = Vec::with_capacity(self.data.len());
         for snap in &self.data {
-            if snap.borrow().deleted == false {
+            if !snap.borrow().deleted {
                 view.push(Rc::clone(snap));
             }
         }
@@ -130,11 +137,7 @@ impl View {
         let n = self.view.len();
         if max >= n {
             let max = n;
-            let min = if max < self.height {
-                0
-            } else {
-                max - self.height
-            };
+            let min = max.saturating_sub(self.height);
             self.window = (min, max);
             // If there are less items than the position of the cursor
             if self.cursor + 1 >= max {
@@ -148,7 +151,7 @@ impl View {
 
     /// Applies a tag filter.
     fn apply_tag_filter(&mut self, tag: &String) {
-        let old_view = std::mem::replace(&mut self.view, Vec::new());
+        let old_view = std::mem::take(&mut self.view);
         for snap in old_view {
             if snap.borrow().tags.contains(tag) {
                 self.view.push(snap);
@@ -156,11 +159,11 @@ impl View {
         }
     }
 
-    /// Applies a name filter.
-    fn apply_name_filter(&mut self, name: &String) {
-        let old_view = std::mem::replace(&mut self.view, Vec::new());
+    /// Applies a name filter, the name may be a glob.
+    fn apply_name_filter(&mut self, name: &str) {
+        let old_view = std::mem::take(&mut self.view);
         for snap in old_view {
-            if snap.borrow().name.contains(name) {
+            if name_matches(name, &snap.borrow().name) {
                 self.view.push(snap);
             }
         }
@@ -168,7 +171,7 @@ impl View {
 
     /// Applies a status filter.
     fn apply_status_filter(&mut self, status: SnapshotStatus) {
-        let old_view = std::mem::replace(&mut self.view, Vec::new());
+        let old_view = std::mem::take(&mut self.view);
         for snap in old_view {
             if snap.borrow().status == status {
                 self.view.push(snap);
@@ -178,9 +181,9 @@ impl View {
 
     /// Applies the deleted filter.
     fn apply_deleted_filter(&mut self) {
-        let old_view = std::mem::replace(&mut self.view, Vec::new());
+        let old_view = std::mem::take(&mut self.view);
         for snap in old_view {
-            if snap.borrow().deleted == false {
+            if !snap.borrow().deleted {
                 self.view.push(snap);
             }
         }
@@ -1,8 +1,372 @@
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path};
+use std::os::unix::process::ExitStatusExt;
 use std::process::Output;
 
-use crate::data::{Snapshot, SnapshotData, SnapshotStatus};
+use super::cmd::Execution;
+use crate::compare::{Assertion, Compare, ExitCodes, Fnv, LineOrder, Normalize, Tolerance, Transform};
+use crate::data::{Artifact, Expectation, Redaction, Snapshot, SnapshotData, SnapshotStatus};
+use crate::editor::{parse_tags, TAG_PATTERN};
+use crate::error::{wrap, Error};
+use crate::formats::structural_diff;
+use crate::term::{is_binary, strip_ansi};
+
+/// A command to add as snapshot, as read from a commands file.
+#[derive(Deserialize)]
+pub struct CommandEntry {
+    pub cmd: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub timeout: Option<u64>,
+    pub max_duration_ms: Option<u64>,
+    #[serde(default)]
+    pub pty: bool,
+    #[serde(default)]
+    pub combined: bool,
+    pub runner: Option<String>,
+    pub hermetic: Option<Vec<String>>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub needs: Vec<String>,
+    pub serial: Option<String>,
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    #[serde(default)]
+    pub strip_ansi: bool,
+    #[serde(default)]
+    pub filters: Vec<String>,
+    #[serde(default)]
+    pub redact: Vec<Redaction>,
+    #[serde(default)]
+    pub pipeline: Vec<Transform>,
+    #[serde(default)]
+    pub normalize: Normalize,
+    #[serde(default)]
+    pub exit_code_only: bool,
+    #[serde(default)]
+    pub ignore_stdout: bool,
+    #[serde(default)]
+    pub ignore_stderr: bool,
+    #[serde(default)]
+    pub binary: bool,
+    #[serde(default)]
+    pub compare: Compare,
+    #[serde(default)]
+    pub tolerance: Tolerance,
+    #[serde(default)]
+    pub contains: Option<LineOrder>,
+    #[serde(default)]
+    pub exit_codes: Option<ExitCodes>,
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+    #[serde(default)]
+    pub comparator: Option<String>,
+    #[serde(default)]
+    pub xfail: bool,
+    #[serde(default)]
+    pub artifacts: Vec<String>,
+    pub expected_stdout: Option<String>,
+}
+
+/// Parses the content of a commands file. JSON files must contain a list of
+/// command entries, other files a command per line, blank lines and lines
+/// starting with '#' are ignored.
+pub fn parse_commands(content: &str, is_json: bool) -> Result<Vec<CommandEntry>, Error> {
+    if is_json {
+        return wrap(serde_json::from_str(content), "Invalid JSON commands file.");
+    }
+    let entries = content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| CommandEntry {
+            cmd: line.to_owned(),
+            name: None,
+            description: None,
+            tags: Vec::new(),
+            env: BTreeMap::new(),
+            timeout: None,
+            max_duration_ms: None,
+            pty: false,
+            combined: false,
+            runner: None,
+            hermetic: None,
+            before: None,
+            after: None,
+            retries: 0,
+            needs: Vec::new(),
+            serial: None,
+            inputs: Vec::new(),
+            strip_ansi: false,
+            filters: Vec::new(),
+            redact: Vec::new(),
+            pipeline: Vec::new(),
+            normalize: Normalize::default(),
+            exit_code_only: false,
+            ignore_stdout: false,
+            ignore_stderr: false,
+            binary: false,
+            compare: Compare::Text,
+            tolerance: Tolerance::default(),
+            contains: None,
+            exit_codes: None,
+            assertions: Vec::new(),
+            comparator: None,
+            xfail: false,
+            artifacts: Vec::new(),
+            expected_stdout: None,
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Groups snapshots, given by name along with the names of the snapshots they
+/// need, in levels such that each snapshot only needs snapshots of previous
+/// levels. Snapshots keep their order within a level and needed snapshots
+/// missing from the list are ignored.
+/// Returns the indices of the snapshots of each level.
+pub fn order_by_needs(snaps: &[(String, Vec<String>)]) -> Result<Vec<Vec<usize>>, Error> {
+    let index: HashMap<&str, usize> = snaps.iter().enumerate().map(|(idx, (name, _))| (&name[..], idx)).collect();
+    let mut ordered = vec![false; snaps.len()];
+    let mut remaining = snaps.len();
+    let mut levels = Vec::new();
+    while remaining > 0 {
+        let level: Vec<usize> = (0..snaps.len())
+            .filter(|&idx| !ordered[idx])
+            .filter(|&idx| snaps[idx].1.iter().all(|needs| index.get(&needs[..]).is_none_or(|&dep| ordered[dep])))
+            .collect();
+        if level.is_empty() {
+            let names: Vec<&str> = (0..snaps.len()).filter(|&idx| !ordered[idx]).map(|idx| &snaps[idx].0[..]).collect();
+            return Error::from_str(&format!("Snapshots {} depend on each other.", names.join(", ")));
+        }
+        for &idx in &level {
+            ordered[idx] = true;
+        }
+        remaining -= level.len();
+        levels.push(level);
+    }
+    Ok(levels)
+}
+
+/// Returns a hash of everything the outputs of a snapshot depend on: its
+/// command and how it is executed, its input, the content of its declared
+/// input files, relative to `dir`, and the filters and redactions of its
+/// outputs, after the `global` redactions.
+/// Returns None if the snapshot declares no input files, as its outputs may
+/// then depend on anything.
+pub fn cache_key(snap: &Snapshot, global: &[Redaction], dir: &Path) -> Option<String> {
+    if snap.inputs.is_empty() {
+        return None;
+    }
+    let mut hash = Fnv::new();
+    hash.write(snap.cmd.as_bytes());
+    for (var, value) in &snap.env {
+        hash.write(var.as_bytes());
+        hash.write(value.as_bytes());
+    }
+    hash.write(snap.stdin.as_ref().map_or(&[], |data| &data.body));
+    let options = (&snap.hermetic, &snap.runner, &snap.before, &snap.after, snap.pty, snap.combined, snap.strip_ansi);
+    hash.write(format!("{:?}", options).as_bytes());
+    hash.write(format!("{:?} {:?} {:?} {:?}", global, snap.filters, snap.redact, snap.pipeline).as_bytes());
+    for input in &snap.inputs {
+        hash.write(input.as_bytes());
+        match fs::read(dir.join(input)) {
+            Ok(content) => hash.write(&content),
+            Err(_) => hash.write(b"missing"),
+        }
+    }
+    Some(format!("{:016x}", hash.0))
+}
+
+/// Names of the built-in filters, which redact common sources of
+/// nondeterminism.
+pub const FILTERS: &[&str] = &["timestamps", "uuids", "hex-addresses", "durations", "tmp-paths", "home-dir"];
+
+/// Returns the redactions replacing the repository root (the closest folder
+/// with a `.git` above the project, or the project itself), the home
+/// directory and the temporary directory with `<ROOT>`, `<HOME>` and `<TMP>`
+/// placeholders, longest path first so that nested ones are replaced by the
+/// most specific placeholder.
+pub fn path_redactions(project: &Path) -> Vec<Redaction> {
+    let mut paths = Vec::new();
+    if let Ok(project) = project.canonicalize() {
+        let root = project.ancestors().find(|dir| dir.join(".git").exists()).unwrap_or(&project);
+        paths.push((root.to_owned(), "<ROOT>"));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        paths.push((home.into(), "<HOME>"));
+    }
+    paths.push((std::env::temp_dir(), "<TMP>"));
+    let mut paths: Vec<(String, &str)> = paths
+        .into_iter()
+        .map(|(path, placeholder)| (path.to_string_lossy().trim_end_matches('/').to_owned(), placeholder))
+        .filter(|(path, _)| path.len() > 1)
+        .collect();
+    paths.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+    paths
+        .into_iter()
+        .map(|(path, placeholder)| Redaction {
+            // The path must not be followed by more of a file name
+            pattern: format!("{}([^A-Za-z0-9_.-]|$)", regex::escape(&path)),
+            replacement: format!("{}$1", placeholder),
+        })
+        .collect()
+}
+
+/// Returns the redactions of the given built-in filters, in order.
+pub fn filter_redactions(filters: &[String]) -> Result<Vec<Redaction>, Error> {
+    let redaction = |pattern: &str, replacement: &str| Redaction {
+        pattern: pattern.to_owned(),
+        replacement: replacement.to_owned(),
+    };
+    let mut redactions = Vec::new();
+    for filter in filters {
+        match &filter[..] {
+            "timestamps" => redactions.push(redaction(
+                r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?",
+                "<TIMESTAMP>",
+            )),
+            "uuids" => redactions.push(redaction(
+                r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b",
+                "<UUID>",
+            )),
+            "hex-addresses" => redactions.push(redaction(r"\b0x[0-9a-fA-F]{4,}\b", "<ADDRESS>")),
+            "durations" => redactions.push(redaction(r"\b\d+(\.\d+)? ?(ns|µs|us|ms|s|min|h)\b", "<DURATION>")),
+            "tmp-paths" => {
+                let tmp = std::env::temp_dir();
+                let tmp = regex::escape(tmp.to_string_lossy().trim_end_matches('/'));
+                redactions.push(redaction(&format!(r#"{}/[^\s'":]+"#, tmp), "<TMP>"));
+            }
+            "home-dir" => {
+                // Nothing to redact without a home directory
+                if let Some(home) = std::env::var_os("HOME").filter(|home| home.len() > 1) {
+                    let home = regex::escape(home.to_string_lossy().trim_end_matches('/'));
+                    redactions.push(redaction(&home, "<HOME>"));
+                }
+            }
+            _ => {
+                return Error::from_str(&format!("Unknown filter '{}', expected one of {}.", filter, FILTERS.join(", ")))
+            }
+        }
+    }
+    Ok(redactions)
+}
+
+/// Returns true if the outputs of a snapshot are shown as binary data: the
+/// snapshot is declared as binary or one of the outputs does not look like
+/// text.
+pub fn is_binary_output(snap: &Snapshot, old: &[u8], new: &[u8]) -> bool {
+    snap.binary || is_binary(old) || is_binary(new)
+}
+
+/// Returns true if two outputs are the same but for their CRLF and LF line
+/// endings.
+pub fn only_line_endings_differ(old: &[u8], new: &[u8]) -> bool {
+    let newlines = Normalize {
+        normalize_newlines: true,
+        ..Normalize::default()
+    };
+    old != new && newlines.same(old, new)
+}
+
+/// Returns the structural differences between the stored and actual stdout
+/// of a snapshot, or `None` if it is compared as text.
+pub fn structural_differences(snap: &Snapshot, old: &[u8], new: &[u8]) -> Option<Vec<(char, String)>> {
+    structural_diff(snap.compare, old, new)
+}
+
+/// Returns true if the stored and actual stdout of a snapshot are the same,
+/// or if the actual one has the properties the snapshot asserts.
+pub fn same_stdout(snap: &Snapshot, normalize: &Normalize, old: &[u8], new: &[u8]) -> bool {
+    snap.ignores_stdout()
+        || match (snap.failed_assertions(false, new), structural_differences(snap, old, new)) {
+            (Some(failures), _) => failures.is_empty(),
+            (None, Some(differences)) => differences.is_empty(),
+            (None, None) => snap.same_output(normalize, old, new),
+        }
+}
+
+/// Returns true if the stored and actual stderr of a snapshot are the same,
+/// or if the actual one has the properties the snapshot asserts.
+pub fn same_stderr(snap: &Snapshot, normalize: &Normalize, old: &[u8], new: &[u8]) -> bool {
+    snap.ignores_stderr()
+        || match snap.failed_assertions(true, new) {
+            Some(failures) => failures.is_empty(),
+            None => snap.same_output(normalize, old, new),
+        }
+}
+
+/// Formats a tolerance, such as `±0.5 or 2%`.
+pub fn format_tolerance(tolerance: &Tolerance) -> String {
+    let mut bounds = Vec::new();
+    if tolerance.absolute != 0.0 {
+        bounds.push(format!("±{}", tolerance.absolute));
+    }
+    if tolerance.relative != 0.0 {
+        bounds.push(format!("{}%", tolerance.relative * 100.0));
+    }
+    bounds.join(" or ")
+}
+
+/// Replaces the matches of each redaction pattern in an output, in order.
+pub fn apply_redactions(body: &[u8], redactions: &[Redaction]) -> Result<Vec<u8>, Error> {
+    let mut body = body.to_owned();
+    for redaction in redactions {
+        let message = format!("Invalid redaction pattern '{}'.", redaction.pattern);
+        let re = wrap(regex::bytes::Regex::new(&redaction.pattern), &message)?;
+        body = re.replace_all(&body, redaction.replacement.as_bytes()).into_owned();
+    }
+    Ok(body)
+}
+
+/// Parses the steps of a pipeline, as written in the description file.
+pub fn parse_pipeline(steps: &[String]) -> Result<Vec<Transform>, Error> {
+    steps
+        .iter()
+        .map(|step| step.parse().or_else(|err| Error::from_str(&format!("Invalid pipeline step: {}.", err))))
+        .collect()
+}
+
+/// Applies the steps of a pipeline to an output, in order.
+pub fn apply_pipeline(body: &[u8], pipeline: &[Transform]) -> Result<Vec<u8>, Error> {
+    let mut body = body.to_owned();
+    for step in pipeline {
+        body = match step {
+            Transform::StripAnsi => strip_ansi(&body),
+            Transform::Filter(filter) => apply_redactions(&body, &filter_redactions(std::slice::from_ref(filter))?)?,
+            Transform::Redact(redaction) => apply_redactions(&body, std::slice::from_ref(redaction))?,
+            Transform::Normalize(normalize) => normalize.apply(&body).into_owned(),
+        };
+    }
+    Ok(body)
+}
+
+/// Returns true if the execution took longer than the snapshot's maximum
+/// duration.
+pub fn is_too_slow(snap: &Snapshot, execution: &Execution) -> bool {
+    snap.max_duration_ms.is_some_and(|max| execution.duration.as_millis() > max as u128)
+}
+
+/// Returns the message of an error along with its cause, if any, on the next
+/// line.
+pub fn describe_error(err: &Error) -> String {
+    match &err.cause {
+        Some(cause) => format!("{}\n{}", err.message, cause),
+        None => err.message.to_owned(),
+    }
+}
 
 /// Creates a snapshot out of an execution result
 pub fn to_snapshot(
@@ -10,38 +374,262 @@ pub fn to_snapshot(
     description: Option<String>,
     tags: Vec<String>,
     cmd: String,
+    stdin: Option<Vec<u8>>,
     snap: Output,
 ) -> Snapshot {
     let exit_code = snap.status.code();
+    let signal = snap.status.signal();
     let stdout = to_snapshot_data(snap.stdout, &name, ".out");
     let stderr = to_snapshot_data(snap.stderr, &name, ".err");
+    let stdin = stdin.and_then(|stdin| to_snapshot_data(stdin, &name, ".in"));
     Snapshot {
         cmd,
         name,
         description,
         tags,
         exit_code,
+        signal,
         stdout,
         stderr,
+        stdin,
+        artifacts: Vec::new(),
+        expected_stdout: None,
         status: SnapshotStatus::Waiting,
+        last_run: None,
+        history: Vec::new(),
+        env: BTreeMap::new(),
+        timeout: None,
+        max_duration_ms: None,
+        pty: false,
+        combined: false,
+        runner: None,
+        hermetic: None,
+        before: None,
+        after: None,
+        retries: 0,
+        needs: Vec::new(),
+        serial: None,
+        inputs: Vec::new(),
+        strip_ansi: false,
+        filters: Vec::new(),
+        redact: Vec::new(),
+        pipeline: Vec::new(),
+        normalize: Normalize::default(),
+        exit_code_only: false,
+        ignore_stdout: false,
+        ignore_stderr: false,
+        binary: false,
+        compare: Compare::Text,
+        tolerance: Tolerance::default(),
+        contains: None,
+        exit_codes: None,
+        assertions: Vec::new(),
+        comparator: None,
+        xfail: false,
+        cache_key: None,
+        archived: false,
+        created: None,
+        updated: None,
         deleted: false,
     }
 }
 
 /// Creates a snapshot_data item from raw body.
 pub fn to_snapshot_data(body: Vec<u8>, path: &str, path_extension: &str) -> Option<SnapshotData> {
-    if body.len() > 0 {
+    if !body.is_empty() {
         let mut path = path.to_owned();
         path.push_str(path_extension);
-        Some(SnapshotData { body, path })
+        Some(SnapshotData { body: body.into(), path })
     } else {
         None
     }
 }
 
+/// Checks the file of a new artifact: it must be within the directory the
+/// commands run in, relative, without `..`, and not lead out of the directory
+/// through a symbolic link. It must not exist yet either, as the recorded
+/// artifacts are removed before their command runs again.
+pub fn check_artifact(dir: &Path, file: &str) -> Result<(), Error> {
+    let path = Path::new(file);
+    let is_relative = path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    let within = || {
+        let dir = dir.canonicalize().ok()?;
+        // The deepest existing ancestor resolves the symbolic links
+        let existing = dir.join(path).ancestors().find(|ancestor| ancestor.exists())?.canonicalize().ok()?;
+        Some(existing.starts_with(&dir))
+    };
+    if file.is_empty() || !is_relative || within() == Some(false) {
+        return Error::from_str(&format!("The artifact '{}' is not a relative path within the project.", file));
+    }
+    if dir.join(path).exists() {
+        return Error::from_str(&format!("The artifact '{}' already exists, it must be written by the command.", file));
+    }
+    Ok(())
+}
+
+/// Creates the artifacts of a snapshot out of the content of their files, as
+/// read by an execution.
+pub fn to_artifacts(name: &str, files: &[String], contents: Vec<Option<Vec<u8>>>) -> Vec<Artifact> {
+    files
+        .iter()
+        .zip(contents)
+        .enumerate()
+        .map(|(idx, (file, body))| Artifact {
+            file: file.to_owned(),
+            data: body.map(|body| SnapshotData {
+                path: format!("{}.artifact-{}", name, idx),
+                body: body.into(),
+            }),
+        })
+        .collect()
+}
+
+/// Returns what a snapshot would expect if it was updated with the result of
+/// an execution, its ignored outputs left out.
+pub fn to_expectation(snap: &Snapshot, execution: &Execution) -> Expectation {
+    let output = |ignored: bool, body: &[u8]| Some(body.to_vec()).filter(|body| !ignored && !body.is_empty());
+    Expectation {
+        exit_code: execution.output.status.code(),
+        signal: execution.output.status.signal(),
+        stdout: output(snap.ignores_stdout(), &execution.output.stdout),
+        stderr: output(snap.ignores_stderr(), &execution.output.stderr),
+        artifacts: snap
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.file.clone())
+            .zip(execution.artifacts.iter().cloned())
+            .collect(),
+    }
+}
+
+/// Replaces the expectation of a snapshot, the previous one is not kept.
+pub fn set_expectation(snap: &mut Snapshot, expectation: Expectation) {
+    snap.exit_code = expectation.exit_code;
+    snap.signal = expectation.signal;
+    snap.stdout = expectation.stdout.and_then(|body| to_snapshot_data(body, &snap.name, ".out"));
+    snap.stderr = expectation.stderr.and_then(|body| to_snapshot_data(body, &snap.name, ".err"));
+    let (files, contents): (Vec<String>, Vec<Option<Vec<u8>>>) = expectation.artifacts.into_iter().unzip();
+    snap.artifacts = to_artifacts(&snap.name, &files, contents);
+}
+
 /// Normalizes a string for use a file name.
 pub fn normalize_name(name: &str) -> String {
-    name.trim().replace(' ', "_").replace('\t', "_")
+    name.trim().replace([' ', '\t'], "_")
+}
+
+/// Returns true if the string is a valid tag name (without the leading '#').
+pub fn is_valid_tag(tag: &str) -> bool {
+    Regex::new(&format!("^{}$", TAG_PATTERN)).unwrap().is_match(tag)
+}
+
+/// Adds a tag to the snapshot by appending it to the description, tags on the
+/// last line of the description are kept together.
+/// Returns true if the tag was added, false if the snapshot already had it.
+pub fn add_tag(snap: &mut Snapshot, tag: &str) -> bool {
+    if snap.tags.iter().any(|t| t == tag) {
+        return false;
+    }
+    let description = match snap.description.take() {
+        None => format!("#{}", tag),
+        Some(description) => {
+            let last_line = description.lines().last().unwrap_or("");
+            if !last_line.trim().is_empty() && last_line.split_whitespace().all(|word| word.starts_with('#')) {
+                format!("{} #{}", description, tag)
+            } else {
+                format!("{}\n#{}", description, tag)
+            }
+        }
+    };
+    snap.tags = parse_tags(&description);
+    snap.description = Some(description);
+    true
+}
+
+/// Removes a tag from the snapshot, along with all its occurences in the
+/// description.
+/// Returns true if the tag was removed, false if the snapshot did not have it.
+pub fn remove_tag(snap: &mut Snapshot, tag: &str) -> bool {
+    if !snap.tags.iter().any(|t| t == tag) {
+        return false;
+    }
+    if let Some(description) = snap.description.take() {
+        let pattern = format!(r"(?m)(^|[ \t]+)#{}([ \t]+|$|[^a-zA-Z0-9_-])", regex::escape(tag));
+        let re = Regex::new(&pattern).unwrap();
+        let description = re.replace_all(&description, |caps: &regex::Captures| {
+            // Keep a single separator between the surrounding words
+            let (before, after) = (&caps[1], &caps[2]);
+            if before.is_empty() && after.trim().is_empty() {
+                String::new()
+            } else {
+                after.to_owned()
+            }
+        });
+        let description: Vec<&str> = description.lines().map(|line| line.trim_end()).collect();
+        let description = description.join("\n").trim().to_owned();
+        if !description.is_empty() {
+            snap.description = Some(description);
+        }
+    }
+    snap.tags = snap.description.as_deref().map(parse_tags).unwrap_or_default();
+    true
+}
+
+/// Returns true if the name matches the pattern. Patterns containing `*` or `?`
+/// are treated as globs matching the whole name, other patterns match any name
+/// containing them.
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    if pattern.contains(['*', '?']) {
+        glob_match(pattern, name)
+    } else {
+        name.contains(pattern)
+    }
+}
+
+/// Matches a name against a glob, where `*` matches any sequence of characters
+/// and `?` a single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position in the pattern after the last '*' and in the name where it matched
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p + 1, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            // Let the last '*' consume one more character
+            p = star_p;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Returns every combination of the matrix variables values, along with the
+/// suffix of the matching snapshot name (values separated by commas).
+/// An empty matrix has a single, empty, combination.
+pub fn expand_matrix(matrix: &[(String, Vec<String>)]) -> Vec<(String, Vec<(String, String)>)> {
+    let mut combinations = vec![(String::new(), Vec::new())];
+    for (var, values) in matrix {
+        let mut expanded = Vec::with_capacity(combinations.len() * values.len());
+        for (suffix, vars) in &combinations {
+            for value in values {
+                let suffix = if suffix.is_empty() { value.to_owned() } else { format!("{},{}", suffix, value) };
+                let mut vars = vars.clone();
+                vars.push((var.to_owned(), value.to_owned()));
+                expanded.push((suffix, vars));
+            }
+        }
+        combinations = expanded;
+    }
+    combinations
 }
 
 /// Generates a random name starting with '_'.
@@ -50,3 +638,182 @@ pub fn get_random_name() -> String {
     random_name.extend(thread_rng().sample_iter(&Alphanumeric).take(30));
     random_name
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_matches() {
+        // Should match
+        assert!(name_matches("run", "fail-run"));
+        assert!(name_matches("*-run", "fail-run"));
+        assert!(name_matches("*", "fail-run"));
+        assert!(name_matches("f?il*", "fail-run"));
+        assert!(name_matches("*a*u*", "fail-run"));
+        assert!(name_matches("fail-run*", "fail-run"));
+
+        // Should not match
+        assert!(!name_matches("init", "fail-run"));
+        assert!(!name_matches("run*", "fail-run"));
+        assert!(!name_matches("?", "fail-run"));
+        assert!(!name_matches("*-ru", "fail-run"));
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let entries = parse_commands("ls -a\n\n# Comment\n  echo 'hello'  \n", false).unwrap();
+        let cmds: Vec<&str> = entries.iter().map(|entry| entry.cmd.as_str()).collect();
+        assert_eq!(cmds, ["ls -a", "echo 'hello'"]);
+
+        let json = r#"[{"cmd": "ls", "name": "list", "tags": ["fs"]}, {"cmd": "pwd"}]"#;
+        let entries = parse_commands(json, true).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name.as_deref(), Some("list"));
+        assert_eq!(entries[0].tags, ["fs"]);
+        assert!(entries[1].name.is_none() && entries[1].tags.is_empty());
+        assert!(parse_commands(r#"{"cmd": "ls"}"#, true).is_err());
+    }
+
+    #[test]
+    fn test_tags() {
+        let output = Output {
+            status: std::process::ExitStatus::default(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        };
+        let mut snap = to_snapshot("test".into(), None, Vec::new(), "ls".into(), None, output);
+        assert!(add_tag(&mut snap, "cli"));
+        assert!(!add_tag(&mut snap, "cli"));
+        assert_eq!(snap.description.as_deref(), Some("#cli"));
+        snap.description = Some("Some test\n#cli".into());
+        assert!(add_tag(&mut snap, "slow"));
+        assert_eq!(snap.description.as_deref(), Some("Some test\n#cli #slow"));
+        assert_eq!(snap.tags, ["cli", "slow"]);
+
+        snap.description = Some("Checks #cli-run output\n#cli #slow".into());
+        snap.tags = parse_tags(snap.description.as_ref().unwrap());
+        assert!(remove_tag(&mut snap, "cli"));
+        assert!(!remove_tag(&mut snap, "cli"));
+        assert_eq!(snap.description.as_deref(), Some("Checks #cli-run output\n#slow"));
+        assert_eq!(snap.tags, ["cli-run", "slow"]);
+        assert!(remove_tag(&mut snap, "slow"));
+        assert!(remove_tag(&mut snap, "cli-run"));
+        assert_eq!(snap.description.as_deref(), Some("Checks output"));
+        assert!(snap.tags.is_empty());
+    }
+
+    #[test]
+    fn test_order_by_needs() {
+        let snap = |name: &str, needs: &[&str]| (name.to_owned(), needs.iter().map(|&n| n.to_owned()).collect());
+        let snaps = vec![snap("c", &["b"]), snap("a", &[]), snap("b", &["a", "missing"]), snap("d", &[])];
+        assert_eq!(order_by_needs(&snaps).unwrap(), vec![vec![1, 3], vec![2], vec![0]]);
+        let snaps = vec![snap("a", &["b"]), snap("b", &["a"]), snap("c", &[])];
+        assert!(order_by_needs(&snaps).is_err());
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let output = Output {
+            status: ExitStatusExt::from_raw(0),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        };
+        let mut snap = to_snapshot("test".into(), None, Vec::new(), "cat input".into(), None, output);
+        let dir = std::env::temp_dir().join(format!("parrot-cache-key-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(cache_key(&snap, &[], &dir), None);
+        snap.inputs = vec![String::from("input")];
+        let missing = cache_key(&snap, &[], &dir);
+        fs::write(dir.join("input"), "a").unwrap();
+        let key = cache_key(&snap, &[], &dir);
+        assert!(key.is_some() && key != missing);
+        assert_eq!(cache_key(&snap, &[], &dir), key);
+        fs::write(dir.join("input"), "b").unwrap();
+        assert_ne!(cache_key(&snap, &[], &dir), key);
+        snap.cmd = String::from("cat input input");
+        fs::write(dir.join("input"), "a").unwrap();
+        assert_ne!(cache_key(&snap, &[], &dir), key);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_redactions() {
+        let redactions = vec![
+            Redaction {
+                pattern: String::from(r"\d{4}-\d{2}-\d{2}"),
+                replacement: String::from("<DATE>"),
+            },
+            Redaction {
+                pattern: String::from(r"pid (\d+)"),
+                replacement: String::from("pid <$1>"),
+            },
+        ];
+        let body = apply_redactions(b"2024-01-31 started, pid 42\n", &redactions).unwrap();
+        assert_eq!(body, b"<DATE> started, pid <42>\n");
+        let invalid = Redaction {
+            pattern: String::from("("),
+            replacement: String::new(),
+        };
+        assert!(apply_redactions(b"", &[invalid]).is_err());
+    }
+
+    #[test]
+    fn test_path_redactions() {
+        let tmp = std::env::temp_dir().canonicalize().unwrap();
+        let repo = tmp.join(format!("parrot-paths-{}", std::process::id()));
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(repo.join("tests")).unwrap();
+        let redactions = path_redactions(&repo.join("tests"));
+        let (repo, tmp) = (repo.to_string_lossy().into_owned(), tmp.to_string_lossy().into_owned());
+        let output = format!("{}/src/main.rs\n{}/out {}\n{}x\n", repo, tmp, repo, tmp);
+        let body = apply_redactions(output.as_bytes(), &redactions).unwrap();
+        assert_eq!(body, format!("<ROOT>/src/main.rs\n<TMP>/out <ROOT>\n{}x\n", tmp).as_bytes());
+        fs::remove_dir_all(&repo).unwrap();
+    }
+
+    #[test]
+    fn test_filter_redactions() {
+        let filters: Vec<String> = FILTERS.iter().map(|&filter| filter.to_owned()).collect();
+        let redactions = filter_redactions(&filters).unwrap();
+        let output = "at 2024-01-31T12:00:00.123Z id 123e4567-e89b-12d3-a456-426614174000 \
+                      ptr 0x7ffd5e8c took 12.5ms";
+        let body = apply_redactions(output.as_bytes(), &redactions).unwrap();
+        assert_eq!(body, &b"at <TIMESTAMP> id <UUID> ptr <ADDRESS> took <DURATION>"[..]);
+        let tmp = std::env::temp_dir().join("parrot-abc").join("file.txt");
+        let body = apply_redactions(tmp.to_string_lossy().as_bytes(), &redactions).unwrap();
+        assert_eq!(body, b"<TMP>");
+        assert!(filter_redactions(&[String::from("nope")]).is_err());
+    }
+
+    #[test]
+    fn test_expand_matrix() {
+        assert_eq!(expand_matrix(&[]), vec![(String::new(), Vec::new())]);
+        let matrix = vec![
+            (String::from("FMT"), vec![String::from("json"), String::from("yaml")]),
+            (String::from("LANG"), vec![String::from("C")]),
+        ];
+        let combinations = expand_matrix(&matrix);
+        let suffixes: Vec<&str> = combinations.iter().map(|(suffix, _)| suffix.as_str()).collect();
+        assert_eq!(suffixes, vec!["json,C", "yaml,C"]);
+        assert_eq!(
+            combinations[1].1,
+            vec![(String::from("FMT"), String::from("yaml")), (String::from("LANG"), String::from("C"))]
+        );
+    }
+
+    #[test]
+    fn test_check_artifact() {
+        let dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink("/tmp", dir.path().join("out")).unwrap();
+        assert!(check_artifact(dir.path(), "report.json").is_ok());
+        assert!(check_artifact(dir.path(), "./build/new/report.json").is_ok());
+        assert!(check_artifact(dir.path(), "").is_err());
+        assert!(check_artifact(dir.path(), "/etc/passwd").is_err());
+        assert!(check_artifact(dir.path(), "../report.json").is_err());
+        assert!(check_artifact(dir.path(), "build/../../report.json").is_err());
+        assert!(check_artifact(dir.path(), "out/report.json").is_err());
+        fs::write(dir.path().join("readme.md"), "").unwrap();
+        assert!(check_artifact(dir.path(), "readme.md").is_err());
+    }
+}
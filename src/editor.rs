@@ -5,53 +5,104 @@ use std::path::Path;
 use std::process::Command;
 use regex::Regex;
 
-use crate::data::PARROT_PATH;
 use crate::error::{wrap, Error};
 
-const FILE_NAME: &'static str = "PARROT_SNAPSHOT";
+const FILE_NAME: &str = "PARROT_SNAPSHOT";
+const STDIN_FILE_NAME: &str = "PARROT_STDIN";
+/// A line of the description file selecting the exit code only mode.
+const EXIT_CODE_ONLY: &str = "@exit-code-only";
+/// The prefix of the lines adding a step to the pipeline of the snapshot.
+const PIPE: &str = "@pipe ";
+/// The characters a tag is made of.
+pub const TAG_PATTERN: &str = "[a-zA-Z0-9_-]+";
 
 pub struct EditResult {
     pub name: Option<String>,
     pub description: Option<String>,
     pub tags: Vec<String>,
+    /// Only the exit code of the command is compared.
+    pub exit_code_only: bool,
+    /// The steps of the pipeline transforming the outputs, in order.
+    pub pipeline: Vec<String>,
 }
 
 /// Opens an empty description in the user's favorite editor.
 pub fn open_empty<P: AsRef<Path>>(path: P, cmd: &str) -> Result<EditResult, Error> {
-    open(path, "", "", cmd)
+    open(path, "", "", false, &[], cmd)
 }
 
 /// Opens the snapshot's description file in the user's favorite editor.
-pub fn open_snap<P: AsRef<Path>>(path: P, name: &str, description: &str, cmd: &str) -> Result<EditResult, Error> {
-    open(path, name, description, cmd)
+pub fn open_snap<P: AsRef<Path>>(
+    path: P,
+    name: &str,
+    description: &str,
+    exit_code_only: bool,
+    pipeline: &[String],
+    cmd: &str,
+) -> Result<EditResult, Error> {
+    open(path, name, description, exit_code_only, pipeline, cmd)
 }
 
 /// Opens a new description file in the user's favorite editor.
-fn open<P: AsRef<Path>>(path: P, name: &str, description: &str, cmd: &str) -> Result<EditResult, Error> {
+fn open<P: AsRef<Path>>(
+    path: P,
+    name: &str,
+    description: &str,
+    exit_code_only: bool,
+    pipeline: &[String],
+    cmd: &str,
+) -> Result<EditResult, Error> {
+    let mut mode = if exit_code_only { format!("{}\n", EXIT_CODE_ONLY) } else { String::new() };
+    for step in pipeline {
+        mode.push_str(&format!("{}{}\n", PIPE, step));
+    }
+    let content = format!(
+        "{}\n\
+         {}\n\
+         {}\n\
+         // The first line will be used as snapshot name, the following as description.\n\
+         // If the first line is blank, a random name will be used.\n\
+         // Hastag in the description (#example) will serve as tag for the snapshot.\n\
+         // A line with {} only compares the exit code of the command, not its outputs.\n\
+         // Lines with {}STEP transform the outputs in order before they are stored and compared, steps are\n\
+         // strip-ansi, filter NAME, redact REGEX=REPLACEMENT, sort-lines, trim-trailing-whitespace,\n\
+         // collapse-blank-lines and normalize-newlines.\n\
+         // Characters after '//' are ignored.\n\
+         //\n\
+         // Test command: {}",
+        name, description, mode, EXIT_CODE_ONLY, PIPE, cmd
+    );
+    let content = edit(path, FILE_NAME, &content)?;
+    Ok(parse_file(String::from_utf8_lossy(&content).into_owned()))
+}
+
+/// Opens an empty stdin transcript in the user's favorite editor.
+/// Returns the transcript, without the leading comment lines, byte for byte.
+pub fn open_stdin<P: AsRef<Path>>(path: P, cmd: &str) -> Result<Vec<u8>, Error> {
+    let content = format!(
+        "// Write below the input fed to the command each time it runs.\n\
+         // Leading lines starting with '//' are ignored.\n\
+         //\n\
+         // Test command: {}\n",
+        cmd
+    );
+    let content = edit(path, STDIN_FILE_NAME, &content)?;
+    let lines = content.split(|byte| *byte == b'\n');
+    let transcript: Vec<&[u8]> = lines.skip_while(|line| line.starts_with(b"//")).collect();
+    Ok(transcript.join(&b'\n'))
+}
+
+/// Writes `content` to a temporary file in the parrot folder at `path` and
+/// opens it in the user's favorite editor, returns the content of the file once edited, which may not be valid
+/// UTF-8.
+fn edit<P: AsRef<Path>>(path: P, file_name: &str, content: &str) -> Result<Vec<u8>, Error> {
     let editor = var("EDITOR").expect("No 'EDITOR' environment variable.");
-    let mut file_path = path.as_ref().to_owned();
-    file_path.push(PARROT_PATH);
-    file_path.push(FILE_NAME);
+    let file_path = path.as_ref().join(file_name);
     let mut file = wrap(
         File::create(&file_path),
         "Could not create description file, try using `parrot init` first.",
     )?;
-    wrap(
-        write!(
-            file,
-            "{}\n\
-             {}\n\n\
-
-             // The first line will be used as snapshot name, the following as description.\n\
-             // If the first line is blank, a random name will be used.\n\
-             // Hastag in the description (#example) will serve as tag for the snapshot.\n\
-             // Characters after '//' are ignored.\n\
-             //\n\
-             // Test command: {}",
-            name, description, cmd
-        ),
-        "Could not write description file",
-    )?;
+    wrap(file.write_all(content.as_bytes()), "Could not write description file")?;
 
     let status = wrap(
         Command::new(editor).arg(&file_path).status(),
@@ -61,18 +112,17 @@ fn open<P: AsRef<Path>>(path: P, name: &str, description: &str, cmd: &str) -> Re
         return Error::from_str("Aborting");
     }
 
-    let mut content = String::new();
+    let mut content = Vec::new();
     wrap(
         wrap(
             File::open(&file_path),
             "Could not open description file after editing",
         )?
-        .read_to_string(&mut content),
+        .read_to_end(&mut content),
         "Could not read the description file",
     )?;
     let _ = remove_file(&file_path);
-
-    Ok(parse_file(content))
+    Ok(content)
 }
 
 /// Parse the content of the description file and return both title, description
@@ -81,8 +131,9 @@ fn parse_file(content: String) -> EditResult {
     let lines = content.split('\n');
     let mut name = String::from("");
     let mut description = String::from("");
-    let mut tags = Vec::new();
     let mut is_title = true;
+    let mut exit_code_only = false;
+    let mut pipeline = Vec::new();
     for line in lines {
         let (line, has_comment)  = strip_comment(line);
         if is_title {
@@ -90,27 +141,32 @@ fn parse_file(content: String) -> EditResult {
             is_title = false;
             continue;
         }
+        if line.trim() == EXIT_CODE_ONLY {
+            exit_code_only = true;
+            continue;
+        }
+        if let Some(step) = line.trim_start().strip_prefix(PIPE) {
+            pipeline.push(step.trim().to_owned());
+            continue;
+        }
        
-        if line.len() > 0 || !has_comment {
+        if !line.is_empty() || !has_comment {
             description.push_str(line);
-            description.push_str("\n");
+            description.push('\n');
         }
     }
 
     // Remove leadin/trainling whitespaces
     description = description.trim().to_owned();
 
-    let re = Regex::new(r"#[a-zA-Z0-9_-]+").unwrap();
-    for tag in re.captures_iter(&description) {
-        tags.push(tag[0][1..].to_owned());
-    }
-    
-    let name = if name.len() > 0 {
+    let tags = parse_tags(&description);
+
+    let name = if !name.is_empty() {
         Some(name)
     } else {
         None
     };
-    let description = if description.len() > 0 {
+    let description = if !description.is_empty() {
         Some(description)
     } else {
         None
@@ -118,7 +174,9 @@ fn parse_file(content: String) -> EditResult {
     EditResult {
         name,
         description,
-        tags
+        tags,
+        exit_code_only,
+        pipeline,
     }
 }
 
@@ -130,3 +188,9 @@ fn strip_comment(line: &str) -> (&str, bool) {
     let has_comment = iterator.next().is_some();
     (line, has_comment)
 }
+
+/// Returns the tags (hashtags such as #example) found in a description.
+pub fn parse_tags(description: &str) -> Vec<String> {
+    let re = Regex::new(&format!("#{}", TAG_PATTERN)).unwrap();
+    re.find_iter(description).map(|tag| tag.as_str()[1..].to_owned()).collect()
+}
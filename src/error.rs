@@ -1,5 +1,6 @@
 /// An error wrapper, contains a message for the user
 /// and a cause to be logged.
+#[derive(Debug)]
 pub struct Error {
     pub message: String,
     pub cause: Option<String>,
@@ -7,6 +8,12 @@ pub struct Error {
 
 const DEBUG: bool = true;
 
+/// Exit code used when snapshots (or checks) fail.
+pub const FAILURE_EXIT_CODE: i32 = 1;
+
+/// Exit code used on internal errors, such as a broken parrot folder.
+pub const ERROR_EXIT_CODE: i32 = 2;
+
 impl Error {
     pub fn from_str<T>(message: &str) -> Result<T, Error> {
         Err(Error {
@@ -36,7 +43,7 @@ impl<T> Log for Result<T, Error> {
                         println!("log: {}", cause)
                     }
                 }
-                std::process::exit(1)
+                std::process::exit(ERROR_EXIT_CODE)
             }
         }
     }
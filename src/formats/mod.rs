@@ -0,0 +1,46 @@
+//! Parsing of structured outputs, so that they are compared regardless of
+//! their formatting or the order of their keys.
+
+use serde_json::{Map, Value};
+
+use crate::compare::Compare;
+use crate::term::value_diff;
+
+pub mod toml;
+pub mod yaml;
+
+/// Parses an output in the format it is compared as, returns `None` if it is
+/// compared as text or is not valid.
+pub fn parse(compare: Compare, body: &[u8]) -> Option<Value> {
+    let text = std::str::from_utf8(body).ok()?;
+    match compare {
+        Compare::Text => None,
+        Compare::Json => serde_json::from_str(text).ok(),
+        Compare::Yaml => yaml::parse(text),
+        Compare::Toml => toml::parse(text),
+    }
+}
+
+/// Returns the structural differences between two outputs, or `None` if
+/// either output can't be parsed in the format they are compared as.
+pub fn structural_diff(compare: Compare, old: &[u8], new: &[u8]) -> Option<Vec<(char, String)>> {
+    Some(value_diff(&parse(compare, old)?, &parse(compare, new)?))
+}
+
+/// Returns a copy of an object without its `null` values, at any depth, as
+/// they have no representation in either YAML front matter or TOML.
+fn without_nulls(table: &Map<String, Value>) -> Map<String, Value> {
+    table
+        .iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| (key.clone(), strip_nulls(value)))
+        .collect()
+}
+
+fn strip_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(table) => Value::Object(without_nulls(table)),
+        Value::Array(values) => Value::Array(values.iter().map(strip_nulls).collect()),
+        value => value.clone(),
+    }
+}
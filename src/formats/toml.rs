@@ -0,0 +1,114 @@
+use serde_json::{Map, Number, Value};
+use toml::Value as Toml;
+
+use super::without_nulls;
+
+/// Parses a TOML document, dates are kept as strings.
+pub fn parse(text: &str) -> Option<Value> {
+    text.parse::<toml::Table>().ok().map(|table| to_json(Toml::Table(table)))
+}
+
+/// Converts a TOML value to a JSON one.
+fn to_json(value: Toml) -> Value {
+    match value {
+        Toml::String(string) => Value::String(string),
+        Toml::Integer(integer) => Value::from(integer),
+        Toml::Float(float) => Number::from_f64(float).map_or(Value::Null, Value::Number),
+        Toml::Boolean(boolean) => Value::Bool(boolean),
+        Toml::Datetime(datetime) => Value::String(datetime.to_string()),
+        Toml::Array(values) => Value::Array(values.into_iter().map(to_json).collect()),
+        Toml::Table(table) => Value::Object(table.into_iter().map(|(key, value)| (key, to_json(value))).collect()),
+    }
+}
+
+/// Writes a JSON object as a TOML document: tables and arrays of tables come
+/// after the other keys, `null` values are left out.
+pub fn write(root: &Map<String, Value>) -> Result<String, toml::ser::Error> {
+    toml::to_string(&without_nulls(root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let document = r#"
+# A comment
+title = "TOML \"example\"" # comment
+"quoted key" = 'C:\path'
+site.name = "parrot"
+
+[owner]
+dob = 1979-05-27 07:32:00-08:00
+numbers = [ 1_000, 0xff, -2.5,
+  1e3, ] # trailing comma
+
+[servers.alpha]
+ip = { address = "10.0.0.1", port = 8080 }
+
+[[products]]
+name = """
+multi \
+  line"""
+
+[[products]]
+name = '''raw \n'''
+"#;
+        let expected = json!({
+            "title": "TOML \"example\"",
+            "quoted key": "C:\\path",
+            "site": {"name": "parrot"},
+            "owner": {"dob": "1979-05-27T07:32:00-08:00", "numbers": [1000, 255, -2.5, 1000.0]},
+            "servers": {"alpha": {"ip": {"address": "10.0.0.1", "port": 8080}}},
+            "products": [{"name": "multi line"}, {"name": "raw \\n"}],
+        });
+        assert_eq!(parse(document), Some(expected));
+        assert_eq!(parse("a = 1\na = 2"), None);
+        assert_eq!(parse("a = "), None);
+        assert_eq!(parse("[a\nb = 1"), None);
+        assert_eq!(parse("not toml at all"), None);
+    }
+
+    #[test]
+    fn test_write() {
+        let value = json!({
+            "cmd": "echo \"hi\"\n",
+            "description": null,
+            "tags": ["a", "b"],
+            "exit_code": 0,
+            "tolerance": {"absolute": 0.5, "relative": 1.0},
+            "env": {"MY VAR": "1"},
+            "redact": [{"pattern": "[0-9]+", "replacement": "<N>"}, {"pattern": "x", "replacement": "y"}],
+            "matrix": [[1, 2], []],
+        });
+        let document = write(value.as_object().unwrap()).unwrap();
+        let expected = r#"cmd = """
+echo "hi"
+"""
+exit_code = 0
+matrix = [[1, 2], []]
+tags = ["a", "b"]
+
+[env]
+"MY VAR" = "1"
+
+[[redact]]
+pattern = "[0-9]+"
+replacement = "<N>"
+
+[[redact]]
+pattern = "x"
+replacement = "y"
+
+[tolerance]
+absolute = 0.5
+relative = 1.0
+"#;
+        assert_eq!(document, expected);
+        let mut round_trip = value;
+        round_trip.as_object_mut().unwrap().remove("description");
+        assert_eq!(parse(&document), Some(round_trip));
+    }
+}
@@ -0,0 +1,128 @@
+use serde_json::{Map, Number, Value};
+use serde_yaml::Value as Yaml;
+
+use super::without_nulls;
+
+/// Parses a YAML document, keys that are not strings are written as YAML and
+/// tags are left out.
+pub fn parse(text: &str) -> Option<Value> {
+    serde_yaml::from_str(text).ok().map(to_json)
+}
+
+/// Converts a YAML value to a JSON one.
+fn to_json(value: Yaml) -> Value {
+    match value {
+        Yaml::Null => Value::Null,
+        Yaml::Bool(boolean) => Value::Bool(boolean),
+        Yaml::Number(number) => match (number.as_i64(), number.as_u64(), number.as_f64()) {
+            (Some(integer), _, _) => Value::from(integer),
+            (_, Some(integer), _) => Value::from(integer),
+            (_, _, Some(float)) => Number::from_f64(float).map_or(Value::Null, Value::Number),
+            _ => Value::Null,
+        },
+        Yaml::String(string) => Value::String(string),
+        Yaml::Sequence(values) => Value::Array(values.into_iter().map(to_json).collect()),
+        Yaml::Mapping(mapping) => {
+            let mut map = Map::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    Yaml::String(key) => key,
+                    key => serde_yaml::to_string(&key).unwrap_or_default().trim_end().to_owned(),
+                };
+                map.insert(key, to_json(value));
+            }
+            Value::Object(map)
+        }
+        Yaml::Tagged(tagged) => to_json(tagged.value),
+    }
+}
+
+/// Writes a JSON object as a block YAML document, `null` values are left out.
+/// Strings spanning several lines are written as literal block scalars, an
+/// empty object as an empty document.
+pub fn write(root: &Map<String, Value>) -> Result<String, serde_yaml::Error> {
+    let root = without_nulls(root);
+    if root.is_empty() {
+        return Ok(String::new());
+    }
+    serde_yaml::to_string(&root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let document = "---
+# A comment
+name: parrot  # comment
+version: 1.2
+'quoted: key': \"tab\\there\"
+empty:
+tags: [cli, 'snapshot', {kind: test}]
+servers:
+- host: alpha
+  ports:
+    - 80
+    - 0x1bb
+-
+  host: beta
+options: {debug: false, level: ~}
+script: |
+  echo a
+
+  echo b
+folded: >-
+  a
+  b
+...
+";
+        let expected = json!({
+            "name": "parrot",
+            "version": 1.2,
+            "quoted: key": "tab\there",
+            "empty": null,
+            "tags": ["cli", "snapshot", {"kind": "test"}],
+            "servers": [{"host": "alpha", "ports": [80, 443]}, {"host": "beta"}],
+            "options": {"debug": false, "level": null},
+            "script": "echo a\n\necho b\n",
+            "folded": "a b",
+        });
+        assert_eq!(parse(document), Some(expected));
+        assert_eq!(parse("- a\n- b: 1\n  c: it's\n"), Some(json!(["a", {"b": 1, "c": "it's"}])));
+        assert_eq!(parse("just text"), Some(json!("just text")));
+        assert_eq!(parse("a: &anchor 1\nb: *anchor\n1: !tag c"), Some(json!({"a": 1, "b": 1, "1": "c"})));
+        assert_eq!(parse("a: 1\na: 2"), None);
+        assert_eq!(parse("a: 1\n  b: 2"), None);
+        assert_eq!(parse("a: [1, 2"), None);
+        assert_eq!(parse("a: 1\n---\nb: 2"), None);
+    }
+
+    #[test]
+    fn test_write() {
+        let value = json!({
+            "cmd": "echo 'hi' # not a comment",
+            "name": "value",
+            "description": "Two lines\n#cli\n",
+            "script": "a\n\n  b",
+            "control": "tab\there\u{1b}",
+            "code": "0",
+            "exit_code": 0,
+            "missing": null,
+            "tags": ["cli", "true"],
+            "env": {"MY VAR": "1", "UNSET": null},
+            "redact": [{"pattern": "[0-9]+", "replacement": "<N>"}],
+            "inputs": [],
+        });
+        let document = write(value.as_object().unwrap()).unwrap();
+        assert!(document.contains("description: |\n  Two lines\n  #cli\n"));
+        assert!(!document.contains("missing") && !document.contains("UNSET"));
+        assert_eq!(write(json!({"missing": null}).as_object().unwrap()).unwrap(), "");
+        let mut round_trip = value;
+        round_trip.as_object_mut().unwrap().remove("missing");
+        round_trip["env"].as_object_mut().unwrap().remove("UNSET");
+        assert_eq!(parse(&document), Some(round_trip));
+    }
+}
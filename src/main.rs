@@ -1,35 +1,278 @@
-use cli::Command;
-use std::process::exit;
+use cli::{Command, TagCommand};
+use compare::{Normalize, Tolerance};
+use driver::{AddOptions, ListOptions, RunOptions, Verbosity};
 use error::Log;
+use term::ColorChoice;
+use std::path::{Path, PathBuf};
+use std::process::exit;
 
 mod cli;
+mod compare;
 mod data;
 mod diff;
 mod driver;
 mod editor;
 mod error;
-mod term;
+mod formats;
 mod parser;
+mod report;
+mod term;
 
 fn main() {
     let config = cli::parse();
-    let mut context = driver::Context::new(config.path).unwrap_log();
+    if let Some(Command::Completions { shell }) = &config.cmd {
+        // Completion scripts don't depend on the parrot folder, which may be
+        // locked by another process
+        cli::completions(shell, &mut std::io::stdout());
+        return;
+    }
+    // Commands run from a subdirectory apply to the closest project above,
+    // a new project is initialized where asked
+    let path = match (config.path, &config.cmd, &config.store) {
+        (Some(path), _, _) => path,
+        (None, Some(Command::Init {}), _) | (None, _, Some(_)) => PathBuf::from("."),
+        (None, _, _) => data::find_project(Path::new(".")),
+    };
+    // `diff --no-color` is the same as `--color never`
+    let color = match &config.cmd {
+        Some(Command::Diff { no_color: true, .. }) => ColorChoice::Never,
+        _ => config.color.unwrap_or(ColorChoice::Auto),
+    };
+    let mut context = driver::Context::new(
+        path,
+        config.json,
+        color,
+        config.timeout,
+        config.max_output.unwrap_or(cli::DEFAULT_MAX_OUTPUT),
+        config.store,
+        config.suite,
+        config.wait,
+    )
+    .unwrap_log();
     match config.cmd {
+        Some(Command::Import { format, ref path, yes }) => context.import(format, path, yes),
         Some(Command::Init {}) => {
             context.init();
         }
+        Some(Command::Accept { ref name }) => context.accept(name),
         Some(Command::Add {
-            ref cmd,
-            ref name,
+            cmd,
+            name,
+            stdin,
+            stdin_file,
+            edit_stdin,
+            env,
+            capture_env,
+            max_duration,
+            pty,
+            combined,
+            runner,
+            hermetic,
+            pass_env,
+            before,
+            after,
+            retries,
+            matrix,
+            needs,
+            serial,
+            inputs,
+            strip_ansi,
+            filters,
+            redact,
+            pipeline,
+            trim_trailing_whitespace,
+            collapse_blank_lines,
+            ignore_all_whitespace,
+            sort_lines,
+            normalize_newlines,
+            exit_code_only,
+            ignore_stdout,
+            ignore_stderr,
+            binary,
+            compare,
+            abs_tolerance,
+            rel_tolerance,
+            contains,
+            exit_codes,
+            assertions,
+            comparator,
+            xfail,
+            artifacts,
+            expected_stdout,
+            from_file,
             yes,
-        }) => context.add(cmd, name, yes),
-        Some(Command::Run {}) => {
-            if context.run() {
+        }) => {
+            let options = AddOptions {
+                name,
+                stdin,
+                stdin_file,
+                edit_stdin,
+                env,
+                capture_env,
+                timeout: config.timeout,
+                max_duration_ms: max_duration,
+                pty,
+                combined,
+                runner,
+                hermetic,
+                pass_env,
+                before,
+                after,
+                retries,
+                matrix,
+                needs,
+                serial,
+                inputs,
+                strip_ansi,
+                filters,
+                redact,
+                pipeline,
+                normalize: Normalize {
+                    trim_trailing_whitespace,
+                    collapse_blank_lines,
+                    ignore_all_whitespace,
+                    sort_lines,
+                    normalize_newlines,
+                },
+                exit_code_only,
+                ignore_stdout,
+                ignore_stderr,
+                binary,
+                compare: compare.unwrap_or_default(),
+                tolerance: Tolerance {
+                    absolute: abs_tolerance.unwrap_or_default(),
+                    relative: rel_tolerance.unwrap_or_default(),
+                },
+                contains,
+                exit_codes,
+                assertions,
+                comparator,
+                xfail,
+                artifacts,
+                expected_stdout,
+                yes,
+            };
+            match (cmd, from_file) {
+                (Some(cmd), _) => context.add(&cmd, &options),
+                (None, Some(from_file)) => context.add_from_file(&from_file, yes),
+                (None, None) => unreachable!("clap requires either a command or a file"),
+            }
+        }
+        Some(Command::List {
+            tags,
+            name,
+            names_only,
+            archived,
+            older_than,
+            newer_than,
+        }) => {
+            let options = ListOptions {
+                tags,
+                name,
+                names_only,
+                archived,
+                older_than,
+                newer_than,
+            };
+            context.list(&options, config.verbose > 0)
+        }
+        Some(Command::Migrate {}) => context.migrate(),
+        Some(Command::Remove { ref name, yes }) => context.remove(name, yes),
+        Some(Command::Rename { ref name, ref new_name }) => context.rename(name, new_name),
+        Some(Command::Restore { ref name }) => context.restore(name),
+        Some(Command::History { ref name }) => context.history(name),
+        Some(Command::Rollback { ref name, to }) => context.rollback(name, to),
+        Some(Command::Reject { ref name }) => context.reject(name),
+        Some(Command::Review {}) => context.review(),
+        Some(Command::Resolve {}) => context.resolve(),
+        Some(Command::Run {
+            pattern,
+            max_failures,
+            allow_failures,
+            last_failed,
+            tags,
+            exclude_tags,
+            name_regex,
+            dry_run,
+            jobs,
+            repeat,
+            until_failure,
+            pending,
+            report,
+            gha,
+        }) => {
+            let options = RunOptions {
+                pattern,
+                max_failures,
+                allow_failures,
+                last_failed,
+                tags,
+                exclude_tags,
+                name_regex,
+                dry_run,
+                jobs,
+                repeat,
+                until_failure,
+                pending,
+                reports: report,
+                annotations: gha || std::env::var("GITHUB_ACTIONS").is_ok_and(|value| value == "true"),
+                verbosity: match (config.quiet, config.verbose) {
+                    (true, _) => Verbosity::Quiet,
+                    (false, 0) => Verbosity::Normal,
+                    (false, 1) => Verbosity::Verbose,
+                    (false, _) => Verbosity::Outputs,
+                },
+            };
+            if context.run(&options) {
                 exit(0);
             } else {
-                exit(1);
+                exit(error::FAILURE_EXIT_CODE);
             }
+        }
+        Some(Command::Show { ref name, raw }) => context.show(name, raw),
+        Some(Command::Stats { top }) => context.stats(top),
+        Some(Command::Status {}) => context.status(),
+        Some(Command::Tag { ref cmd }) => match cmd {
+            TagCommand::Add { tag, name, all } => context.tag_add(tag, name, *all),
+            TagCommand::List {} => context.tag_list(),
+            TagCommand::Remove { tag, name, all } => context.tag_remove(tag, name, *all),
+        },
+        Some(Command::Trash { ref action, ref name }) => match (action.as_str(), name) {
+            ("restore", Some(name)) => context.trash_restore(name),
+            ("empty", _) => context.trash_empty(),
+            _ => context.trash_list(),
         },
+        Some(Command::Update {
+            ref pattern,
+            all,
+            failed_only,
+            dry_run,
+            no_cache,
+        }) => context.update(pattern, all, failed_only, dry_run, no_cache),
+        Some(Command::Archive { ref name }) => context.archive(name),
+        Some(Command::Clean { dry_run }) => context.clean(dry_run),
+        Some(Command::Gc {
+            dry_run,
+            aggressive,
+            keep_history,
+        }) => context.gc(dry_run, aggressive, keep_history),
+        Some(Command::Completions { .. }) => unreachable!("completions are generated without a context"),
+        Some(Command::Copy {
+            ref name,
+            ref new_name,
+            ref cmd,
+        }) => context.copy(name, new_name, cmd),
+        Some(Command::Diff { ref name, .. }) => {
+            if !context.diff(name) {
+                exit(error::FAILURE_EXIT_CODE);
+            }
+        }
+        Some(Command::Doctor {}) => {
+            if !context.doctor() {
+                exit(error::FAILURE_EXIT_CODE);
+            }
+        }
+        Some(Command::Edit { ref name }) => context.edit(name),
         Some(Command::Exec { ref cmd }) => context.exec(cmd),
         None => {
             context.repl();
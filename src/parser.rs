@@ -74,7 +74,7 @@ fn whitespaces(i: &str) -> CResult<&str, &str> {
 /// EOF counts as a separator.
 fn peek_separator(i: &str) -> CResult<&str, ()> {
     let chars = " \t\r\n#+-*~;";
-    if i.len() == 0 {
+    if i.is_empty() {
         Ok((i, ()))
     } else {
         value((), peek(one_of(chars)))(i)
@@ -84,7 +84,7 @@ fn peek_separator(i: &str) -> CResult<&str, ()> {
 /// Parses a the end of the script.
 fn end_of_script(i: &str) -> CResult<&str, &str> {
     let (i, _) = whitespaces(i)?;
-    if i.len() == 0 {
+    if i.is_empty() {
         Ok((i, ""))
     } else {
         Err(Error::recoverable(ErrorKind::Nom(i, nom::error::ErrorKind::NoneOf)))
@@ -104,6 +104,12 @@ fn name(i: &str) -> CResult<&str, &str> {
     take_while1(is_name)(i)
 }
 
+/// Parses a name pattern, that is a name which may contain `*` and `?`.
+fn name_pattern(i: &str) -> CResult<&str, &str> {
+    let is_pattern = move |c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '*' || c == '?';
+    take_while1(is_pattern)(i)
+}
+
 /// Parses a hashtag.
 fn hashtag(i: &str) -> CResult<&str, &str> {
     preceded(tag("#"), name)(i)
@@ -138,7 +144,7 @@ fn filter_arg(i: &str) -> CResult<&str, Filter> {
     let passed = value(Filter::Passed, tag("+"));
     let failed = value(Filter::Failed, tag("-"));
     let hashtag = map(hashtag, move |t| Filter::Tag(t.to_owned()));
-    let name = map(name, move |n| Filter::Name(n.to_owned()));
+    let name = map(name_pattern, move |n| Filter::Name(n.to_owned()));
     let parser = alt((waiting, passed, failed, hashtag, name));
     let parser = preceded(whitespaces, parser);
     match parser(i) {
@@ -212,9 +218,8 @@ fn commands(i: &str) -> CResult<&str, Vec<Command>> {
         i = input;
         commands.push(cmd);
         // Terminate at eof
-        match end_of_script(i) {
-            Ok(_) => return Ok((i, commands)),
-            Err(_) => (),
+        if end_of_script(i).is_ok() {
+            return Ok((i, commands));
         }
     }
 }
@@ -381,6 +386,7 @@ mod tests {
         assert_eq!(filter_arg("-"), Ok(("", Filter::Failed)));
         assert_eq!(filter_arg("~"), Ok(("", Filter::Waiting)));
         assert_eq!(filter_arg(" #test "), Ok((" ", Filter::Tag(String::from("test")))));
+        assert_eq!(filter_arg("te?t-*"), Ok(("", Filter::Name(String::from("te?t-*")))));
 
         // Should return an error
         assert_eq!(
@@ -400,7 +406,7 @@ mod tests {
 
         // Should return an error
         if let Ok((i, _)) = quit("qt") {
-            panic!(format!("Should have failed matching 'quit', got: {}", i));
+            panic!("Should have failed matching 'quit', got: {}", i);
         }
     }
 
@@ -442,6 +448,10 @@ mod tests {
             commands("f name"),
             Ok(("", vec![Command::Filter(Filter::Name(String::from("name")))]))
         );
+        assert_eq!(
+            commands("f*-run"),
+            Ok(("", vec![Command::Filter(Filter::Name(String::from("*-run")))]))
+        );
         assert_eq!(commands("q;"), Ok(("", vec![Command::Quit])));
         assert_eq!(commands("h; c"), Ok(("", vec![Command::Help, Command::Clear])));
         assert_eq!(
@@ -0,0 +1,871 @@
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Output;
+use std::time::Duration;
+
+use crate::compare::{missing_sections, Assertion, Compare, ExitCodes, LineOrder, Normalize, Tolerance, Transform};
+use crate::data::{Redaction, Snapshot, SnapshotData, SnapshotStatus, Stamp};
+use crate::diff::{get_diff, DiffLine};
+use crate::formats::structural_diff;
+use crate::term::{format_duration, format_size};
+
+/// The diffs of the failures kept in the JSON report files are cut after that
+/// many bytes.
+const MAX_REPORT_DIFF: usize = 64 << 10;
+
+/// A summary of the differences between an expected and an actual output.
+#[derive(Serialize)]
+pub struct DiffSummary {
+    pub changed: bool,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// The sizes of the compared outputs, once normalized.
+    pub expected_bytes: usize,
+    pub actual_bytes: usize,
+}
+
+impl DiffSummary {
+    /// Counts the inserted and deleted lines between two outputs.
+    pub fn new(old: &[u8], new: &[u8]) -> DiffSummary {
+        let mut summary = DiffSummary {
+            changed: old != new,
+            insertions: 0,
+            deletions: 0,
+            expected_bytes: old.len(),
+            actual_bytes: new.len(),
+        };
+        if summary.changed {
+            let old_lines: Vec<&[u8]> = old.split(|byte| *byte == b'\n').collect();
+            let new_lines: Vec<&[u8]> = new.split(|byte| *byte == b'\n').collect();
+            for line in get_diff(&old_lines, &new_lines) {
+                match line {
+                    DiffLine::Insert(_) => summary.insertions += 1,
+                    DiffLine::Delete(_) => summary.deletions += 1,
+                    DiffLine::Keep(_) => (),
+                }
+            }
+        }
+        summary
+    }
+
+    /// The summary of outputs judged the same, or not compared.
+    pub fn same(expected_bytes: usize, actual_bytes: usize) -> DiffSummary {
+        DiffSummary {
+            changed: false,
+            insertions: 0,
+            deletions: 0,
+            expected_bytes,
+            actual_bytes,
+        }
+    }
+}
+
+/// An expected value along with the actual one.
+#[derive(Serialize)]
+pub struct Expectation<T> {
+    pub expected: T,
+    pub actual: T,
+}
+
+/// The result of the execution of a single snapshot.
+#[derive(Serialize)]
+pub struct SnapshotReport {
+    pub name: String,
+    pub cmd: String,
+    pub status: SnapshotStatus,
+    pub timed_out: bool,
+    pub output_exceeded: bool,
+    /// The snapshot ran for longer than its maximum duration.
+    pub too_slow: bool,
+    /// Number of executions, more than one if the snapshot was retried.
+    pub attempts: u32,
+    /// The snapshot passed, but only after a retry.
+    pub flaky: bool,
+    /// The snapshot is expected to fail and it did.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub expected_failure: bool,
+    /// The snapshot is expected to fail but it passed.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unexpected_pass: bool,
+    /// The snapshot was not run because a snapshot it needs failed.
+    pub skipped: bool,
+    /// Why the snapshot could not be run, such as a failing `before` command.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The outputs were compared regardless of the order of their lines.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub line_order_ignored: bool,
+    pub duration_ms: u64,
+    pub exit_code: Expectation<Option<i32>>,
+    /// The accepted exit codes, instead of the expected one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_codes: Option<ExitCodes>,
+    pub signal: Expectation<Option<i32>>,
+    pub stdout: DiffSummary,
+    pub stderr: DiffSummary,
+    /// Why the assertions on the outputs failed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_assertions: Vec<String>,
+    /// The files written by the command whose content changed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changed_artifacts: Vec<String>,
+    /// The actual outputs of a failed snapshot.
+    #[serde(skip)]
+    pub output: Option<Output>,
+    /// What a failed snapshot would expect once updated, unless it is expected
+    /// to fail or its command was killed.
+    #[serde(skip)]
+    pub pending: Option<crate::data::Expectation>,
+    /// The plain diff of a failed snapshot, when a report file needs it.
+    #[serde(skip)]
+    pub diff: Option<String>,
+}
+
+impl SnapshotReport {
+    /// Creates a report comparing an execution result against a snapshot, once
+    /// normalized, the status is taken from the snapshot and, as well as
+    /// `timed_out`, `output_exceeded`, `too_slow`, `attempts`, `flaky`,
+    /// `changed_artifacts` and the expected failure flags, must be set by the
+    /// caller.
+    pub fn new(snap: &Snapshot, result: &Output, duration: Duration, normalize: &Normalize) -> SnapshotReport {
+        let summary = |ignored: bool, expected: &Option<SnapshotData>, actual: &[u8]| {
+            let expected = body(expected);
+            if ignored || snap.same_output(normalize, expected, actual) {
+                DiffSummary::same(expected.len(), actual.len())
+            } else if let Some(order) = snap.contains {
+                let (expected, actual) = (normalize.apply(expected), normalize.apply(actual));
+                let missing = missing_sections(&expected, &actual, order);
+                DiffSummary {
+                    changed: true,
+                    insertions: 0,
+                    deletions: missing.iter().map(Vec::len).sum(),
+                    expected_bytes: expected.len(),
+                    actual_bytes: actual.len(),
+                }
+            } else {
+                DiffSummary::new(&normalize.apply(expected), &normalize.apply(actual))
+            }
+        };
+        let stdout_failures = snap.failed_assertions(false, &result.stdout);
+        let stderr_failures = snap.failed_assertions(true, &result.stderr);
+        let holds = |failures: &Option<Vec<String>>| failures.as_ref().is_some_and(Vec::is_empty);
+        let structural = if snap.ignores_stdout() || stdout_failures.is_some() {
+            None
+        } else {
+            structural_diff(snap.compare, body(&snap.stdout), &result.stdout)
+        };
+        SnapshotReport {
+            name: snap.name.to_owned(),
+            cmd: snap.cmd.to_owned(),
+            status: snap.status,
+            timed_out: false,
+            output_exceeded: false,
+            too_slow: false,
+            attempts: 1,
+            flaky: false,
+            expected_failure: false,
+            unexpected_pass: false,
+            skipped: false,
+            error: None,
+            line_order_ignored: normalize.sort_lines,
+            duration_ms: duration.as_millis() as u64,
+            exit_code: Expectation {
+                expected: snap.exit_code,
+                actual: result.status.code(),
+            },
+            exit_codes: snap.exit_codes.clone(),
+            signal: Expectation {
+                expected: snap.signal,
+                actual: result.status.signal(),
+            },
+            stdout: match structural {
+                Some(differences) => DiffSummary {
+                    changed: !differences.is_empty(),
+                    insertions: differences.iter().filter(|(prefix, _)| *prefix == '+').count(),
+                    deletions: differences.iter().filter(|(prefix, _)| *prefix == '-').count(),
+                    expected_bytes: body(&snap.stdout).len(),
+                    actual_bytes: result.stdout.len(),
+                },
+                None => summary(snap.ignores_stdout() || holds(&stdout_failures), &snap.stdout, &result.stdout),
+            },
+            stderr: summary(snap.ignores_stderr() || holds(&stderr_failures), &snap.stderr, &result.stderr),
+            failed_assertions: stdout_failures.into_iter().chain(stderr_failures).flatten().collect(),
+            changed_artifacts: Vec::new(),
+            output: None,
+            pending: None,
+            diff: None,
+        }
+    }
+
+    /// Creates the report of a snapshot that was not run, it counts as a
+    /// failure.
+    pub fn skipped(snap: &Snapshot) -> SnapshotReport {
+        SnapshotReport {
+            name: snap.name.to_owned(),
+            cmd: snap.cmd.to_owned(),
+            status: SnapshotStatus::Failed,
+            timed_out: false,
+            output_exceeded: false,
+            too_slow: false,
+            attempts: 0,
+            flaky: false,
+            expected_failure: false,
+            unexpected_pass: false,
+            skipped: true,
+            error: None,
+            line_order_ignored: false,
+            duration_ms: 0,
+            exit_code: Expectation {
+                expected: snap.exit_code,
+                actual: None,
+            },
+            exit_codes: snap.exit_codes.clone(),
+            signal: Expectation {
+                expected: snap.signal,
+                actual: None,
+            },
+            stdout: DiffSummary::new(&[], &[]),
+            stderr: DiffSummary::new(&[], &[]),
+            failed_assertions: Vec::new(),
+            changed_artifacts: Vec::new(),
+            output: None,
+            pending: None,
+            diff: None,
+        }
+    }
+
+    /// Creates the report of a snapshot whose execution failed, it counts as a
+    /// failure.
+    pub fn errored(snap: &Snapshot, error: &str, duration: Duration, attempts: u32) -> SnapshotReport {
+        SnapshotReport {
+            attempts,
+            skipped: false,
+            error: Some(error.to_owned()),
+            duration_ms: duration.as_millis() as u64,
+            ..SnapshotReport::skipped(snap)
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.status == SnapshotStatus::Passed
+    }
+
+    /// Describes why a snapshot failed, such as "stdout differs, exit code 1
+    /// instead of 0".
+    pub fn failure_message(&self) -> String {
+        let mut reasons = Vec::new();
+        if let Some(error) = &self.error {
+            reasons.push(error.lines().next().unwrap_or_default().to_owned());
+        }
+        if self.unexpected_pass {
+            reasons.push(String::from("expected to fail, but passed"));
+        }
+        if self.timed_out {
+            reasons.push(String::from("timed out"));
+        }
+        if self.output_exceeded {
+            reasons.push(String::from("output exceeded limit"));
+        }
+        if self.too_slow {
+            reasons.push(String::from("too slow"));
+        }
+        let code = |code: Option<i32>| code.map_or(String::from("none"), |code| code.to_string());
+        if self.exit_code.expected != self.exit_code.actual && self.exit_codes.is_none() {
+            let (actual, expected) = (code(self.exit_code.actual), code(self.exit_code.expected));
+            reasons.push(format!("exit code {} instead of {}", actual, expected));
+        }
+        if self.signal.expected != self.signal.actual {
+            reasons.push(format!("signal {} instead of {}", code(self.signal.actual), code(self.signal.expected)));
+        }
+        if !self.failed_assertions.is_empty() {
+            reasons.push(self.failed_assertions.join(", "));
+        }
+        for (output, summary) in [("stdout", &self.stdout), ("stderr", &self.stderr)].iter() {
+            if summary.changed {
+                reasons.push(format!("{} differs", output));
+            }
+        }
+        if !self.changed_artifacts.is_empty() {
+            reasons.push(format!("artifacts changed: {}", self.changed_artifacts.join(", ")));
+        }
+        if reasons.is_empty() {
+            reasons.push(String::from("failed"));
+        }
+        reasons.join(", ")
+    }
+}
+
+/// The formats of the report files a run can write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// JUnit XML, which most CI services display.
+    JUnit,
+    /// The JSON report of `--json`, along with the diffs of the failures.
+    Json,
+    /// A single HTML page, to review the failures from a browser.
+    Html,
+    /// A Markdown summary, to post as a pull request comment.
+    Markdown,
+}
+
+/// Writes the reports of a run as JUnit XML, with a test case per snapshot.
+/// The failures hold the diff of the snapshot.
+pub fn write_junit<B: Write>(reports: &[SnapshotReport], duration_ms: u64, buffer: &mut B) -> std::io::Result<()> {
+    let errors = reports.iter().filter(|report| report.error.is_some()).count();
+    let failures = reports.iter().filter(|report| !report.passed() && !report.skipped).count() - errors;
+    let skipped = reports.iter().filter(|report| report.skipped).count();
+    let seconds = |ms: u64| format!("{:.3}", ms as f64 / 1000.0);
+    let counts = format!(
+        "tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{}\"",
+        reports.len(),
+        failures,
+        errors,
+        skipped,
+        seconds(duration_ms)
+    );
+    writeln!(buffer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(buffer, "<testsuites name=\"parrot\" {}>", counts)?;
+    writeln!(buffer, "  <testsuite name=\"parrot\" {}>", counts)?;
+    for report in reports {
+        let name = escape_xml(&report.name);
+        let time = seconds(report.duration_ms);
+        write!(buffer, "    <testcase name=\"{}\" classname=\"parrot\" time=\"{}\"", name, time)?;
+        if report.skipped {
+            writeln!(buffer, ">")?;
+            writeln!(buffer, "      <skipped message=\"a snapshot it needs failed\"/>")?;
+        } else if let Some(error) = &report.error {
+            writeln!(buffer, ">")?;
+            let message = escape_xml(&report.failure_message());
+            writeln!(buffer, "      <error message=\"{}\">{}</error>", message, escape_xml(error))?;
+        } else if !report.passed() {
+            writeln!(buffer, ">")?;
+            let message = escape_xml(&report.failure_message());
+            let diff = escape_xml(report.diff.as_deref().unwrap_or_default());
+            writeln!(buffer, "      <failure message=\"{}\">{}</failure>", message, diff)?;
+        } else {
+            writeln!(buffer, "/>")?;
+            continue;
+        }
+        writeln!(buffer, "    </testcase>")?;
+    }
+    writeln!(buffer, "  </testsuite>")?;
+    writeln!(buffer, "</testsuites>")
+}
+
+/// The style and the sorting of the table of the HTML reports, inlined so that
+/// a report is a single file.
+const HTML_HEAD: &str = r#"<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.4em 0.8em; text-align: left; vertical-align: top; }
+th { cursor: pointer; user-select: none; background: #f4f4f4; }
+.passed { color: #1a7f37; }
+.failed { color: #cf222e; }
+.skipped { color: #9a6700; }
+pre { margin: 0.5em 0; padding: 0.5em; background: #f6f8fa; overflow-x: auto; }
+pre span { display: block; }
+.insert { background: #dafbe1; }
+.delete { background: #ffebe9; }
+.header { font-weight: bold; }
+</style>
+<script>
+function sortBy(header) {
+  var table = header.closest("table"), body = table.tBodies[0], column = header.cellIndex;
+  var ascending = header.dataset.order != "asc";
+  table.querySelectorAll("th").forEach(function (th) { delete th.dataset.order; });
+  header.dataset.order = ascending ? "asc" : "desc";
+  var key = function (row) { return row.cells[column].dataset.sort || row.cells[column].textContent; };
+  Array.from(body.rows).sort(function (a, b) {
+    var x = key(a), y = key(b), order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+    return ascending ? order : -order;
+  }).forEach(function (row) { body.appendChild(row); });
+}
+</script>"#;
+
+/// Writes the reports of a run as a single HTML page, with a table of the
+/// snapshots that sorts by the clicked column, the diffs of the failures can
+/// be expanded.
+pub fn write_html<B: Write>(reports: &[SnapshotReport], duration_ms: u64, buffer: &mut B) -> std::io::Result<()> {
+    let failures = reports.iter().filter(|report| !report.passed()).count();
+    writeln!(buffer, "<!DOCTYPE html>")?;
+    writeln!(buffer, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>parrot report</title>")?;
+    writeln!(buffer, "{}\n</head>\n<body>", HTML_HEAD)?;
+    writeln!(buffer, "<h1>parrot report</h1>")?;
+    writeln!(
+        buffer,
+        "<p>{} snapshot(s), {} failure(s), in {}.</p>",
+        reports.len(),
+        failures,
+        format_duration(duration_ms)
+    )?;
+    writeln!(buffer, "<table>\n<thead>\n<tr>")?;
+    for column in ["Name", "Status", "Duration", "Attempts", "Details"].iter() {
+        writeln!(buffer, "<th onclick=\"sortBy(this)\">{}</th>", column)?;
+    }
+    writeln!(buffer, "</tr>\n</thead>\n<tbody>")?;
+    for report in reports {
+        let status = if report.skipped {
+            "skipped"
+        } else if report.passed() {
+            "passed"
+        } else {
+            "failed"
+        };
+        writeln!(buffer, "<tr>")?;
+        writeln!(buffer, "<td>{}</td>", escape_xml(&report.name))?;
+        writeln!(buffer, "<td class=\"{}\">{}</td>", status, status)?;
+        writeln!(buffer, "<td data-sort=\"{}\">{}</td>", report.duration_ms, format_duration(report.duration_ms))?;
+        writeln!(buffer, "<td>{}</td>", report.attempts)?;
+        write!(buffer, "<td>")?;
+        if report.skipped {
+            write!(buffer, "a snapshot it needs failed")?;
+        } else if !report.passed() {
+            let message = escape_xml(&report.failure_message());
+            match &report.diff {
+                Some(diff) => {
+                    writeln!(buffer, "<details>\n<summary>{}</summary>\n<pre>", message)?;
+                    for line in diff.lines() {
+                        let class = match line.as_bytes().first() {
+                            _ if line.starts_with("--- ") => "header",
+                            Some(b'+') => "insert",
+                            Some(b'-') => "delete",
+                            _ => "keep",
+                        };
+                        writeln!(buffer, "<span class=\"{}\">{}</span>", class, escape_xml(line))?;
+                    }
+                    write!(buffer, "</pre>\n</details>")?;
+                }
+                None => write!(buffer, "{}", message)?,
+            }
+        } else if report.flaky {
+            write!(buffer, "passed after a retry")?;
+        } else if report.expected_failure {
+            write!(buffer, "failed as expected")?;
+        }
+        writeln!(buffer, "</td>\n</tr>")?;
+    }
+    writeln!(buffer, "</tbody>\n</table>\n</body>\n</html>")
+}
+
+/// Writes a compact Markdown summary of a run: a table of the counts, then the
+/// failures, each one with its diff folded in a `<details>` section.
+pub fn write_markdown<B: Write>(reports: &[SnapshotReport], duration_ms: u64, buffer: &mut B) -> std::io::Result<()> {
+    let passed = reports.iter().filter(|report| report.passed()).count();
+    let skipped = reports.iter().filter(|report| report.skipped).count();
+    let failed: Vec<&SnapshotReport> = reports.iter().filter(|report| !report.passed() && !report.skipped).collect();
+    let icon = if failed.is_empty() && skipped == 0 { "✅" } else { "❌" };
+    writeln!(buffer, "### {} parrot\n", icon)?;
+    writeln!(buffer, "| Passed | Failed | Skipped | Duration |")?;
+    writeln!(buffer, "| ---: | ---: | ---: | ---: |")?;
+    writeln!(buffer, "| {} | {} | {} | {} |", passed, failed.len(), skipped, format_duration(duration_ms))?;
+    for report in failed {
+        let message = escape_xml(&report.failure_message());
+        writeln!(buffer, "\n<details>\n<summary><code>{}</code>: {}</summary>\n", escape_xml(&report.name), message)?;
+        let diff = report.diff.as_deref().unwrap_or_default();
+        // The fence must be longer than any run of backticks in the diff
+        let mut fence = String::from("```");
+        while diff.contains(&fence[..]) {
+            fence.push('`');
+        }
+        writeln!(buffer, "{}diff\n{}{}\n</details>", fence, diff, fence)?;
+    }
+    Ok(())
+}
+
+/// Prints a GitHub Actions `::error` workflow command for each failing
+/// snapshot, so that the failures show in the summary of the job. Their
+/// message holds the diff.
+pub fn write_annotations<B: Write>(reports: &[SnapshotReport], buffer: &mut B) {
+    for report in reports.iter().filter(|report| !report.passed()) {
+        let message = match &report.diff {
+            _ if report.skipped => format!("{}: a snapshot it needs failed", report.name),
+            Some(diff) => format!("{}: {}\n{}", report.name, report.failure_message(), diff.trim_end()),
+            None => format!("{}: {}", report.name, report.failure_message()),
+        };
+        writeln!(buffer, "::error title=Snapshot failed::{}", escape_workflow_data(&message)).unwrap();
+    }
+}
+
+/// Escapes the message of a workflow command, which holds a single line.
+fn escape_workflow_data(text: &str) -> String {
+    text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Escapes text for XML, the characters XML does not allow are replaced.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if (c as u32) < 0x20 => escaped.push(char::REPLACEMENT_CHARACTER),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The result of a whole run.
+#[derive(Serialize)]
+pub struct RunReport<S = SnapshotReport> {
+    pub success: bool,
+    pub failures: usize,
+    /// Number of snapshots that passed only after a retry.
+    pub flaky: usize,
+    /// Number of snapshots that failed as expected.
+    pub expected_failures: usize,
+    /// Number of snapshots not run because a snapshot they need failed.
+    pub skipped: usize,
+    /// Number of times the snapshots were run.
+    pub iterations: usize,
+    pub duration_ms: u64,
+    pub snapshots: Vec<S>,
+}
+
+/// Writes the report of a run as JSON, each failed snapshot also holds its
+/// plain diff, cut after `MAX_REPORT_DIFF` bytes.
+pub fn write_json_report<B: Write>(run: &RunReport, buffer: &mut B) -> std::io::Result<()> {
+    let detailed = RunReport {
+        success: run.success,
+        failures: run.failures,
+        flaky: run.flaky,
+        expected_failures: run.expected_failures,
+        skipped: run.skipped,
+        iterations: run.iterations,
+        duration_ms: run.duration_ms,
+        snapshots: run
+            .snapshots
+            .iter()
+            .map(|report| DetailedReport {
+                report,
+                diff: report.diff.as_deref().map(|diff| truncate(diff, MAX_REPORT_DIFF)),
+            })
+            .collect(),
+    };
+    serde_json::to_writer_pretty(&mut *buffer, &detailed)?;
+    writeln!(buffer)
+}
+
+/// The report of a snapshot along with its diff.
+#[derive(Serialize)]
+struct DetailedReport<'a> {
+    #[serde(flatten)]
+    report: &'a SnapshotReport,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<String>,
+}
+
+/// Cuts a text after `max` bytes, on a character boundary, noting how many
+/// bytes were left out.
+fn truncate(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_owned();
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n[{} more bytes]\n", &text[..end], text.len() - end)
+}
+
+/// The description of a snapshot, outputs are only included when `full` is
+/// set.
+#[derive(Serialize)]
+pub struct SnapshotEntry<'a> {
+    pub name: &'a str,
+    pub cmd: &'a str,
+    pub description: Option<&'a str>,
+    pub tags: &'a [String],
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: &'a BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    pub needs: &'a [String],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub strip_ansi: bool,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    pub filters: &'a [String],
+    #[serde(skip_serializing_if = "<[Redaction]>::is_empty")]
+    pub redact: &'a [Redaction],
+    #[serde(skip_serializing_if = "<[Transform]>::is_empty")]
+    pub pipeline: &'a [Transform],
+    #[serde(skip_serializing_if = "Normalize::is_none")]
+    pub normalize: Normalize,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub exit_code_only: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_stdout: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_stderr: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub binary: bool,
+    #[serde(skip_serializing_if = "Compare::is_text")]
+    pub compare: Compare,
+    #[serde(skip_serializing_if = "Tolerance::is_none")]
+    pub tolerance: Tolerance,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contains: Option<LineOrder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_codes: Option<ExitCodes>,
+    #[serde(skip_serializing_if = "<[Assertion]>::is_empty")]
+    pub assertions: &'a [Assertion],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comparator: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub xfail: bool,
+    /// The files written by the command, captured along with its outputs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_stdout: Option<&'a str>,
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<&'a Stamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<&'a Stamp>,
+    pub status: SnapshotStatus,
+    pub last_run: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stderr: Option<String>,
+}
+
+impl<'a> SnapshotEntry<'a> {
+    pub fn new(snap: &'a Snapshot, full: bool) -> SnapshotEntry<'a> {
+        let text = |data: &Option<SnapshotData>| {
+            if full {
+                data.as_ref().map(|data| String::from_utf8_lossy(&data.body).into_owned())
+            } else {
+                None
+            }
+        };
+        SnapshotEntry {
+            name: &snap.name,
+            cmd: &snap.cmd,
+            description: snap.description.as_deref(),
+            tags: &snap.tags,
+            env: &snap.env,
+            before: snap.before.as_deref(),
+            after: snap.after.as_deref(),
+            needs: &snap.needs,
+            strip_ansi: snap.strip_ansi,
+            filters: &snap.filters,
+            redact: &snap.redact,
+            pipeline: &snap.pipeline,
+            normalize: snap.normalize,
+            exit_code_only: snap.exit_code_only,
+            ignore_stdout: snap.ignore_stdout,
+            ignore_stderr: snap.ignore_stderr,
+            binary: snap.binary,
+            compare: snap.compare,
+            tolerance: snap.tolerance,
+            contains: snap.contains,
+            exit_codes: snap.exit_codes.clone(),
+            assertions: &snap.assertions,
+            comparator: snap.comparator.clone(),
+            xfail: snap.xfail,
+            artifacts: snap.artifacts.iter().map(|artifact| artifact.file.as_str()).collect(),
+            expected_stdout: snap.expected_stdout.as_deref(),
+            exit_code: snap.exit_code,
+            signal: snap.signal,
+            max_duration_ms: snap.max_duration_ms,
+            created: snap.created.as_ref(),
+            updated: snap.updated.as_ref(),
+            status: snap.status,
+            last_run: snap.last_run,
+            last_duration_ms: snap.history.last().map(|record| record.duration_ms),
+            stdin: text(&snap.stdin),
+            stdout: text(&snap.stdout),
+            stderr: text(&snap.stderr),
+        }
+    }
+}
+
+/// A summary of the snapshots status.
+#[derive(Serialize)]
+pub struct StatusReport {
+    pub snapshots: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub never_run: usize,
+    pub stale: usize,
+    pub archived: usize,
+    pub disk_usage: u64,
+}
+
+/// Aggregated run history of a group of snapshots.
+#[derive(Serialize, Default)]
+pub struct GroupStats {
+    pub snapshots: usize,
+    pub runs: usize,
+    pub passed_runs: usize,
+    /// Share of passed runs, between 0 and 1.
+    pub pass_rate: Option<f64>,
+    pub average_ms: Option<u64>,
+    pub size: u64,
+    #[serde(skip)]
+    total_ms: u64,
+}
+
+impl GroupStats {
+    fn add(&mut self, snap: &Snapshot) {
+        self.snapshots += 1;
+        self.runs += snap.history.len();
+        self.passed_runs += snap.history.iter().filter(|record| record.passed).count();
+        self.total_ms += snap.history.iter().map(|record| record.duration_ms).sum::<u64>();
+        self.size += snap.size();
+        if self.runs > 0 {
+            self.pass_rate = Some(self.passed_runs as f64 / self.runs as f64);
+            self.average_ms = Some(self.total_ms / self.runs as u64);
+        }
+    }
+
+    /// Formats the statistics on a single line for humans.
+    pub fn summary(&self) -> String {
+        let runs = match self.pass_rate {
+            Some(rate) => format!("{:>3.0}% of {} runs passed", rate * 100.0, self.runs),
+            None => String::from("never run"),
+        };
+        let average = match self.average_ms {
+            Some(ms) => format!("avg {}", format_duration(ms)),
+            None => String::new(),
+        };
+        format!("{:>3} snapshot(s)  {:<24}  {:<12}  {}", self.snapshots, runs, average, format_size(self.size))
+    }
+}
+
+#[derive(Serialize)]
+pub struct SlowSnapshot {
+    pub name: String,
+    pub average_ms: u64,
+}
+
+#[derive(Serialize)]
+pub struct LargeSnapshot {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Statistics over the snapshots and their run history.
+#[derive(Serialize)]
+pub struct StatsReport {
+    pub by_status: BTreeMap<&'static str, GroupStats>,
+    pub by_tag: BTreeMap<String, GroupStats>,
+    pub untagged: GroupStats,
+    pub slowest: Vec<SlowSnapshot>,
+    pub largest: Vec<LargeSnapshot>,
+}
+
+impl StatsReport {
+    /// Computes the statistics of the snapshots, only the `top` slowest and
+    /// largest snapshots are kept.
+    pub fn new(snaps: &[&Snapshot], top: usize) -> StatsReport {
+        let mut by_status: BTreeMap<&'static str, GroupStats> = BTreeMap::new();
+        let mut by_tag: BTreeMap<String, GroupStats> = BTreeMap::new();
+        let mut untagged = GroupStats::default();
+        let mut slowest = Vec::new();
+        let mut largest = Vec::new();
+        for snap in snaps {
+            let status = match snap.status {
+                SnapshotStatus::Passed => "passed",
+                SnapshotStatus::Failed => "failed",
+                SnapshotStatus::Waiting => "waiting",
+            };
+            by_status.entry(status).or_default().add(snap);
+            for tag in &snap.tags {
+                by_tag.entry(tag.to_owned()).or_default().add(snap);
+            }
+            if snap.tags.is_empty() {
+                untagged.add(snap);
+            }
+            if !snap.history.is_empty() {
+                let total: u64 = snap.history.iter().map(|record| record.duration_ms).sum();
+                slowest.push(SlowSnapshot {
+                    name: snap.name.to_owned(),
+                    average_ms: total / snap.history.len() as u64,
+                });
+            }
+            largest.push(LargeSnapshot {
+                name: snap.name.to_owned(),
+                size: snap.size(),
+            });
+        }
+        slowest.sort_by_key(|snap| Reverse(snap.average_ms));
+        slowest.truncate(top);
+        largest.sort_by_key(|snap| Reverse(snap.size));
+        largest.truncate(top);
+        StatsReport {
+            by_status,
+            by_tag,
+            untagged,
+            slowest,
+            largest,
+        }
+    }
+}
+
+/// Writes a value as pretty printed JSON, followed by a new line.
+pub fn write_json<B: Write, T: Serialize>(value: &T, buffer: &mut B) {
+    serde_json::to_writer_pretty(&mut *buffer, value).unwrap();
+    writeln!(buffer).unwrap();
+}
+
+fn body(data: &Option<SnapshotData>) -> &[u8] {
+    match data {
+        Some(data) => &data.body,
+        None => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_summary() {
+        let summary = DiffSummary::new(b"a\nb\nc", b"a\nB\nc\nd");
+        assert!(summary.changed);
+        assert_eq!(summary.insertions, 2);
+        assert_eq!(summary.deletions, 1);
+        let summary = DiffSummary::new(b"a\nb", b"a\nb");
+        assert!(!summary.changed);
+        assert_eq!(summary.insertions + summary.deletions, 0);
+        assert_eq!((summary.expected_bytes, summary.actual_bytes), (3, 3));
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("abc", 3), "abc");
+        assert_eq!(truncate("abcd", 3), "abc\n[1 more bytes]\n");
+        assert_eq!(truncate("aé", 2), "a\n[2 more bytes]\n");
+    }
+
+    #[test]
+    fn test_escape_workflow_data() {
+        assert_eq!(escape_workflow_data("100% off\r\nnext"), "100%25 off%0D%0Anext");
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(escape_xml("a < \"b\" & c\n"), "a &lt; &quot;b&quot; &amp; c\n");
+        assert_eq!(escape_xml("\x1b[31mred"), "\u{fffd}[31mred");
+    }
+}
@@ -0,0 +1,75 @@
+//! When to color the output, and the escape sequences which style it.
+use termion::{color, style};
+
+/// When to color the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When stdout is a terminal and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(choice: &str) -> Result<ColorChoice, String> {
+        match choice {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice '{}'", choice)),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Whether the output is colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                termion::is_tty(&std::io::stdout()) && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+/// The escape sequences of the colors and styles of the output, which are
+/// all empty when it is not colored.
+#[derive(Clone, Default)]
+pub struct Style {
+    pub bold: String,
+    pub reset: String,
+    pub light_red: String,
+    pub light_green: String,
+    pub light_yellow: String,
+    pub light_blue: String,
+    pub yellow: String,
+    pub white: String,
+    pub fg_reset: String,
+    pub bg_black: String,
+    pub bg_reset: String,
+}
+
+impl Style {
+    pub fn new(colors: bool) -> Style {
+        if !colors {
+            return Style::default();
+        }
+        Style {
+            bold: style::Bold.to_string(),
+            reset: style::Reset.to_string(),
+            light_red: color::LightRed.fg_str().to_string(),
+            light_green: color::LightGreen.fg_str().to_string(),
+            light_yellow: color::LightYellow.fg_str().to_string(),
+            light_blue: color::LightBlue.fg_str().to_string(),
+            yellow: color::Yellow.fg_str().to_string(),
+            white: color::White.fg_str().to_string(),
+            fg_reset: color::Reset.fg_str().to_string(),
+            bg_black: color::Black.bg_str().to_string(),
+            bg_reset: color::Reset.bg_str().to_string(),
+        }
+    }
+}
@@ -1,39 +1,327 @@
+use serde_json::Value;
+use std::borrow::Cow;
 use std::io::Write;
-use termion::color;
 
 use crate::diff::{get_diff, DiffLine};
 use super::theme::Theme;
 
-/// Writes the diff between two snapshots to buffer.
-pub fn write_diff<B: Write>(old: &Vec<u8>, new: &Vec<u8>, buffer: &mut B, theme: &Theme) {
+/// Returns a line with its invalid UTF-8 sequences escaped as `\xNN`, so that
+/// they don't garble the terminal.
+pub fn escape_invalid(line: &[u8]) -> Cow<'_, str> {
+    let mut chunks = line.utf8_chunks();
+    match chunks.next() {
+        None => return Cow::Borrowed(""),
+        Some(chunk) if chunk.invalid().is_empty() => return Cow::Borrowed(chunk.valid()),
+        Some(_) => (),
+    }
+    let mut escaped = String::new();
+    for chunk in line.utf8_chunks() {
+        escaped.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Writes the diff between two snapshots to buffer, invalid UTF-8 sequences
+/// are escaped.
+pub fn write_diff<B: Write>(old: &[u8], new: &[u8], buffer: &mut B, theme: &Theme) {
     let old_lines: Vec<&[u8]> = old.split(|byte| *byte == b'\n').collect();
     let new_lines: Vec<&[u8]> = new.split(|byte| *byte == b'\n').collect();
     let diff = get_diff(&old_lines, &new_lines);
     // Define colors
-    let bg_color = color::Bg(color::Black);
-    let bg_reset = color::Bg(color::Reset);
+    let bg_color = &theme.style.bg_black;
+    let bg_reset = &theme.style.bg_reset;
     let fg_blue = &theme.blue;
-    let fg_green = color::Fg(color::LightGreen);
-    let fg_red = color::Fg(color::LightRed);
-    let fg_reset = color::Fg(color::Reset);
+    let fg_green = &theme.style.light_green;
+    let fg_red = &theme.style.light_red;
+    let fg_reset = &theme.style.fg_reset;
     // Display diff
     for line in diff {
         match line {
             DiffLine::Keep(bytes) => {
-                write!(buffer, "{}│{} ", fg_blue, fg_reset).unwrap();
-                buffer.write_all(bytes).unwrap();
-                write!(buffer, "\r\n").unwrap();
+                write!(buffer, "{}│{} {}\r\n", fg_blue, fg_reset, escape_invalid(bytes)).unwrap();
             }
             DiffLine::Delete(bytes) => {
-                write!(buffer, "{}-{} {}", fg_red, fg_reset, bg_color).unwrap();
-                buffer.write_all(bytes).unwrap();
-                write!(buffer, "{}\r\n", bg_reset).unwrap();
+                let line = escape_invalid(bytes);
+                write!(buffer, "{}-{} {}{}{}\r\n", fg_red, fg_reset, bg_color, line, bg_reset).unwrap();
             }
             DiffLine::Insert(bytes) => {
-                write!(buffer, "{}+{} {}", fg_green, fg_reset, bg_color).unwrap();
-                buffer.write_all(bytes).unwrap();
-                write!(buffer, "{}\r\n", bg_reset).unwrap();
+                let line = escape_invalid(bytes);
+                write!(buffer, "{}+{} {}{}{}\r\n", fg_green, fg_reset, bg_color, line, bg_reset).unwrap();
+            }
+        }
+    }
+}
+
+/// Writes the diff between two snapshots to buffer, without colors nor box
+/// drawing so that it can be consumed by other tools, the lines are written
+/// verbatim.
+pub fn write_plain_diff<B: Write>(old: &[u8], new: &[u8], buffer: &mut B) {
+    let old_lines: Vec<&[u8]> = old.split(|byte| *byte == b'\n').collect();
+    let new_lines: Vec<&[u8]> = new.split(|byte| *byte == b'\n').collect();
+    for line in get_diff(&old_lines, &new_lines) {
+        let (prefix, bytes) = match line {
+            DiffLine::Keep(bytes) => (b' ', bytes),
+            DiffLine::Delete(bytes) => (b'-', bytes),
+            DiffLine::Insert(bytes) => (b'+', bytes),
+        };
+        buffer.write_all(&[prefix]).unwrap();
+        buffer.write_all(bytes).unwrap();
+        buffer.write_all(b"\n").unwrap();
+    }
+}
+
+/// Number of bytes per row of hexdumps.
+const HEX_ROW: usize = 16;
+/// Number of rows of hexdumps.
+const HEX_ROWS: usize = 8;
+
+/// Returns true if an output looks like binary data rather than text: it
+/// contains NUL bytes or more than a quarter of its bytes are not valid UTF-8.
+/// Text with a few invalid sequences, such as Latin-1 file names, is shown
+/// with them escaped instead.
+pub fn is_binary(body: &[u8]) -> bool {
+    let invalid: usize = body.utf8_chunks().map(|chunk| chunk.invalid().len()).sum();
+    body.contains(&0) || invalid * 4 > body.len()
+}
+
+/// Formats a row of the hexdump of `body`, if it is not past its end.
+fn hex_row(body: &[u8], row: usize) -> Option<String> {
+    let start = row * HEX_ROW;
+    if start >= body.len() {
+        return None;
+    }
+    let bytes = &body[start..body.len().min(start + HEX_ROW)];
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let ascii: String = bytes
+        .iter()
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+        .collect();
+    Some(format!("{:08x}  {:<47}  |{}|", start, hex.join(" "), ascii))
+}
+
+/// Returns the offset of the first byte that differs between two outputs, if
+/// any.
+fn first_difference(old: &[u8], new: &[u8]) -> Option<usize> {
+    match old.iter().zip(new).position(|(old, new)| old != new) {
+        Some(offset) => Some(offset),
+        None if old.len() != new.len() => Some(old.len().min(new.len())),
+        None => None,
+    }
+}
+
+/// Summarizes the difference between two binary outputs on a single line.
+pub fn binary_summary(old: &[u8], new: &[u8]) -> String {
+    let offset = match first_difference(old, new) {
+        Some(offset) => offset,
+        None => return format!("same {} bytes", old.len()),
+    };
+    if old.len() == new.len() {
+        format!("bytes differ at offset {}, size {} bytes", offset, old.len())
+    } else {
+        format!("bytes differ at offset {}, size changed {} → {} bytes", offset, old.len(), new.len())
+    }
+}
+
+/// Returns the hexdump rows around the first difference between two outputs,
+/// prefixed by '-' if they were deleted, '+' if inserted and ' ' otherwise.
+fn binary_diff(old: &[u8], new: &[u8]) -> Vec<(char, String)> {
+    let mut lines = Vec::new();
+    let first_row = first_difference(old, new).unwrap_or(0) / HEX_ROW;
+    for row in first_row..first_row + HEX_ROWS {
+        match (hex_row(old, row), hex_row(new, row)) {
+            (None, None) => break,
+            (Some(old), Some(new)) if old == new => lines.push((' ', old)),
+            (old, new) => {
+                lines.extend(old.map(|row| ('-', row)));
+                lines.extend(new.map(|row| ('+', row)));
             }
         }
     }
+    lines
+}
+
+/// Writes the difference between two binary outputs to buffer, as a summary
+/// followed by a hexdump of the rows around the first difference.
+pub fn write_binary_diff<B: Write>(old: &[u8], new: &[u8], buffer: &mut B, theme: &Theme) {
+    write_rows(&binary_summary(old, new), &binary_diff(old, new), buffer, theme);
+}
+
+/// Writes a summary followed by rows prefixed by '-', '+' or ' ' to buffer.
+fn write_rows<B: Write>(summary: &str, rows: &[(char, String)], buffer: &mut B, theme: &Theme) {
+    let fg_blue = &theme.blue;
+    let fg_green = &theme.style.light_green;
+    let fg_red = &theme.style.light_red;
+    let fg_reset = &theme.style.fg_reset;
+    write!(buffer, "{}│{} {}\r\n", fg_blue, fg_reset, summary).unwrap();
+    for (prefix, row) in rows {
+        match prefix {
+            '-' => write!(buffer, "{}-{} {}\r\n", fg_red, fg_reset, row).unwrap(),
+            '+' => write!(buffer, "{}+{} {}\r\n", fg_green, fg_reset, row).unwrap(),
+            _ => write!(buffer, "{}│{} {}\r\n", fg_blue, fg_reset, row).unwrap(),
+        }
+    }
+}
+
+/// Writes the difference between two binary outputs to buffer, without colors
+/// nor box drawing.
+pub fn write_plain_binary_diff<B: Write>(old: &[u8], new: &[u8], buffer: &mut B) {
+    write_plain_rows(&binary_summary(old, new), &binary_diff(old, new), buffer);
+}
+
+fn write_plain_rows<B: Write>(summary: &str, rows: &[(char, String)], buffer: &mut B) {
+    writeln!(buffer, " {}", summary).unwrap();
+    for (prefix, row) in rows {
+        writeln!(buffer, "{}{}", prefix, row).unwrap();
+    }
+}
+
+fn missing_rows(sections: &[Vec<&[u8]>]) -> (String, Vec<(char, String)>) {
+    let mut rows = Vec::new();
+    for (index, section) in sections.iter().enumerate() {
+        if index > 0 {
+            rows.push((' ', String::new()));
+        }
+        rows.extend(section.iter().map(|line| ('-', escape_invalid(line).into_owned())));
+    }
+    (format!("{} section(s) missing from the output", sections.len()), rows)
+}
+
+/// Writes the sections of a stored output missing from an actual output to
+/// buffer.
+pub fn write_missing_sections<B: Write>(sections: &[Vec<&[u8]>], buffer: &mut B, theme: &Theme) {
+    let (summary, rows) = missing_rows(sections);
+    write_rows(&summary, &rows, buffer, theme);
+}
+
+/// Writes the sections of a stored output missing from an actual output to
+/// buffer, without colors nor box drawing.
+pub fn write_plain_missing_sections<B: Write>(sections: &[Vec<&[u8]>], buffer: &mut B) {
+    let (summary, rows) = missing_rows(sections);
+    write_plain_rows(&summary, &rows, buffer);
+}
+
+/// Returns the structural differences between two parsed outputs, as the
+/// JSON pointers of the values that were removed ('-'), added ('+') or
+/// changed (both).
+pub fn value_diff(old: &Value, new: &Value) -> Vec<(char, String)> {
+    let mut differences = Vec::new();
+    diff_values("", old, new, &mut differences);
+    differences
+}
+
+fn diff_values(pointer: &str, old: &Value, new: &Value, differences: &mut Vec<(char, String)>) {
+    let path = |pointer: &str| if pointer.is_empty() { String::from("(root)") } else { pointer.to_owned() };
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                let pointer = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                match new.get(key) {
+                    Some(new_value) => diff_values(&pointer, old_value, new_value, differences),
+                    None => differences.push(('-', format!("{}: {}", path(&pointer), old_value))),
+                }
+            }
+            for (key, new_value) in new.iter().filter(|(key, _)| !old.contains_key(*key)) {
+                let pointer = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                differences.push(('+', format!("{}: {}", path(&pointer), new_value)));
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for index in 0..old.len().max(new.len()) {
+                let pointer = format!("{}/{}", pointer, index);
+                match (old.get(index), new.get(index)) {
+                    (Some(old_value), Some(new_value)) => diff_values(&pointer, old_value, new_value, differences),
+                    (Some(old_value), None) => differences.push(('-', format!("{}: {}", path(&pointer), old_value))),
+                    (None, Some(new_value)) => differences.push(('+', format!("{}: {}", path(&pointer), new_value))),
+                    (None, None) => (),
+                }
+            }
+        }
+        _ if old != new => {
+            differences.push(('-', format!("{}: {}", path(pointer), old)));
+            differences.push(('+', format!("{}: {}", path(pointer), new)));
+        }
+        _ => (),
+    }
+}
+
+fn structural_summary(format: &str, differences: &[(char, String)]) -> String {
+    let path = |row: &str| row.split(": ").next().map(str::to_owned);
+    let changed = differences
+        .windows(2)
+        .filter(|rows| rows[0].0 == '-' && rows[1].0 == '+' && path(&rows[0].1) == path(&rows[1].1))
+        .count();
+    format!("{} values differ at {} path(s)", format, differences.len() - changed)
+}
+
+/// Writes the structural differences between two outputs in a format such
+/// as JSON to buffer.
+pub fn write_structural_diff<B: Write>(format: &str, differences: &[(char, String)], buffer: &mut B, theme: &Theme) {
+    write_rows(&structural_summary(format, differences), differences, buffer, theme);
+}
+
+/// Writes the structural differences between two outputs to buffer, without
+/// colors nor box drawing.
+pub fn write_plain_structural_diff<B: Write>(format: &str, differences: &[(char, String)], buffer: &mut B) {
+    write_plain_rows(&structural_summary(format, differences), differences, buffer);
+}
+
+/// Writes the first rows of the hexdump of a binary output to buffer.
+pub fn write_hexdump<B: Write>(body: &[u8], buffer: &mut B, theme: &Theme) {
+    let fg_blue = &theme.blue;
+    let fg_reset = &theme.style.fg_reset;
+    write!(buffer, "{}│{} {} bytes of binary data\r\n", fg_blue, fg_reset, body.len()).unwrap();
+    for row in (0..HEX_ROWS).map_while(|row| hex_row(body, row)) {
+        write!(buffer, "{}│{} {}\r\n", fg_blue, fg_reset, row).unwrap();
+    }
+    if body.len() > HEX_ROWS * HEX_ROW {
+        write!(buffer, "{}│{} ...\r\n", fg_blue, fg_reset).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_binary_diff() {
+        assert!(is_binary(b"PNG\0\x01"));
+        assert!(is_binary(b"\xff\xfe"));
+        assert!(!is_binary("héllo\n".as_bytes()));
+        assert!(!is_binary(b"caf\xe9 au lait, cr\xe8me\n"));
+        assert_eq!(escape_invalid(b"caf\xe9 \xf0\x9f"), "caf\\xe9 \\xf0\\x9f");
+        assert!(matches!(escape_invalid("héllo".as_bytes()), Cow::Borrowed("héllo")));
+        let old: Vec<u8> = (0..40).collect();
+        let mut new = old.clone();
+        new[20] = 0xff;
+        new.push(0);
+        assert_eq!(binary_summary(&old, &new), "bytes differ at offset 20, size changed 40 → 41 bytes");
+        assert_eq!(binary_summary(&old, &old[..]), "same 40 bytes");
+        let lines = binary_diff(&old, &new);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].0 == '-' && lines[0].1.starts_with("00000010  10 11 12 13 14"));
+        assert!(lines[1].0 == '+' && lines[1].1.contains(" 13 ff 15 "));
+        assert_eq!(lines[2].0, '-');
+        assert!(lines[3].0 == '+' && lines[3].1.starts_with("00000020  20 21"));
+        assert_eq!(hex_row(b"ab\n", 0).unwrap(), format!("00000000  {:<47}  |ab.|", "61 62 0a"));
+    }
+
+    #[test]
+    fn test_value_diff() {
+        assert_eq!(value_diff(&json!({"a": 1, "b": [1, 2]}), &json!({"b": [1, 2], "a": 1})), vec![]);
+        let (old, new) = (json!({"a": {"x/y": 1}, "b": [1, 2]}), json!({"a": {"x/y": 2}, "b": [1], "c": null}));
+        let differences = value_diff(&old, &new);
+        let expected = vec![
+            ('-', String::from("/a/x~1y: 1")),
+            ('+', String::from("/a/x~1y: 2")),
+            ('-', String::from("/b/1: 2")),
+            ('+', String::from("/c: null")),
+        ];
+        assert_eq!(structural_summary("JSON", &expected), "JSON values differ at 3 path(s)");
+        assert_eq!(differences, expected);
+        assert_eq!(value_diff(&json!(1), &json!(2))[0].1, "(root): 1");
+    }
 }
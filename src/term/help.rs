@@ -1,16 +1,16 @@
 use std::io::Write;
-use termion::color;
-use termion::style;
+
+use super::theme::Theme;
 
 /// Writes the help message.
-pub fn write_help<B: Write>(buffer: &mut B) {
-    let bold = style::Bold;
-    let reset_style = style::Reset;
-    let red = color::Fg(color::LightRed);
-    let yellow = color::Fg(color::LightYellow);
-    let green = color::Fg(color::LightGreen);
-    let blue = color::Fg(color::LightBlue);
-    let reset_color = color::Fg(color::Reset);
+pub fn write_help<B: Write>(buffer: &mut B, theme: &Theme) {
+    let bold = &theme.style.bold;
+    let reset_style = &theme.style.reset;
+    let red = &theme.style.light_red;
+    let yellow = &theme.style.light_yellow;
+    let green = &theme.style.light_green;
+    let blue = &theme.style.light_blue;
+    let reset_color = &theme.style.fg_reset;
     write!(
         buffer,
         "{b}┌──{g}──────{y}──────────{r}────────────────────────────{y}──────────{g}──────{b}──┐{rc}\r\n\
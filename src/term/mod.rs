@@ -1,15 +1,27 @@
 use std::io;
 use std::io::{stdin, stdout, Write};
-use termion::{color, style};
+use std::os::unix::process::ExitStatusExt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
 
+use crate::data::{Snapshot, SnapshotStatus, Stamp};
 use crate::error::{wrap, Error};
 
+mod colors;
 mod diff;
 pub mod help;
+mod progress;
 mod repl;
 mod theme;
 
-pub use diff::write_diff;
+pub use diff::{
+    is_binary, value_diff, write_binary_diff, write_diff, write_missing_sections, write_plain_binary_diff,
+    write_plain_diff, write_plain_missing_sections, write_plain_structural_diff, write_structural_diff,
+};
+pub use colors::ColorChoice;
+pub use progress::Progress;
 pub use repl::Input;
 pub use repl::Repl;
 pub use theme::Theme;
@@ -26,6 +38,14 @@ pub fn writeln<B: Write>(message: &str, buffer: &mut B) {
     write!(buffer, "{}\r\n", message).unwrap();
 }
 
+/// Writes a message of several lines to a buffer, which can be a terminal in
+/// raw mode.
+pub fn write_lines<B: Write>(message: &str, buffer: &mut B) {
+    for line in message.lines() {
+        writeln(line, buffer);
+    }
+}
+
 /// Asks a binary question to the user. Return true for yes, false for no.
 pub fn binary_qestion(question: &str) -> Result<bool, Error> {
     let stdin = stdin();
@@ -45,25 +65,82 @@ pub fn binary_qestion(question: &str) -> Result<bool, Error> {
     }
 }
 
+/// Asks a question answered with a single key press among `choices`.
+/// Falls back to reading a whole line if stdin is not a terminal.
+pub fn key_question(question: &str, choices: &[char]) -> Result<char, Error> {
+    let stdin = stdin();
+    let mut stdout = stdout();
+    print!("{} ", question);
+    wrap(stdout.flush(), "Unable to write to stdout")?;
+    if termion::is_tty(&stdin) {
+        let raw = wrap(stdout.lock().into_raw_mode(), "Unable to switch stdout to raw mode")?;
+        for key in stdin.lock().keys() {
+            match wrap(key, "Unable to read from stdin")? {
+                Key::Char(c) if choices.contains(&c) => {
+                    drop(raw);
+                    println!("{}", c);
+                    return Ok(c);
+                }
+                Key::Ctrl('c') | Key::Esc => {
+                    drop(raw);
+                    println!();
+                    return Error::from_str("Aborting");
+                }
+                _ => (),
+            }
+        }
+        drop(raw);
+        Error::from_str("Unable to read from stdin")
+    } else {
+        loop {
+            let mut buffer = String::new();
+            let n = wrap(stdin.read_line(&mut buffer), "Undable to read from stdin")?;
+            if n == 0 {
+                return Error::from_str("Unable to read from stdin");
+            }
+            if let Some(c) = buffer.trim().chars().next() {
+                if choices.contains(&c) {
+                    println!("{}", c);
+                    return Ok(c);
+                }
+            }
+        }
+    }
+}
+
+/// Writes a boxed output, as a hexdump if it is binary data.
+pub fn write_output<B: Write>(body: &[u8], binary: bool, buffer: &mut B, theme: &Theme) {
+    if binary || is_binary(body) {
+        diff::write_hexdump(body, buffer, theme);
+    } else {
+        buffer.boxed_write(body, theme).unwrap();
+    }
+}
+
 pub fn snap_preview<B: Write>(snap: &std::process::Output, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
     box_separator("status code", SeparatorKind::Top, buffer, theme);
     let exit_code = snap.status.code();
     if let Some(code) = exit_code {
         buffer
-            .boxed_write_str(&format!("{}{}{}", style::Bold, code, style::Reset), theme)
+            .boxed_write_str(&format!("{}{}{}", style.bold, code, style.reset), theme)
             .unwrap();
     } else {
         buffer
-            .boxed_write_str(&format!("{}None{}", style::Bold, style::Reset), theme)
+            .boxed_write_str(&format!("{}None{}", style.bold, style.reset), theme)
             .unwrap();
     }
-    if snap.stdout.len() > 0 {
+    if let Some(signal) = snap.status.signal() {
+        let signal = format!("signal: {}{}{}", style.bold, format_signal(signal), style.reset);
+        buffer.boxed_write_str(&signal, theme).unwrap();
+    }
+    if !snap.stdout.is_empty() {
         box_separator("stdout", SeparatorKind::Middle, buffer, theme);
-        buffer.boxed_write(&snap.stdout, theme).unwrap();
+        write_output(&snap.stdout, false, buffer, theme);
     }
-    if snap.stderr.len() > 0 {
+    if !snap.stderr.is_empty() {
         box_separator("stderr", SeparatorKind::Middle, buffer, theme);
-        buffer.boxed_write(&snap.stderr, theme).unwrap();
+        write_output(&snap.stderr, false, buffer, theme);
     }
     box_separator("", SeparatorKind::Bottom, buffer, theme);
 }
@@ -73,11 +150,12 @@ pub fn snap_summary<B: Write>(
     description: Option<&String>,
     cmd: &str,
     status_code: Option<i32>,
+    signal: Option<i32>,
     buffer: &mut B,
     theme: &Theme,
 ) {
-    let bold = style::Bold;
-    let reset = style::Reset;
+    let bold = &theme.style.bold;
+    let reset = &theme.style.reset;
     let code = if let Some(code) = status_code {
         format!("{}", code)
     } else {
@@ -94,39 +172,279 @@ pub fn snap_summary<B: Write>(
             theme,
         )
         .unwrap();
+    if let Some(signal) = signal {
+        let signal = format!("signal: {}{}{}", bold, format_signal(signal), reset);
+        buffer.boxed_write_str(&signal, theme).unwrap();
+    }
     if let Some(description) = description {
         buffer.boxed_write_str(&format!("\n{}\n", description), theme).unwrap();
     }
 }
 
+/// Writes a one line summary of a snapshot, the name is padded to `name_width`.
+/// In verbose mode the description is written below the summary.
+pub fn list_item<B: Write>(snap: &Snapshot, name_width: usize, verbose: bool, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
+    let code = if let Some(code) = snap.exit_code {
+        format!("{}", code)
+    } else {
+        String::from("None")
+    };
+    let tags: Vec<String> = snap.tags.iter().map(|tag| format!("#{}", tag)).collect();
+    write!(
+        buffer,
+        "{} {}{:<width$}{}  code: {:<4} {}  {}{}{}\r\n",
+        status_symbol(&snap.status, theme),
+        style.bold,
+        snap.name,
+        style.reset,
+        code,
+        snap.cmd,
+        theme.blue,
+        tags.join(" "),
+        style.fg_reset,
+        width = name_width
+    )
+    .unwrap();
+    if verbose {
+        if let Some(record) = snap.history.last() {
+            write!(buffer, "    last run: {}\r\n", format_duration(record.duration_ms)).unwrap();
+        }
+        if let Some(created) = &snap.created {
+            write!(buffer, "    created: {}\r\n", format_stamp(created)).unwrap();
+        }
+        if let Some(updated) = &snap.updated {
+            write!(buffer, "    updated: {}\r\n", format_stamp(updated)).unwrap();
+        }
+        if let Some(description) = &snap.description {
+            for line in description.lines() {
+                write!(buffer, "    {}\r\n", line).unwrap();
+            }
+        }
+    }
+}
+
+/// Returns the colored symbol representing a snapshot status.
+pub fn status_symbol(status: &SnapshotStatus, theme: &Theme) -> String {
+    let style = &theme.style;
+    match status {
+        SnapshotStatus::Waiting => format!("{}~{}", style.light_blue, style.fg_reset),
+        SnapshotStatus::Failed => format!("{}✗{}", style.light_red, style.fg_reset),
+        SnapshotStatus::Passed => format!("{}✓{}", style.light_green, style.fg_reset),
+    }
+}
+
+/// Formats a size in bytes for humans.
+pub fn format_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Formats an age in seconds for humans, such as "3 hours ago".
+pub fn format_age(seconds: u64) -> String {
+    let units = [("day", 24 * 3600), ("hour", 3600), ("minute", 60)];
+    for (unit, length) in units.iter() {
+        let count = seconds / length;
+        if count > 0 {
+            let plural = if count > 1 { "s" } else { "" };
+            return format!("{} {}{} ago", count, unit, plural);
+        }
+    }
+    "just now".to_owned()
+}
+
+/// Formats when and by whom a change was made, such as "3 hours ago by alice".
+pub fn format_stamp(stamp: &Stamp) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let age = format_age(now.saturating_sub(stamp.at));
+    match &stamp.by {
+        Some(by) => format!("{} by {}", age, by),
+        None => age,
+    }
+}
+
+/// Formats a signal number along with its name, if known.
+pub fn format_signal(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGTRAP => "SIGTRAP",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGUSR1 => "SIGUSR1",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGUSR2 => "SIGUSR2",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        _ => return signal.to_string(),
+    };
+    format!("{} ({})", name, signal)
+}
+
+/// Formats a duration in milliseconds for humans.
+pub fn format_duration(ms: u64) -> String {
+    if ms < 1000 {
+        format!("{} ms", ms)
+    } else {
+        format!("{:.1} s", ms as f64 / 1000.0)
+    }
+}
+
+/// Removes ANSI escape sequences, such as colors or cursor moves, from an
+/// output.
+pub fn strip_ansi(body: &[u8]) -> Vec<u8> {
+    const ESC: u8 = 0x1b;
+    const BEL: u8 = 0x07;
+    let mut stripped = Vec::with_capacity(body.len());
+    let mut idx = 0;
+    while idx < body.len() {
+        if body[idx] != ESC {
+            stripped.push(body[idx]);
+            idx += 1;
+            continue;
+        }
+        idx += 1;
+        match body.get(idx) {
+            // Control sequence, up to a final byte in '@'..='~'
+            Some(b'[') => {
+                idx += 1;
+                while idx < body.len() && !(0x40..=0x7e).contains(&body[idx]) {
+                    idx += 1;
+                }
+                idx += 1;
+            }
+            // Operating system command, up to a BEL or an ESC \
+            Some(b']') => {
+                idx += 1;
+                while idx < body.len() && body[idx] != BEL && !(body[idx] == ESC && body.get(idx + 1) == Some(&b'\\')) {
+                    idx += 1;
+                }
+                idx += if body.get(idx) == Some(&ESC) { 2 } else { 1 };
+            }
+            Some(_) => idx += 1,
+            None => (),
+        }
+    }
+    stripped
+}
+
+/// Writes a boxed message stating that the command timed out.
+pub fn timed_out<B: Write>(timeout: Option<u64>, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
+    let after = timeout.map_or(String::new(), |timeout| format!(" after {} s", timeout));
+    buffer
+        .boxed_write_str(
+            &format!("{}{}timed out{}{}{}", style.light_red, style.bold, after, style.reset, style.fg_reset),
+            theme,
+        )
+        .unwrap();
+}
+
+/// Writes a boxed message stating that the output exceeded its size limit.
+pub fn output_exceeded<B: Write>(limit: usize, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
+    let message = format!("output exceeded limit of {}", format_size(limit as u64));
+    buffer
+        .boxed_write_str(
+            &format!("{}{}{}{}{}", style.light_red, style.bold, message, style.reset, style.fg_reset),
+            theme,
+        )
+        .unwrap();
+}
+
+/// Writes a boxed message stating that the command ran for longer than its
+/// maximum duration.
+pub fn too_slow<B: Write>(duration_ms: u64, max_ms: u64, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
+    let message = format!("took {}, more than {}", format_duration(duration_ms), format_duration(max_ms));
+    buffer
+        .boxed_write_str(
+            &format!("{}{}{}{}{}", style.light_red, style.bold, message, style.reset, style.fg_reset),
+            theme,
+        )
+        .unwrap();
+}
+
+/// Writes a boxed message stating that the snapshot was skipped because a
+/// snapshot it needs failed.
+pub fn skipped<B: Write>(needs: &str, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
+    let message = format!("skipped (dependency '{}' failed)", needs);
+    buffer
+        .boxed_write_str(
+            &format!("{}{}{}{}{}", style.yellow, style.bold, message, style.reset, style.fg_reset),
+            theme,
+        )
+        .unwrap();
+}
+
+/// Writes why a snapshot could not be run: the first line of the message in
+/// red, the rest as is.
+pub fn errored<B: Write>(message: &str, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
+    let (first, rest) = message.split_once('\n').unwrap_or((message, ""));
+    buffer
+        .boxed_write_str(
+            &format!("{}{}{}{}{}", style.light_red, style.bold, first, style.reset, style.fg_reset),
+            theme,
+        )
+        .unwrap();
+    if !rest.is_empty() {
+        buffer.boxed_write_str(rest, theme).unwrap();
+    }
+}
+
+/// Writes the line of a snapshot that ran, in verbose mode.
+pub fn run_line<B: Write>(name: &str, cmd: &str, duration_ms: u64, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
+    write!(buffer, "{}{}{} {} ({})\r\n", style.bold, name, style.reset, cmd, format_duration(duration_ms)).unwrap();
+}
+
 /// Writes the success message.
-pub fn success<B: Write>(buffer: &mut B) {
+pub fn success<B: Write>(buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
     write!(
         buffer,
         "{}{}Success ✓{}{}\r\n",
-        color::Fg(color::LightGreen),
-        style::Bold,
-        style::Reset,
-        color::Fg(color::Reset)
+        style.light_green,
+        style.bold,
+        style.reset,
+        style.fg_reset
     )
     .unwrap();
 }
 
 /// Writes the failure message.
-pub fn failure<B: Write>(buffer: &mut B) {
+pub fn failure<B: Write>(buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
     write!(
         buffer,
         "{}{}Failure ✗{}{}\r\n",
-        color::Fg(color::LightRed),
-        style::Bold,
-        style::Reset,
-        color::Fg(color::Reset)
+        style.light_red,
+        style.bold,
+        style.reset,
+        style.fg_reset
     )
     .unwrap();
 }
 
 /// Draws a separator for boxed messages.
 pub fn box_separator<B: Write>(title: &str, kind: SeparatorKind, buffer: &mut B, theme: &Theme) {
+    let style = &theme.style;
     let corner = match kind {
         SeparatorKind::Top => '┌',
         SeparatorKind::Middle => '├',
@@ -138,10 +456,10 @@ pub fn box_separator<B: Write>(title: &str, kind: SeparatorKind, buffer: &mut B,
         "{}{}────{} {}{}{}\r\n",
         theme.blue,
         corner,
-        color::Fg(color::Reset),
-        style::Bold,
+        style.fg_reset,
+        style.bold,
         title,
-        style::Reset
+        style.reset
     )
     .unwrap();
 }
@@ -156,21 +474,39 @@ pub trait BoxedWriter: Write {
 impl<W: Write> BoxedWriter for W {
     fn boxed_write(&mut self, buf: &[u8], theme: &Theme) -> io::Result<()> {
         let colorize = &theme.blue;
-        let reset_color = color::Fg(color::Reset);
+        let reset_color = &theme.style.fg_reset;
         for line in buf.split(|c| c == &b'\n') {
-            write!(self, "{}│{} ", colorize, reset_color)?;
-            self.write_all(line)?;
-            self.write_all(&[b'\n', b'\r'])?;
+            write!(self, "{}│{} {}\n\r", colorize, reset_color, diff::escape_invalid(line))?;
         }
         Ok(())
     }
 
     fn boxed_write_str(&mut self, string: &str, theme: &Theme) -> io::Result<()> {
         let colorize = &theme.blue;
-        let reset_color = color::Fg(color::Reset);
+        let reset_color = &theme.style.fg_reset;
         for line in string.lines() {
             write!(self, "{}│{} {}\r\n", colorize, reset_color, line)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi(b"\x1b[1;31merror\x1b[0m: oops\x1b[K"), b"error: oops");
+        assert_eq!(strip_ansi(b"\x1b]0;title\x07a\x1b]8;;url\x1b\\b\x1b=c"), b"abc");
+        assert_eq!(strip_ansi(b"plain\x1b"), b"plain");
+    }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(59), "just now");
+        assert_eq!(format_age(60), "1 minute ago");
+        assert_eq!(format_age(3 * 3600 + 59), "3 hours ago");
+        assert_eq!(format_age(2 * 24 * 3600), "2 days ago");
+    }
+}
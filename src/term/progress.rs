@@ -0,0 +1,81 @@
+use std::io::{stderr, Write};
+use std::time::{Duration, Instant};
+
+use super::format_duration;
+
+/// Interval between the progress lines when stderr is not a terminal.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reports the progress of a run on stderr, on a line updated in place when
+/// stderr is a terminal, or else on a plain line every few seconds.
+pub struct Progress {
+    enabled: bool,
+    total: usize,
+    start: Instant,
+    tty: bool,
+    /// When the last plain line was printed.
+    printed_at: Instant,
+    /// Whether a line is displayed on the terminal, to erase before other
+    /// output.
+    shown: bool,
+}
+
+impl Progress {
+    /// Initialize a new Progress for a run of `total` snapshots, which reports
+    /// nothing unless `enabled` is set.
+    pub fn new(total: usize, enabled: bool) -> Progress {
+        let now = Instant::now();
+        Progress {
+            enabled,
+            total,
+            start: now,
+            tty: termion::is_tty(&stderr()),
+            printed_at: now,
+            shown: false,
+        }
+    }
+
+    /// Reports that `done` snapshots ran, `failed` of them failed, and that
+    /// `running` is being run if known.
+    pub fn update(&mut self, done: usize, failed: usize, running: Option<&str>) {
+        if !self.enabled {
+            return;
+        }
+        let mut line = format!("[{}/{}]", done, self.total);
+        if let Some(name) = running {
+            line.push_str(&format!(" running {}…", name));
+        }
+        if failed > 0 {
+            line.push_str(&format!(" {} failed", failed));
+        }
+        if done > 0 && done < self.total {
+            let elapsed = self.start.elapsed().as_millis() as u64;
+            let eta = elapsed / done as u64 * (self.total - done) as u64;
+            line.push_str(&format!(" (ETA {})", format_duration(eta)));
+        }
+        let mut stderr = stderr();
+        if self.tty {
+            // The line is cut to the width of the terminal, a wrapped line
+            // could not be erased
+            let width = match termion::terminal_size() {
+                Ok((width, _)) if width > 0 => width as usize,
+                _ => 80,
+            };
+            let line: String = line.chars().take(width.saturating_sub(1)).collect();
+            write!(stderr, "\r\x1b[K{}", line).unwrap();
+            self.shown = true;
+        } else if self.printed_at.elapsed() >= PLAIN_INTERVAL {
+            writeln!(stderr, "{}", line).unwrap();
+            self.printed_at = Instant::now();
+        }
+        stderr.flush().unwrap();
+    }
+
+    /// Erases the line from the terminal, before any other output.
+    pub fn clear(&mut self) {
+        if self.shown {
+            eprint!("\r\x1b[K");
+            self.shown = false;
+        }
+    }
+}
@@ -3,8 +3,9 @@ use termion::cursor::DetectCursorPos;
 use termion::event::Key;
 use termion::input::{Keys, TermRead};
 use termion::raw::{IntoRawMode, RawTerminal};
-use termion::{clear, color, cursor, style, terminal_size};
+use termion::{clear, cursor, terminal_size};
 
+use super::status_symbol;
 use super::theme::Theme;
 use crate::data::SnapshotStatus;
 use crate::driver::View;
@@ -35,7 +36,7 @@ pub struct Repl {
 
 impl Repl {
     /// Initialize the REPL internal state.
-    pub fn new(stdin: Stdin, stdout: Stdout) -> Repl {
+    pub fn new(stdin: Stdin, stdout: Stdout, theme: Theme) -> Repl {
         let mut stdout = BufWriter::new(stdout).into_raw_mode().unwrap();
         let stdin = stdin.keys();
         let input = String::from("");
@@ -50,12 +51,12 @@ impl Repl {
             suspended: false,
 
             // Symbols
-            waiting_symbol: format!("{}~{}", color::Fg(color::LightBlue), color::Fg(color::Reset)),
-            failed_symbol: format!("{}✗{}", color::Fg(color::LightRed), color::Fg(color::Reset)),
-            passed_symbol: format!("{}✓{}", color::Fg(color::LightGreen), color::Fg(color::Reset)),
+            waiting_symbol: status_symbol(&SnapshotStatus::Waiting, &theme),
+            failed_symbol: status_symbol(&SnapshotStatus::Failed, &theme),
+            passed_symbol: status_symbol(&SnapshotStatus::Passed, &theme),
 
             // Colors
-            theme: Theme::new(),
+            theme,
         };
         repl.restore();
         repl
@@ -114,13 +115,12 @@ impl Repl {
                     self.input.pop();
                     self.render(view);
                 }
-                Key::Char('\n') => {
-                    if self.input.len() > 0 {
-                        let mut command = String::new();
-                        std::mem::swap(&mut self.input, &mut command);
-                        return Input::Command(command);
-                    }
+                Key::Char('\n') if !self.input.is_empty() => {
+                    let mut command = String::new();
+                    std::mem::swap(&mut self.input, &mut command);
+                    return Input::Command(command);
                 }
+                Key::Char('\n') => (),
                 Key::Char(c) => {
                     self.input.push(c);
                     self.render(view);
@@ -164,13 +164,14 @@ impl Repl {
 
     /// Displays the REPL snapshot list.
     fn display_list(&mut self, view: &View) {
+        let style = &self.theme.style;
         // Style & colors
-        let bg = color::Bg(color::Black);
-        let clear_bg = color::Bg(color::Reset);
+        let bg = &style.bg_black;
+        let clear_bg = &style.bg_reset;
         let cursor_color = &self.theme.cursor;
-        let clear_color = color::Fg(color::Reset);
-        let bold = style::Bold;
-        let clear_bold = style::Reset;
+        let clear_color = &style.fg_reset;
+        let bold = &style.bold;
+        let clear_bold = &style.reset;
 
         let (min, max) = view.window;
         let data = view.get_view();
@@ -192,14 +193,14 @@ impl Repl {
                 write!(self.stdout, "{} {} {} {}\r\n", bg, clear_bg, status, snap.name).unwrap();
             };
         }
-        let current = if data.len() == 0 { 0 } else { min + view.cursor + 1 };
+        let current = if data.is_empty() { 0 } else { min + view.cursor + 1 };
         write!(
             self.stdout,
             "  {}{}/{}{}",
-            color::Fg(color::White),
+            style.white,
             current,
             data.len(),
-            color::Fg(color::Reset)
+            style.fg_reset
         )
         .unwrap();
     }
@@ -207,9 +208,9 @@ impl Repl {
     /// Displays the input, return the offset of the input line.
     fn display_input(&mut self) -> u16 {
         let input_color = &self.theme.input;
-        let clear_color = color::Fg(color::Reset);
-        let bold = style::Bold;
-        let clear_bold = style::Reset;
+        let clear_color = &self.theme.style.fg_reset;
+        let bold = &self.theme.style.bold;
+        let clear_bold = &self.theme.style.reset;
         write!(
             self.stdout,
             "{}{}>{} {}{}",
@@ -224,13 +225,13 @@ impl Repl {
         let (w, _) = terminal_size().unwrap_or((80, 24));
 
         // Style & colors
-        let bold = style::Bold;
-        let reset_style = style::Reset;
+        let bold = &self.theme.style.bold;
+        let reset_style = &self.theme.style.reset;
         let red = &self.theme.red;
         let yellow = &self.theme.yellow;
         let green = &self.theme.green;
         let blue = &self.theme.blue;
-        let reset_color = color::Fg(color::Reset);
+        let reset_color = &self.theme.style.fg_reset;
 
         // Compute sizes
         let w = (w - 2) as usize; // remove 2 units for the box's borders
@@ -1,6 +1,9 @@
 use std::env;
 use termion::color;
 
+use super::colors::Style;
+
+#[derive(Clone)]
 pub struct Theme {
     pub red: String,
     pub yellow: String,
@@ -8,10 +11,25 @@ pub struct Theme {
     pub blue: String,
     pub cursor: String,
     pub input: String,
+    pub style: Style,
 }
 
 impl Theme {
-    pub fn new() -> Self {
+    /// Initialize the theme chosen with `PARROT_THEME`, or a theme without
+    /// any color unless `colors` is set.
+    pub fn new(colors: bool) -> Self {
+        let style = Style::new(colors);
+        if !colors {
+            return Theme {
+                red: String::new(),
+                yellow: String::new(),
+                green: String::new(),
+                blue: String::new(),
+                cursor: String::new(),
+                input: String::new(),
+                style,
+            };
+        }
         let theme = env::var("PARROT_THEME").unwrap_or("scarlet".to_string());
         match theme.as_str() {
             "scarlet" => Theme {
@@ -21,6 +39,7 @@ impl Theme {
                 blue: color::Rgb(59, 99, 172).fg_string(),
                 cursor: color::Rgb(241, 9, 6).fg_string(),
                 input: color::Rgb(59, 99, 172).fg_string(),
+                style,
             },
             "blue-and-yellow" => Theme {
                 red: color::Rgb(22, 157, 215).fg_string(),
@@ -29,6 +48,7 @@ impl Theme {
                 blue: color::Rgb(255, 211, 47).fg_string(),
                 cursor: color::Rgb(22, 157, 215).fg_string(),
                 input: color::Rgb(255, 211, 47).fg_string(),
+                style,
             },
             "hyacinth" => Theme {
                 red: color::Rgb(74, 95, 188).fg_string(),
@@ -37,6 +57,7 @@ impl Theme {
                 blue: color::Rgb(74, 95, 188).fg_string(),
                 cursor: color::Rgb(255, 204, 85).fg_string(),
                 input: color::Rgb(74, 95, 188).fg_string(),
+                style,
             },
             "military" => Theme {
                 red: color::Rgb(109, 207, 60).fg_string(),
@@ -45,6 +66,7 @@ impl Theme {
                 blue: color::Rgb(42, 200, 255).fg_string(),
                 cursor: color::Rgb(109, 207, 60).fg_string(),
                 input: color::Rgb(59, 99, 172).fg_string(),
+                style,
             },
             "gray" => Theme {
                 red: color::Rgb(177, 176, 194).fg_string(),
@@ -53,6 +75,7 @@ impl Theme {
                 blue: color::Rgb(177, 176, 194).fg_string(),
                 cursor: color::Rgb(177, 176, 194).fg_string(),
                 input: color::Rgb(59, 99, 172).fg_string(),
+                style,
             },
             "yellow-crested" => Theme {
                 red: color::Rgb(177, 176, 194).fg_string(),
@@ -61,14 +84,17 @@ impl Theme {
                 blue: color::Rgb(235, 226, 95).fg_string(),
                 cursor: color::Rgb(235, 226, 95).fg_string(),
                 input: color::Rgb(59, 99, 172).fg_string(),
+                style,
             },
-            "ansi" | _ => Theme {
+            // Default to "ansi" for unknown themes
+            _ => Theme {
                 red: color::LightRed.fg_str().to_string(),
                 yellow: color::LightYellow.fg_str().to_string(),
                 green: color::LightGreen.fg_str().to_string(),
                 blue: color::LightBlue.fg_str().to_string(),
                 cursor: color::LightRed.fg_str().to_string(),
                 input: color::LightBlue.fg_str().to_string(),
+                style,
             },
         }
     }